@@ -0,0 +1,117 @@
+create table if not exists users (
+	id integer primary key,
+	name text,
+	username text,
+
+	-- glicko-1 rating
+	rating real not null default 1500,
+	rating_deviation real not null default 350,
+	rated_games integer not null default 0,
+	deviation_updated_at integer,
+
+	-- daily summary of games waiting for the user's move
+	digest boolean not null default 0,
+	digest_sent_at integer,
+
+	-- pin the board message of the current game
+	pin_board boolean not null default 0,
+
+	-- promoted by another admin, on top of those listed in ADMINS
+	admin boolean not null default 0,
+
+	-- set while the user is on vacation and their clocks are paused
+	vacation_started_at integer
+);
+
+create table if not exists vacations (
+	user_id integer not null,
+	started_at integer not null,
+	ended_at integer,
+
+	foreign key (user_id) references users (id)
+);
+
+-- holds a row while the bot is in maintenance mode
+create table if not exists maintenance (
+	started_at integer not null
+);
+
+create table if not exists games (
+	id integer primary key,
+	w_id integer,
+	b_id integer,
+
+	-- whether the seek's creator doesn't mind playing either color
+	random_color boolean not null default 0,
+
+	ended boolean,
+
+	-- null - draw, 0 - black, 1 white
+	winner boolean, 
+
+	-- null - not over, 0 - timeout, 1 - resign, 2 - checkmate, 3 - draw, 4 - aborted, 5 - abandoned
+	termination integer, -- 
+
+	fen text not null,
+
+	created_at integer not null default (unixepoch()),
+	-- ratings before the game and their changes, null for unrated games
+	w_rating integer,
+	b_rating integer,
+	w_rating_diff integer,
+	b_rating_diff integer,
+
+	started_at integer,
+	ended_at integer,
+	last_move_at integer not null default (unixepoch()),
+
+	-- clocks in milliseconds, null for untimed games
+	initial_ms integer,
+	increment_ms integer,
+	delay_ms integer,
+	bronstein boolean, -- 0 - simple delay, 1 - bronstein delay
+	w_clock_ms integer,
+	b_clock_ms integer,
+	turn_started_ms integer,
+
+	-- last board message sent to each player, kept up to date with the clocks
+	w_message_id integer,
+	b_message_id integer,
+	board_text text,
+
+	-- moves played so far, the ply of the next move
+	plies integer not null default 0,
+
+	-- set when the game is deleted; it is purged for good after a grace period
+	deleted_at integer,
+
+	foreign key (w_id) references users (id)
+	foreign key (b_id) references users (id)
+);
+
+create table if not exists moves (
+	game_id integer,
+	ply integer not null,
+	uci text not null,
+	-- unix time in milliseconds
+	played_at integer not null,
+	-- left on the mover's clock after the move, null for untimed games
+	clock_ms integer,
+	-- zobrist hash of the position after the move, for repetition checks
+	zobrist integer not null,
+
+	foreign key (game_id) references games (id)
+);
+
+-- every message looks up the sender's ongoing game, history looks up ended ones
+create index if not exists games_player_w on games (w_id, ended);
+create index if not exists games_player_b on games (b_id, ended);
+
+-- open seeks, oldest first, for pairing
+create index if not exists games_seeks on games (created_at) where (b_id is null or w_id is null) and ended = 0;
+
+-- running clocks, checked every few seconds
+create index if not exists games_running on games (turn_started_ms) where ended = 0 and turn_started_ms is not null;
+
+create index if not exists moves_game on moves (game_id, ply);
+create index if not exists moves_zobrist on moves (game_id, zobrist);
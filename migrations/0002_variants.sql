@@ -0,0 +1,2 @@
+-- 0 - standard, 1 - fog of war
+alter table games add column variant integer not null default 0;
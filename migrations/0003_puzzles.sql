@@ -0,0 +1,107 @@
+-- Puzzles in the format of the Lichess puzzle database: `fen` is the position
+-- before the opponent's move, `moves` the UCI line starting with that move.
+create table puzzles (
+    id text primary key,
+    fen text not null,
+    moves text not null,
+    rating integer not null,
+    themes text not null default ''
+);
+
+create index puzzles_rating on puzzles (rating);
+
+create table rushes (
+    id integer primary key,
+    user_id integer not null references users (id),
+    started_at integer not null, -- unix ms
+    ends_at integer not null, -- unix ms
+    score integer not null default 0,
+    strikes integer not null default 0,
+    puzzle_id text references puzzles (id),
+    -- moves of the current puzzle's line played so far, the opponent's included
+    step integer not null default 0,
+    ended boolean not null default 0
+);
+
+create index rushes_running on rushes (ends_at) where ended = 0;
+create index rushes_user on rushes (user_id, score);
+
+create table rush_attempts (
+    rush_id integer not null references rushes (id),
+    puzzle_id text not null references puzzles (id),
+    solved boolean not null,
+    primary key (rush_id, puzzle_id)
+);
+
+-- A starter set of mates so that rushes work before a database is imported.
+insert into puzzles (id, fen, moves, rating, themes) values
+    ('seed001', 'rnb1kbnr/pppp1p1p/6p1/4p1q1/3P4/1QP3P1/PP2PP1P/RNB1KBNR w KQkq - 3 5', 'b3b5 g5c1', 600, 'mate mateIn1 oneMove'),
+    ('seed002', 'rnb1kb2/pQqppp1p/7n/2p2p2/8/1P2P2N/P1PP2rP/RNB1KB1R b KQq - 0 8', 'c7e5 b7c8', 615, 'mate mateIn1 oneMove'),
+    ('seed003', 'rn2kbnr/pbpp2pp/4p3/5p2/6qP/4P3/PPPPQPP1/RNB2KNR w kq - 3 7', 'e2b5 g4d1', 630, 'mate mateIn1 oneMove'),
+    ('seed004', '4k2r/1bpppnbp/8/Pp3Kp1/6P1/2P3N1/3P3P/3R4 w k - 1 25', 'd1b1 e7e6', 645, 'mate mateIn1 oneMove'),
+    ('seed005', '2b4r/rpppk2p/p1n3p1/6qn/8/PPPP2P1/2Q1PP1P/bN2KBNR w K - 3 16', 'c2c1 g5c1', 660, 'mate mateIn1 oneMove'),
+    ('seed006', 'r1b2k1N/pp4b1/n3p2p/2p5/2PQ4/PP3PB1/R3PnPP/1N2KB1R b K - 2 21', 'f2h1 d4d8', 675, 'mate mateIn1 oneMove'),
+    ('seed007', '1n3bn1/2p3pr/3p3p/1P2k3/7P/r4b2/3P1P1R/4KB2 w - - 0 22', 'h2h1 a3a1', 690, 'mate mateIn1 oneMove'),
+    ('seed008', '8/6kp/1pr1pn1b/8/8/PP5N/4P2P/R2K4 w - - 0 37', 'a1c1 c6c1', 705, 'mate mateIn1 oneMove'),
+    ('seed009', '1r6/2p2pn1/2nk4/8/2P3B1/5P2/7r/3KR3 w - - 2 28', 'g4c8 b8b1', 720, 'mate mateIn1 oneMove'),
+    ('seed010', 'r1qk2n1/1pp1bp2/1N2p3/p4rp1/QnPp4/8/P2PPPRP/RNB1KB2 b Q - 1 17', 'c8b8 a4d7', 735, 'mate mateIn1 oneMove'),
+    ('seed011', 'rnb1kbnr/1p1pp1pQ/p1p2p2/q7/3P4/8/PPP1PPPP/RNBK1BNR b kq - 2 5', 'a5d8 h7g6', 750, 'mate mateIn1 oneMove'),
+    ('seed012', 'r4qnr/pppb1p2/2n4p/5k2/4NP1P/1P3KQ1/PbPPP2R/R1B3N1 b - - 15 21', 'f8e8 g3g4', 765, 'mate mateIn1 oneMove'),
+    ('seed013', '1n2k2r/rb1p1nbp/8/3Np1B1/p3P3/Q7/P5PP/R3KBR1 b Qk - 0 26', 'b7d5 a3e7', 780, 'mate mateIn1 oneMove'),
+    ('seed014', 'r1b5/1pppk1Q1/5p2/p2p4/1n6/2P1P3/PP1N2Pr/R1B1KBNR b KQ - 0 17', 'e7d8 g7f8', 795, 'mate mateIn1 oneMove'),
+    ('seed015', 'rnb1kbnr/pp1ppppp/2p5/6q1/3P4/6PN/PPPQPP1P/RNB1KB1R w KQkq - 9 7', 'd2b4 g5c1', 810, 'mate mateIn1 oneMove'),
+    ('seed016', '2bqkb1r/r1p1p3/7Q/pN1p3P/6p1/1nP3P1/P2PPP2/R1B1KBN1 b Qk - 0 15', 'c8d7 h6g6', 825, 'mate mateIn1 oneMove'),
+    ('seed017', 'r1bqkbnr/ppppp1pp/n7/5p2/6Q1/P3P3/1PPP1PPP/RNB1KBNR b KQkq - 1 3', 'h7h5 g4g6', 840, 'mate mateIn1 oneMove'),
+    ('seed018', '1nq1kbn1/r1pppQ2/pp3p2/7p/P4p2/6NP/RPPP2P1/2B1KBNR b K - 0 16', 'e8d8 f7f8', 855, 'mate mateIn1 oneMove'),
+    ('seed019', 'rnb1kbnr/ppp1pppp/3p4/q7/P5Q1/1PN1P3/2PP1PPP/R1B1KBNR b Kkq - 2 8', 'a5a4 g4c8', 870, 'mate mateIn1 oneMove'),
+    ('seed020', 'rn2kbnr/pppb4/4p3/8/3P2p1/5N2/PqP1PP1P/RNB1KB1R w KQkq - 0 11', 'f1g2 b2c1', 885, 'mate mateIn1 oneMove'),
+    ('seed021', 'rnb1kbn1/p2pppp1/Q5qr/2p4p/8/2PP1P2/PP2P1PP/RNB1KBNR b KQq - 4 12', 'g6g2 a6c8', 900, 'mate mateIn1 oneMove'),
+    ('seed022', 'r3k3/2p4r/p6B/4p3/N3p2b/7P/BP2NP1q/R3K2R w KQq - 2 28', 'e1f1 h2f2', 915, 'mate mateIn1 oneMove'),
+    ('seed023', 'rnbqkb1r/pppppp1p/8/6p1/2P5/5Q1P/PP1PPnP1/RNB1KBNR b KQkq - 1 5', 'f7f6 f3h5', 930, 'mate mateIn1 oneMove'),
+    ('seed024', 'r3qbnr/ppp3pp/5kB1/n3pb2/2Q1P3/P6N/1PPP1PPP/RNB1K1R1 b Q - 4 12', 'e8a4 c4f7', 945, 'mate mateIn1 oneMove'),
+    ('seed025', 'rnb1kbnr/3ppppp/1pQ5/p4B2/1P6/4P2N/P1PP1PPP/RNB1K1R1 b Qkq - 0 9', 'g8f6 c6c8', 960, 'mate mateIn1 oneMove'),
+    ('seed026', 'r2k1bnr/pQpppppp/5q2/1p6/8/N2PB3/PPP1NPPP/R3KB1R b KQ - 2 17', 'f6g6 b7a8', 975, 'mate mateIn1 oneMove'),
+    ('seed027', 'rnbqkbnr/p1ppp2Q/1p4p1/8/PP2p3/8/2PP1PPP/RNB1KBNR b KQkq - 0 5', 'b6b5 h7g6', 990, 'mate mateIn1 oneMove'),
+    ('seed028', 'r3b3/p1p3b1/n2p2pk/8/PP3P2/R7/2P5/1NK5 b - - 2 31', 'e8a4 a3h3', 1005, 'mate mateIn1 oneMove'),
+    ('seed029', 'rn2k3/p2b1p2/2p2B2/3p4/1P6/P2P4/5PP1/RN1K3R b q - 0 18', 'c6c5 h1h8', 1020, 'mate mateIn1 oneMove'),
+    ('seed030', 'r2k1bnr/p2p4/bp3Pp1/2p4Q/8/P1P3PP/RP1P2q1/1NB1K1NR w K - 0 18', 'h5h4 g2f1', 1035, 'mate mateIn1 oneMove'),
+    ('seed031', 'rnbk2nr/pppp1ppp/4p3/1P6/1Q1P1q2/5PPP/P1P1N3/RNB1KB1R b KQ - 0 10', 'f4e5 b4f8', 1050, 'mate mateIn1 oneMove'),
+    ('seed032', '1n2Qr2/7p/1pk4n/R3P2P/6p1/NP6/3PK1P1/1q4NR b - - 0 26', 'c6c7 a5a7', 1065, 'mate mateIn1 oneMove'),
+    ('seed033', 'rnbk2nr/p2p4/7p/1p2Q1p1/6p1/3P4/PPPBPK1P/RN3BNR b - - 0 12', 'h8h7 d2a5', 1080, 'mate mateIn1 oneMove'),
+    ('seed034', '1n1qkbnr/2pppppp/rp6/p3N2Q/4b3/8/PPPP1PPP/RNBK1B1R b k - 1 6', 'e4g2 h5f7', 1095, 'mate mateIn1 oneMove'),
+    ('seed035', 'rnb1kbnr/pppp1ppp/8/4p1q1/8/3P4/PPPQPPPP/RNB1KBNR w KQkq - 2 3', 'd2a5 g5c1', 1110, 'mate mateIn1 oneMove'),
+    ('seed036', 'rnb2b1r/pppp2qp/2n2pp1/1P2pk2/Q7/2P2PPN/PB1PP2P/RN1K1B1R b - - 8 15', 'g7h6 a4g4', 1125, 'mate mateIn1 oneMove'),
+    ('seed037', 'r1b1kbnr/1p2Bp1p/7q/np6/1P1P4/8/R1P1PPPP/4KBNR w Kkq - 1 20', 'e7d6 h6c1', 1140, 'mate mateIn1 oneMove'),
+    ('seed038', '2b1k2r/2p3q1/n1r1pp2/1R1p4/pPP5/P2PBPp1/R2NP3/4KBN1 w k - 0 19', 'c4d5 c6c1', 1155, 'mate mateIn1 oneMove'),
+    ('seed039', 'rnb1kbnr/pp1pp1pp/B1Q2p2/8/5P2/Pq2P3/1PPP2PP/RNB1K1NR b KQkq - 0 6', 'b3f7 c6c8', 1170, 'mate mateIn1 oneMove'),
+    ('seed040', 'rn2k1n1/p1q5/3p1pp1/1bpPp3/1P5r/2N3PB/2PKPb1P/2Q4R w q - 0 19', 'b4c5 h4d4', 1185, 'mate mateIn1 oneMove'),
+    ('seed041', '4kb2/3bppp1/8/rPq2Q2/P3P1n1/3P3r/3N2P1/R1B1KB1R w KQ - 2 25', 'f5e5 c5f2 e1d1 g4e3', 1200, 'mate mateIn2 short'),
+    ('seed042', 'rn3b1r/p1p1p3/1pkpQ2N/8/P2Pn3/RP3qPb/1BP4P/1N2K1R1 w - - 2 24', 'b2c3 f3e3 e1d1 e4f2', 1220, 'mate mateIn2 short'),
+    ('seed043', 'rnbqkb2/ppppppp1/8/6Q1/8/PP1P4/2P1pP2/RN2KB1r w Qq - 0 11', 'g5a5 e2f1q e1d2 f1e1', 1240, 'mate mateIn2 short'),
+    ('seed044', '8/5rR1/1R6/3k3p/5P1P/4PP1N/3P4/B2BK3 b - - 0 36', 'f7e7 d1b3 d5c5 a1d4', 1260, 'mate mateIn2 short'),
+    ('seed045', '1n2kb1r/4p3/4Pn1p/1R4p1/8/r7/5PPP/1K4NR b k - 0 26', 'a3c3 b5b8 c3c8 b8c8', 1280, 'mate mateIn2 short'),
+    ('seed046', 'r3kbn1/p1p1pppr/4N3/2p4p/3PP3/N2B1P2/1B1P3P/R3K2R b KQq - 0 22', 'a7a5 d3b5 c7c6 b5c6', 1300, 'mate mateIn2 short'),
+    ('seed047', '1rn1r1b1/6p1/3P1k2/pp3p1p/5P2/8/5K1P/2n1q3 w - - 1 41', 'f2g2 g8d5 g2h3 e8e3', 1320, 'mate mateIn2 short'),
+    ('seed048', 'r1bk1bnr/2qnp3/1ppp1Q2/4P1N1/p7/3P4/PPP1BPPP/RNB1K2R b KQ - 2 11', 'c7b8 g5e6 d8e8 f6g6', 1340, 'mate mateIn2 short'),
+    ('seed049', 'rn3Q1r/p1k4p/bppBp3/5p2/5P2/2N5/R3P2P/5KNR b - - 3 28', 'c7d7 f8e7 d7c8 e7c7', 1360, 'mate mateIn2 short'),
+    ('seed050', '7r/2p5/2nb2Q1/p6p/2B2P1P/k3P3/1r1B2b1/3RK2R b K - 1 35', 'd6b4 d1a1 b2a2 a1a2', 1380, 'mate mateIn2 short'),
+    ('seed051', '1r2kbnr/2pbp3/2P3p1/p1qp2Bp/P1P2P1P/3P4/4P1P1/1N1K1BNR w k - 1 24', 'c4d5 b8b1 d1d2 c5c1', 1400, 'mate mateIn2 short'),
+    ('seed052', 'rnb1kb2/p3pp2/3p3r/7p/1P2nP2/B1PP1K2/P6P/RN1Qq2R w q - 0 23', 'h2h4 c8g4 f3g2 e1f2', 1420, 'mate mateIn2 short'),
+    ('seed053', 'N4kr1/n2b1ppp/Rp1p4/3Q3n/4PB1P/1P6/2P1PPP1/4KBNR b K - 0 18', 'd7g4 d5d6 f8e8 a8c7', 1440, 'mate mateIn2 short'),
+    ('seed054', '1nb4r/1pppn2B/r7/p1P1k3/8/P3P1PP/3Kq3/RN5R w - - 1 20', 'd2c3 e7d5 c3b3 a5a4', 1460, 'mate mateIn2 short'),
+    ('seed055', '1nb3nr/1kp5/5pq1/2P1P2p/1P5b/7P/3BPPPR/1N2KB2 w - - 1 24', 'g2g4 g6b1 d2c1 b1c1', 1480, 'mate mateIn2 short'),
+    ('seed056', '1n2kb2/r1p1ppr1/BR5p/7p/7P/P6K/7P/1N5R b - - 0 25', 'e8d8 h1d1 d8e8 b6b8', 1500, 'mate mateIn2 short'),
+    ('seed057', '4k3/2r5/6Q1/4pp2/2p1N3/P3PNP1/8/b1B1K2R b - - 0 35', 'e8f8 h1h8 f8e7 g6e8', 1520, 'mate mateIn2 short'),
+    ('seed058', 'rn2kbnr/pp2p3/2p3p1/7Q/P3P1bP/1P5N/2PP1P1q/RNB1K2R w KQkq - 1 12', 'c1a3 h2h1 h3g1 h1g1', 1540, 'mate mateIn2 short'),
+    ('seed059', 'r1b1k3/p2p1RpQ/n1p1p2r/1N6/2PP2n1/q7/PP2P1BP/R1B3K1 b q - 2 20', 'g4h2 h7g8 a3f8 g8f8', 1560, 'mate mateIn2 short'),
+    ('seed060', 'rn6/p4k1p/3B4/1p5P/8/P3QP1b/1PP1P3/1R2KR2 b - - 4 24', 'h3f1 e3e7 f7g8 e7f8', 1580, 'mate mateIn2 short'),
+    ('seed061', '1nb2bk1/1p1n4/3p2p1/5p2/1P1Pp3/7r/2PK4/7r w - - 0 36', 'c2c3 h3h2 d2e3 f8h6', 1600, 'mate mateIn2 short'),
+    ('seed062', '4kb1r/3p1ppp/8/1Q6/2R2P1P/6n1/3P4/2BK1BNR b k - 1 22', 'g7g6 c4c8 e8e7 b5e5', 1620, 'mate mateIn2 short'),
+    ('seed063', '8/2p1kp2/2n5/3P2pR/2p3n1/8/r2P4/R2K1BN1 w - - 0 25', 'd1e1 a2a1 e1e2 c6d4', 1640, 'mate mateIn2 short'),
+    ('seed064', 'rn3b1r/1b1BPkpp/6p1/p1p5/8/P2p1P2/1PP3PP/R1BN1KNR b - - 0 15', 'b7c8 e7e8q f7g8 e8e6', 1660, 'mate mateIn2 short'),
+    ('seed065', '1nb1k2r/2pp1p2/1p2p1p1/3P3p/4nqP1/B6N/2P1PP1P/bQ2KB1R w Kk - 0 16', 'b1d1 a1c3 d1d2 f4d2', 1680, 'mate mateIn2 short'),
+    ('seed066', '2b1k2N/Rpp3p1/7p/2B5/3P4/7P/1P2P3/4KB1n b - - 0 23', 'c8h3 f1h3 b7b6 a7a8', 1700, 'mate mateIn2 short'),
+    ('seed067', 'rnbqkb1r/pppp1ppp/7n/4p3/8/5P1P/PPPPP1P1/RNBQKBNR w KQkq - 0 3', 'c2c3 d8h4 g2g3 h4g3', 1720, 'mate mateIn2 short'),
+    ('seed068', 'N1b1k1nr/pp4pp/3Q4/5p2/4p3/1n2P2N/1BP1PPPP/1R2KB1R b K - 0 15', 'b7b6 a8c7 e8f7 h3g5', 1740, 'mate mateIn2 short'),
+    ('seed069', 'rn2kbnr/p3ppp1/3Q3p/q1pp4/8/N3P1P1/PP1P1P1P/R1B1KBNR b KQkq - 6 14', 'a5b5 f1b5 b8c6 b5c6', 1760, 'mate mateIn2 short'),
+    ('seed070', '1qb2k1r/1p1p2p1/8/2P2BPp/3p1N1P/5P2/2rN1K2/2R5 w - - 0 24', 'f4h5 b8h2 f2e1 c2c1', 1780, 'mate mateIn2 short');
@@ -0,0 +1,33 @@
+-- Games replayed by the guess-the-move trainer, moves in UCI.
+create table master_games (
+    id integer primary key,
+    white text not null,
+    black text not null,
+    event text,
+    date text,
+    result text not null,
+    moves text not null
+);
+
+create table guess_sessions (
+    id integer primary key,
+    user_id integer not null references users (id),
+    game_id integer not null references master_games (id),
+    -- the side whose moves the user guesses
+    white boolean not null,
+    -- the ply to be guessed next
+    ply integer not null,
+    points integer not null default 0,
+    guesses integer not null default 0,
+    matched integer not null default 0,
+    ended boolean not null default 0
+);
+
+create index guess_sessions_running on guess_sessions (user_id) where ended = 0;
+
+insert into master_games (white, black, event, date, result, moves) values
+    ('Paul Morphy', 'Duke Karl / Count Isouard', 'Paris', '1858.??.??', '1-0', 'e2e4 e7e5 g1f3 d7d6 d2d4 c8g4 d4e5 g4f3 d1f3 d6e5 f1c4 g8f6 f3b3 d8e7 b1c3 c7c6 c1g5 b7b5 c3b5 c6b5 c4b5 b8d7 e1c1 a8d8 d1d7 d8d7 h1d1 e7e6 b5d7 f6d7 b3b8 d7b8 d1d8'),
+    ('Adolf Anderssen', 'Lionel Kieseritzky', 'London', '1851.06.21', '1-0', 'e2e4 e7e5 f2f4 e5f4 f1c4 d8h4 e1f1 b7b5 c4b5 g8f6 g1f3 h4h6 d2d3 f6h5 f3h4 h6g5 h4f5 c7c6 g2g4 h5f6 h1g1 c6b5 h2h4 g5g6 h4h5 g6g5 d1f3 f6g8 c1f4 g5f6 b1c3 f8c5 c3d5 f6b2 f4d6 c5g1 e4e5 b2a1 f1e2 b8a6 f5g7 e8d8 f3f6 g8f6 d6e7'),
+    ('Adolf Anderssen', 'Jean Dufresne', 'Berlin', '1852.??.??', '1-0', 'e2e4 e7e5 g1f3 b8c6 f1c4 f8c5 b2b4 c5b4 c2c3 b4a5 d2d4 e5d4 e1g1 d4d3 d1b3 d8f6 e4e5 f6g6 f1e1 g8e7 c1a3 b7b5 b3b5 a8b8 b5a4 a5b6 b1d2 c8b7 d2e4 g6f5 c4d3 f5h5 e4f6 g7f6 e5f6 h8g8 a1d1 h5f3 e1e7 c6e7 a4d7 e8d7 d3f5 d7e8 f5d7 e8f8 a3e7'),
+    ('Donald Byrne', 'Robert James Fischer', 'Rosenwald Memorial, New York', '1956.10.17', '0-1', 'g1f3 g8f6 c2c4 g7g6 b1c3 f8g7 d2d4 e8g8 c1f4 d7d5 d1b3 d5c4 b3c4 c7c6 e2e4 b8d7 a1d1 d7b6 c4c5 c8g4 f4g5 b6a4 c5a3 a4c3 b2c3 f6e4 g5e7 d8b6 f1c4 e4c3 e7c5 f8e8 e1f1 g4e6 c5b6 e6c4 f1g1 c3e2 g1f1 e2d4 f1g1 d4e2 g1f1 e2c3 f1g1 a7b6 a3b4 a8a4 b4b6 c3d1 h2h3 a4a2 g1h2 d1f2 h1e1 e8e1 b6d8 g7f8 f3e1 c4d5 e1f3 f2e4 d8b8 b7b5 h3h4 h7h5 f3e5 g8g7 h2g1 f8c5 g1f1 e4g3 f1e1 c5b4 e1d1 d5b3 d1c1 g3e2 c1b1 e2c3 b1c1 a2c2'),
+    ('Richard Réti', 'Savielly Tartakower', 'Vienna', '1910.??.??', '1-0', 'e2e4 c7c6 d2d4 d7d5 b1c3 d5e4 c3e4 g8f6 d1d3 e7e5 d4e5 d8a5 c1d2 a5e5 e1c1 f6e4 d3d8 e8d8 d2g5 d8c7 g5d8');
@@ -0,0 +1,31 @@
+-- Positions from users' opening repertoires, reviewed with spaced repetition.
+create table repertoire_cards (
+    id integer primary key,
+    user_id integer not null references users (id),
+    white boolean not null,
+    -- FEN without the move counters, so that transpositions share a card
+    position text not null,
+    -- UCI moves from the starting position to the first line reaching it
+    path text not null,
+    -- the user's moves here in UCI, more than one if the repertoire branches
+    answers text not null,
+    ease real not null default 2.5,
+    interval_days real not null default 0,
+    repetitions integer not null default 0,
+    due_at integer not null, -- unix s
+    unique (user_id, white, position)
+);
+
+create index repertoire_cards_due on repertoire_cards (user_id, due_at);
+
+create table drills (
+    id integer primary key,
+    user_id integer not null references users (id),
+    card_id integer references repertoire_cards (id) on delete set null,
+    prompted_at integer, -- unix ms
+    reviewed integer not null default 0,
+    correct integer not null default 0,
+    ended boolean not null default 0
+);
+
+create index drills_running on drills (user_id) where ended = 0;
@@ -0,0 +1,14 @@
+create table endgame_sessions (
+    id integer primary key,
+    user_id integer not null references users (id),
+    endgame text not null,
+    -- the current position
+    fen text not null,
+    -- the user's side
+    white boolean not null,
+    plies integer not null default 0,
+    mistakes integer not null default 0,
+    ended boolean not null default 0
+);
+
+create index endgame_sessions_running on endgame_sessions (user_id) where ended = 0;
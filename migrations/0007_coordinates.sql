@@ -0,0 +1,19 @@
+create table coordinate_rounds (
+    id integer primary key,
+    user_id integer not null references users (id),
+    -- 'white' or 'black' to find a marked square on the board seen from that
+    -- side, 'blind' to tell the color of a named one
+    mode text not null,
+    started_at integer not null, -- unix ms
+    -- the square being asked and when, unix ms
+    square text not null,
+    asked_at integer not null,
+    answered integer not null default 0,
+    correct integer not null default 0,
+    -- time spent on all answers
+    answer_ms integer not null default 0,
+    ended boolean not null default 0
+);
+
+create index coordinate_rounds_running on coordinate_rounds (user_id) where ended = 0;
+create index coordinate_rounds_user on coordinate_rounds (user_id, started_at);
@@ -0,0 +1,19 @@
+create table clubs (
+    id integer primary key,
+    name text not null unique collate nocase,
+    created_at integer not null default (unixepoch())
+);
+
+create table club_members (
+    club_id integer not null references clubs (id) on delete cascade,
+    user_id integer not null references users (id),
+    -- 'owner', 'admin' or 'member'
+    role text not null default 'member',
+    joined_at integer not null default (unixepoch()),
+    primary key (club_id, user_id)
+);
+
+create index club_members_user on club_members (user_id);
+
+-- seeks with a club are only paired with its members
+alter table games add column club_id integer references clubs (id);
@@ -0,0 +1,23 @@
+create table team_matches (
+    id integer primary key,
+    -- the club that issued the challenge
+    home_club_id integer not null references clubs (id),
+    away_club_id integer not null references clubs (id),
+    boards integer not null,
+    -- 'proposed', 'accepted' (signing up), 'declined', 'playing' or 'finished'
+    status text not null default 'proposed',
+    created_at integer not null default (unixepoch()),
+    finished_at integer
+);
+
+create index team_matches_open on team_matches (status) where status != 'finished' and status != 'declined';
+
+create table team_match_players (
+    match_id integer not null references team_matches (id),
+    club_id integer not null references clubs (id),
+    user_id integer not null references users (id),
+    primary key (match_id, user_id)
+);
+
+alter table games add column team_match_id integer references team_matches (id);
+alter table games add column board integer;
@@ -0,0 +1,8 @@
+-- null for users who started before this was tracked
+alter table users add column joined_at integer;
+-- the `/start` payload of a user's first message, from a deep link
+alter table users add column start_payload text;
+-- whose invite link brought the user in
+alter table users add column invited_by integer references users (id);
+
+create index users_invited_by on users (invited_by) where invited_by is not null;
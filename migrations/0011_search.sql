@@ -0,0 +1,5 @@
+-- whether the user shows up in /find
+alter table users add column searchable boolean not null default 1;
+
+-- seeks that only this user can join
+alter table games add column challenged_id integer references users (id);
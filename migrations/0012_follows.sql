@@ -0,0 +1,11 @@
+create table follows (
+    follower_id integer not null references users (id),
+    followed_id integer not null references users (id),
+    -- which of the followed player's games to hear about
+    notify_start boolean not null default 1,
+    notify_end boolean not null default 1,
+    created_at integer not null default (unixepoch()),
+    primary key (follower_id, followed_id)
+);
+
+create index follows_followed on follows (followed_id);
@@ -0,0 +1,30 @@
+-- engine comparison of each player's moves in a finished rated game
+create table engine_reviews (
+    game_id integer not null references games (id),
+    user_id integer not null references users (id),
+    -- the player's rating going into the game
+    rating integer not null,
+    -- moves compared, the ones matching the engine's choice, and their summed centipawn loss
+    moves integer not null,
+    matches integer not null,
+    cp_loss integer not null,
+    primary key (game_id, user_id)
+);
+
+create index engine_reviews_user on engine_reviews (user_id);
+
+-- players whose recent games look engine assisted, for admins to look into
+create table cheat_flags (
+    id integer primary key,
+    user_id integer not null references users (id),
+    created_at integer not null default (unixepoch()),
+    games integer not null,
+    match_rate real not null,
+    avg_cp_loss real not null,
+    rating integer not null,
+    -- the admin who dismissed the flag
+    reviewed_by integer references users (id),
+    reviewed_at integer
+);
+
+create index cheat_flags_user on cheat_flags (user_id);
@@ -0,0 +1,16 @@
+-- players who seem to throw rated games to lose rating on purpose
+create table sandbag_flags (
+    id integer primary key,
+    user_id integer not null references users (id),
+    created_at integer not null default (unixepoch()),
+    -- rated games resigned after hardly playing, the rating they cost, and
+    -- the wins against lower-rated players that followed
+    quick_losses integer not null,
+    rating_lost integer not null,
+    wins integer not null,
+    -- the admin who dismissed the flag
+    reviewed_by integer references users (id),
+    reviewed_at integer
+);
+
+create index sandbag_flags_user on sandbag_flags (user_id);
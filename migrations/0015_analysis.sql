@@ -0,0 +1,13 @@
+create table analysis_boards (
+    id integer primary key,
+    user_id integer not null references users (id),
+    -- the position the user set up, and the moves since in UCI, space separated
+    start_fen text not null,
+    moves text not null default '',
+    -- the side the board is shown from
+    white boolean not null,
+    created_at integer not null default (unixepoch()),
+    ended boolean not null default 0
+);
+
+create index analysis_boards_running on analysis_boards (user_id) where ended = 0;
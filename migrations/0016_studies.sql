@@ -0,0 +1,34 @@
+create table studies (
+    id integer primary key,
+    -- the game being looked at, if it's a post-mortem
+    game_id integer references games (id) on delete set null,
+    -- the position it started from, and the moves since in UCI, space separated
+    start_fen text not null,
+    moves text not null default '',
+    created_at integer not null default (unixepoch()),
+    last_activity_at integer not null default (unixepoch()),
+    ended boolean not null default 0
+);
+
+create table study_members (
+    study_id integer not null references studies (id),
+    user_id integer not null references users (id),
+    -- the side the member sees the board from
+    white boolean not null default 1,
+    joined_at integer not null default (unixepoch()),
+    primary key (study_id, user_id)
+);
+
+create index study_members_user on study_members (user_id);
+
+create table study_notes (
+    id integer primary key,
+    study_id integer not null references studies (id),
+    user_id integer not null references users (id),
+    -- the number of moves played when the note was written
+    ply integer not null,
+    text text not null,
+    created_at integer not null default (unixepoch())
+);
+
+create index study_notes_study on study_notes (study_id, ply);
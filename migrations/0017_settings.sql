@@ -0,0 +1,28 @@
+create table user_settings (
+    user_id integer primary key references users (id),
+    -- only 'en' so far
+    language text not null default 'en',
+    -- how boards are drawn: 'figurines' or 'letters'
+    theme text not null default 'figurines',
+    -- how moves are written in game messages: 'long' (e2-e4), 'san' (e4) or 'uci' (e2e4)
+    notation text not null default 'long',
+    -- whether to hear about other players: followed players' games, invites accepted
+    notifications boolean not null default 1,
+    -- whether a game move has to be confirmed before it is played
+    confirm_moves boolean not null default 0,
+    -- whether a pawn move to the last rank without a promotion piece makes a queen
+    auto_queen boolean not null default 0
+);
+
+-- moves waiting for the player's confirmation
+create table pending_moves (
+    user_id integer primary key references users (id),
+    game_id integer not null references games (id) on delete cascade,
+    -- the ply the move was meant for, so a stale one is never played
+    ply integer not null,
+    uci text not null
+);
+
+-- each player's board message reads differently with their own notation
+alter table games rename column board_text to w_board_text;
+alter table games add column b_board_text text;
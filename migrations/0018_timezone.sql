@@ -0,0 +1,3 @@
+-- minutes east of UTC that times are shown in; a fixed offset, so users
+-- change it themselves when daylight saving time starts or ends
+alter table user_settings add column utc_offset integer not null default 0;
@@ -0,0 +1,10 @@
+-- the Telegram channel live games are shown in; there is at most one
+create table featured_channel (
+    id integer primary key check (id = 1),
+    chat_id integer not null,
+    -- needed to post in the channel when resolved through Telegram
+    access_hash integer,
+    -- the game on the board message, if one is being shown
+    game_id integer references games (id) on delete set null,
+    message_id integer
+);
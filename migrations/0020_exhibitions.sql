@@ -0,0 +1,10 @@
+-- engine-vs-engine games started by an admin between two house players,
+-- users with negative ids who are played by the engine
+create table exhibitions (
+    game_id integer primary key references games (id) on delete cascade,
+    -- the strength each side is limited to, null for full strength
+    w_elo integer,
+    b_elo integer,
+    -- how long the engine thinks about each move
+    movetime_ms integer not null
+);
@@ -0,0 +1,16 @@
+-- a rating for each kind of game a user has played rated, next to the
+-- overall one in users; categories start fresh, the overall rating keeps the
+-- history from before they existed
+create table ratings (
+    user_id integer not null references users (id),
+    -- 'bullet', 'blitz', 'rapid', 'classical' and 'correspondence' for
+    -- standard chess by time control, 'fog' for fog of war at any speed
+    category text not null,
+    rating real not null default 1500,
+    rating_deviation real not null default 350,
+    rated_games integer not null default 0,
+    deviation_updated_at integer,
+    primary key (user_id, category)
+);
+
+create index ratings_category_rating on ratings (category, rating);
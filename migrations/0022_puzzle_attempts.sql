@@ -0,0 +1,18 @@
+-- single puzzles picked with /puzzle, one at a time
+create table puzzle_attempts (
+    id integer primary key,
+    user_id integer not null references users (id),
+    puzzle_id text not null references puzzles (id),
+    -- what the user asked for, so the next puzzle can be picked the same way
+    theme text,
+    min_rating integer,
+    max_rating integer,
+    -- moves of the line played so far, the opponent's included
+    step integer not null default 1,
+    -- null while the puzzle is being solved
+    solved boolean,
+    created_at integer not null default (unixepoch())
+);
+
+create index puzzle_attempts_running on puzzle_attempts (user_id) where solved is null;
+create index puzzle_attempts_user on puzzle_attempts (user_id, puzzle_id);
@@ -0,0 +1,5 @@
+-- puzzles made from a user's own games, given only to them
+alter table puzzles add column owner_id integer references users (id);
+alter table puzzles add column game_id integer references games (id) on delete set null;
+
+create index puzzles_owner on puzzles (owner_id) where owner_id is not null;
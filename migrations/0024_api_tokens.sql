@@ -0,0 +1,10 @@
+-- tokens for the read-only JSON API, handed out by admins to club websites
+-- and other tools
+create table api_tokens (
+    id integer primary key,
+    label text not null unique,
+    token text not null unique,
+    created_by integer not null references users (id),
+    created_at integer not null default (unixepoch()),
+    last_used_at integer
+);
@@ -0,0 +1,7 @@
+-- armageddon: black starts with less time but wins if the game is drawn
+alter table games add column armageddon boolean not null default 0;
+-- black's starting time when it differs from white's
+alter table games add column b_initial_ms integer;
+
+-- the armageddon game deciding a tied team match
+alter table team_matches add column playoff_game_id integer references games (id) on delete set null;
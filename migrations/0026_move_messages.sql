@@ -0,0 +1,2 @@
+-- the player's message that played the move, so editing it can correct the move
+alter table moves add column message_id integer;
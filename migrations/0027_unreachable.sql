@@ -0,0 +1,2 @@
+-- when the user blocked the bot or their account went away, while it lasts
+alter table users add column unreachable_since integer;
@@ -0,0 +1,2 @@
+-- banned users' messages are ignored
+alter table users add column banned_at integer;
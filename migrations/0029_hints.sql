@@ -0,0 +1,9 @@
+-- engine suggestions given with /hint, counted against rated games' allowance
+create table hints (
+    game_id integer not null references games (id),
+    user_id integer not null references users (id),
+    ply integer not null,
+    uci text not null,
+    created_at integer not null default (unixepoch()),
+    primary key (game_id, user_id, ply)
+);
@@ -0,0 +1,2 @@
+-- the game a player's moves go to when they have more than one, set with /game
+alter table users add column active_game_id integer references games (id);
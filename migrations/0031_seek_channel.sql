@@ -0,0 +1,9 @@
+-- the Telegram channel or group open seeks are posted in; there is at most one
+create table seek_channel (
+    id integer primary key check (id = 1),
+    -- the packed chat in hex, as grammers writes it
+    chat text not null
+);
+
+-- the post announcing the seek, while it is open
+alter table games add column seek_message_id integer;
@@ -0,0 +1,31 @@
+-- multi-week leagues between clubs, each division playing a round robin of
+-- team matches, one round at a time
+create table leagues (
+    id integer primary key,
+    name text not null unique collate nocase,
+    boards integer not null,
+    -- days each round lasts before boards nobody played are forfeited
+    round_days integer not null default 7,
+    -- 'open' (taking clubs), 'running' or 'finished'
+    status text not null default 'open',
+    created_at integer not null default (unixepoch()),
+    started_at integer
+);
+
+create table league_clubs (
+    league_id integer not null references leagues (id),
+    club_id integer not null references clubs (id),
+    division integer not null default 1,
+    primary key (league_id, club_id)
+);
+
+-- a league fixture is 'scheduled' until its round opens for sign-ups
+alter table team_matches add column league_id integer references leagues (id);
+alter table team_matches add column round integer;
+alter table team_matches add column opens_at integer;
+alter table team_matches add column deadline integer;
+-- boards a club fielded nobody for while the other club did
+alter table team_matches add column home_forfeits integer not null default 0;
+alter table team_matches add column away_forfeits integer not null default 0;
+
+create index team_matches_league on team_matches (league_id, round) where league_id is not null;
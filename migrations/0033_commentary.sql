@@ -0,0 +1,38 @@
+-- the game each user is watching, and hearing the commentary of
+create table spectators (
+    user_id integer primary key references users (id),
+    game_id integer not null references games (id) on delete cascade,
+    created_at integer not null default (unixepoch())
+);
+
+create index spectators_game on spectators (game_id);
+
+-- spectators' comments, passed on to the players once the game is over
+create table comments (
+    id integer primary key,
+    game_id integer not null references games (id) on delete cascade,
+    user_id integer not null references users (id),
+    -- moves played when the comment was made
+    ply integer not null,
+    text text not null,
+    created_at integer not null default (unixepoch())
+);
+
+create index comments_game on comments (game_id);
+
+-- commenters a user doesn't want to hear from
+create table comment_mutes (
+    user_id integer not null references users (id),
+    muted_id integer not null references users (id),
+    primary key (user_id, muted_id)
+);
+
+create table comment_reports (
+    comment_id integer not null references comments (id) on delete cascade,
+    reporter_id integer not null references users (id),
+    created_at integer not null default (unixepoch()),
+    primary key (comment_id, reporter_id)
+);
+
+-- users an admin stopped from commenting
+alter table users add column silenced_at integer;
@@ -0,0 +1,12 @@
+-- whether the user sees the emotes opponents send
+alter table user_settings add column emotes boolean not null default 1;
+
+-- emotes sent in games, kept to limit how often players send them
+create table emotes (
+    game_id integer not null references games (id) on delete cascade,
+    user_id integer not null references users (id),
+    emote text not null,
+    created_at integer not null default (unixepoch())
+);
+
+create index emotes_game on emotes (game_id, user_id);
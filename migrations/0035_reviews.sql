@@ -0,0 +1,10 @@
+-- a user stepping through a finished game, one at a time
+create table reviews (
+    user_id integer primary key references users (id),
+    game_id integer not null references games (id) on delete cascade,
+    -- moves shown so far
+    ply integer not null default 0,
+    -- the side the board is shown from
+    white boolean not null,
+    created_at integer not null default (unixepoch())
+);
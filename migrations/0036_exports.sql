@@ -0,0 +1,7 @@
+-- archives of all their games users asked for, built in the background
+create table exports (
+    user_id integer primary key references users (id),
+    -- the notice sent with the request, edited once the file is sent
+    message_id integer,
+    created_at integer not null default (unixepoch())
+);
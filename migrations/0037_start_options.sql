@@ -0,0 +1,5 @@
+-- unrated by choice, with /start casual
+alter table games add column casual boolean not null default 0;
+-- correspondence games started with e.g. /start 3d, abandoned after that
+-- many days without a move rather than the bot's default
+alter table games add column days_per_move integer;
@@ -0,0 +1,12 @@
+-- players' notes on the moves of their finished games, one per player and move
+create table move_notes (
+    game_id integer not null references games (id) on delete cascade,
+    user_id integer not null references users (id),
+    -- the move noted, 1 for White's first
+    ply integer not null,
+    -- a PGN numeric annotation glyph, e.g. 2 for ?
+    nag integer,
+    text text not null default '',
+    created_at integer not null default (unixepoch()),
+    primary key (game_id, user_id, ply)
+);
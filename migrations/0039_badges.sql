@@ -0,0 +1,11 @@
+-- marks admins grant players, shown next to their names
+create table badges (
+    user_id integer not null references users (id),
+    -- titled, arena or founder
+    badge text not null,
+    -- the title of a titled player, e.g. GM
+    detail text,
+    granted_by integer not null references users (id),
+    granted_at integer not null default (unixepoch()),
+    primary key (user_id, badge)
+);
@@ -0,0 +1,3 @@
+-- weekly summary of the user's games and puzzles
+alter table users add column weekly_digest boolean not null default 0;
+alter table users add column weekly_digest_sent_at integer;
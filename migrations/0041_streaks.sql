@@ -0,0 +1,3 @@
+-- whether the user is reminded in the evening that their play streak is about to end
+alter table user_settings add column streak_reminders boolean not null default 1;
+alter table users add column streak_reminded_at integer;
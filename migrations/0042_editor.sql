@@ -0,0 +1,9 @@
+-- games started from a position set up with /edit, null for the usual start
+alter table games add column initial_fen text;
+
+create table editors (
+    user_id integer primary key references users (id),
+    -- the position being set up; move counters are always 0 1
+    fen text not null,
+    created_at integer not null default (unixepoch())
+);
@@ -0,0 +1,350 @@
+//! Analysis board: the user moves for both sides from any position, takes
+//! moves back and asks the engine, with nothing rated or recorded as a game.
+
+use crate::diagram::{self, Theme};
+use crate::engine::{Score, DEFAULT_MOVETIME, MAX_MULTIPV};
+use crate::{
+    ongoing_game, packed_chat, parse_typed_move, position_from_fen, settings, training, State, Variant, STARTING_FEN,
+};
+use anyhow::Result;
+use log::debug;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{Bitboard, CastlingMode, Chess, Color, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str = "Usage: /analysis [fen] | back [n] | eval [lines] | stop";
+
+/// Engine moves shown after an evaluation.
+const EVAL_PV_PLIES: usize = 6;
+
+/// Candidate moves shown when the user doesn't say how many.
+const DEFAULT_CANDIDATES: usize = 3;
+
+/// Engine moves shown after each candidate, fewer than for a single line so
+/// the candidates fit a screen.
+const CANDIDATE_PV_PLIES: usize = 4;
+
+/// Cells in the bar showing who is better.
+const EVAL_BAR_CELLS: usize = 10;
+
+const COLUMNS: &str = "id, user_id, start_fen, moves, white";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Board {
+    id: i64,
+    user_id: i64,
+    start_fen: String,
+    moves: String,
+    white: bool,
+}
+
+impl Board {
+    fn ucis(&self) -> Vec<&str> {
+        self.moves.split_whitespace().collect()
+    }
+
+    /// The position after the moves played so far.
+    fn position(&self) -> Chess {
+        replay(&self.start_fen, &self.moves)
+    }
+
+    fn side(&self) -> Color {
+        if self.white {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    async fn show(&self, db: &Pool<Sqlite>, position: &Chess) -> Result<String> {
+        Ok(show(position, self.side(), settings::theme(db, self.user_id).await?))
+    }
+
+    async fn save(&self, db: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("update analysis_boards set moves = $2 where id = $1")
+            .bind(self.id)
+            .bind(&self.moves)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The board seen from `side`, whose turn it is and how the game stands.
+pub fn show(position: &Chess, side: Color, theme: Theme) -> String {
+    let turn = if position.turn().is_white() { "White" } else { "Black" };
+    let status = if position.is_checkmate() {
+        format!("Checkmate, {} wins.", if position.turn().is_white() { "Black" } else { "White" })
+    } else if position.is_stalemate() {
+        "Stalemate.".to_string()
+    } else if position.is_insufficient_material() {
+        "Insufficient material.".to_string()
+    } else if position.is_check() {
+        format!("{turn} to move, in check.")
+    } else {
+        format!("{turn} to move.")
+    };
+    format!("{}\n{status}", diagram::render(position.board(), side, Bitboard::FULL, theme))
+}
+
+/// The position after `moves`, UCI separated by spaces, stopping at the first
+/// one that doesn't fit.
+pub fn replay(start_fen: &str, moves: &str) -> Chess {
+    let mut position = position_from_fen(start_fen);
+    for uci in moves.split_whitespace() {
+        let Some(m) = uci.parse::<Uci>().ok().and_then(|uci| uci.to_move(&position).ok()) else {
+            break;
+        };
+        position.play_unchecked(&m);
+    }
+    position
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Board>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from analysis_boards where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+/// The move as numbered in a game score, such as `12... Nf6`.
+pub fn numbered(position: &Chess, san: &San) -> String {
+    let dots = if position.turn().is_white() { "." } else { "..." };
+    format!("{}{dots} {san}", position.fullmoves())
+}
+
+/// The engine's view of the position, from White's side.
+pub async fn evaluate(state: &State, position: &Chess) -> Result<String> {
+    candidates(state, position, 1).await
+}
+
+/// The engine's best `lines` moves in the position with their evaluations
+/// from White's side and the play it expects after them.
+pub async fn candidates(state: &State, position: &Chess, lines: usize) -> Result<String> {
+    let Some(engine) = &state.engine else {
+        return Ok("No engine is set up for evaluations.".to_string());
+    };
+    if position.is_game_over() {
+        return Ok("The game is over.".to_string());
+    }
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    // a single line is remembered for whoever asks next
+    let found = match lines {
+        1 => engine.evaluate(&fen).await?.into_iter().collect(),
+        lines => engine.analyse(&fen, lines, DEFAULT_MOVETIME, &[]).await?,
+    };
+    let Some(best) = found.first() else {
+        return Ok("The engine found nothing.".to_string());
+    };
+    let sign = if position.turn().is_white() { 1 } else { -1 };
+    let bar = eval_bar(sign * best.score.centipawns());
+    if found.len() == 1 {
+        let (score, pv) = (format_score(best.score, sign), format_pv(position, &best.pv, EVAL_PV_PLIES));
+        return Ok(format!("Eval {score}: {pv}\nWhite {bar} Black"));
+    }
+    let mut text = format!("Top {} moves:", found.len());
+    for line in &found {
+        let (score, pv) = (format_score(line.score, sign), format_pv(position, &line.pv, CANDIDATE_PV_PLIES));
+        text = format!("{text}\n{score}: {pv}");
+    }
+    Ok(format!("{text}\nWhite {bar} Black"))
+}
+
+/// A score for the side to move as White sees it, e.g. `+0.35` or `#-3`.
+fn format_score(score: Score, sign: i64) -> String {
+    match score {
+        Score::Cp(cp) => format!("{:+.2}", (sign * cp) as f64 / 100.0),
+        Score::Mate(n) => format!("#{}", sign * n),
+    }
+}
+
+/// The first `plies` moves of a line in SAN, numbered as in a game score.
+fn format_pv(position: &Chess, pv: &[String], plies: usize) -> String {
+    let mut sans = Vec::new();
+    let mut after = position.clone();
+    for uci in pv.iter().take(plies) {
+        let Some(m) = uci.parse::<Uci>().ok().and_then(|uci| uci.to_move(&after).ok()) else {
+            break;
+        };
+        let san = San::from_move(&after, &m);
+        sans.push(if sans.is_empty() || after.turn().is_white() {
+            numbered(&after, &san)
+        } else {
+            san.to_string()
+        });
+        after.play_unchecked(&m);
+    }
+    sans.join(" ")
+}
+
+/// The number of candidate moves asked for, e.g. `3` in `/eval 3`.
+fn parse_lines(s: &str) -> Option<usize> {
+    match s.trim() {
+        "" => Some(DEFAULT_CANDIDATES),
+        n => n.parse().ok().filter(|n| (1..=MAX_MULTIPV).contains(n)),
+    }
+}
+
+fn lines_usage() -> String {
+    format!("Ask for 1 to {MAX_MULTIPV} candidate moves, e.g. /eval {DEFAULT_CANDIDATES}.")
+}
+
+/// White's share of the bar, by the expected score for the centipawns, as
+/// with the bars beside boards on chess sites.
+fn eval_bar(centipawns: i64) -> String {
+    let expected = 1.0 / (1.0 + 10f64.powf(-(centipawns as f64) / 400.0));
+    let white = (expected * EVAL_BAR_CELLS as f64).round() as usize;
+    format!("{}{}", "█".repeat(white), "░".repeat(EVAL_BAR_CELLS - white))
+}
+
+/// `/eval [lines]`: the engine's best moves on the analysis board, or in the
+/// user's game if it's unrated.
+pub async fn on_eval(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let Some(lines) = parse_lines(args) else {
+        state.client.send_message(chat, lines_usage()).await?;
+        return Ok(());
+    };
+    let text = if let Some(board) = running(&state.db, user_id).await? {
+        candidates(state, &board.position(), lines).await?
+    } else {
+        match ongoing_game(&state.db, user_id).await? {
+            Some(game) if game.variant() == Variant::FogOfWar => "The engine would see through the fog.".to_string(),
+            Some(game) if game.rated() => "Evaluations are only for unrated games.".to_string(),
+            Some(game) => candidates(state, &position_from_fen(&game.fen), lines).await?,
+            None => "Open a board with /analysis, or play an unrated game, to ask the engine.".to_string(),
+        }
+    };
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+pub async fn on_analysis(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let (command, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let board = running(&state.db, user_id).await?;
+    let text = match (command, board) {
+        ("stop", Some(board)) => {
+            sqlx::query("update analysis_boards set ended = 1 where id = $1")
+                .bind(board.id)
+                .execute(&state.db)
+                .await?;
+            "Analysis board closed.".to_string()
+        }
+        ("back", Some(mut board)) => {
+            let n = match rest.trim() {
+                "" => 1,
+                n => match n.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => {
+                        state.client.send_message(chat, USAGE).await?;
+                        return Ok(());
+                    }
+                },
+            };
+            let ucis = board.ucis();
+            if ucis.is_empty() {
+                "There are no moves to take back.".to_string()
+            } else {
+                board.moves = ucis[..ucis.len().saturating_sub(n)].join(" ");
+                board.save(&state.db).await?;
+                board.show(&state.db, &board.position()).await?
+            }
+        }
+        ("eval", Some(board)) => match parse_lines(rest) {
+            Some(lines) => candidates(state, &board.position(), lines).await?,
+            None => lines_usage(),
+        },
+        ("", Some(board)) => board.show(&state.db, &board.position()).await?,
+        ("stop" | "back" | "eval", None) => format!("You have no analysis board open.\n{USAGE}"),
+        _ => return start(state, user_id, args.trim()).await,
+    };
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Opens a board at the FEN, or the starting position, replacing the user's
+/// current one.
+pub async fn start(state: &mut State, user_id: i64, fen: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let fen = if fen.is_empty() { STARTING_FEN } else { fen };
+    let Some(position) = fen
+        .parse::<Fen>()
+        .ok()
+        .and_then(|fen| fen.into_position::<Chess>(CastlingMode::Standard).ok())
+    else {
+        state
+            .client
+            .send_message(chat, format!("That is not a valid position.\n{USAGE}"))
+            .await?;
+        return Ok(());
+    };
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state.client.send_message(chat, "Finish your game first.").await?;
+        return Ok(());
+    }
+    let replacing = running(&state.db, user_id).await?;
+    if replacing.is_none() {
+        if let Some(what) = training(&state.db, user_id).await? {
+            state
+                .client
+                .send_message(chat, format!("Finish your {what} first."))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    let start_fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let mut tx = state.db.begin().await?;
+    sqlx::query("update analysis_boards set ended = 1 where user_id = $1 and ended = 0")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let id: i64 =
+        sqlx::query_scalar("insert into analysis_boards (user_id, start_fen, white) values ($1, $2, $3) returning id")
+            .bind(user_id)
+            .bind(&start_fen)
+            .bind(position.turn().is_white())
+            .fetch_one(&mut *tx)
+            .await?;
+    tx.commit().await?;
+    debug!("analysis board {id} for {user_id}");
+
+    let board = Board {
+        id,
+        user_id,
+        start_fen,
+        moves: String::new(),
+        white: position.turn().is_white(),
+    };
+    let text = format!(
+        "Analysis board: send moves for both sides. /analysis back takes them back, /analysis eval asks the engine, /analysis stop closes the board.\n{}",
+        board.show(&state.db, &position).await?
+    );
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Plays a move sent while the board is open.
+pub async fn on_move(state: &mut State, mut board: Board, notation: &str) -> Result<()> {
+    let chat = packed_chat(board.user_id);
+    let mut position = board.position();
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+    let played = numbered(&position, &San::from_move(&position, &m));
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    board.moves = if board.moves.is_empty() { uci } else { format!("{} {uci}", board.moves) };
+    board.save(&state.db).await?;
+    position.play_unchecked(&m);
+    state
+        .client
+        .send_message(chat, format!("{played}\n{}", board.show(&state.db, &position).await?))
+        .await?;
+    Ok(())
+}
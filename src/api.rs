@@ -0,0 +1,324 @@
+//! Read-only JSON API under `/api/` for club websites and other tools:
+//! games with their moves, team match standings and the leaderboards.
+//! Requests need a token handed out by an admin, sent as
+//! `Authorization: Bearer <token>`.
+
+use crate::rating::{self, Category};
+use crate::{game_by_id, game_ucis, san_moves, settings, teams, user_name, web, Game, State, Termination, Variant, GAME_COLUMNS};
+use anyhow::Result;
+use log::info;
+use serde_json::{json, Value};
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str = "/admin api [new <label> | revoke <label>]";
+
+/// Games listed when the request doesn't say how many.
+const DEFAULT_LIST_LEN: i64 = 20;
+const MAX_LIST_LEN: i64 = 100;
+
+enum Reply {
+    Json(Value),
+    NotFound,
+    BadRequest(&'static str),
+}
+
+/// The status and JSON body answering a GET request for `path`, which
+/// starts with `/api/`. `head` is the whole request head, for the token.
+pub async fn respond(db: &Pool<Sqlite>, head: &str, path: &str) -> Result<(&'static str, String)> {
+    if !authorized(db, head).await? {
+        return Ok(("401 Unauthorized", json!({ "error": "missing or unknown token" }).to_string()));
+    }
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    };
+    let limit = match param("limit").map(str::parse::<i64>) {
+        None => Ok(DEFAULT_LIST_LEN),
+        Some(Ok(n)) if n > 0 => Ok(n.min(MAX_LIST_LEN)),
+        Some(_) => Err("limit must be a positive number"),
+    };
+    let segments: Vec<&str> = path.trim_start_matches("/api/").trim_end_matches('/').split('/').collect();
+    let reply = match (segments.as_slice(), limit) {
+        (_, Err(e)) => Reply::BadRequest(e),
+        (["games"], Ok(limit)) => match param("user").map(str::parse::<i64>) {
+            Some(Err(_)) => Reply::BadRequest("user must be a user id"),
+            user => Reply::Json(games(db, user.and_then(Result::ok), param("live") == Some("1"), limit).await?),
+        },
+        (["games", id], _) => match id.parse() {
+            Ok(id) => game(db, id).await?.map_or(Reply::NotFound, Reply::Json),
+            Err(_) => Reply::NotFound,
+        },
+        (["leaderboard"], Ok(limit)) => match param("category").map(Category::parse) {
+            Some(None) => Reply::BadRequest("unknown category"),
+            category => Reply::Json(leaderboard(db, category.flatten(), limit).await?),
+        },
+        (["matches"], Ok(limit)) => Reply::Json(matches(db, limit).await?),
+        (["matches", id], _) => match id.parse() {
+            Ok(id) => team_match(db, id).await?.map_or(Reply::NotFound, Reply::Json),
+            Err(_) => Reply::NotFound,
+        },
+        _ => Reply::NotFound,
+    };
+    Ok(match reply {
+        Reply::Json(value) => ("200 OK", value.to_string()),
+        Reply::NotFound => ("404 Not Found", json!({ "error": "not found" }).to_string()),
+        Reply::BadRequest(e) => ("400 Bad Request", json!({ "error": e }).to_string()),
+    })
+}
+
+/// Whether the request carries a known token, noting when it was last used.
+async fn authorized(db: &Pool<Sqlite>, head: &str) -> Result<bool> {
+    let token = web::header(head, "Authorization").and_then(|value| value.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return Ok(false);
+    };
+    let used = sqlx::query("update api_tokens set last_used_at = unixepoch() where token = $1")
+        .bind(token.trim())
+        .execute(db)
+        .await?
+        .rows_affected();
+    Ok(used > 0)
+}
+
+async fn player(db: &Pool<Sqlite>, id: Option<i64>, rating: Option<i64>, rating_diff: Option<i64>) -> Result<Value> {
+    Ok(match id {
+        Some(id) => json!({
+            "id": id,
+            "name": user_name(db, id).await?,
+            "rating": rating,
+            "rating_diff": rating_diff,
+        }),
+        None => Value::Null,
+    })
+}
+
+/// A game without its moves.
+async fn summary(db: &Pool<Sqlite>, game: &Game) -> Result<Value> {
+    let time_control = game.initial_ms.map(|initial| {
+        json!({
+            "initial_ms": initial,
+            "increment_ms": game.increment_ms.unwrap_or(0),
+            "delay_ms": game.delay_ms,
+        })
+    });
+    Ok(json!({
+        "id": game.id,
+        "white": player(db, game.w_id, game.w_rating, game.w_rating_diff).await?,
+        "black": player(db, game.b_id, game.b_rating, game.b_rating_diff).await?,
+        "variant": match game.variant() {
+            Variant::Standard => "standard",
+            Variant::FogOfWar => "fog_of_war",
+        },
+        "category": game.category().as_str(),
+        "armageddon": game.armageddon,
+        "time_control": time_control,
+        "started_at": game.started_at,
+        "ended_at": game.ended_at,
+        "ended": game.ended,
+        "result": game.result(),
+        "termination": game.termination.and_then(Termination::from_i64).map(|t| t.reason().to_lowercase()),
+        "fen": game.fen,
+        "plies": game.plies,
+    }))
+}
+
+/// The latest games, the user's only if given, and only those in progress
+/// if `live`.
+async fn games(db: &Pool<Sqlite>, user: Option<i64>, live: bool, limit: i64) -> Result<Value> {
+    // a live fog of war game would give away what the players can't see
+    let games: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where deleted_at is null and w_id is not null and b_id is not null
+         and ($1 is null or w_id = $1 or b_id = $1) and (not $2 or not ended)
+         and not (variant = 1 and not ended) order by id desc limit $3"
+    ))
+    .bind(user)
+    .bind(live)
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+    let mut list = Vec::with_capacity(games.len());
+    for game in &games {
+        list.push(summary(db, game).await?);
+    }
+    Ok(json!({ "games": list }))
+}
+
+/// The game with its moves and, while it is played, the clocks.
+async fn game(db: &Pool<Sqlite>, id: i64) -> Result<Option<Value>> {
+    let Some(game) = game_by_id(db, id).await? else {
+        return Ok(None);
+    };
+    if game.variant() == Variant::FogOfWar && !game.ended {
+        return Ok(None);
+    }
+    let ucis = game_ucis(db, id).await?;
+    let moves: Vec<Value> = ucis
+        .iter()
+        .zip(san_moves(game.start_fen(), &ucis))
+        .map(|(uci, san)| json!({ "uci": uci, "san": san }))
+        .collect();
+    let mut value = summary(db, &game).await?;
+    value["moves"] = json!(moves);
+    if !game.ended && game.initial_ms.is_some() {
+        value["clock"] = json!({
+            "white_ms": game.w_clock_ms,
+            "black_ms": game.b_clock_ms,
+            "turn_started_ms": game.turn_started_ms,
+        });
+    }
+    Ok(Some(value))
+}
+
+async fn leaderboard(db: &Pool<Sqlite>, category: Option<Category>, limit: i64) -> Result<Value> {
+    let players: Vec<Value> = rating::top(db, category, limit)
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (id, name, rating))| {
+            json!({
+                "rank": i + 1,
+                "id": id,
+                "name": name.unwrap_or_else(|| id.to_string()),
+                "rating": rating.round() as i64,
+            })
+        })
+        .collect();
+    Ok(json!({ "category": category.map(Category::as_str), "players": players }))
+}
+
+const SELECT_MATCH: &str = "select m.id, m.status, m.boards, home.name, away.name, m.playoff_game_id from team_matches m
+    join clubs home on home.id = m.home_club_id join clubs away on away.id = m.away_club_id";
+
+async fn matches(db: &Pool<Sqlite>, limit: i64) -> Result<Value> {
+    let matches: Vec<(i64, String, i64, String, String, Option<i64>)> =
+        sqlx::query_as(&format!("{SELECT_MATCH} order by m.id desc limit $1"))
+            .bind(limit)
+            .fetch_all(db)
+            .await?;
+    let matches: Vec<Value> = matches
+        .into_iter()
+        .map(|(id, status, boards, home, away, _)| {
+            json!({ "id": id, "status": status, "boards": boards, "home": home, "away": away })
+        })
+        .collect();
+    Ok(json!({ "matches": matches }))
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct MatchGame {
+    id: i64,
+    board: i64,
+    w_id: i64,
+    b_id: i64,
+    ended: bool,
+    winner: Option<bool>,
+    termination: Option<i64>,
+}
+
+/// A team match with its boards and both clubs' scores.
+async fn team_match(db: &Pool<Sqlite>, id: i64) -> Result<Option<Value>> {
+    let found: Option<(i64, String, i64, String, String, Option<i64>)> = sqlx::query_as(&format!("{SELECT_MATCH} where m.id = $1"))
+        .bind(id)
+        .fetch_optional(db)
+        .await?;
+    let Some((id, status, boards, home, away, playoff_game_id)) = found else {
+        return Ok(None);
+    };
+    let games: Vec<MatchGame> = sqlx::query_as(
+        "select id, board, w_id, b_id, coalesce(ended, 0) as ended, winner, termination from games
+         where team_match_id = $1 order by board",
+    )
+    .bind(id)
+    .fetch_all(db)
+    .await?;
+    let (mut home_score, mut away_score) = (0, 0);
+    let mut list = Vec::with_capacity(games.len());
+    for MatchGame { id: game_id, board, w_id, b_id, ended, winner, termination } in games {
+        let result = if ended {
+            let (white, black) = teams::half_points(winner, termination);
+            let (home, away) = if teams::home_is_white(board) { (white, black) } else { (black, white) };
+            home_score += home;
+            away_score += away;
+            json!({ "white": white as f64 / 2.0, "black": black as f64 / 2.0 })
+        } else {
+            Value::Null
+        };
+        list.push(json!({
+            "board": board,
+            "game_id": game_id,
+            "white": { "id": w_id, "name": user_name(db, w_id).await? },
+            "black": { "id": b_id, "name": user_name(db, b_id).await? },
+            "result": result,
+        }));
+    }
+    Ok(Some(json!({
+        "id": id,
+        "status": status,
+        "boards": boards,
+        "home": { "name": home, "score": home_score as f64 / 2.0 },
+        "away": { "name": away, "score": away_score as f64 / 2.0 },
+        "games": list,
+        // the armageddon game settling a tie
+        "playoff_game_id": playoff_game_id,
+    })))
+}
+
+/// `/admin api`: lists the tokens, hands out a new one or revokes one.
+pub async fn admin(state: &mut State, admin_id: i64, args: &str) -> Result<String> {
+    let (command, label) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let label = label.trim();
+    match command {
+        "" => {
+            let tokens: Vec<(String, Option<i64>)> =
+                sqlx::query_as("select label, last_used_at from api_tokens order by label")
+                    .fetch_all(&state.db)
+                    .await?;
+            if tokens.is_empty() {
+                return Ok(format!("No API tokens yet.\nUsage: {USAGE}"));
+            }
+            let settings = settings::get(&state.db, admin_id).await?;
+            let lines: Vec<String> = tokens
+                .into_iter()
+                .map(|(label, used)| match used {
+                    Some(used) => format!("{label}, last used {}", settings.local_time(used * 1000)),
+                    None => format!("{label}, never used"),
+                })
+                .collect();
+            Ok(format!("API tokens:\n{}", lines.join("\n")))
+        }
+        "new" if !label.is_empty() => {
+            let token = format!("{:032x}", rand::random::<u128>());
+            let created = sqlx::query("insert or ignore into api_tokens (label, token, created_by) values ($1, $2, $3)")
+                .bind(label)
+                .bind(&token)
+                .bind(admin_id)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if created == 0 {
+                return Ok(format!("There is already a token labelled {label}."));
+            }
+            info!("{admin_id} created API token {label}");
+            Ok(format!(
+                "API token for {label}: {token}\nSend it as `Authorization: Bearer {token}`. It is not shown again."
+            ))
+        }
+        "revoke" if !label.is_empty() => {
+            let revoked = sqlx::query("delete from api_tokens where label = $1")
+                .bind(label)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if revoked == 0 {
+                return Ok(format!("No token is labelled {label}."));
+            }
+            info!("{admin_id} revoked API token {label}");
+            Ok(format!("The token for {label} is revoked."))
+        }
+        _ => Ok(format!("Usage: {USAGE}")),
+    }
+}
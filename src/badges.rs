@@ -0,0 +1,109 @@
+//! Badges: marks admins grant players, like a chess title or an arena win,
+//! shown next to their name in profiles, pairings and leaderboards.
+
+use crate::{find_user, user_name, State};
+use anyhow::Result;
+use log::info;
+use sqlx::{Pool, Sqlite};
+
+/// The badges, the mark shown by the name and what the badge is for.
+const BADGES: [(&str, &str, &str); 3] = [
+    ("titled", "🎖", "titled player"),
+    ("arena", "🏆", "arena winner"),
+    ("founder", "🏛", "club founder"),
+];
+
+/// Titles a titled player can be given, shown before their name in place of
+/// the badge's mark.
+const TITLES: [&str; 10] = ["GM", "IM", "FM", "CM", "NM", "WGM", "WIM", "WFM", "WCM", "WNM"];
+
+const USAGE: &str = "/admin badge <user> titled [GM|IM|FM|...] | arena | founder, /admin unbadge <user> <badge>";
+
+async fn of_user(db: &Pool<Sqlite>, user_id: i64) -> Result<Vec<(String, Option<String>)>> {
+    Ok(sqlx::query_as("select badge, detail from badges where user_id = $1 order by granted_at, badge")
+        .bind(user_id)
+        .fetch_all(db)
+        .await?)
+}
+
+fn badge(name: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    BADGES.into_iter().find(|&(badge, _, _)| badge == name)
+}
+
+/// The name with the user's badges, e.g. `GM Alice 🏆`.
+pub async fn decorate(db: &Pool<Sqlite>, user_id: i64, name: &str) -> Result<String> {
+    let mut text = name.to_string();
+    for (name, detail) in of_user(db, user_id).await? {
+        let Some((_, mark, _)) = badge(&name) else {
+            continue;
+        };
+        text = match detail {
+            Some(title) => format!("{title} {text}"),
+            None => format!("{text} {mark}"),
+        };
+    }
+    Ok(text)
+}
+
+/// What the user's badges are for, for their profile.
+pub async fn describe(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<String>> {
+    let mut described = Vec::new();
+    for (name, detail) in of_user(db, user_id).await? {
+        let Some((_, mark, what)) = badge(&name) else {
+            continue;
+        };
+        described.push(match detail {
+            Some(title) => format!("{mark} {what} ({title})"),
+            None => format!("{mark} {what}"),
+        });
+    }
+    Ok((!described.is_empty()).then(|| format!("Badges: {}", described.join(", "))))
+}
+
+/// Handles `/admin badge` and `/admin unbadge`, returning the reply.
+pub async fn admin(state: &State, admin_id: i64, args: &str, grant: bool) -> Result<String> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (who, name, detail) = match words[..] {
+        [who, name] => (who, name, None),
+        [who, name, title] if grant => (who, name, Some(title.to_uppercase())),
+        _ => return Ok(format!("Usage: {USAGE}")),
+    };
+    let Some((name, _, what)) = badge(&name.to_lowercase()) else {
+        return Ok(format!("No badge {name}.\nUsage: {USAGE}"));
+    };
+    if let Some(title) = &detail {
+        if name != "titled" || !TITLES.contains(&title.as_str()) {
+            return Ok(format!("Titles are {}, for titled players.", TITLES.join(", ")));
+        }
+    }
+    let Some(user_id) = find_user(&state.db, who).await? else {
+        return Ok(format!("No user {who}."));
+    };
+    let player = user_name(&state.db, user_id).await?;
+    if !grant {
+        let revoked = sqlx::query("delete from badges where user_id = $1 and badge = $2")
+            .bind(user_id)
+            .bind(name)
+            .execute(&state.db)
+            .await?
+            .rows_affected();
+        if revoked == 0 {
+            return Ok(format!("{player} has no {what} badge."));
+        }
+        info!("{admin_id} revoked {user_id}'s {name} badge");
+        return Ok(format!("{player} no longer has the {what} badge."));
+    }
+    sqlx::query(
+        "insert into badges (user_id, badge, detail, granted_by) values ($1, $2, $3, $4)
+         on conflict (user_id, badge) do update set detail = excluded.detail, granted_by = excluded.granted_by,
+            granted_at = unixepoch()",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(&detail)
+    .bind(admin_id)
+    .execute(&state.db)
+    .await?;
+    info!("{admin_id} granted {user_id} the {name} badge");
+    Ok(format!("{} now has the {what} badge.", decorate(&state.db, user_id, &player).await?))
+}
@@ -0,0 +1,130 @@
+//! Users who blocked the bot, or whose accounts are gone, can't be sent
+//! anything. They are found from Telegram's bot-stopped updates and from
+//! failed sends; their games wait a while for them to come back and are then
+//! adjudicated.
+
+use crate::bot::Bot;
+use crate::{end_game, finish_game, packed_chat, Termination};
+use anyhow::Result;
+use log::info;
+use shakmaty::Color;
+use sqlx::{Pool, Sqlite};
+
+/// How long games wait for an unreachable player.
+const GRACE_DAYS: i64 = 3;
+
+/// Loads the users known to be unreachable, so nothing is sent to them.
+pub async fn load(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let users: Vec<i64> = sqlx::query_scalar("select id from users where unreachable_since is not null")
+        .fetch_all(db)
+        .await?;
+    for user_id in users {
+        client.unreachable().insert(user_id);
+    }
+    Ok(())
+}
+
+/// The games waiting on the user, with the opponent in each.
+async fn games(db: &Pool<Sqlite>, user_id: i64) -> Result<Vec<(i64, i64)>> {
+    Ok(sqlx::query_as(
+        "select id, case when w_id = $1 then b_id else w_id end from games
+         where $1 in (w_id, b_id) and w_id is not null and b_id is not null and ended = 0 and deleted_at is null",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?)
+}
+
+/// Records that the user can't be reached and warns their opponents.
+pub async fn gone(db: &Pool<Sqlite>, client: &Bot, user_id: i64) -> Result<()> {
+    client.unreachable().insert(user_id);
+    let marked = sqlx::query("update users set unreachable_since = unixepoch() where id = $1 and unreachable_since is null")
+        .bind(user_id)
+        .execute(db)
+        .await?
+        .rows_affected();
+    if marked == 0 {
+        return Ok(());
+    }
+    info!("{user_id} is unreachable");
+    for (id, opponent) in games(db, user_id).await? {
+        let text = format!(
+            "Your opponent in game #{id} can't be reached on Telegram. If they don't come back within {GRACE_DAYS} days, the game is adjudicated."
+        );
+        client.send_message(packed_chat(opponent), text).await?;
+    }
+    Ok(())
+}
+
+/// Clears the user's mark once they unblock the bot or write to it again.
+pub async fn back(db: &Pool<Sqlite>, client: &Bot, user_id: i64) -> Result<()> {
+    if !client.unreachable().remove(user_id) {
+        return Ok(());
+    }
+    let mut tx = db.begin().await?;
+    sqlx::query("update users set unreachable_since = null where id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    // the days per move start over rather than take in the time away
+    sqlx::query("update games set last_move_at = unixepoch() where $1 in (w_id, b_id) and ended = 0")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("{user_id} is reachable again");
+    for (id, opponent) in games(db, user_id).await? {
+        let text = format!("Your opponent in game #{id} is back, so the game goes on.");
+        client.send_message(packed_chat(opponent), text).await?;
+    }
+    Ok(())
+}
+
+/// Records the users found by failed sends, and adjudicates the games of
+/// those gone for longer than the grace period: the player still around wins,
+/// unless nothing much was played or both are gone.
+pub async fn sweep(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    for user_id in client.unreachable().take_found() {
+        gone(db, client, user_id).await?;
+    }
+
+    let due: Vec<(i64, i64, i64, i64, bool, bool)> = sqlx::query_as(
+        "select games.id, w_id, b_id, plies,
+            coalesce(w.unreachable_since <= unixepoch() - $1 * 86400, 0),
+            coalesce(b.unreachable_since <= unixepoch() - $1 * 86400, 0)
+         from games join users w on w.id = w_id join users b on b.id = b_id
+         where ended = 0 and deleted_at is null
+            and (w.unreachable_since <= unixepoch() - $1 * 86400 or b.unreachable_since <= unixepoch() - $1 * 86400)",
+    )
+    .bind(GRACE_DAYS)
+    .fetch_all(db)
+    .await?;
+    for (id, w_id, b_id, plies, w_gone, b_gone) in due {
+        let (winner, termination) = match (w_gone, b_gone) {
+            (true, false) if plies >= 2 => (Some(Color::Black), Termination::Abandoned),
+            (false, true) if plies >= 2 => (Some(Color::White), Termination::Abandoned),
+            _ => (None, Termination::Aborted),
+        };
+        if !end_game(db, id, winner, termination).await? {
+            continue;
+        }
+        info!("adjudicate game {id} with an unreachable player");
+        match winner {
+            Some(color) => {
+                let text = format!(
+                    "Your opponent couldn't be reached for {GRACE_DAYS} days, so you win game #{id}. Type `/start` to play again."
+                );
+                let user_id = if color.is_white() { w_id } else { b_id };
+                client.send_message(packed_chat(user_id), text).await?;
+            }
+            None => {
+                let text = format!("A player in game #{id} couldn't be reached, so the game was aborted.");
+                for user_id in [w_id, b_id] {
+                    client.send_message(packed_chat(user_id), text.as_str()).await?;
+                }
+            }
+        }
+        finish_game(db, client, id).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use crate::exhibition;
+use anyhow::Result;
+use grammers_client::client::messages::InvocationError;
+use grammers_client::{button, reply_markup, Client, InputMessage};
+use grammers_session::{PackedChat, PackedType};
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Where the bot's messages go: Telegram, or an in-memory outbox when games
+/// are driven without a network connection.
+#[derive(Clone)]
+pub enum Bot {
+    Telegram(Client, Unreachable),
+    Mock(Outbox),
+}
+
+/// A message the bot has sent.
+pub struct Sent {
+    pub user_id: i64,
+    pub message_id: i32,
+    pub text: String,
+}
+
+impl Sent {
+    pub fn id(&self) -> i32 {
+        self.message_id
+    }
+}
+
+/// Collects messages sent through `Bot::Mock` until they are drained.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    sent: Arc<Mutex<Vec<Sent>>>,
+    next_id: Arc<AtomicI32>,
+    unreachable: Unreachable,
+}
+
+impl Outbox {
+    pub fn drain(&self) -> Vec<Sent> {
+        std::mem::take(&mut *self.sent.lock().expect("outbox lock"))
+    }
+}
+
+/// Users who blocked the bot or whose accounts are gone. Nothing is sent to
+/// them until they are removed again.
+#[derive(Clone, Default)]
+pub struct Unreachable {
+    users: Arc<Mutex<HashSet<i64>>>,
+    /// Users found by failed sends, not yet recorded in the database.
+    found: Arc<Mutex<Vec<i64>>>,
+}
+
+impl Unreachable {
+    pub fn contains(&self, user_id: i64) -> bool {
+        self.users.lock().expect("unreachable lock").contains(&user_id)
+    }
+
+    /// Adds a user, returning whether they were reachable so far.
+    pub fn insert(&self, user_id: i64) -> bool {
+        self.users.lock().expect("unreachable lock").insert(user_id)
+    }
+
+    /// Removes a user, returning whether they were unreachable.
+    pub fn remove(&self, user_id: i64) -> bool {
+        self.users.lock().expect("unreachable lock").remove(&user_id)
+    }
+
+    /// Users found by failed sends since the last call who are still
+    /// unreachable.
+    pub fn take_found(&self) -> Vec<i64> {
+        let found = std::mem::take(&mut *self.found.lock().expect("unreachable lock"));
+        found.into_iter().filter(|&user_id| self.contains(user_id)).collect()
+    }
+
+    fn found(&self, user_id: i64) {
+        if self.insert(user_id) {
+            warn!("user {user_id} is unreachable");
+            self.found.lock().expect("unreachable lock").push(user_id);
+        }
+    }
+}
+
+/// Errors Telegram gives for users who blocked the bot or deleted their
+/// account.
+fn is_unreachable_error(e: &InvocationError) -> bool {
+    ["USER_IS_BLOCKED", "PEER_ID_INVALID", "INPUT_USER_DEACTIVATED", "USER_DEACTIVATED"]
+        .iter()
+        .any(|name| e.is(name))
+}
+
+/// House players have nobody to read their messages.
+fn is_house_player(chat: PackedChat) -> bool {
+    chat.ty == PackedType::User && exhibition::is_house_player(chat.id)
+}
+
+impl Bot {
+    /// The Telegram client, unless this is a mock.
+    pub fn telegram(&self) -> Option<&Client> {
+        match self {
+            Bot::Telegram(client, _) => Some(client),
+            Bot::Mock(_) => None,
+        }
+    }
+
+    pub fn unreachable(&self) -> &Unreachable {
+        match self {
+            Bot::Telegram(_, unreachable) => unreachable,
+            Bot::Mock(outbox) => &outbox.unreachable,
+        }
+    }
+
+    /// Nobody reads messages sent to house players and unreachable users.
+    fn skips(&self, chat: PackedChat) -> bool {
+        is_house_player(chat) || (chat.ty == PackedType::User && self.unreachable().contains(chat.id))
+    }
+
+    pub async fn send_message(&self, chat: PackedChat, text: impl Into<String>) -> Result<Sent> {
+        let text = text.into();
+        let message_id = match self {
+            _ if self.skips(chat) => 0,
+            Bot::Telegram(client, unreachable) => match client.send_message(chat, text.as_str()).await {
+                Ok(message) => message.id(),
+                Err(e) if is_unreachable_error(&e) => {
+                    unreachable.found(chat.id);
+                    0
+                }
+                Err(e) => return Err(e.into()),
+            },
+            Bot::Mock(outbox) => {
+                let message_id = outbox.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                outbox.sent.lock().expect("outbox lock").push(Sent {
+                    user_id: chat.id,
+                    message_id,
+                    text: text.clone(),
+                });
+                message_id
+            }
+        };
+        Ok(Sent {
+            user_id: chat.id,
+            message_id,
+            text,
+        })
+    }
+
+    /// Sends a message with a button under it that opens `url`.
+    pub async fn send_with_link(
+        &self,
+        chat: PackedChat,
+        text: impl Into<String>,
+        label: &str,
+        url: &str,
+    ) -> Result<Sent> {
+        match self {
+            Bot::Telegram(client, _) if !self.skips(chat) => {
+                let text = text.into();
+                let markup = reply_markup::inline(vec![vec![button::url(label, url)]]);
+                let message = client.send_message(chat, InputMessage::text(&text).reply_markup(&markup)).await?;
+                Ok(Sent {
+                    user_id: chat.id,
+                    message_id: message.id(),
+                    text,
+                })
+            }
+            _ => self.send_message(chat, format!("{}\n[{label}: {url}]", text.into())).await,
+        }
+    }
+
+    /// Sends `contents` as a file called `name`, with `caption` as its text.
+    pub async fn send_document(&self, chat: PackedChat, name: &str, contents: Vec<u8>, caption: &str) -> Result<Sent> {
+        match self {
+            Bot::Telegram(client, unreachable) if !self.skips(chat) => {
+                let size = contents.len();
+                let uploaded = client.upload_stream(&mut Cursor::new(contents), size, name.to_string()).await?;
+                match client.send_message(chat, InputMessage::text(caption).document(uploaded)).await {
+                    Ok(message) => Ok(Sent {
+                        user_id: chat.id,
+                        message_id: message.id(),
+                        text: caption.to_string(),
+                    }),
+                    Err(e) if is_unreachable_error(&e) => {
+                        unreachable.found(chat.id);
+                        self.send_message(chat, caption).await
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            _ => self.send_message(chat, format!("{caption}\n[{name}, {} bytes]", contents.len())).await,
+        }
+    }
+
+    pub async fn edit_message(&self, chat: PackedChat, message_id: i32, text: impl Into<String>) -> Result<()> {
+        match self {
+            // skipped sends have no message to edit
+            _ if self.skips(chat) || message_id == 0 => {}
+            Bot::Telegram(client, unreachable) => match client.edit_message(chat, message_id, text.into()).await {
+                Err(e) if is_unreachable_error(&e) => unreachable.found(chat.id),
+                result => result?,
+            },
+            Bot::Mock(_) => debug!("edit message {message_id} for {}", chat.id),
+        }
+        Ok(())
+    }
+
+    pub async fn delete_message(&self, chat: PackedChat, message_id: i32) -> Result<()> {
+        match self {
+            _ if self.skips(chat) || message_id == 0 => {}
+            Bot::Telegram(client, _) => {
+                client.delete_messages(chat, &[message_id]).await?;
+            }
+            Bot::Mock(_) => debug!("delete message {message_id} for {}", chat.id),
+        }
+        Ok(())
+    }
+
+    pub async fn pin_message(&self, chat: PackedChat, message_id: i32) -> Result<()> {
+        if let Bot::Telegram(client, _) = self {
+            client.pin_message(chat, message_id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn unpin_message(&self, chat: PackedChat, message_id: i32) -> Result<()> {
+        if let Bot::Telegram(client, _) = self {
+            client.unpin_message(chat, message_id).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Operational subcommands that only need the database.
+
+use crate::{admin_stats, connect_db, game_pgn, guess, migrate, puzzles, Game, GAME_COLUMNS};
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use std::env;
+
+pub const USAGE: &str = "usage: tgpawn [serve | migrate | export --user <id> | stats | import-puzzles <csv> | import-games <pgn> | repl | replay <log> | simulate [users]]";
+
+fn database_url() -> String {
+    env::var("DATABASE_URL").expect("need DATABASE_URL env var")
+}
+
+pub async fn run_migrate() -> Result<()> {
+    let db = connect_db(&database_url()).await?;
+    let applied = migrate(&db).await?;
+    println!("applied {applied} migrations");
+    Ok(())
+}
+
+/// Prints the PGN of every finished game of a user.
+pub async fn run_export(args: &[String]) -> Result<()> {
+    let user_id: i64 = match args {
+        [flag, id] if flag == "--user" => id.parse()?,
+        _ => bail!(USAGE),
+    };
+    let db = connect_db(&database_url()).await?;
+    let games: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where (w_id = $1 or b_id = $1) and ended = 1 and started_at is not null and deleted_at is null order by id"
+    ))
+    .bind(user_id)
+    .fetch_all(&db)
+    .await?;
+    let bot_username = env::var("BOT_USERNAME").ok();
+    for game in games {
+        println!("{}", game_pgn(&db, bot_username.as_deref(), &game).await?);
+    }
+    Ok(())
+}
+
+pub async fn run_stats() -> Result<()> {
+    let db = connect_db(&database_url()).await?;
+    println!("{}", admin_stats(&db, &VecDeque::new()).await?);
+    Ok(())
+}
+
+/// Loads puzzles from the Lichess puzzle database CSV
+/// (https://database.lichess.org/#puzzles), decompressed.
+pub async fn run_import_puzzles(args: &[String]) -> Result<()> {
+    let [path] = args else {
+        bail!(USAGE);
+    };
+    let db = connect_db(&database_url()).await?;
+    migrate(&db).await?;
+    let imported = puzzles::import(&db, path).await?;
+    println!("imported {imported} puzzles");
+    Ok(())
+}
+
+/// Loads master games from a PGN file for guess-the-move.
+pub async fn run_import_games(args: &[String]) -> Result<()> {
+    let [path] = args else {
+        bail!(USAGE);
+    };
+    let text = std::fs::read_to_string(path)?;
+    let db = connect_db(&database_url()).await?;
+    migrate(&db).await?;
+    let imported = guess::import(&db, &text).await?;
+    println!("imported {imported} games");
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use anyhow::{anyhow, bail, Error};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Delay before a player's clock starts eating into their time.
+#[derive(Debug, Clone, Copy)]
+pub enum Delay {
+    /// US-style delay: the clock doesn't run for the first N seconds of a move.
+    Simple(Duration),
+    /// Bronstein delay: time spent on a move is given back, up to N seconds.
+    Bronstein(Duration),
+}
+
+/// Time control of a timed game.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeControl {
+    pub initial: Duration,
+    pub increment: Duration,
+    pub delay: Option<Delay>,
+}
+
+impl TimeControl {
+    /// Time left at `now_ms` on a clock which started running at `turn_started_ms`.
+    pub fn running(&self, clock_ms: i64, turn_started_ms: i64, now_ms: i64) -> i64 {
+        let spent = (now_ms - turn_started_ms).max(0);
+        match self.delay {
+            Some(Delay::Simple(delay)) => clock_ms - (spent - delay.as_millis() as i64).max(0),
+            _ => clock_ms - spent,
+        }
+    }
+
+    /// Time left after a move made at `now_ms`, with increment and delay applied.
+    pub fn after_move(&self, clock_ms: i64, turn_started_ms: i64, now_ms: i64) -> i64 {
+        let spent = (now_ms - turn_started_ms).max(0);
+        let refund = match self.delay {
+            Some(Delay::Bronstein(delay)) => spent.min(delay.as_millis() as i64),
+            _ => 0,
+        };
+        self.running(clock_ms, turn_started_ms, now_ms) + refund + self.increment.as_millis() as i64
+    }
+}
+
+/// Parses `<minutes>[+<increment>][ d<delay>| b<delay>]`, e.g. `5+3`, `5 d5`
+/// or `25+5 b10`, with increment and delay in seconds.
+impl FromStr for TimeControl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let base = parts.next().ok_or_else(|| anyhow!("empty time control"))?;
+        let (minutes, increment) = base.split_once('+').unwrap_or((base, "0"));
+        let minutes: u64 = minutes.parse().map_err(|_| anyhow!("invalid minutes {minutes:?}"))?;
+        let increment: u64 = increment.parse().map_err(|_| anyhow!("invalid increment {increment:?}"))?;
+
+        let delay = match parts.next() {
+            None => None,
+            Some(d) => {
+                let secs = |n: &str| -> Result<Duration, Error> {
+                    Ok(Duration::from_secs(n.parse().map_err(|_| anyhow!("invalid delay {d:?}"))?))
+                };
+                if let Some(n) = d.strip_prefix('d') {
+                    Some(Delay::Simple(secs(n)?))
+                } else if let Some(n) = d.strip_prefix('b') {
+                    Some(Delay::Bronstein(secs(n)?))
+                } else {
+                    bail!("invalid delay {d:?}, expected e.g. d5 or b5")
+                }
+            }
+        };
+        if let Some(rest) = parts.next() {
+            bail!("unexpected {rest:?} in time control");
+        }
+
+        Ok(TimeControl {
+            initial: Duration::from_secs(minutes * 60),
+            increment: Duration::from_secs(increment),
+            delay,
+        })
+    }
+}
+
+/// Longest initial time `/start` accepts, in minutes. Slower games are
+/// played by correspondence.
+const MAX_START_MINUTES: u64 = 180;
+
+/// Largest increment `/start` accepts, in seconds.
+const MAX_START_INCREMENT_SECS: u64 = 180;
+
+/// Most days per move of a correspondence game.
+const MAX_DAYS_PER_MOVE: i64 = 14;
+
+/// How a game asked for with `/start` is timed.
+#[derive(Debug, Clone, Copy)]
+pub enum Pace {
+    Clock(TimeControl),
+    /// Correspondence, with this many days for each move.
+    Days(i64),
+}
+
+impl Pace {
+    /// Parses a `/start` argument like `5+3`, `15+10`, `10` (no increment) or
+    /// `3d`. Arguments not starting with a digit are not time controls and
+    /// give `None`; ones that are but don't parse give an error saying why.
+    pub fn parse(s: &str) -> Option<Result<Pace, Error>> {
+        s.starts_with(|c: char| c.is_ascii_digit()).then(|| Pace::parse_time_control(s))
+    }
+
+    fn parse_time_control(s: &str) -> Result<Pace, Error> {
+        if let Some(days) = s.strip_suffix('d') {
+            let days: i64 = days
+                .parse()
+                .map_err(|_| anyhow!("{s} is not a number of days per move, e.g. 3d."))?;
+            if !(1..=MAX_DAYS_PER_MOVE).contains(&days) {
+                bail!("Correspondence games have 1 to {MAX_DAYS_PER_MOVE} days per move.");
+            }
+            return Ok(Pace::Days(days));
+        }
+        let (minutes, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let minutes: u64 = minutes
+            .parse()
+            .map_err(|_| anyhow!("{minutes} is not a number of minutes, e.g. 5+3 is 5 minutes and 3 seconds a move."))?;
+        if increment.is_empty() {
+            bail!("The increment is missing after +, e.g. 5+3.");
+        }
+        let increment: u64 = increment
+            .parse()
+            .map_err(|_| anyhow!("{increment} is not a number of seconds, e.g. 5+3 adds 3 seconds a move."))?;
+        // the clock starts running when the game does, so a 0 would flag White
+        if minutes == 0 {
+            bail!("Games have at least 1 minute, e.g. 1+0.");
+        }
+        if minutes > MAX_START_MINUTES {
+            bail!("Games have at most {MAX_START_MINUTES} minutes, for slower ones play correspondence, e.g. 3d.");
+        }
+        if increment > MAX_START_INCREMENT_SECS {
+            bail!("The increment is at most {MAX_START_INCREMENT_SECS} seconds.");
+        }
+        Ok(Pace::Clock(TimeControl {
+            initial: Duration::from_secs(minutes * 60),
+            increment: Duration::from_secs(increment),
+            delay: None,
+        }))
+    }
+}
+
+impl fmt::Display for Pace {
+    /// The way it is typed, e.g. `5+3` or `3d`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pace::Clock(tc) => write!(f, "{}+{}", tc.initial.as_secs() / 60, tc.increment.as_secs()),
+            Pace::Days(days) => write!(f, "{days}d"),
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch, as stored in the database.
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis() as i64
+}
+
+/// Formats both clocks on one line, e.g. `White 4:07 · Black 3:59`.
+pub fn format_clocks(w_clock_ms: i64, b_clock_ms: i64) -> String {
+    format!("White {} · Black {}", format_clock(w_clock_ms), format_clock(b_clock_ms))
+}
+
+/// Formats a clock reading: `4:07` and `0:09.3` for blitz, `5h 03m` and
+/// `2d 7h` for correspondence.
+pub fn format_clock(ms: i64) -> String {
+    let ms = ms.max(0);
+    let secs = ms / 1000;
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {mins:02}m")
+    } else if secs >= 10 {
+        format!("{mins}:{:02}", secs % 60)
+    } else {
+        format!("0:{:02}.{}", secs, ms % 1000 / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(s: &str) -> TimeControl {
+        match Pace::parse(s) {
+            Some(Ok(Pace::Clock(tc))) => tc,
+            other => panic!("{s} parsed as {other:?}"),
+        }
+    }
+
+    fn rejected(s: &str) -> bool {
+        matches!(Pace::parse(s), Some(Err(_)))
+    }
+
+    #[test]
+    fn parses_start_arguments() {
+        let tc = clock("5+3");
+        assert_eq!((tc.initial, tc.increment), (Duration::from_secs(300), Duration::from_secs(3)));
+        assert_eq!(clock("10").increment, Duration::ZERO);
+        assert!(matches!(Pace::parse("3d"), Some(Ok(Pace::Days(3)))));
+        assert!(Pace::parse("white").is_none());
+        assert_eq!(Pace::Clock(clock("15+10")).to_string(), "15+10");
+    }
+
+    #[test]
+    fn rejects_a_clock_without_minutes() {
+        assert!(rejected("0+0"));
+        assert!(rejected("0+3"));
+        assert!(rejected("0"));
+        assert_eq!(clock("1+0").initial, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn keeps_to_the_limits() {
+        clock(&format!("{MAX_START_MINUTES}+{MAX_START_INCREMENT_SECS}"));
+        assert!(rejected(&format!("{}+0", MAX_START_MINUTES + 1)));
+        assert!(rejected(&format!("5+{}", MAX_START_INCREMENT_SECS + 1)));
+        assert!(rejected("5+"));
+        assert!(rejected("5+x"));
+        assert!(matches!(Pace::parse(&format!("{MAX_DAYS_PER_MOVE}d")), Some(Ok(Pace::Days(MAX_DAYS_PER_MOVE)))));
+        assert!(rejected("0d"));
+        assert!(rejected(&format!("{}d", MAX_DAYS_PER_MOVE + 1)));
+    }
+
+    #[test]
+    fn rejects_a_delay_starting_with_another_letter() {
+        assert!("5 ð5".parse::<TimeControl>().is_err());
+        assert!("5 é".parse::<TimeControl>().is_err());
+        assert!("5 x5".parse::<TimeControl>().is_err());
+        assert!("5 d".parse::<TimeControl>().is_err());
+    }
+
+    #[test]
+    fn simple_delay_holds_the_clock() {
+        let tc: TimeControl = "1+2 d5".parse().unwrap();
+        assert!(matches!(tc.delay, Some(Delay::Simple(d)) if d == Duration::from_secs(5)));
+        // within the delay nothing is taken, after it only the time past it
+        assert_eq!(tc.running(60_000, 0, 3_000), 60_000);
+        assert_eq!(tc.after_move(60_000, 0, 3_000), 62_000);
+        assert_eq!(tc.running(60_000, 0, 8_000), 57_000);
+        assert_eq!(tc.after_move(60_000, 0, 8_000), 59_000);
+    }
+
+    #[test]
+    fn bronstein_delay_gives_time_back() {
+        let tc: TimeControl = "1+2 b5".parse().unwrap();
+        assert!(matches!(tc.delay, Some(Delay::Bronstein(d)) if d == Duration::from_secs(5)));
+        // the clock runs, and the time spent comes back up to the delay
+        assert_eq!(tc.running(60_000, 0, 3_000), 57_000);
+        assert_eq!(tc.after_move(60_000, 0, 3_000), 62_000);
+        assert_eq!(tc.running(60_000, 0, 8_000), 52_000);
+        assert_eq!(tc.after_move(60_000, 0, 8_000), 59_000);
+    }
+}
@@ -0,0 +1,411 @@
+//! Clubs: groups of players with their own seek pool. Whoever creates a club
+//! owns it; the owner and the admins they appoint can promote and remove
+//! members.
+
+use crate::rating;
+use crate::teams::format_points;
+use crate::{find_user, packed_chat, user_name, State, Termination};
+use anyhow::Result;
+use log::info;
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str = "Usage:
+/club list
+/club create <name>
+/club join|leave|members <name>
+/club top <name> [rating|games|points] — members by rating, or by games and points this month
+/club promote|demote|kick <name> <user id or @username>
+/start club <name> — play a club member";
+
+const MAX_NAME_LEN: usize = 32;
+
+/// Members shown by /club top.
+const CLUB_LEADERBOARD_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    fn parse(s: &str) -> Role {
+        match s {
+            "owner" => Role::Owner,
+            "admin" => Role::Admin,
+            _ => Role::Member,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Member => "member",
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Club {
+    pub id: i64,
+    pub name: String,
+}
+
+pub async fn find(db: &Pool<Sqlite>, name: &str) -> Result<Option<Club>> {
+    Ok(sqlx::query_as("select id, name from clubs where name = $1")
+        .bind(name)
+        .fetch_optional(db)
+        .await?)
+}
+
+async fn role(db: &Pool<Sqlite>, club_id: i64, user_id: i64) -> Result<Option<Role>> {
+    let role: Option<String> = sqlx::query_scalar("select role from club_members where club_id = $1 and user_id = $2")
+        .bind(club_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+    Ok(role.as_deref().map(Role::parse))
+}
+
+pub async fn is_member(db: &Pool<Sqlite>, club_id: i64, user_id: i64) -> Result<bool> {
+    Ok(role(db, club_id, user_id).await?.is_some())
+}
+
+/// Whether the user is the club's owner or one of its admins.
+pub async fn is_admin(db: &Pool<Sqlite>, club_id: i64, user_id: i64) -> Result<bool> {
+    Ok(role(db, club_id, user_id).await?.is_some_and(|role| role >= Role::Admin))
+}
+
+pub async fn member_ids(db: &Pool<Sqlite>, club_id: i64) -> Result<Vec<i64>> {
+    Ok(sqlx::query_scalar("select user_id from club_members where club_id = $1")
+        .bind(club_id)
+        .fetch_all(db)
+        .await?)
+}
+
+pub async fn admin_ids(db: &Pool<Sqlite>, club_id: i64) -> Result<Vec<i64>> {
+    Ok(
+        sqlx::query_scalar("select user_id from club_members where club_id = $1 and role != 'member'")
+            .bind(club_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+fn count_members(n: i64) -> String {
+    if n == 1 {
+        "1 member".to_string()
+    } else {
+        format!("{n} members")
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+pub async fn on_club(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let mut words = args.split_whitespace();
+    let text = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => my_clubs(&state.db, user_id).await?,
+        (Some("list"), None, _) => list(&state.db).await?,
+        (Some("create"), Some(name), None) => create(&state.db, user_id, name).await?,
+        (Some(command @ ("join" | "leave" | "members")), Some(name), None) => {
+            let Some(club) = find(&state.db, name).await? else {
+                return reply(state, user_id, format!("There is no club {name}.")).await;
+            };
+            match command {
+                "join" => join(&state.db, user_id, &club).await?,
+                "leave" => leave(&state.db, user_id, &club).await?,
+                _ => members(&state.db, &club).await?,
+            }
+        }
+        (Some("top"), Some(name), order) if words.next().is_none() => {
+            let Some(club) = find(&state.db, name).await? else {
+                return reply(state, user_id, format!("There is no club {name}.")).await;
+            };
+            leaderboard(&state.db, &club, order.unwrap_or("rating")).await?
+        }
+        (Some(command @ ("promote" | "demote" | "kick")), Some(name), Some(target)) => {
+            let Some(club) = find(&state.db, name).await? else {
+                return reply(state, user_id, format!("There is no club {name}.")).await;
+            };
+            let Some(target) = find_user(&state.db, target).await? else {
+                return reply(state, user_id, format!("No user {target}.")).await;
+            };
+            manage(state, user_id, &club, command, target).await?
+        }
+        _ => USAGE.to_string(),
+    };
+    reply(state, user_id, text).await
+}
+
+async fn reply(state: &State, user_id: i64, text: String) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn my_clubs(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let clubs: Vec<(String, String)> = sqlx::query_as(
+        "select clubs.name, club_members.role from club_members join clubs on clubs.id = club_members.club_id
+         where club_members.user_id = $1 order by clubs.name",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    if clubs.is_empty() {
+        return Ok(format!("You are not in any club.\n{USAGE}"));
+    }
+    let clubs: Vec<String> = clubs
+        .into_iter()
+        .map(|(name, role)| match Role::parse(&role) {
+            Role::Member => name,
+            role => format!("{name} ({})", role.as_str()),
+        })
+        .collect();
+    Ok(format!("Your clubs: {}", clubs.join(", ")))
+}
+
+async fn list(db: &Pool<Sqlite>) -> Result<String> {
+    let clubs: Vec<(String, i64)> = sqlx::query_as(
+        "select clubs.name, count(*) as members from clubs join club_members on club_members.club_id = clubs.id
+         group by clubs.id order by members desc, clubs.name limit 20",
+    )
+    .fetch_all(db)
+    .await?;
+    if clubs.is_empty() {
+        return Ok("There are no clubs yet. Start one with /club create <name>".to_string());
+    }
+    Ok(clubs
+        .iter()
+        .map(|(name, members)| format!("{name}, {}", count_members(*members)))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+async fn create(db: &Pool<Sqlite>, user_id: i64, name: &str) -> Result<String> {
+    if !valid_name(name) {
+        return Ok(format!(
+            "Club names are up to {MAX_NAME_LEN} letters, digits, dashes and underscores."
+        ));
+    }
+    if find(db, name).await?.is_some() {
+        return Ok(format!("There already is a club {name}."));
+    }
+    let mut tx = db.begin().await?;
+    let (club_id,): (i64,) = sqlx::query_as("insert into clubs (name) values ($1) returning id")
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query("insert into club_members (club_id, user_id, role) values ($1, $2, $3)")
+        .bind(club_id)
+        .bind(user_id)
+        .bind(Role::Owner.as_str())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("{user_id} created club {name}");
+    Ok(format!("Created the club {name}. Others can join with /club join {name}"))
+}
+
+async fn join(db: &Pool<Sqlite>, user_id: i64, club: &Club) -> Result<String> {
+    let joined = sqlx::query("insert into club_members (club_id, user_id) values ($1, $2) on conflict do nothing")
+        .bind(club.id)
+        .bind(user_id)
+        .execute(db)
+        .await?
+        .rows_affected();
+    Ok(if joined > 0 {
+        format!("Welcome to {}! Play other members with /start club {}", club.name, club.name)
+    } else {
+        format!("You are already in {}.", club.name)
+    })
+}
+
+/// Removes the user from the club. An owner hands the club over to the
+/// longest-standing admin, or member; the last one out closes it.
+async fn leave(db: &Pool<Sqlite>, user_id: i64, club: &Club) -> Result<String> {
+    let Some(role) = role(db, club.id, user_id).await? else {
+        return Ok(format!("You are not in {}.", club.name));
+    };
+    let seeking: Option<i64> = sqlx::query_scalar(
+        "select id from games where club_id = $1 and ended = 0 and (w_id = $2 or b_id = $2) limit 1",
+    )
+    .bind(club.id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+    if seeking.is_some() {
+        return Ok(format!("Finish your {} game first.", club.name));
+    }
+
+    let mut tx = db.begin().await?;
+    sqlx::query("delete from club_members where club_id = $1 and user_id = $2")
+        .bind(club.id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let successor: Option<i64> = sqlx::query_scalar(
+        "select user_id from club_members where club_id = $1
+         order by role = 'admin' desc, joined_at, user_id limit 1",
+    )
+    .bind(club.id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    match successor {
+        Some(successor) if role == Role::Owner => {
+            sqlx::query("update club_members set role = $3 where club_id = $1 and user_id = $2")
+                .bind(club.id)
+                .bind(successor)
+                .bind(Role::Owner.as_str())
+                .execute(&mut *tx)
+                .await?;
+            info!("club {} passed from {user_id} to {successor}", club.name);
+        }
+        Some(_) => {}
+        None => {
+            sqlx::query("update games set club_id = null where club_id = $1")
+                .bind(club.id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("delete from clubs where id = $1")
+                .bind(club.id)
+                .execute(&mut *tx)
+                .await?;
+            info!("club {} closed", club.name);
+        }
+    }
+    tx.commit().await?;
+    Ok(format!("You left {}.", club.name))
+}
+
+async fn members(db: &Pool<Sqlite>, club: &Club) -> Result<String> {
+    let members: Vec<(i64, Option<String>, Option<String>, String)> = sqlx::query_as(
+        "select users.id, users.name, users.username, club_members.role from club_members
+         join users on users.id = club_members.user_id where club_members.club_id = $1
+         order by case club_members.role when 'owner' then 0 when 'admin' then 1 else 2 end, club_members.joined_at",
+    )
+    .bind(club.id)
+    .fetch_all(db)
+    .await?;
+    let mut text = format!("{}, {}:", club.name, count_members(members.len() as i64));
+    for (id, name, username, role) in members {
+        let mut line = name.unwrap_or_else(|| id.to_string());
+        if let Some(username) = username {
+            line = format!("{line} (@{username})");
+        }
+        match Role::parse(&role) {
+            Role::Member => {}
+            role => line = format!("{line}, {}", role.as_str()),
+        }
+        text = format!("{text}\n{line}");
+    }
+    Ok(text)
+}
+
+/// A club member's standing, for /club top.
+#[derive(sqlx::FromRow)]
+struct Standing {
+    id: i64,
+    name: Option<String>,
+    rating: f64,
+    rated_games: i64,
+    /// Finished games this month, anywhere on the bot.
+    games: i64,
+    /// Half points this month from games in the club's pool and from team
+    /// matches played for the club.
+    half_points: i64,
+}
+
+async fn leaderboard(db: &Pool<Sqlite>, club: &Club, order: &str) -> Result<String> {
+    let mut standings: Vec<Standing> = sqlx::query_as(
+        "select u.id, u.name, u.rating, u.rated_games,
+            (select count(*) from games g where (g.w_id = u.id or g.b_id = u.id) and g.ended = 1
+                and g.started_at is not null and g.deleted_at is null
+                and g.ended_at >= unixepoch('now', 'start of month')) as games,
+            (select coalesce(sum(case when g.winner = (g.w_id = u.id) then 2
+                when g.winner is null and g.termination = $2 then 1 else 0 end), 0) from games g
+                where (g.w_id = u.id or g.b_id = u.id) and g.ended = 1 and g.deleted_at is null
+                and g.ended_at >= unixepoch('now', 'start of month')
+                and (g.club_id = $1 or exists (select 1 from team_match_players p
+                    where p.match_id = g.team_match_id and p.user_id = u.id and p.club_id = $1))) as half_points
+         from club_members m join users u on u.id = m.user_id where m.club_id = $1",
+    )
+    .bind(club.id)
+    .bind(Termination::Draw as i64)
+    .fetch_all(db)
+    .await?;
+    match order {
+        "rating" => standings.sort_by(|a, b| b.rating.total_cmp(&a.rating)),
+        "games" => standings.sort_by_key(|s| (-s.games, -s.half_points)),
+        "points" => standings.sort_by_key(|s| (-s.half_points, -s.games)),
+        _ => return Ok(USAGE.to_string()),
+    }
+    let active = standings.iter().filter(|s| s.games > 0).count();
+    let mut text = format!("{} by {order}, {active} of {} played this month:", club.name, standings.len());
+    for (i, standing) in standings.iter().take(CLUB_LEADERBOARD_LEN).enumerate() {
+        let name = standing.name.clone().unwrap_or_else(|| standing.id.to_string());
+        text = format!(
+            "{text}\n{}. {name} {} · games {} · points {}",
+            i + 1,
+            rating::display(standing.rating, standing.rated_games),
+            standing.games,
+            format_points(standing.half_points),
+        );
+    }
+    Ok(text)
+}
+
+/// Changes another member's role or removes them. Admins manage members;
+/// only the owner can demote or remove admins.
+async fn manage(state: &State, user_id: i64, club: &Club, command: &str, target: i64) -> Result<String> {
+    let db = &state.db;
+    let own = role(db, club.id, user_id).await?.unwrap_or(Role::Member);
+    if own < Role::Admin {
+        return Ok(format!("Only admins of {} can do that.", club.name));
+    }
+    let name = user_name(db, target).await?;
+    let Some(theirs) = role(db, club.id, target).await? else {
+        return Ok(format!("{name} is not in {}.", club.name));
+    };
+    if target == user_id || theirs >= own || (command == "demote" && own != Role::Owner) {
+        return Ok(format!("You can't {command} {name}."));
+    }
+    let (text, notice) = match (command, theirs) {
+        ("promote", Role::Member) => {
+            set_role(db, club.id, target, Role::Admin).await?;
+            (format!("{name} is now an admin of {}.", club.name), format!("You are now an admin of {}.", club.name))
+        }
+        ("demote", Role::Admin) => {
+            set_role(db, club.id, target, Role::Member).await?;
+            (format!("{name} is no longer an admin of {}.", club.name), format!("You are no longer an admin of {}.", club.name))
+        }
+        ("kick", _) => {
+            sqlx::query("delete from club_members where club_id = $1 and user_id = $2")
+                .bind(club.id)
+                .bind(target)
+                .execute(db)
+                .await?;
+            (format!("Removed {name} from {}.", club.name), format!("You were removed from {}.", club.name))
+        }
+        (_, Role::Admin) => return Ok(format!("{name} is already an admin of {}.", club.name)),
+        _ => return Ok(format!("{name} is not an admin of {}.", club.name)),
+    };
+    info!("{user_id} did {command} {target} in club {}", club.name);
+    state.client.send_message(packed_chat(target), notice).await?;
+    Ok(text)
+}
+
+async fn set_role(db: &Pool<Sqlite>, club_id: i64, user_id: i64, role: Role) -> Result<()> {
+    sqlx::query("update club_members set role = $3 where club_id = $1 and user_id = $2")
+        .bind(club_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .execute(db)
+        .await?;
+    Ok(())
+}
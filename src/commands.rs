@@ -0,0 +1,447 @@
+//! The slash commands. Each is a `Command` in `COMMANDS`, which the
+//! dispatcher looks names up in and `/help` lists, so a feature brings its
+//! commands along as an entry here rather than a new arm in a match.
+
+use crate::{
+    analysis, clubs, commentary, coords, editor, emotes, endgame, exports, follows, guess, hints, invites, is_admin,
+    leagues, on_admin, on_board, on_clock, on_digest, on_draw_claim, on_fen, on_find, on_flag, on_game, on_last,
+    on_leaderboard, notes, on_pgn, on_pin, on_profile, on_resign, on_start, on_vacation_command, ongoing_game,
+    packed_chat, repertoire, review, rush, settings, studies, tactics, teams, templates, State,
+};
+use anyhow::Result;
+use futures_util::future::LocalBoxFuture;
+
+/// What a command needs before it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requires {
+    Nothing,
+    /// A game the user is playing, or waiting for an opponent in.
+    Game,
+    Admin,
+}
+
+pub trait Command: Sync {
+    /// The name with its slash, like `/start`.
+    fn name(&self) -> &'static str;
+
+    /// Other names for the command.
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// A line for `/help`, with the arguments first if there are any, like
+    /// `<user>: stop following a player`.
+    fn help(&self) -> &'static str;
+
+    fn requires(&self) -> Requires {
+        Requires::Nothing
+    }
+
+    fn handle<'a>(&'a self, state: &'a mut State, user_id: i64, args: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+type Handle = for<'a> fn(&'a mut State, i64, &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+/// A command that is just a handler function, which is most of them.
+pub struct Simple {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    help: &'static str,
+    requires: Requires,
+    handle: Handle,
+}
+
+impl Command for Simple {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        self.aliases
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn requires(&self) -> Requires {
+        self.requires
+    }
+
+    fn handle<'a>(&'a self, state: &'a mut State, user_id: i64, args: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        (self.handle)(state, user_id, args)
+    }
+}
+
+/// Every command, in the order `/help` lists them.
+static COMMANDS: &[&dyn Command] = &[
+    &Simple {
+        name: "/start",
+        aliases: &["/play"],
+        help: "[5+3|3d] [rated|casual] [white|black|random] [fog] [armageddon] [club <name>] [vs <user>]: \
+            find or challenge an opponent, or bot [queen-odds|rook-odds|knight-odds|pawn-odds] to play the engine",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_start(state, user_id, args)),
+    },
+    &Simple {
+        name: "/game",
+        aliases: &[],
+        help: "[id]: your games, or switch the one your moves go to",
+        requires: Requires::Game,
+        handle: |state, user_id, args| Box::pin(on_game(state, user_id, args)),
+    },
+    &Simple {
+        name: "/board",
+        aliases: &[],
+        help: "show your game's board again",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_board(state, user_id)),
+    },
+    &Simple {
+        name: "/clock",
+        aliases: &[],
+        help: "show the clocks of your game",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_clock(state, user_id)),
+    },
+    &Simple {
+        name: "/last",
+        aliases: &[],
+        help: "show the last move of your game",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_last(state, user_id)),
+    },
+    &Simple {
+        name: "/flag",
+        aliases: &["/claim"],
+        help: "claim the game when your opponent's time is up",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_flag(state, user_id)),
+    },
+    &Simple {
+        name: "/draw",
+        aliases: &[],
+        help: "claim a draw by repetition or the fifty-move rule",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_draw_claim(state, user_id)),
+    },
+    &Simple {
+        name: "/hint",
+        aliases: &[],
+        help: "a quick suggestion from the engine for your move",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(hints::on_hint(state, user_id)),
+    },
+    &Simple {
+        name: "/emote",
+        aliases: &[],
+        help: "send your opponent gg, wow, oops or a handshake",
+        requires: Requires::Game,
+        handle: |state, user_id, args| Box::pin(emotes::on_emote(state, user_id, args)),
+    },
+    &Simple {
+        name: "/resign",
+        aliases: &[],
+        help: "resign your game, or cancel it while nobody has joined",
+        requires: Requires::Game,
+        handle: |state, user_id, _| Box::pin(on_resign(state, user_id)),
+    },
+    &Simple {
+        name: "/pgn",
+        aliases: &[],
+        help: "[game id]: the moves of a game as PGN",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_pgn(state, user_id, args)),
+    },
+    &Simple {
+        name: "/export",
+        aliases: &[],
+        help: "all: all your finished games as a PGN file",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(exports::on_export(state, user_id, args)),
+    },
+    &Simple {
+        name: "/fen",
+        aliases: &[],
+        help: "[game id]: the position of a game as FEN",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_fen(state, user_id, args)),
+    },
+    &Simple {
+        name: "/profile",
+        aliases: &[],
+        help: "your ratings and record",
+        requires: Requires::Nothing,
+        handle: |state, user_id, _| Box::pin(on_profile(state, user_id)),
+    },
+    &Simple {
+        name: "/top",
+        aliases: &[],
+        help: "[category]: the leaderboard",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_leaderboard(state, user_id, args)),
+    },
+    &Simple {
+        name: "/find",
+        aliases: &[],
+        help: "<name or @username>: look up players",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_find(state, user_id, args)),
+    },
+    &Simple {
+        name: "/follow",
+        aliases: &[],
+        help: "<user> [all|starts|results]: hear about a player's games",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(follows::on_follow(state, user_id, args)),
+    },
+    &Simple {
+        name: "/unfollow",
+        aliases: &[],
+        help: "<user>: stop following a player",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(follows::on_unfollow(state, user_id, args)),
+    },
+    &Simple {
+        name: "/following",
+        aliases: &[],
+        help: "the players you follow",
+        requires: Requires::Nothing,
+        handle: |state, user_id, _| Box::pin(follows::on_following(state, user_id)),
+    },
+    &Simple {
+        name: "/watch",
+        aliases: &[],
+        help: "watch a game and hear its commentary",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(commentary::on_watch(state, user_id, args)),
+    },
+    &Simple {
+        name: "/say",
+        aliases: &[],
+        help: "comment on the game you are watching",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(commentary::on_say(state, user_id, args)),
+    },
+    &Simple {
+        name: "/mute",
+        aliases: &[],
+        help: "stop hearing a spectator's comments",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(commentary::on_mute(state, user_id, args)),
+    },
+    &Simple {
+        name: "/report",
+        aliases: &[],
+        help: "report a comment to the admins",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(commentary::on_report(state, user_id, args)),
+    },
+    &Simple {
+        name: "/club",
+        aliases: &[],
+        help: "create, join and see clubs",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(clubs::on_club(state, user_id, args)),
+    },
+    &Simple {
+        name: "/match",
+        aliases: &[],
+        help: "team matches between clubs",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(teams::on_match(state, user_id, args)),
+    },
+    &Simple {
+        name: "/league",
+        aliases: &[],
+        help: "team league tables and fixtures",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(leagues::on_league(state, user_id, args)),
+    },
+    &Simple {
+        name: "/invite",
+        aliases: &[],
+        help: "a link to invite friends",
+        requires: Requires::Nothing,
+        handle: |state, user_id, _| Box::pin(invites::on_invite(state, user_id)),
+    },
+    &Simple {
+        name: "/puzzle",
+        aliases: &[],
+        help: "[theme|mine] [min-max]: solve tactics puzzles",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(tactics::on_puzzle(state, user_id, args)),
+    },
+    &Simple {
+        name: "/rush",
+        aliases: &[],
+        help: "solve as many puzzles as you can against the clock",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(rush::on_rush(state, user_id, args)),
+    },
+    &Simple {
+        name: "/guess",
+        aliases: &[],
+        help: "[white|black]: guess the moves of a master game",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(guess::on_guess(state, user_id, args)),
+    },
+    &Simple {
+        name: "/repertoire",
+        aliases: &[],
+        help: "white|black <pgn>: save opening lines to drill",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(repertoire::on_repertoire(state, user_id, args)),
+    },
+    &Simple {
+        name: "/drill",
+        aliases: &[],
+        help: "practise your repertoire",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(repertoire::on_drill(state, user_id, args)),
+    },
+    &Simple {
+        name: "/endgame",
+        aliases: &[],
+        help: "play out endgames against perfect defence",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(endgame::on_endgame(state, user_id, args)),
+    },
+    &Simple {
+        name: "/coords",
+        aliases: &[],
+        help: "[white|black|blind]: learn the squares",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(coords::on_coords(state, user_id, args)),
+    },
+    &Simple {
+        name: "/analysis",
+        aliases: &[],
+        help: "[fen]: move pieces for both sides and ask the engine",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(analysis::on_analysis(state, user_id, args)),
+    },
+    &Simple {
+        name: "/edit",
+        aliases: &[],
+        help: "[fen|empty]: set up a position to analyze or play from",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(editor::on_edit(state, user_id, args)),
+    },
+    &Simple {
+        name: "/eval",
+        aliases: &[],
+        help: "[lines]: the engine's best moves on your analysis board or in your unrated game",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(analysis::on_eval(state, user_id, args)),
+    },
+    &Simple {
+        name: "/review",
+        aliases: &[],
+        help: "step through a finished game with the engine",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(review::on_review(state, user_id, args)),
+    },
+    &Simple {
+        name: "/note",
+        aliases: &[],
+        help: "[#game] <move> [!|?] <text>: note a move of your finished game",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(notes::on_note(state, user_id, args)),
+    },
+    &Simple {
+        name: "/study",
+        aliases: &[],
+        help: "go through a position together with friends",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(studies::on_study(state, user_id, args)),
+    },
+    &Simple {
+        name: "/settings",
+        aliases: &[],
+        help: "board theme, notation, time zone and more",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(settings::on_settings(state, user_id, args)),
+    },
+    &Simple {
+        name: "/pin",
+        aliases: &[],
+        help: "on|off: keep your game's board pinned",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_pin(state, user_id, args)),
+    },
+    &Simple {
+        name: "/digest",
+        aliases: &[],
+        help: "[weekly] on|off: a daily summary of games waiting for your move, or a weekly one of your games",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_digest(state, user_id, args)),
+    },
+    &Simple {
+        name: "/vacation",
+        aliases: &[],
+        help: "[on|off]: pause your clocks while you are away",
+        requires: Requires::Nothing,
+        handle: |state, user_id, args| Box::pin(on_vacation_command(state, user_id, args)),
+    },
+    &Simple {
+        name: "/help",
+        aliases: &[],
+        help: "this list",
+        requires: Requires::Nothing,
+        handle: |state, user_id, _| Box::pin(on_help(state, user_id)),
+    },
+    &Simple {
+        name: "/admin",
+        aliases: &[],
+        help: "running the bot",
+        requires: Requires::Admin,
+        handle: |state, user_id, args| Box::pin(on_admin(state, user_id, args)),
+    },
+];
+
+/// The command going by `name`.
+pub fn find(name: &str) -> Option<&'static dyn Command> {
+    COMMANDS
+        .iter()
+        .copied()
+        .find(|command| command.name() == name || command.aliases().contains(&name))
+}
+
+/// Runs the command if it exists and its requirements are met, returning
+/// whether it does exist.
+pub async fn dispatch(state: &mut State, user_id: i64, name: &str, args: &str) -> Result<bool> {
+    let Some(command) = find(name) else {
+        return Ok(false);
+    };
+    let refusal = match command.requires() {
+        Requires::Nothing => None,
+        Requires::Game if ongoing_game(&state.db, user_id).await?.is_none() => Some("no_game"),
+        Requires::Game => None,
+        Requires::Admin if !is_admin(state, user_id).await? => Some("admins_only"),
+        Requires::Admin => None,
+    };
+    match refusal {
+        Some(key) => {
+            let text = templates::text(state, key, &[]);
+            state.client.send_message(packed_chat(user_id), text).await?;
+        }
+        None => command.handle(state, user_id, args).await?,
+    }
+    Ok(true)
+}
+
+async fn on_help(state: &mut State, user_id: i64) -> Result<()> {
+    let admin = is_admin(state, user_id).await?;
+    let lines: Vec<String> = COMMANDS
+        .iter()
+        .filter(|command| admin || command.requires() != Requires::Admin)
+        .map(|command| match command.help().split_once(": ") {
+            Some((args, what)) => format!("{} {args} - {what}", command.name()),
+            None => format!("{} - {}", command.name(), command.help()),
+        })
+        .collect();
+    let text = templates::text(state, "help", &[("commands", &lines.join("\n"))]);
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
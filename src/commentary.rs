@@ -0,0 +1,250 @@
+//! Spectator commentary: users who `/watch` a game can `/say` something
+//! about it, which the other spectators of the game hear right away. The
+//! players only get the comments once the game is over, so nobody is helped
+//! along. Spectators can mute commenters they don't want to hear and report
+//! comments to the admins, who can silence the commenter.
+
+use crate::bot::Bot;
+use crate::{fairplay, find_user, game_by_id, is_admin, packed_chat, user_name, State, Variant};
+use anyhow::Result;
+use log::info;
+use sqlx::{Pool, Sqlite};
+
+/// Comments are kept short, like chat in a broadcast.
+const MAX_COMMENT_CHARS: usize = 200;
+
+/// The most comments sent to the players after the game, the latest ones.
+const MAX_COMMENTS_AFTER_GAME: i64 = 30;
+
+const USAGE: &str = "Usage: /watch <game> to hear its commentary, /watch off to stop, /say <comment>";
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+/// The game the user is watching.
+async fn watching(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<i64>> {
+    Ok(sqlx::query_scalar("select game_id from spectators where user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?)
+}
+
+pub async fn on_watch(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let args = args.trim();
+    if args.is_empty() {
+        let text = match watching(&state.db, user_id).await? {
+            Some(game_id) => format!("You are watching game #{game_id}.\n{USAGE}"),
+            None => USAGE.to_string(),
+        };
+        return reply(state, user_id, text).await;
+    }
+    if args == "off" {
+        let stopped = sqlx::query("delete from spectators where user_id = $1")
+            .bind(user_id)
+            .execute(&state.db)
+            .await?
+            .rows_affected();
+        let text = if stopped > 0 { "Stopped watching." } else { "You are not watching a game." };
+        return reply(state, user_id, text).await;
+    }
+    let Ok(game_id) = args.trim_start_matches('#').parse::<i64>() else {
+        return reply(state, user_id, USAGE).await;
+    };
+    let game = game_by_id(&state.db, game_id).await?;
+    let Some(game) = game.filter(|g| !g.ended && g.w_id.is_some() && g.b_id.is_some()) else {
+        return reply(state, user_id, format!("No game #{game_id} in progress.")).await;
+    };
+    if game.w_id == Some(user_id) || game.b_id == Some(user_id) {
+        return reply(state, user_id, "You are playing this game.").await;
+    }
+    // spectators would see through the fog
+    if game.variant() != Variant::Standard {
+        return reply(state, user_id, "Fog of war games can't be watched.").await;
+    }
+    sqlx::query(
+        "insert into spectators (user_id, game_id) values ($1, $2)
+         on conflict (user_id) do update set game_id = excluded.game_id, created_at = unixepoch()",
+    )
+    .bind(user_id)
+    .bind(game_id)
+    .execute(&state.db)
+    .await?;
+    let spectators: i64 = sqlx::query_scalar("select count(*) from spectators where game_id = $1")
+        .bind(game_id)
+        .fetch_one(&state.db)
+        .await?;
+    let mut text = format!("Watching game #{game_id}, {}. Comment with /say <text>", count_spectators(spectators));
+    if let Some(url) = &state.config.get().public_url {
+        text = format!("{text}\nBoard: {url}/game/{game_id}");
+    }
+    reply(state, user_id, text).await
+}
+
+pub async fn on_say(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let comment = args.trim();
+    if comment.is_empty() {
+        return reply(state, user_id, USAGE).await;
+    }
+    if comment.chars().count() > MAX_COMMENT_CHARS {
+        return reply(state, user_id, format!("Comments are up to {MAX_COMMENT_CHARS} characters.")).await;
+    }
+    let silenced: Option<i64> = sqlx::query_scalar("select silenced_at from users where id = $1")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+    if silenced.is_some() {
+        return reply(state, user_id, "You can't comment on games.").await;
+    }
+    let Some(game_id) = watching(&state.db, user_id).await? else {
+        return reply(state, user_id, format!("You are not watching a game.\n{USAGE}")).await;
+    };
+    let Some(game) = game_by_id(&state.db, game_id).await? else {
+        return reply(state, user_id, "You are not watching a game.").await;
+    };
+    let (id,): (i64,) =
+        sqlx::query_as("insert into comments (game_id, user_id, ply, text) values ($1, $2, $3, $4) returning id")
+            .bind(game_id)
+            .bind(user_id)
+            .bind(game.plies)
+            .bind(comment)
+            .fetch_one(&state.db)
+            .await?;
+    let listeners: Vec<i64> = sqlx::query_scalar(
+        "select user_id from spectators where game_id = $1 and user_id != $2
+         and user_id not in (select user_id from comment_mutes where muted_id = $2)",
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+    let text = format!("{} on game #{game_id} (#{id}): {comment}", user_name(&state.db, user_id).await?);
+    for listener in &listeners {
+        state.client.send_message(packed_chat(*listener), text.as_str()).await?;
+    }
+    reply(state, user_id, format!("Sent to {}.", count_spectators(listeners.len() as i64))).await
+}
+
+fn count_spectators(n: i64) -> String {
+    if n == 1 {
+        "1 spectator".to_string()
+    } else {
+        format!("{n} spectators")
+    }
+}
+
+pub async fn on_mute(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let (mute, who) = match args.trim().split_once(' ') {
+        Some(("off", who)) => (false, who.trim()),
+        _ => (true, args.trim()),
+    };
+    let Some(muted) = find_user(&state.db, who).await?.filter(|&id| id != user_id) else {
+        return reply(state, user_id, "Usage: /mute [off] <user id or @username>").await;
+    };
+    let query = match mute {
+        true => "insert into comment_mutes (user_id, muted_id) values ($1, $2) on conflict do nothing",
+        false => "delete from comment_mutes where user_id = $1 and muted_id = $2",
+    };
+    sqlx::query(query).bind(user_id).bind(muted).execute(&state.db).await?;
+    let name = user_name(&state.db, muted).await?;
+    let text = match mute {
+        true => format!("You won't hear {name}'s comments."),
+        false => format!("You'll hear {name}'s comments again."),
+    };
+    reply(state, user_id, text).await
+}
+
+pub async fn on_report(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let Ok(comment_id) = args.trim().trim_start_matches('#').parse::<i64>() else {
+        return reply(state, user_id, "Usage: /report <comment number>").await;
+    };
+    let comment: Option<(i64, i64, String)> =
+        sqlx::query_as("select game_id, user_id, text from comments where id = $1")
+            .bind(comment_id)
+            .fetch_optional(&state.db)
+            .await?;
+    let Some((game_id, author, text)) = comment else {
+        return reply(state, user_id, format!("No comment #{comment_id}.")).await;
+    };
+    let reported = sqlx::query(
+        "insert into comment_reports (comment_id, reporter_id) values ($1, $2) on conflict do nothing",
+    )
+    .bind(comment_id)
+    .bind(user_id)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if reported > 0 {
+        info!("{user_id} reported comment {comment_id}");
+        let text = format!(
+            "{} reported comment #{comment_id} by {} ({author}) on game #{game_id}: {text}\n/admin silence {author}",
+            user_name(&state.db, user_id).await?,
+            user_name(&state.db, author).await?,
+        );
+        fairplay::notify_admins(&state.db, &state.client, &state.config.get().admins, &text).await?;
+    }
+    let text = format!("Thanks, the admins will look at it. /mute {author} to stop hearing them.");
+    reply(state, user_id, text).await
+}
+
+/// Handles `/admin silence` and `/admin unsilence`, returning the reply.
+pub async fn admin_silence(state: &State, admin_id: i64, who: &str, silence: bool) -> Result<String> {
+    let Some(user_id) = find_user(&state.db, who).await? else {
+        return Ok(format!("No user {who}."));
+    };
+    if silence && is_admin(state, user_id).await? {
+        return Ok("Admins can't be silenced.".to_string());
+    }
+    sqlx::query(
+        "update users set silenced_at = case when $2 then coalesce(silenced_at, unixepoch()) end where id = $1",
+    )
+    .bind(user_id)
+    .bind(silence)
+    .execute(&state.db)
+    .await?;
+    let name = user_name(&state.db, user_id).await?;
+    Ok(if silence {
+        info!("{admin_id} silenced {user_id}");
+        format!("{name} can no longer comment on games.")
+    } else {
+        info!("{admin_id} unsilenced {user_id}");
+        format!("{name} can comment on games again.")
+    })
+}
+
+/// Sends the players the spectators' comments, and tells the spectators the
+/// game is over.
+pub async fn game_finished(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some(game) = game_by_id(db, game_id).await? else {
+        return Ok(());
+    };
+    let spectators: Vec<i64> = sqlx::query_scalar("delete from spectators where game_id = $1 returning user_id")
+        .bind(game_id)
+        .fetch_all(db)
+        .await?;
+    let result = format!("Game #{game_id} is over: {}", game.result().replace("1/2", "½"));
+    for spectator in spectators {
+        client.send_message(packed_chat(spectator), result.as_str()).await?;
+    }
+
+    let mut comments: Vec<(i64, i64, String)> = sqlx::query_as(
+        "select user_id, ply, text from comments where game_id = $1 order by id desc limit $2",
+    )
+    .bind(game_id)
+    .bind(MAX_COMMENTS_AFTER_GAME)
+    .fetch_all(db)
+    .await?;
+    if comments.is_empty() {
+        return Ok(());
+    }
+    comments.reverse();
+    let mut text = format!("What spectators said during game #{game_id}:");
+    for (author, ply, comment) in comments {
+        text = format!("{text}\nmove {}, {}: {comment}", ply / 2 + 1, user_name(db, author).await?);
+    }
+    for player in [game.w_id, game.b_id].into_iter().flatten() {
+        client.send_message(packed_chat(player), text.as_str()).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Settings that can change while the bot runs: the admins, the default time
+//! control, the viewer URL, voice moves, the rush length, the rate limit,
+//! hints and the bot's messages.
+//! They come from the environment, overridden by the file in `CONFIG_FILE`
+//! if there is one, and are read again on SIGHUP or `/admin reload`. Games
+//! and their clocks live in the database, so a reload leaves them be.
+
+use crate::clock::TimeControl;
+use crate::templates::Templates;
+use crate::voice::Transcriber;
+use crate::{pipeline, rush};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The configuration in use, shared by everything handling updates and by
+/// the background jobs, so a reload reaches all of them.
+#[derive(Clone, Default)]
+pub struct Current(Arc<RwLock<Arc<Config>>>);
+
+impl Current {
+    pub fn new(config: Config) -> Self {
+        Current(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().expect("config lock").clone()
+    }
+
+    pub fn set(&self, config: Config) {
+        *self.0.write().expect("config lock") = Arc::new(config);
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub admins: Vec<i64>,
+    pub time_control: Option<TimeControl>,
+    pub public_url: Option<String>,
+    pub transcriber: Option<Transcriber>,
+    pub rush_duration: Duration,
+    /// Most messages a user may send within `rate_window`.
+    pub rate_limit: usize,
+    pub rate_window: Duration,
+    /// Hints a player may take in each rated game; unrated games have no limit.
+    pub hints_per_rated_game: i64,
+    pub templates: Arc<Templates>,
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let file = match env::var("CONFIG_FILE") {
+            Ok(path) => {
+                let text = fs::read_to_string(&path).with_context(|| format!("cannot read config file {path}"))?;
+                parse_file(&text)
+            }
+            Err(_) => HashMap::new(),
+        };
+        let var = |name: &str| file.get(name).cloned().or_else(|| env::var(name).ok());
+        let invalid = |name: &str| anyhow!("{name} invalid");
+
+        let admins = match var("ADMINS") {
+            Some(s) => s
+                .split(',')
+                .filter(|id| !id.trim().is_empty())
+                .map(|id| id.trim().parse::<i64>().map_err(|_| invalid("ADMINS")))
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        let time_control = var("TIME_CONTROL")
+            .map(|s| s.parse::<TimeControl>().map_err(|_| invalid("TIME_CONTROL")))
+            .transpose()?;
+        let public_url = var("PUBLIC_URL").map(|url| url.trim_end_matches('/').to_string());
+        let transcriber = var("VOICE_TRANSCRIBER")
+            .map(|s| s.parse::<Transcriber>().map_err(|_| invalid("VOICE_TRANSCRIBER")))
+            .transpose()?;
+        let rush_duration = match var("RUSH_SECS") {
+            Some(s) => Duration::from_secs(s.parse().map_err(|_| invalid("RUSH_SECS"))?),
+            None => rush::DEFAULT_DURATION,
+        };
+        let rate_limit = match var("RATE_LIMIT_MESSAGES") {
+            Some(s) => s.parse().map_err(|_| invalid("RATE_LIMIT_MESSAGES"))?,
+            None => pipeline::DEFAULT_RATE_LIMIT,
+        };
+        let rate_window = match var("RATE_LIMIT_SECS") {
+            Some(s) => Duration::from_secs(s.parse().map_err(|_| invalid("RATE_LIMIT_SECS"))?),
+            None => pipeline::DEFAULT_RATE_WINDOW,
+        };
+        let hints_per_rated_game = match var("HINTS_PER_RATED_GAME") {
+            Some(s) => s.parse().map_err(|_| invalid("HINTS_PER_RATED_GAME"))?,
+            None => 0,
+        };
+        let templates = Templates::load(var("TEMPLATES_DIR").as_deref())?;
+        Ok(Config {
+            admins,
+            time_control,
+            public_url,
+            transcriber,
+            rush_duration,
+            rate_limit,
+            rate_window,
+            hints_per_rated_game,
+            templates: Arc::new(templates),
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            admins: Vec::new(),
+            time_control: None,
+            public_url: None,
+            transcriber: None,
+            rush_duration: rush::DEFAULT_DURATION,
+            rate_limit: pipeline::DEFAULT_RATE_LIMIT,
+            rate_window: pipeline::DEFAULT_RATE_WINDOW,
+            hints_per_rated_game: 0,
+            templates: Arc::default(),
+        }
+    }
+}
+
+/// Reads `KEY=value` lines, as in a shell script of `export KEY="value"`
+/// lines, skipping comments.
+fn parse_file(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.trim_start_matches("export ").split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Reads the configuration again, keeping the current one if it's invalid.
+pub fn reload(current: &Current) -> String {
+    match Config::load() {
+        Ok(config) => {
+            let admins = config.admins.len();
+            current.set(config);
+            info!("configuration reloaded");
+            format!("Configuration reloaded, {admins} admins listed.")
+        }
+        Err(e) => format!("Configuration not reloaded: {e:#}"),
+    }
+}
@@ -0,0 +1,268 @@
+//! Coordinates trainer: name the square marked on an empty board, or, for
+//! blindfold practice, tell the color of a named one. Rounds are timed and
+//! kept for the user's profile.
+
+use crate::bot::Bot;
+use crate::{clock, diagram, ongoing_game, packed_chat, training, State};
+use anyhow::Result;
+use log::debug;
+use rand::Rng;
+use shakmaty::{Color, Square};
+use sqlx::{Pool, Sqlite};
+
+/// Squares asked in a round.
+const ROUND_LENGTH: i64 = 10;
+
+/// Rounds counted as the user's current form in the profile.
+const RECENT_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Find the marked square on the board seen from this side.
+    Board(Color),
+    Blind,
+}
+
+impl Mode {
+    fn parse(s: &str) -> Option<Mode> {
+        match s {
+            "white" => Some(Mode::Board(Color::White)),
+            "black" => Some(Mode::Board(Color::Black)),
+            "blind" => Some(Mode::Blind),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Board(Color::White) => "white",
+            Mode::Board(Color::Black) => "black",
+            Mode::Blind => "blind",
+        }
+    }
+
+    fn question(self, square: Square) -> String {
+        match self {
+            Mode::Board(color) => format!("Which square is this?\n{}", diagram::marked(square, color)),
+            Mode::Blind => format!("What color is {square}?"),
+        }
+    }
+}
+
+const COLUMNS: &str = "id, user_id, mode, square, asked_at, answered, correct, answer_ms";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Round {
+    id: i64,
+    user_id: i64,
+    mode: String,
+    square: String,
+    asked_at: i64,
+    answered: i64,
+    correct: i64,
+    answer_ms: i64,
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Round>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from coordinate_rounds where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+/// A random square other than `previous`.
+fn random_square(previous: Option<Square>) -> Square {
+    let mut rng = rand::thread_rng();
+    loop {
+        let square = Square::new(rng.gen_range(0..64));
+        if Some(square) != previous {
+            return square;
+        }
+    }
+}
+
+pub async fn on_coords(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let mode = match args.trim() {
+        "stop" => {
+            return match running(&state.db, user_id).await? {
+                Some(round) => end(&state.db, &state.client, &round, "Stopped.").await,
+                None => {
+                    state.client.send_message(chat, "You are not practicing coordinates.").await?;
+                    Ok(())
+                }
+            };
+        }
+        "" => Mode::Board(Color::White),
+        args => match Mode::parse(args) {
+            Some(mode) => mode,
+            None => {
+                state
+                    .client
+                    .send_message(chat, "Usage: /coords [white|black|blind|stop]")
+                    .await?;
+                return Ok(());
+            }
+        },
+    };
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state.client.send_message(chat, "Finish your game first.").await?;
+        return Ok(());
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        state
+            .client
+            .send_message(chat, format!("Finish your {what} first."))
+            .await?;
+        return Ok(());
+    }
+
+    let square = random_square(None);
+    let now = clock::now_ms();
+    sqlx::query(
+        "insert into coordinate_rounds (user_id, mode, started_at, square, asked_at) values ($1, $2, $3, $4, $5)",
+    )
+    .bind(user_id)
+    .bind(mode.as_str())
+    .bind(now)
+    .bind(square.to_string())
+    .bind(now)
+    .execute(&state.db)
+    .await?;
+    let task = match mode {
+        Mode::Board(_) => "Name the marked squares",
+        Mode::Blind => "Tell light squares from dark ones",
+    };
+    let text = format!("{task}, {ROUND_LENGTH} in a row, as fast as you can.\n{}", mode.question(square));
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Handles an answer sent during a round.
+pub async fn on_move(state: &mut State, mut round: Round, text: &str) -> Result<()> {
+    let chat = packed_chat(round.user_id);
+    let (Some(mode), Ok(square)) = (Mode::parse(&round.mode), round.square.parse::<Square>()) else {
+        return end(&state.db, &state.client, &round, "This round can't go on.").await;
+    };
+    let answer = text.trim().to_lowercase();
+    let (right, feedback) = match mode {
+        Mode::Board(_) => {
+            let Ok(guess) = answer.parse::<Square>() else {
+                state.client.send_message(chat, "Answer with a square, such as e4.").await?;
+                return Ok(());
+            };
+            if guess == square {
+                (true, format!("✓ {square}"))
+            } else {
+                (false, format!("✗ It was {square}."))
+            }
+        }
+        Mode::Blind => {
+            let light = match answer.as_str() {
+                "light" | "l" | "white" | "w" => true,
+                "dark" | "d" | "black" | "b" => false,
+                _ => {
+                    state.client.send_message(chat, "Answer light or dark.").await?;
+                    return Ok(());
+                }
+            };
+            let color = if square.is_light() { "light" } else { "dark" };
+            if light == square.is_light() {
+                (true, format!("✓ {square} is {color}."))
+            } else {
+                (false, format!("✗ {square} is {color}."))
+            }
+        }
+    };
+
+    let now = clock::now_ms();
+    round.answered += 1;
+    round.correct += i64::from(right);
+    round.answer_ms += now - round.asked_at;
+    let next = random_square(Some(square));
+    sqlx::query(
+        "update coordinate_rounds set answered = $2, correct = $3, answer_ms = $4, square = $5, asked_at = $6
+         where id = $1",
+    )
+    .bind(round.id)
+    .bind(round.answered)
+    .bind(round.correct)
+    .bind(round.answer_ms)
+    .bind(next.to_string())
+    .bind(now)
+    .execute(&state.db)
+    .await?;
+    debug!("coordinate round {} at {}", round.id, round.answered);
+
+    if round.answered >= ROUND_LENGTH {
+        return end(&state.db, &state.client, &round, &feedback).await;
+    }
+    state
+        .client
+        .send_message(chat, format!("{feedback}\n{}", mode.question(next)))
+        .await?;
+    Ok(())
+}
+
+async fn end(db: &Pool<Sqlite>, client: &Bot, round: &Round, reason: &str) -> Result<()> {
+    sqlx::query("update coordinate_rounds set ended = 1 where id = $1")
+        .bind(round.id)
+        .execute(db)
+        .await?;
+    let mut text = reason.to_string();
+    if round.answered > 0 {
+        text = format!(
+            "{text}\n{} of {} right, {:.1}s per square.",
+            round.correct,
+            round.answered,
+            round.answer_ms as f64 / 1000.0 / round.answered as f64
+        );
+    }
+    client.send_message(packed_chat(round.user_id), text).await?;
+    Ok(())
+}
+
+/// Accuracy and speed over a set of rounds.
+fn form(rounds: &[(i64, i64, i64)]) -> String {
+    let (answered, correct, ms) = rounds
+        .iter()
+        .fold((0, 0, 0), |(a, c, m), (answered, correct, ms)| (a + answered, c + correct, m + ms));
+    format!(
+        "{}% right, {:.1}s per square",
+        correct * 100 / answered,
+        ms as f64 / 1000.0 / answered as f64
+    )
+}
+
+/// The user's coordinates results for the profile: the recent rounds of each
+/// mode, compared with the ones before.
+pub async fn summary(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<String>> {
+    let mut lines = Vec::new();
+    for (blind, title) in [(false, "Coordinates"), (true, "Square colors")] {
+        let rounds: Vec<(i64, i64, i64)> = sqlx::query_as(
+            "select answered, correct, answer_ms from coordinate_rounds
+             where user_id = $1 and ended and answered > 0 and (mode = 'blind') = $2
+             order by started_at desc",
+        )
+        .bind(user_id)
+        .bind(blind)
+        .fetch_all(db)
+        .await?;
+        if rounds.is_empty() {
+            continue;
+        }
+        let (recent, earlier) = rounds.split_at(rounds.len().min(RECENT_ROUNDS));
+        let mut line = format!("{title}: {}", form(recent));
+        if !earlier.is_empty() {
+            line = format!("{line} over the last {} rounds, {} before", recent.len(), form(earlier));
+        }
+        lines.push(line);
+    }
+    Ok(if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    })
+}
@@ -0,0 +1,135 @@
+//! Move corrections: a player who edits the message with their move before
+//! the opponent replies, and within a grace period, has the move taken back
+//! and the edited one played instead.
+
+use crate::analysis;
+use crate::{clock, game_ucis, on_move, ongoing_game, packed_chat, parse_typed_move, State, Variant};
+use anyhow::Result;
+use log::info;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{CastlingMode, EnPassantMode, Position};
+
+/// How long after a move its message can still be edited to correct it.
+const GRACE_MS: i64 = 60 * 1000;
+
+/// Handles an edited message, which only matters if it played the user's
+/// last move.
+pub async fn on_edit(state: &mut State, user_id: i64, message_id: i32, text: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(());
+    };
+    // once the opponent has replied, the move is no longer the last one
+    let played: Option<(String, i64)> =
+        sqlx::query_as("select uci, played_at from moves where game_id = $1 and ply = $2 and message_id = $3")
+            .bind(game.id)
+            .bind(game.plies - 1)
+            .bind(message_id)
+            .fetch_optional(&state.db)
+            .await?;
+    let Some((old_uci, played_at)) = played else {
+        return Ok(());
+    };
+    let ply = game.plies - 1;
+    let white = ply % 2 == 0;
+    if (if white { w_id } else { b_id }) != user_id {
+        return Ok(());
+    }
+
+    let ucis = game_ucis(&state.db, game.id).await?;
+    let before = analysis::replay(game.start_fen(), &ucis[..ply as usize].join(" "));
+    let old = old_uci
+        .parse::<Uci>()
+        .ok()
+        .and_then(|uci| uci.to_move(&before).ok())
+        .map_or(old_uci.clone(), |m| San::from_move(&before, &m).to_string());
+    if clock::now_ms() - played_at > GRACE_MS {
+        let text = format!("Too late to change {old}: a move can only be corrected within a minute.");
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+    let Some(m) = parse_typed_move(&state.language, text, &before).filter(|m| before.is_legal(m)) else {
+        let text = format!("{} is not a legal move, {old} stands.", text.trim());
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    };
+    let new_uci = m.to_uci(CastlingMode::Standard).to_string();
+    if new_uci == old_uci {
+        return Ok(());
+    }
+    let new = San::from_move(&before, &m).to_string();
+
+    // the mover's clock as it stood when their turn began, and when it began
+    let (clock_ms, turn_started_ms) = if game.initial_ms.is_some() {
+        let previous: Option<(i64, Option<i64>)> =
+            sqlx::query_as("select played_at, clock_ms from moves where game_id = $1 and ply = $2")
+                .bind(game.id)
+                .bind(ply - 1)
+                .fetch_optional(&state.db)
+                .await?;
+        let own: Option<Option<i64>> =
+            sqlx::query_scalar("select clock_ms from moves where game_id = $1 and ply = $2")
+                .bind(game.id)
+                .bind(ply - 2)
+                .fetch_optional(&state.db)
+                .await?;
+        let initial: Option<i64> = sqlx::query_scalar(
+            "select case when $2 then initial_ms else coalesce(b_initial_ms, initial_ms) end from games where id = $1",
+        )
+        .bind(game.id)
+        .bind(white)
+        .fetch_one(&state.db)
+        .await?;
+        let started = match previous {
+            Some((played_at, _)) => Some(played_at),
+            None => game.started_at.map(|s| s * 1000),
+        };
+        (own.flatten().or(initial), started)
+    } else {
+        (None, None)
+    };
+
+    let fen = Fen::from_position(before, EnPassantMode::Always).to_string();
+    let mut tx = state.db.begin().await?;
+    sqlx::query("delete from moves where game_id = $1 and ply = $2")
+        .bind(game.id)
+        .bind(ply)
+        .execute(&mut *tx)
+        .await?;
+    let undone = sqlx::query(
+        "update games set fen = $3, plies = $2,
+            w_clock_ms = case when $4 then $5 else w_clock_ms end,
+            b_clock_ms = case when $4 then b_clock_ms else $5 end,
+            turn_started_ms = $6
+         where id = $1 and plies = $2 + 1 and ended = 0",
+    )
+    .bind(game.id)
+    .bind(ply)
+    .bind(&fen)
+    .bind(white)
+    .bind(clock_ms)
+    .bind(turn_started_ms)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if undone == 0 {
+        return Ok(());
+    }
+    tx.commit().await?;
+    state.boards.remove(&game.id);
+    info!("{user_id} corrected {old_uci} to {new_uci} in game {}", game.id);
+
+    let opponent = if white { b_id } else { w_id };
+    // spectators of the fog can't be told what the move was
+    let text = match game.variant() {
+        Variant::FogOfWar => "Your opponent corrected their move.".to_string(),
+        Variant::Standard => format!("Your opponent corrected their move: {new} instead of {old}."),
+    };
+    state.client.send_message(packed_chat(opponent), text).await?;
+    on_move(state, user_id, &new_uci).await
+}
@@ -0,0 +1,100 @@
+use crate::material::figurine;
+use shakmaty::{Bitboard, Board, Color, File, Piece, Rank, Square};
+
+/// How pieces and empty squares are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Figurines,
+    /// Letters as in FEN, for fonts without chess symbols.
+    Letters,
+}
+
+impl Theme {
+    pub fn parse(s: &str) -> Option<Theme> {
+        match s {
+            "figurines" => Some(Theme::Figurines),
+            "letters" => Some(Theme::Letters),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::Figurines => "figurines",
+            Theme::Letters => "letters",
+        }
+    }
+
+    fn piece(self, piece: Piece) -> char {
+        match self {
+            Theme::Figurines => figurine(piece.color, piece.role),
+            Theme::Letters => piece.char(),
+        }
+    }
+
+    fn empty(self) -> char {
+        match self {
+            Theme::Figurines => '·',
+            Theme::Letters => '.',
+        }
+    }
+}
+
+/// Text diagram of the board from `color`'s side, with squares outside
+/// `seen` fogged over.
+pub fn render(board: &Board, color: Color, seen: Bitboard, theme: Theme) -> String {
+    grid(color, true, Bitboard::EMPTY, |square| match board.piece_at(square) {
+        _ if !seen.contains(square) => '▒',
+        Some(piece) => theme.piece(piece),
+        None => theme.empty(),
+    })
+}
+
+/// Text diagram of the board from `color`'s side with a move shown: the
+/// square it came from is ringed and the piece that arrived underlined.
+pub fn with_move(board: &Board, color: Color, from: Square, to: Square, theme: Theme) -> String {
+    grid(color, true, Bitboard::from_square(to), |square| match board.piece_at(square) {
+        Some(piece) => theme.piece(piece),
+        None if square == from => '◦',
+        None => theme.empty(),
+    })
+}
+
+/// An empty board from `color`'s side with `square` marked, and without
+/// coordinates around it.
+pub fn marked(square: Square, color: Color) -> String {
+    grid(color, false, Bitboard::EMPTY, |s| if s == square { '◉' } else { '·' })
+}
+
+/// Cells in `underlined` get a combining low line under them.
+fn grid(color: Color, coordinates: bool, underlined: Bitboard, cell: impl Fn(Square) -> char) -> String {
+    let mut ranks: Vec<Rank> = Rank::ALL.into_iter().rev().collect();
+    let mut files: Vec<File> = File::ALL.into_iter().collect();
+    if color.is_black() {
+        ranks.reverse();
+        files.reverse();
+    }
+    let mut text = String::new();
+    for rank in ranks {
+        if coordinates {
+            text.push(rank.char());
+            text.push(' ');
+        }
+        for &file in &files {
+            let square = Square::from_coords(file, rank);
+            text.push(cell(square));
+            if underlined.contains(square) {
+                text.push('\u{332}');
+            }
+        }
+        text.push('\n');
+    }
+    if coordinates {
+        text.push_str("  ");
+        text.extend(files.iter().map(|f| f.char()));
+    } else {
+        text.pop();
+    }
+    text
+}
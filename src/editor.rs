@@ -0,0 +1,274 @@
+//! Board editor: `/edit` sets up a position piece by piece, with the side to
+//! move and castling rights, checks that it could be played, then opens it on
+//! an analysis board or starts a game from it.
+
+use crate::{analysis, diagram, ongoing_game, packed_chat, settings, start_game, training, State, STARTING_FEN};
+use anyhow::Result;
+use log::debug;
+use shakmaty::fen::Fen;
+use shakmaty::{Bitboard, Board, CastlingMode, Chess, Color, Piece, Position, PositionErrorKinds, Setup, Square};
+use sqlx::{Pool, Sqlite};
+use std::num::NonZeroU32;
+
+const USAGE: &str = "Usage: /edit [fen|empty] opens the editor. Then send e.g. Ke1 kd8 Qh5 to place pieces \
+    (capitals are White's), xh5 to empty a square, turn w|b, castling KQkq|-, clear, reset, \
+    analyze, play [5+3|3d] [white|black] [vs <user>], stop";
+
+/// The castling rights by their FEN letter, with the king's and rook's
+/// squares.
+const CASTLING: [(char, Color, Square, Square); 4] = [
+    ('K', Color::White, Square::E1, Square::H1),
+    ('Q', Color::White, Square::E1, Square::A1),
+    ('k', Color::Black, Square::E8, Square::H8),
+    ('q', Color::Black, Square::E8, Square::A8),
+];
+
+pub struct Editor {
+    user_id: i64,
+    setup: Setup,
+}
+
+impl Editor {
+    fn fen(&self) -> String {
+        Fen::from_setup(self.setup.clone()).to_string()
+    }
+
+    /// The position, or what keeps it from being played.
+    fn position(&self) -> Result<Chess, String> {
+        Fen::from_setup(self.setup.clone())
+            .into_position::<Chess>(CastlingMode::Standard)
+            .map_err(|e| problems(e.kinds()))
+    }
+
+    async fn save(&self, db: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("update editors set fen = $2 where user_id = $1")
+            .bind(self.user_id)
+            .bind(self.fen())
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    async fn show(&self, db: &Pool<Sqlite>) -> Result<String> {
+        let theme = settings::theme(db, self.user_id).await?;
+        let board = diagram::render(&self.setup.board, Color::White, Bitboard::FULL, theme);
+        let turn = if self.setup.turn.is_white() { "White" } else { "Black" };
+        let castling: String = CASTLING
+            .iter()
+            .filter(|&&(_, _, _, rook)| self.setup.castling_rights.contains(rook))
+            .map(|&(letter, ..)| letter)
+            .collect();
+        let castling = if castling.is_empty() { "no castling".to_string() } else { format!("castling {castling}") };
+        let status = match self.position() {
+            Ok(_) => "Ready: analyze or play from it.".to_string(),
+            Err(problems) => format!("Not playable yet: {problems}."),
+        };
+        Ok(format!("{board}\n{turn} to move, {castling}. {status}\n{}", self.fen()))
+    }
+}
+
+/// What is wrong with a position, as told to the user.
+fn problems(kinds: PositionErrorKinds) -> String {
+    if kinds.contains(PositionErrorKinds::EMPTY_BOARD) {
+        return "the board is empty".to_string();
+    }
+    let mut problems = Vec::new();
+    for (kind, problem) in [
+        (PositionErrorKinds::MISSING_KING, "each side needs a king"),
+        (PositionErrorKinds::TOO_MANY_KINGS, "each side has one king only"),
+        (PositionErrorKinds::PAWNS_ON_BACKRANK, "pawns can't stand on the first or last rank"),
+        (PositionErrorKinds::INVALID_CASTLING_RIGHTS, "the castling rights don't fit the pieces"),
+        (PositionErrorKinds::OPPOSITE_CHECK, "the side not to move is in check"),
+        (PositionErrorKinds::IMPOSSIBLE_CHECK, "no move could have given that check"),
+        (PositionErrorKinds::TOO_MUCH_MATERIAL, "there are more pieces than promotions allow"),
+    ] {
+        if kinds.contains(kind) {
+            problems.push(problem);
+        }
+    }
+    if problems.is_empty() {
+        "the position can't come about in a game".to_string()
+    } else {
+        problems.join(", ")
+    }
+}
+
+/// The castling rights the pieces allow: those whose king and rook are still
+/// on their squares.
+fn fitting_castling(board: &Board, rights: Bitboard) -> Bitboard {
+    CASTLING
+        .iter()
+        .filter(|&&(_, color, king, rook)| {
+            rights.contains(rook)
+                && board.piece_at(king) == Some(color.king())
+                && board.piece_at(rook) == Some(color.rook())
+        })
+        .map(|&(_, _, _, rook)| rook)
+        .collect()
+}
+
+/// The setup from a FEN, with move counters reset so that games from it are
+/// numbered from the first move.
+fn setup_from_fen(fen: &str) -> Option<Setup> {
+    let mut setup = fen.parse::<Fen>().ok()?.into_setup();
+    if setup.pockets.is_some() || setup.remaining_checks.is_some() {
+        return None;
+    }
+    setup.ep_square = None;
+    setup.halfmoves = 0;
+    setup.fullmoves = NonZeroU32::MIN;
+    setup.castling_rights = fitting_castling(&setup.board, setup.castling_rights);
+    Some(setup)
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Editor>> {
+    let fen: Option<String> = sqlx::query_scalar("select fen from editors where user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+    Ok(fen.and_then(|fen| setup_from_fen(&fen)).map(|setup| Editor { user_id, setup }))
+}
+
+async fn close(db: &Pool<Sqlite>, user_id: i64) -> Result<()> {
+    sqlx::query("delete from editors where user_id = $1").bind(user_id).execute(db).await?;
+    Ok(())
+}
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+pub async fn on_edit(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    match running(&state.db, user_id).await? {
+        Some(editor) => on_message(state, editor, args).await,
+        None => open(state, user_id, args.trim()).await,
+    }
+}
+
+/// Opens the editor at the FEN, the starting position, or an empty board.
+async fn open(state: &mut State, user_id: i64, fen: &str) -> Result<()> {
+    let setup = match fen {
+        "" => setup_from_fen(STARTING_FEN),
+        "empty" => Some(Setup::empty()),
+        fen => setup_from_fen(fen),
+    };
+    let Some(setup) = setup else {
+        return reply(state, user_id, format!("That is not a FEN.\n{USAGE}")).await;
+    };
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        return reply(state, user_id, "Finish your game first.").await;
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        return reply(state, user_id, format!("Finish your {what} first.")).await;
+    }
+    let editor = Editor { user_id, setup };
+    sqlx::query("insert into editors (user_id, fen) values ($1, $2)")
+        .bind(user_id)
+        .bind(editor.fen())
+        .execute(&state.db)
+        .await?;
+    debug!("editor for {user_id}");
+    let text = format!("Position editor: {USAGE}\n{}", editor.show(&state.db).await?);
+    reply(state, user_id, text).await
+}
+
+/// A change asked for in one message.
+enum Edit {
+    Place(Square, Piece),
+    Empty(Square),
+}
+
+/// Reads `Ke1`, `pe5` or `xh5`.
+fn parse_edit(word: &str) -> Option<Edit> {
+    let mut chars = word.chars();
+    let first = chars.next()?;
+    let square = chars.as_str().parse::<Square>().ok()?;
+    if first == 'x' || first == '-' {
+        return Some(Edit::Empty(square));
+    }
+    Piece::from_char(first).map(|piece| Edit::Place(square, piece))
+}
+
+/// Takes a message sent while the editor is open.
+pub async fn on_message(state: &mut State, mut editor: Editor, text: &str) -> Result<()> {
+    let user_id = editor.user_id;
+    let (command, rest) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+    let rest = rest.trim();
+    match command.to_lowercase().as_str() {
+        "" | "show" => {}
+        "stop" | "quit" => {
+            close(&state.db, user_id).await?;
+            return reply(state, user_id, "Closed the editor.").await;
+        }
+        "clear" => editor.setup = Setup::empty(),
+        "reset" => editor.setup = setup_from_fen(STARTING_FEN).expect("the starting position"),
+        "turn" => {
+            editor.setup.turn = match rest {
+                "w" | "white" => Color::White,
+                "b" | "black" => Color::Black,
+                _ => return reply(state, user_id, "Say turn w or turn b.").await,
+            }
+        }
+        "castling" => {
+            let mut rights = Bitboard::EMPTY;
+            for letter in rest.chars().filter(|&c| c != '-') {
+                let Some(&(_, _, _, rook)) = CASTLING.iter().find(|&&(l, ..)| l == letter) else {
+                    return reply(state, user_id, "Say e.g. castling KQkq, or castling - for none.").await;
+                };
+                rights.add(rook);
+            }
+            if fitting_castling(&editor.setup.board, rights) != rights {
+                let text = "Castling needs the king and that rook on their starting squares.";
+                return reply(state, user_id, text).await;
+            }
+            editor.setup.castling_rights = rights;
+        }
+        "analyze" | "analyse" | "analysis" => {
+            if let Err(problems) = editor.position() {
+                return reply(state, user_id, format!("The position can't be analyzed: {problems}.")).await;
+            }
+            close(&state.db, user_id).await?;
+            return analysis::start(state, user_id, &editor.fen()).await;
+        }
+        "play" => {
+            let problem = match editor.position() {
+                Err(problems) => Some(format!("The position can't be played: {problems}.")),
+                Ok(position) if position.is_game_over() => Some("The game would be over already.".to_string()),
+                // moves are numbered and told apart by ply, White's first
+                Ok(position) if position.turn().is_black() => {
+                    Some("Games from a set-up position start with White to move: turn w.".to_string())
+                }
+                Ok(_) => None,
+            };
+            if let Some(problem) = problem {
+                return reply(state, user_id, problem).await;
+            }
+            if start_game(state, user_id, rest, Some(&editor.fen())).await? {
+                close(&state.db, user_id).await?;
+            }
+            return Ok(());
+        }
+        _ => {
+            let mut edits = Vec::new();
+            for word in text.split_whitespace() {
+                match parse_edit(word) {
+                    Some(edit) => edits.push(edit),
+                    None => return reply(state, user_id, format!("I don't understand {word}.\n{USAGE}")).await,
+                }
+            }
+            for edit in edits {
+                match edit {
+                    Edit::Place(square, piece) => editor.setup.board.set_piece_at(square, piece),
+                    Edit::Empty(square) => editor.setup.board.discard_piece_at(square),
+                }
+            }
+            let rights = editor.setup.castling_rights;
+            editor.setup.castling_rights = fitting_castling(&editor.setup.board, rights);
+        }
+    }
+    editor.save(&state.db).await?;
+    let text = editor.show(&state.db).await?;
+    reply(state, user_id, text).await
+}
+
@@ -0,0 +1,80 @@
+//! Emotes: a few set phrases a player can send their opponent, with `/emote`
+//! or by typing one on its own during a game, in place of free-text chat.
+//! Players who'd rather not see them turn them off in `/settings`.
+
+use crate::{ongoing_game, packed_chat, settings, user_name, State};
+use anyhow::Result;
+
+/// What can be typed, and what the opponent sees.
+const EMOTES: [(&str, &str); 4] = [("gg", "Good game!"), ("wow", "Wow!"), ("oops", "Oops!"), ("handshake", "🤝")];
+
+/// Seconds between two emotes from the same player.
+const EMOTE_INTERVAL_SECS: i64 = 10;
+
+/// Emotes a player may send in one game.
+const MAX_EMOTES_PER_GAME: i64 = 20;
+
+/// The emote the text is, if it is one on its own.
+pub fn parse(text: &str) -> Option<&'static str> {
+    let text = text.trim().trim_start_matches('/').to_lowercase();
+    let text = match text.as_str() {
+        "🤝" => "handshake",
+        text => text,
+    };
+    EMOTES.iter().map(|&(name, _)| name).find(|&name| name == text)
+}
+
+fn usage() -> String {
+    let names: Vec<&str> = EMOTES.iter().map(|&(name, _)| name).collect();
+    format!("Usage: /emote {}", names.join("|"))
+}
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+pub async fn on_emote(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    match parse(args) {
+        Some(emote) => send(state, user_id, emote).await,
+        None => reply(state, user_id, usage()).await,
+    }
+}
+
+/// Sends the emote to the opponent in the user's game.
+pub async fn send(state: &mut State, user_id: i64, emote: &str) -> Result<()> {
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        return reply(state, user_id, "Emotes are for your opponent, and you are not playing.").await;
+    };
+    let Some(opponent) = [game.w_id, game.b_id].into_iter().flatten().find(|&id| id != user_id) else {
+        return reply(state, user_id, "You have no opponent yet.").await;
+    };
+    let (sent, recent): (i64, bool) = sqlx::query_as(
+        "select count(*), coalesce(max(created_at) > unixepoch() - $3, 0) from emotes
+         where game_id = $1 and user_id = $2",
+    )
+    .bind(game.id)
+    .bind(user_id)
+    .bind(EMOTE_INTERVAL_SECS)
+    .fetch_one(&state.db)
+    .await?;
+    if sent >= MAX_EMOTES_PER_GAME {
+        return reply(state, user_id, "That's enough emotes for one game.").await;
+    }
+    if recent {
+        return reply(state, user_id, "Wait a few seconds before sending another emote.").await;
+    }
+    if !settings::get(&state.db, opponent).await?.emotes {
+        return reply(state, user_id, "Your opponent turned emotes off.").await;
+    }
+    sqlx::query("insert into emotes (game_id, user_id, emote) values ($1, $2, $3)")
+        .bind(game.id)
+        .bind(user_id)
+        .bind(emote)
+        .execute(&state.db)
+        .await?;
+    let shown = EMOTES.iter().find(|&&(name, _)| name == emote).map_or(emote, |&(_, shown)| shown);
+    let text = format!("Game #{}: {} says {shown}", game.id, user_name(&state.db, user_id).await?);
+    state.client.send_message(packed_chat(opponent), text).await?;
+    reply(state, user_id, format!("Sent {shown} to {}.", user_name(&state.db, opponent).await?)).await
+}
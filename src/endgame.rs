@@ -0,0 +1,340 @@
+//! Endgame drills against perfect defence. Every move is checked against
+//! the tablebase as soon as it's sent: one that throws away the win (or the
+//! draw, when defending) is refused and explained, and the user tries again.
+
+use crate::bot::Bot;
+use crate::tablebase::{self, Outcome, Wdl};
+use crate::{diagram, ongoing_game, packed_chat, parse_typed_move, position_from_fen, settings, training, State};
+use anyhow::Result;
+use log::debug;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::{Bitboard, Chess, Color, EnPassantMode, Position, Role};
+
+/// A defended drawn position counts as held after this many plies.
+const HOLD_PLIES: i64 = 40;
+
+/// Random start positions are at least this many plies from mate, so that
+/// there is something to practice.
+const MIN_START_DTM: u32 = 15;
+
+enum Start {
+    /// A random position from a built-in table with White's extra piece.
+    Random(Role),
+    Fen(&'static str),
+}
+
+struct Endgame {
+    name: &'static str,
+    title: &'static str,
+    start: Start,
+    user: Color,
+    goal: Wdl,
+}
+
+const ENDGAMES: &[Endgame] = &[
+    Endgame {
+        name: "queen",
+        title: "Mate with king and queen",
+        start: Start::Random(Role::Queen),
+        user: Color::White,
+        goal: Wdl::Win,
+    },
+    Endgame {
+        name: "rook",
+        title: "Mate with king and rook",
+        start: Start::Random(Role::Rook),
+        user: Color::White,
+        goal: Wdl::Win,
+    },
+    Endgame {
+        name: "pawn",
+        title: "Promote a pawn against a lone king",
+        start: Start::Random(Role::Pawn),
+        user: Color::White,
+        goal: Wdl::Win,
+    },
+    Endgame {
+        name: "opposition",
+        title: "Hold the draw against king and pawn",
+        start: Start::Random(Role::Pawn),
+        user: Color::Black,
+        goal: Wdl::Draw,
+    },
+    Endgame {
+        name: "lucena",
+        title: "Lucena position: build a bridge",
+        start: Start::Fen("1K6/1P1k4/8/8/8/8/r7/2R5 w - - 0 1"),
+        user: Color::White,
+        goal: Wdl::Win,
+    },
+    Endgame {
+        name: "philidor",
+        title: "Philidor position: hold the third rank",
+        start: Start::Fen("4k3/R7/1r6/3KP3/8/8/8/8 b - - 0 1"),
+        user: Color::Black,
+        goal: Wdl::Draw,
+    },
+];
+
+impl Endgame {
+    fn by_name(name: &str) -> Option<&'static Endgame> {
+        ENDGAMES.iter().find(|e| e.name == name)
+    }
+
+    /// Whether it can be played without an engine with tablebases.
+    fn builtin(&self) -> bool {
+        matches!(self.start, Start::Random(_))
+    }
+
+    async fn start_position(&self) -> Result<Option<Chess>> {
+        let role = match self.start {
+            Start::Fen(fen) => return Ok(Some(position_from_fen(fen))),
+            Start::Random(role) => role,
+        };
+        let (user, goal) = (self.user, self.goal);
+        let found = tokio::task::spawn_blocking(move || {
+            tablebase::random_position(role, user, |position, outcome| {
+                outcome.wdl == goal
+                    && outcome.dtm.is_none_or(|dtm| dtm >= MIN_START_DTM)
+                    && has_mistake(position, goal)
+            })
+        })
+        .await?;
+        Ok(found)
+    }
+}
+
+/// Whether some move in the position would fall short of `goal`.
+fn has_mistake(position: &Chess, goal: Wdl) -> bool {
+    position.legal_moves().iter().any(|m| {
+        let mut after = position.clone();
+        after.play_unchecked(m);
+        tablebase::probe_builtin(&after)
+            .map(Outcome::for_mover)
+            .is_some_and(|outcome| outcome.wdl < goal)
+    })
+}
+
+const COLUMNS: &str = "id, user_id, endgame, fen, white, plies, mistakes";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Session {
+    id: i64,
+    user_id: i64,
+    endgame: String,
+    fen: String,
+    white: bool,
+    plies: i64,
+    mistakes: i64,
+}
+
+pub async fn running(db: &sqlx::Pool<sqlx::Sqlite>, user_id: i64) -> Result<Option<Session>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from endgame_sessions where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+fn list(state: &State) -> String {
+    let mut text = "Endgames to practice:".to_string();
+    for endgame in ENDGAMES {
+        let unavailable = !endgame.builtin() && !state.engine.as_ref().is_some_and(|e| e.has_tablebases());
+        text = format!(
+            "{text}\n/endgame {} — {}{}",
+            endgame.name,
+            endgame.title,
+            if unavailable { " (needs tablebases)" } else { "" }
+        );
+    }
+    text
+}
+
+pub async fn on_endgame(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let name = args.trim();
+    if name.is_empty() {
+        state.client.send_message(chat, list(state)).await?;
+        return Ok(());
+    }
+    if name == "stop" {
+        match running(&state.db, user_id).await? {
+            Some(session) => end(&state.db, &state.client, &session, "Stopped.").await?,
+            None => {
+                state.client.send_message(chat, "You are not practicing an endgame.").await?;
+            }
+        }
+        return Ok(());
+    }
+    let Some(endgame) = Endgame::by_name(name) else {
+        state.client.send_message(chat, list(state)).await?;
+        return Ok(());
+    };
+    if !endgame.builtin() && !state.engine.as_ref().is_some_and(|e| e.has_tablebases()) {
+        state
+            .client
+            .send_message(chat, "This endgame needs an engine with tablebases, which isn't set up.")
+            .await?;
+        return Ok(());
+    }
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state.client.send_message(chat, "Finish your game first.").await?;
+        return Ok(());
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        state
+            .client
+            .send_message(chat, format!("Finish your {what} first."))
+            .await?;
+        return Ok(());
+    }
+    let Some(mut position) = endgame.start_position().await? else {
+        state
+            .client
+            .send_message(chat, "Couldn't find a position to practice, try again.")
+            .await?;
+        return Ok(());
+    };
+
+    let mut text = format!(
+        "{}. {} as {}.",
+        endgame.title,
+        if endgame.goal == Wdl::Win { "Win" } else { "Draw" },
+        if endgame.user.is_white() { "White" } else { "Black" }
+    );
+    // the bot moves first if it's its turn in the starting position
+    if position.turn() != endgame.user {
+        let Some((m, _)) = tablebase::best_move(state.engine.as_ref(), &position).await? else {
+            state.client.send_message(chat, "The tablebase doesn't cover this position.").await?;
+            return Ok(());
+        };
+        text = format!("{text}\nYour opponent played {}.", San::from_move(&position, &m));
+        position.play_unchecked(&m);
+    }
+    sqlx::query("insert into endgame_sessions (user_id, endgame, fen, white) values ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(endgame.name)
+        .bind(Fen::from_position(position.clone(), EnPassantMode::Legal).to_string())
+        .bind(endgame.user.is_white())
+        .execute(&state.db)
+        .await?;
+    let theme = settings::theme(&state.db, user_id).await?;
+    text = format!("{text}\n{}", diagram::render(position.board(), endgame.user, Bitboard::FULL, theme));
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+fn describe(outcome: Outcome) -> String {
+    match (outcome.wdl, outcome.dtm) {
+        (Wdl::Win, Some(plies)) => format!("mate in {} with best play", plies.div_ceil(2)),
+        (Wdl::Win, None) => "winning".to_string(),
+        (Wdl::Draw, _) => "a draw".to_string(),
+        (Wdl::Loss, _) => "lost".to_string(),
+    }
+}
+
+pub async fn on_move(state: &mut State, mut session: Session, notation: &str) -> Result<()> {
+    let chat = packed_chat(session.user_id);
+    let Some(endgame) = Endgame::by_name(&session.endgame) else {
+        return end(&state.db, &state.client, &session, "This endgame no longer exists.").await;
+    };
+    let mut position = position_from_fen(&session.fen);
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+    let san = San::from_move(&position, &m).to_string();
+    let mut after = position.clone();
+    after.play_unchecked(&m);
+    let Some(outcome) = tablebase::probe(state.engine.as_ref(), &after).await? else {
+        return end(&state.db, &state.client, &session, "The tablebase doesn't cover this position.").await;
+    };
+    let outcome = outcome.for_mover();
+    if outcome.wdl < endgame.goal {
+        session.mistakes += 1;
+        sqlx::query("update endgame_sessions set mistakes = $2 where id = $1")
+            .bind(session.id)
+            .bind(session.mistakes)
+            .execute(&state.db)
+            .await?;
+        let thrown = if endgame.goal == Wdl::Win { "win" } else { "draw" };
+        let text = format!("✗ {san} throws away the {thrown}: it would be {}. Try another move.", describe(outcome));
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+    position = after;
+    session.plies += 1;
+
+    if position.is_game_over() || position.halfmoves() >= 100 {
+        return finish(state, &session, endgame, &position, &san).await;
+    }
+    if endgame.goal == Wdl::Draw && session.plies >= HOLD_PLIES {
+        return end(&state.db, &state.client, &session, &format!("✓ {san}. You held the position, that's a draw!")).await;
+    }
+
+    let Some((reply, _)) = tablebase::best_move(state.engine.as_ref(), &position).await? else {
+        return end(&state.db, &state.client, &session, "The tablebase doesn't cover this position.").await;
+    };
+    let reply_san = San::from_move(&position, &reply).to_string();
+    position.play_unchecked(&reply);
+    session.plies += 1;
+    if position.is_game_over() || position.halfmoves() >= 100 {
+        return finish(state, &session, endgame, &position, &format!("{san}, {reply_san}")).await;
+    }
+    debug!("endgame session {} at {}", session.id, session.plies);
+    sqlx::query("update endgame_sessions set fen = $2, plies = $3 where id = $1")
+        .bind(session.id)
+        .bind(Fen::from_position(position.clone(), EnPassantMode::Legal).to_string())
+        .bind(session.plies)
+        .execute(&state.db)
+        .await?;
+
+    let side = if session.white { Color::White } else { Color::Black };
+    let mut text = format!("✓ {san}, {}. Your opponent replied {reply_san}.", describe(outcome));
+    let theme = settings::theme(&state.db, session.user_id).await?;
+    text = format!("{text}\n{}", diagram::render(position.board(), side, Bitboard::FULL, theme));
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Ends a drill whose game is over after `moves`.
+async fn finish(state: &State, session: &Session, endgame: &Endgame, position: &Chess, moves: &str) -> Result<()> {
+    let result = if position.is_checkmate() {
+        if position.turn() == endgame.user {
+            Wdl::Loss
+        } else {
+            Wdl::Win
+        }
+    } else {
+        Wdl::Draw
+    };
+    let reason = if position.is_checkmate() {
+        "checkmate"
+    } else if position.is_stalemate() {
+        "stalemate"
+    } else if position.is_insufficient_material() {
+        "insufficient material"
+    } else {
+        "fifty-move rule"
+    };
+    let verdict = if result >= endgame.goal { "✓ Done" } else { "✗ Failed" };
+    end(&state.db, &state.client, session, &format!("{moves}: {reason}. {verdict}!")).await
+}
+
+async fn end(db: &sqlx::Pool<sqlx::Sqlite>, client: &Bot, session: &Session, reason: &str) -> Result<()> {
+    sqlx::query("update endgame_sessions set ended = 1 where id = $1")
+        .bind(session.id)
+        .execute(db)
+        .await?;
+    let mistakes = match session.mistakes {
+        0 => "no mistakes".to_string(),
+        1 => "1 mistake".to_string(),
+        n => format!("{n} mistakes"),
+    };
+    client
+        .send_message(packed_chat(session.user_id), format!("{reason} ({mistakes})"))
+        .await?;
+    Ok(())
+}
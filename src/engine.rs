@@ -0,0 +1,301 @@
+//! A UCI engine such as Stockfish, run as a subprocess for the features that
+//! need evaluations. The process is started on first use and restarted if it
+//! dies; searches are serialized.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Search time when a caller has no reason to pick another.
+pub const DEFAULT_MOVETIME: Duration = Duration::from_millis(300);
+
+/// Most lines one search is asked for. They share its search time, and
+/// every player waits on the one engine process.
+pub const MAX_MULTIPV: usize = 5;
+
+/// Positions whose evaluations are remembered, so asking twice costs one
+/// search.
+const EVALUATIONS: usize = 256;
+
+/// Mate scores are mapped to centipawns beyond any real evaluation.
+const MATE_CP: i64 = 100_000;
+
+/// Evaluation from the side to move's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Cp(i64),
+    /// Mate in this many moves; negative if the side to move gets mated.
+    Mate(i64),
+}
+
+impl Score {
+    /// The score on a single scale, mates in fewer moves being worth more.
+    pub fn centipawns(self) -> i64 {
+        match self {
+            Score::Cp(cp) => cp,
+            Score::Mate(n) if n > 0 => MATE_CP - n,
+            Score::Mate(n) => -MATE_CP - n,
+        }
+    }
+}
+
+/// A principal variation found by the engine.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub score: Score,
+    /// Moves in UCI, starting with the engine's choice.
+    pub pv: Vec<String>,
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+#[derive(Clone)]
+pub struct Engine {
+    path: String,
+    /// UCI options set whenever the engine starts.
+    options: Vec<(String, String)>,
+    process: Arc<Mutex<Option<Process>>>,
+    /// Recent evaluations by FEN, oldest first.
+    evaluations: Arc<Mutex<VecDeque<(String, Line)>>>,
+}
+
+impl Engine {
+    pub fn new(path: impl Into<String>) -> Self {
+        Engine {
+            path: path.into(),
+            options: Vec::new(),
+            process: Arc::new(Mutex::new(None)),
+            evaluations: Arc::default(),
+        }
+    }
+
+    /// The engine configured by `ENGINE`, with Syzygy tables from
+    /// `SYZYGY_PATH` if set.
+    pub fn from_env() -> Option<Self> {
+        let mut engine = Engine::new(env::var("ENGINE").ok()?);
+        if let Ok(path) = env::var("SYZYGY_PATH") {
+            engine.options.push(("SyzygyPath".to_string(), path));
+        }
+        Some(engine)
+    }
+
+    pub fn has_tablebases(&self) -> bool {
+        self.options.iter().any(|(name, _)| name == "SyzygyPath")
+    }
+
+    /// The best `multipv` lines in the position, best first. With
+    /// `searchmoves`, only those moves (in UCI) are considered.
+    pub async fn analyse(
+        &self,
+        fen: &str,
+        multipv: usize,
+        movetime: Duration,
+        searchmoves: &[String],
+    ) -> Result<Vec<Line>> {
+        let engine = self.clone();
+        let mut go = format!("go movetime {}", movetime.as_millis());
+        if !searchmoves.is_empty() {
+            go = format!("{go} searchmoves {}", searchmoves.join(" "));
+        }
+        let commands = [
+            // a game at reduced strength may have searched last
+            "setoption name UCI_LimitStrength value false".to_string(),
+            format!("setoption name MultiPV value {multipv}"),
+            format!("position fen {fen}"),
+            go,
+        ];
+        let (lines, _) = tokio::task::spawn_blocking(move || engine.search(&commands))
+            .await
+            .map_err(|e| anyhow!("engine task failed: {e}"))??;
+        Ok(lines)
+    }
+
+    /// The best line in the position at the default search time, remembered
+    /// for the next time it's asked about.
+    pub async fn evaluate(&self, fen: &str) -> Result<Option<Line>> {
+        let cached = self.evaluations.lock().expect("evaluations lock").iter().find(|(f, _)| f == fen).cloned();
+        if let Some((_, line)) = cached {
+            return Ok(Some(line));
+        }
+        let Some(line) = self.analyse(fen, 1, DEFAULT_MOVETIME, &[]).await?.into_iter().next() else {
+            return Ok(None);
+        };
+        let mut evaluations = self.evaluations.lock().expect("evaluations lock");
+        if evaluations.len() == EVALUATIONS {
+            evaluations.pop_front();
+        }
+        evaluations.push_back((fen.to_string(), line.clone()));
+        Ok(Some(line))
+    }
+
+    /// The move the engine plays in the position, in UCI, at full strength
+    /// or limited to about `elo`.
+    pub async fn best_move(&self, fen: &str, movetime: Duration, elo: Option<i64>) -> Result<String> {
+        let engine = self.clone();
+        let mut commands = vec![format!("setoption name UCI_LimitStrength value {}", elo.is_some())];
+        if let Some(elo) = elo {
+            commands.push(format!("setoption name UCI_Elo value {elo}"));
+        }
+        commands.extend([
+            "setoption name MultiPV value 1".to_string(),
+            format!("position fen {fen}"),
+            format!("go movetime {}", movetime.as_millis()),
+        ]);
+        let (_, best) = tokio::task::spawn_blocking(move || engine.search(&commands))
+            .await
+            .map_err(|e| anyhow!("engine task failed: {e}"))??;
+        best.ok_or_else(|| anyhow!("engine has no move in {fen}"))
+    }
+
+    /// The move the engine finds looking only `depth` plies ahead, in UCI:
+    /// quick, and a suggestion rather than the best play.
+    pub async fn shallow_move(&self, fen: &str, depth: u32) -> Result<String> {
+        let engine = self.clone();
+        let commands = [
+            "setoption name UCI_LimitStrength value false".to_string(),
+            "setoption name MultiPV value 1".to_string(),
+            format!("position fen {fen}"),
+            format!("go depth {depth}"),
+        ];
+        let (_, best) = tokio::task::spawn_blocking(move || engine.search(&commands))
+            .await
+            .map_err(|e| anyhow!("engine task failed: {e}"))??;
+        best.ok_or_else(|| anyhow!("engine has no move in {fen}"))
+    }
+
+    /// The engine's evaluation of playing `uci` in the position, from the
+    /// point of view of the side playing it.
+    pub async fn score_move(&self, fen: &str, uci: &str, movetime: Duration) -> Result<Score> {
+        let lines = self.analyse(fen, 1, movetime, &[uci.to_string()]).await?;
+        lines
+            .first()
+            .map(|line| line.score)
+            .ok_or_else(|| anyhow!("engine found no line for {uci}"))
+    }
+
+    fn search(&self, commands: &[String]) -> Result<(Vec<Line>, Option<String>)> {
+        let mut guard = self.process.lock().expect("engine lock");
+        if guard.is_none() {
+            *guard = Some(self.spawn()?);
+        }
+        let process = guard.as_mut().expect("just spawned");
+        let result = process.search(commands);
+        if result.is_err() {
+            warn!("engine failed, restarting it on next use");
+            if let Some(mut process) = guard.take() {
+                let _ = process.child.kill();
+            }
+        }
+        result
+    }
+
+    fn spawn(&self) -> Result<Process> {
+        debug!("start engine {}", self.path);
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("cannot start engine {}", self.path))?;
+        let mut process = Process {
+            stdin: child.stdin.take().expect("piped"),
+            stdout: BufReader::new(child.stdout.take().expect("piped")),
+            child,
+        };
+        process.send("uci")?;
+        process.wait_for("uciok")?;
+        for (name, value) in &self.options {
+            process.send(&format!("setoption name {name} value {value}"))?;
+        }
+        process.send("isready")?;
+        process.wait_for("readyok")?;
+        Ok(process)
+    }
+}
+
+impl Process {
+    fn send(&mut self, command: &str) -> Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            bail!("engine exited");
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    fn wait_for(&mut self, expected: &str) -> Result<()> {
+        while self.read_line()? != expected {}
+        Ok(())
+    }
+
+    /// The lines found and the move chosen, which an engine playing below
+    /// full strength may pick from outside them.
+    fn search(&mut self, commands: &[String]) -> Result<(Vec<Line>, Option<String>)> {
+        for command in commands {
+            self.send(command)?;
+        }
+        let mut lines: Vec<Option<Line>> = Vec::new();
+        let best = loop {
+            let line = self.read_line()?;
+            if let Some(rest) = line.strip_prefix("bestmove") {
+                // `(none)` when there is no legal move
+                break rest.split_whitespace().next().filter(|m| *m != "(none)").map(str::to_string);
+            }
+            if let Some((multipv, parsed)) = parse_info(&line) {
+                if lines.len() < multipv {
+                    lines.resize(multipv, None);
+                }
+                lines[multipv - 1] = Some(parsed);
+            }
+        };
+        Ok((lines.into_iter().flatten().collect(), best))
+    }
+}
+
+/// Parses an `info` line carrying a score and a PV, returning its 1-based
+/// multipv index. Bound scores from aspiration windows are skipped.
+fn parse_info(line: &str) -> Option<(usize, Line)> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next()? != "info" {
+        return None;
+    }
+    let (mut multipv, mut score, mut pv) = (1, None, Vec::new());
+    while let Some(token) = tokens.next() {
+        match token {
+            "multipv" => multipv = tokens.next()?.parse().ok()?,
+            "score" => {
+                let kind = tokens.next()?;
+                let value: i64 = tokens.next()?.parse().ok()?;
+                score = match kind {
+                    "cp" => Some(Score::Cp(value)),
+                    "mate" => Some(Score::Mate(value)),
+                    _ => None,
+                };
+            }
+            "lowerbound" | "upperbound" => return None,
+            "pv" => {
+                pv = tokens.map(str::to_string).collect();
+                break;
+            }
+            _ => {}
+        }
+    }
+    if pv.is_empty() || multipv == 0 {
+        return None;
+    }
+    Some((multipv, Line { score: score?, pv }))
+}
@@ -0,0 +1,271 @@
+//! Engine exhibitions: an admin starts a game between two house players,
+//! each played by the engine at its own strength. Their moves go through
+//! `on_move` like anyone else's, so the game can be watched, followed and
+//! featured. House players have negative ids, get no messages and are never
+//! rated.
+//!
+//! Players take on a house player with `/start bot`, at full strength and
+//! optionally giving odds: `/start bot queen-odds` starts the game without
+//! the engine's queen.
+
+use crate::bot::Bot;
+use crate::clock::{self, Delay, Pace};
+use crate::engine::Engine;
+use crate::{
+    end_game, finish_game, game_by_id, on_move, packed_chat, settings, starting_board, templates, State, Termination,
+    STARTING_FEN,
+};
+use anyhow::Result;
+use log::{debug, info};
+use shakmaty::fen::Fen;
+use shakmaty::{Color, Setup, Square};
+use sqlx::{Pool, Sqlite};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: /admin exhibition <white elo|max> <black elo|max> [seconds per move]";
+
+const DEFAULT_MOVETIME: Duration = Duration::from_secs(1);
+
+const MAX_MOVETIME_SECS: u64 = 60;
+
+/// Strengths Stockfish can be limited to.
+const ELO_RANGE: RangeInclusive<i64> = 1320..=3190;
+
+/// Exhibitions still going after this many plies are drawn.
+const MAX_PLIES: i64 = 400;
+
+/// Material the engine gives in a game against the bot: a piece taken off its
+/// side of the usual starting position.
+#[derive(Debug, Clone, Copy)]
+pub struct Odds {
+    name: &'static str,
+    /// The piece's square on White's side.
+    square: Square,
+}
+
+const ODDS: [Odds; 4] = [
+    Odds { name: "queen", square: Square::D1 },
+    Odds { name: "rook", square: Square::A1 },
+    Odds { name: "knight", square: Square::B1 },
+    Odds { name: "pawn", square: Square::F2 },
+];
+
+impl Odds {
+    /// Reads a `/start` argument like `queen-odds`.
+    pub fn parse(s: &str) -> Option<Odds> {
+        let name = s.strip_suffix("-odds")?;
+        ODDS.iter().find(|odds| odds.name == name).copied()
+    }
+
+    /// The starting position without the piece of the engine's side. A
+    /// missing rook takes its castling right along.
+    fn fen(self, engine: Color) -> String {
+        let square = match engine {
+            Color::White => self.square,
+            Color::Black => self.square.flip_vertical(),
+        };
+        let mut setup: Setup = STARTING_FEN.parse::<Fen>().expect("the starting position").into_setup();
+        setup.board.discard_piece_at(square);
+        setup.castling_rights.discard(square);
+        Fen::from_setup(setup).to_string()
+    }
+}
+
+pub fn is_house_player(user_id: i64) -> bool {
+    user_id < 0
+}
+
+fn house_name(elo: Option<i64>) -> String {
+    match elo {
+        Some(elo) => format!("Engine {elo}"),
+        None => "Engine (full strength)".to_string(),
+    }
+}
+
+/// Parses `max` or an Elo the engine can be limited to; `None` inside for
+/// full strength.
+fn parse_strength(s: &str) -> Option<Option<i64>> {
+    match s {
+        "max" => Some(None),
+        s => s.parse().ok().filter(|elo| ELO_RANGE.contains(elo)).map(Some),
+    }
+}
+
+/// The id for a new house player.
+const NEW_HOUSE_PLAYER: &str = "select min(coalesce(min(id), 0), 0) - 1 from users";
+
+/// Handles `/admin exhibition`, returning the reply.
+pub async fn admin(state: &mut State, admin_id: i64, args: &str) -> Result<String> {
+    if state.engine.is_none() {
+        return Ok("No engine is set up to play exhibitions.".to_string());
+    }
+    let mut words = args.split_whitespace();
+    let (Some(white), Some(black), movetime, None) = (words.next(), words.next(), words.next(), words.next()) else {
+        return Ok(USAGE.to_string());
+    };
+    let (Some(w_elo), Some(b_elo)) = (parse_strength(white), parse_strength(black)) else {
+        return Ok(format!(
+            "Strengths are max or an Elo from {} to {}.\n{USAGE}",
+            ELO_RANGE.start(),
+            ELO_RANGE.end()
+        ));
+    };
+    let movetime = match movetime.map(str::parse::<u64>) {
+        None => DEFAULT_MOVETIME,
+        Some(Ok(secs)) if (1..=MAX_MOVETIME_SECS).contains(&secs) => Duration::from_secs(secs),
+        Some(_) => return Ok(format!("Seconds per move are from 1 to {MAX_MOVETIME_SECS}.\n{USAGE}")),
+    };
+
+    // new house players every time, since nobody plays two games at once
+    let mut tx = state.db.begin().await?;
+    let w_id: i64 = sqlx::query_scalar(NEW_HOUSE_PLAYER).fetch_one(&mut *tx).await?;
+    let b_id = w_id - 1;
+    for (id, elo) in [(w_id, w_elo), (b_id, b_elo)] {
+        sqlx::query("insert into users (id, name, joined_at) values ($1, $2, unixepoch())")
+            .bind(id)
+            .bind(house_name(elo))
+            .execute(&mut *tx)
+            .await?;
+    }
+    let game_id: i64 = sqlx::query_scalar(
+        "insert into games (w_id, b_id, ended, fen, started_at, last_move_at) values ($1, $2, 0, $3, unixepoch(), unixepoch())
+         returning id",
+    )
+    .bind(w_id)
+    .bind(b_id)
+    .bind(STARTING_FEN)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query("insert into exhibitions (game_id, w_elo, b_elo, movetime_ms) values ($1, $2, $3, $4)")
+        .bind(game_id)
+        .bind(w_elo)
+        .bind(b_elo)
+        .bind(movetime.as_millis() as i64)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("{admin_id} started exhibition game {game_id}");
+
+    let mut text = format!(
+        "Exhibition game #{game_id}: {} – {}, {}s per move. /admin feature {game_id} shows it in the channel.",
+        house_name(w_elo),
+        house_name(b_elo),
+        movetime.as_secs()
+    );
+    if let Some(url) = &state.config.get().public_url {
+        text = format!("{text}\nWatch: {url}/game/{game_id}");
+    }
+    Ok(text)
+}
+
+/// Starts a game between the user and a house player at full strength, from
+/// the odds position, the position set up with `/edit`, or the usual one.
+pub async fn against_engine(
+    state: &mut State,
+    user_id: i64,
+    preference: Option<Color>,
+    pace: Option<Pace>,
+    odds: Option<Odds>,
+    initial_fen: Option<String>,
+) -> Result<bool> {
+    if state.engine.is_none() {
+        state.client.send_message(packed_chat(user_id), "No engine is set up to play against.").await?;
+        return Ok(false);
+    }
+    let color = preference.unwrap_or_else(|| Color::from_white(rand::random()));
+    let initial_fen = odds.map(|odds| odds.fen(!color)).or(initial_fen);
+    let (tc, days_per_move) = match pace {
+        Some(Pace::Clock(tc)) => (Some(tc), None),
+        Some(Pace::Days(days)) => (None, Some(days)),
+        None => (state.config.get().time_control, None),
+    };
+    let delay = tc.and_then(|tc| tc.delay);
+    let name = match odds {
+        Some(odds) => format!("Engine ({} odds)", odds.name),
+        None => house_name(None),
+    };
+
+    let mut tx = state.db.begin().await?;
+    let house_id: i64 = sqlx::query_scalar(NEW_HOUSE_PLAYER).fetch_one(&mut *tx).await?;
+    sqlx::query("insert into users (id, name, joined_at) values ($1, $2, unixepoch())")
+        .bind(house_id)
+        .bind(&name)
+        .execute(&mut *tx)
+        .await?;
+    let (w_id, b_id) = match color {
+        Color::White => (user_id, house_id),
+        Color::Black => (house_id, user_id),
+    };
+    let game_id: i64 = sqlx::query_scalar(
+        "insert into games (w_id, b_id, ended, fen, initial_ms, increment_ms, delay_ms, bronstein, w_clock_ms,
+            b_clock_ms, turn_started_ms, casual, days_per_move, initial_fen, started_at, last_move_at)
+         values ($1, $2, 0, coalesce($7, $3), $4, $5, $6, $8, $4, $4, $9, 1, $10, $7, unixepoch(), unixepoch())
+         returning id",
+    )
+    .bind(w_id)
+    .bind(b_id)
+    .bind(STARTING_FEN)
+    .bind(tc.map(|tc| tc.initial.as_millis() as i64))
+    .bind(tc.map(|tc| tc.increment.as_millis() as i64))
+    .bind(delay.map(|d| match d {
+        Delay::Simple(d) | Delay::Bronstein(d) => d.as_millis() as i64,
+    }))
+    .bind(&initial_fen)
+    .bind(delay.map(|d| matches!(d, Delay::Bronstein(_))))
+    .bind(clock::now_ms())
+    .bind(days_per_move)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query("insert into exhibitions (game_id, w_elo, b_elo, movetime_ms) values ($1, null, null, $2)")
+        .bind(game_id)
+        .bind(DEFAULT_MOVETIME.as_millis() as i64)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("{user_id} started game {game_id} against the engine");
+
+    let mut details = String::new();
+    if let Some(fen) = &initial_fen {
+        details = starting_board(fen, color, settings::theme(&state.db, user_id).await?);
+    }
+    let key = if color.is_white() { "game_started_white" } else { "game_started_black" };
+    let text = templates::text(state, key, &[("id", &game_id), ("opponent", &name), ("details", &details)]);
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(true)
+}
+
+/// Plays the next move of every exhibition in progress, and the engine's
+/// moves in games against the bot.
+pub async fn play(db: &Pool<Sqlite>, client: &Bot, engine: &Engine) -> Result<()> {
+    let exhibitions: Vec<(i64, Option<i64>, Option<i64>, i64)> = sqlx::query_as(
+        "select e.game_id, e.w_elo, e.b_elo, e.movetime_ms from exhibitions e join games g on g.id = e.game_id
+         where not g.ended",
+    )
+    .fetch_all(db)
+    .await?;
+    if exhibitions.is_empty() {
+        return Ok(());
+    }
+    let mut state = State::offline(db.clone(), client.clone());
+    for (game_id, w_elo, b_elo, movetime_ms) in exhibitions {
+        let Some(game) = game_by_id(db, game_id).await? else {
+            continue;
+        };
+        if game.plies >= MAX_PLIES {
+            if end_game(db, game_id, None, Termination::Draw).await? {
+                finish_game(db, client, game_id).await?;
+            }
+            continue;
+        }
+        let (mover, elo) = if game.turn().is_white() { (game.w_id, w_elo) } else { (game.b_id, b_elo) };
+        let Some(mover) = mover.filter(|&mover| is_house_player(mover)) else {
+            continue;
+        };
+        let movetime = Duration::from_millis(movetime_ms as u64);
+        let uci = engine.best_move(&game.fen, movetime, elo).await?;
+        debug!("exhibition {game_id}: {uci}");
+        on_move(&mut state, mover, &uci).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! `/export all`: every finished game of the user as one PGN file, sent as a
+//! document. Small archives are sent right away; larger ones are built by a
+//! background job, and the user is told it is on its way.
+
+use crate::bot::Bot;
+use crate::{game_pgn, packed_chat, Game, State, GAME_COLUMNS};
+use anyhow::Result;
+use log::{debug, info, warn};
+use sqlx::{Pool, Sqlite};
+
+/// Archives with more games than this are left to the background job.
+const MAX_GAMES_RIGHT_AWAY: i64 = 100;
+
+/// Archives built per job run, so one run doesn't hold the database long.
+const EXPORTS_PER_SWEEP: i64 = 3;
+
+const USAGE: &str = "Usage: /export all to get all your finished games as a PGN file, /pgn <game> for one game";
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+fn count_games(n: i64) -> String {
+    if n == 1 {
+        "1 game".to_string()
+    } else {
+        format!("{n} games")
+    }
+}
+
+pub async fn on_export(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    if args.trim() != "all" {
+        return reply(state, user_id, USAGE).await;
+    }
+    let games: i64 = sqlx::query_scalar(
+        "select count(*) from games where (w_id = $1 or b_id = $1) and ended = 1 and started_at is not null
+         and deleted_at is null",
+    )
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if games == 0 {
+        return reply(state, user_id, "You have no finished games.").await;
+    }
+    let pending: bool = sqlx::query_scalar("select exists (select 1 from exports where user_id = $1)")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+    if pending {
+        return reply(state, user_id, "Your archive is being prepared, it will follow shortly.").await;
+    }
+    if games <= MAX_GAMES_RIGHT_AWAY {
+        return send(&state.db, &state.client, &state.bot_username, user_id).await;
+    }
+    let notice = format!("Preparing an archive of your {}, it will follow shortly.", count_games(games));
+    let message = state.client.send_message(packed_chat(user_id), notice).await?;
+    sqlx::query("insert into exports (user_id, message_id) values ($1, $2)")
+        .bind(user_id)
+        .bind(message.id())
+        .execute(&state.db)
+        .await?;
+    info!("{user_id} asked for an archive of {games} games");
+    Ok(())
+}
+
+/// Builds the user's archive and sends it.
+async fn send(db: &Pool<Sqlite>, client: &Bot, bot_username: &str, user_id: i64) -> Result<()> {
+    let games: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where (w_id = $1 or b_id = $1) and ended = 1 and started_at is not null
+         and deleted_at is null order by id"
+    ))
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    let mut pgn = String::new();
+    for game in &games {
+        pgn.push_str(&game_pgn(db, Some(bot_username), game).await?);
+        pgn.push_str("\n\n");
+    }
+    let caption = format!("Your {} on @{bot_username}.", count_games(games.len() as i64));
+    let name = format!("tgpawn-{user_id}.pgn");
+    client.send_document(packed_chat(user_id), &name, pgn.into_bytes(), &caption).await?;
+    debug!("sent {user_id} an archive of {} games", games.len());
+    Ok(())
+}
+
+/// Builds and sends the archives asked for, oldest requests first.
+pub async fn sweep(db: &Pool<Sqlite>, client: &Bot, bot_username: &str) -> Result<()> {
+    let due: Vec<(i64, Option<i32>)> =
+        sqlx::query_as("select user_id, message_id from exports order by created_at, user_id limit $1")
+            .bind(EXPORTS_PER_SWEEP)
+            .fetch_all(db)
+            .await?;
+    for (user_id, message_id) in due {
+        // a failure that would repeat, e.g. an archive over the size limit,
+        // mustn't hold up the requests after it
+        let notice = match send(db, client, bot_username, user_id).await {
+            Ok(()) => "Your archive is ready.",
+            Err(e) => {
+                warn!("failed to send {user_id} their archive: {e}");
+                "Your archive couldn't be sent. /pgn <game> still sends single games."
+            }
+        };
+        sqlx::query("delete from exports where user_id = $1").bind(user_id).execute(db).await?;
+        if let Some(message_id) = message_id {
+            if let Err(e) = client.edit_message(packed_chat(user_id), message_id, notice).await {
+                warn!("failed to update {user_id}'s export notice: {e}");
+            }
+        }
+    }
+    Ok(())
+}
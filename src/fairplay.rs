@@ -0,0 +1,445 @@
+//! Fair play review: finished rated games are run through the engine, and
+//! players whose recent moves match it far more often, and lose far less,
+//! than is plausible for their rating are flagged for admins to look into.
+//! So are players who seem to throw games to lower their rating. Nobody is
+//! banned automatically.
+
+use crate::bot::Bot;
+use crate::engine::{Engine, Score};
+use crate::{
+    game_by_id, game_move_times, game_ucis, packed_chat, position_from_fen, puzzles, user_name, Game, Termination,
+};
+use anyhow::Result;
+use log::{info, warn};
+use shakmaty::fen::Fen;
+use shakmaty::uci::Uci;
+use shakmaty::{ByColor, Chess, Color, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+
+/// Games reviewed per run, so a backlog doesn't hog the engine.
+const REVIEW_BATCH: i64 = 3;
+
+/// Search time per position; enough to find the moves an assisted player
+/// would be shown.
+const REVIEW_MOVETIME: Duration = Duration::from_millis(100);
+
+/// Opening moves are skipped, everyone plays book moves there.
+const OPENING_PLIES: usize = 16;
+
+/// Positions already decided by this much are skipped: any reasonable move
+/// wins, and losing ones don't matter.
+const DECIDED_CP: i64 = 500;
+
+/// A single blunder counts as at most this much loss, so one hung mate
+/// doesn't drown out a game's worth of moves.
+const MAX_CP_LOSS: i64 = 1000;
+
+/// A move that leaves a position where the engine found at least this much
+/// for the player, with less than `MISSED_WIN_AFTER_CP` left, makes a puzzle
+/// for them.
+const MISSED_WIN_CP: i64 = 300;
+const MISSED_WIN_AFTER_CP: i64 = 100;
+
+/// Longest engine line kept for a puzzle, in plies.
+const PUZZLE_PLIES: usize = 5;
+
+/// A player's recent reviewed games looked at together.
+const RECENT_GAMES: i64 = 20;
+
+/// Fewer moves say too little to flag anyone.
+const MIN_MOVES: i64 = 150;
+
+/// Standard deviations above the expected match rate that get a player flagged.
+const MIN_Z: f64 = 4.0;
+
+/// Days of rated games looked at for sandbagging.
+const SANDBAG_DAYS: i64 = 30;
+
+/// A resignation after at most this many of the player's own moves, or this
+/// much of their own thinking time, counts as throwing the game.
+const QUICK_LOSS_MOVES: usize = 5;
+const QUICK_LOSS_THINK_MS: i64 = 30_000;
+
+/// Thrown games, the rating they cost and the wins against lower-rated
+/// players after them that get a player flagged.
+const MIN_QUICK_LOSSES: i64 = 3;
+const MIN_RATING_LOST: i64 = 100;
+const MIN_WINS_AFTER: i64 = 3;
+
+/// How often a player at this rating plays the engine's move, roughly.
+fn expected_match_rate(rating: i64) -> f64 {
+    (0.25 + (rating - 800) as f64 * 0.00018).clamp(0.25, 0.6)
+}
+
+/// A typical average centipawn loss at this rating.
+fn expected_cp_loss(rating: i64) -> f64 {
+    (180.0 - rating as f64 * 0.065).clamp(15.0, 120.0)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    moves: i64,
+    matches: i64,
+    cp_loss: i64,
+}
+
+/// A win the player to move had and missed, as a puzzle in the Lichess
+/// format.
+struct MissedWin {
+    ply: usize,
+    color: Color,
+    /// The position before the opponent's move that set it up.
+    fen: String,
+    /// That move, then the engine's line.
+    moves: Vec<String>,
+    themes: String,
+}
+
+/// Compares each side's moves with the engine's choices, and collects the
+/// wins they missed.
+async fn review_game(engine: &Engine, game: &Game, ucis: &[String]) -> Result<(ByColor<Tally>, Vec<MissedWin>)> {
+    let mut tallies = ByColor::<Tally>::default();
+    let mut missed = Vec::new();
+    let mut position = position_from_fen(game.start_fen());
+    let mut before_last: Option<Chess> = None;
+    for (ply, uci) in ucis.iter().enumerate() {
+        let Some(m) = uci.parse::<Uci>().ok().and_then(|m| m.to_move(&position).ok()) else {
+            warn!("cannot replay {uci} at ply {ply}, reviewing the moves before it");
+            break;
+        };
+        if ply >= OPENING_PLIES && position.legal_moves().len() > 1 {
+            let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+            let lines = engine.analyse(&fen, 1, REVIEW_MOVETIME, &[]).await?;
+            if let Some(best) = lines.first() {
+                let best_cp = best.score.centipawns();
+                let decided = best_cp.abs() > DECIDED_CP;
+                let played = if best.pv.first() == Some(uci) || decided && best_cp < MISSED_WIN_CP {
+                    None
+                } else {
+                    Some(engine.score_move(&fen, uci, REVIEW_MOVETIME).await?.centipawns())
+                };
+                if !decided {
+                    let tally = tallies.get_mut(position.turn());
+                    tally.moves += 1;
+                    match played {
+                        None => tally.matches += 1,
+                        Some(played) => tally.cp_loss += (best_cp - played).clamp(0, MAX_CP_LOSS),
+                    }
+                }
+                if let (Some(played), Some(before)) = (played, &before_last) {
+                    if best_cp >= MISSED_WIN_CP && played < MISSED_WIN_AFTER_CP {
+                        // the line ends on the player's move
+                        let plies = best.pv.len().min(PUZZLE_PLIES);
+                        let plies = plies - (plies + 1) % 2;
+                        let mut moves = vec![ucis[ply - 1].clone()];
+                        moves.extend(best.pv[..plies].iter().cloned());
+                        missed.push(MissedWin {
+                            ply,
+                            color: position.turn(),
+                            fen: Fen::from_position(before.clone(), EnPassantMode::Legal).to_string(),
+                            moves,
+                            themes: match best.score {
+                                Score::Mate(n) => format!("mate mateIn{n}"),
+                                Score::Cp(_) => "advantage".to_string(),
+                            },
+                        });
+                    }
+                }
+            }
+        }
+        before_last = Some(position.clone());
+        position.play_unchecked(&m);
+    }
+    Ok((tallies, missed))
+}
+
+/// Reviews a batch of finished rated games, then looks again at their
+/// players.
+pub async fn review(db: &Pool<Sqlite>, client: &Bot, engine: &Engine, admins: &[i64]) -> Result<()> {
+    // fog of war players can't see the whole board, the engine can
+    let ids: Vec<i64> = sqlx::query_scalar(
+        "select id from games g where ended and w_rating is not null and variant = 0 and deleted_at is null
+         and not exists (select 1 from engine_reviews r where r.game_id = g.id)
+         order by ended_at limit $1",
+    )
+    .bind(REVIEW_BATCH)
+    .fetch_all(db)
+    .await?;
+    for id in ids {
+        let Some(game) = game_by_id(db, id).await? else {
+            continue;
+        };
+        let (Some(w_id), Some(b_id), Some(w_rating), Some(b_rating)) =
+            (game.w_id, game.b_id, game.w_rating, game.b_rating)
+        else {
+            continue;
+        };
+        let (tallies, missed) = review_game(engine, &game, &game_ucis(db, id).await?).await?;
+        let players = [(w_id, w_rating, tallies.white), (b_id, b_rating, tallies.black)];
+        for (user_id, rating, tally) in players {
+            sqlx::query(
+                "insert into engine_reviews (game_id, user_id, rating, moves, matches, cp_loss)
+                 values ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(rating)
+            .bind(tally.moves)
+            .bind(tally.matches)
+            .bind(tally.cp_loss)
+            .execute(db)
+            .await?;
+        }
+        info!("reviewed game #{id} for fair play");
+        let mut saved = ByColor::<i64>::default();
+        for win in &missed {
+            let (owner, rating) = win.color.fold_wb((w_id, w_rating), (b_id, b_rating));
+            if puzzles::save_personal(db, owner, id, win.ply, rating, &win.fen, &win.moves, &win.themes).await? {
+                *saved.get_mut(win.color) += 1;
+            }
+        }
+        for (user_id, n) in [(w_id, saved.white), (b_id, saved.black)] {
+            if n > 0 {
+                let text = if n == 1 {
+                    format!("Game #{id} had a position where you missed a win. Try it with /puzzle mine.")
+                } else {
+                    format!("Game #{id} had {n} positions where you missed a win. Try them with /puzzle mine.")
+                };
+                client.send_message(packed_chat(user_id), text).await?;
+            }
+        }
+        for (user_id, _, _) in players {
+            check(db, client, admins, user_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Flags the player if their recent games look engine assisted. Games
+/// reviewed before an admin last cleared them aren't held against them again.
+async fn check(db: &Pool<Sqlite>, client: &Bot, admins: &[i64], user_id: i64) -> Result<()> {
+    let open: bool = sqlx::query_scalar(
+        "select exists (select 1 from cheat_flags where user_id = $1 and reviewed_at is null)",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+    if open {
+        return Ok(());
+    }
+    let (games, moves, matches, cp_loss, rating): (i64, i64, i64, i64, i64) = sqlx::query_as(
+        "select count(*), coalesce(sum(moves), 0), coalesce(sum(matches), 0), coalesce(sum(cp_loss), 0),
+            coalesce(cast(avg(rating) as integer), 0)
+         from (select r.* from engine_reviews r join games g on g.id = r.game_id
+            where r.user_id = $1
+            and g.ended_at > coalesce((select max(reviewed_at) from cheat_flags where user_id = $1), 0)
+            order by g.ended_at desc limit $2)",
+    )
+    .bind(user_id)
+    .bind(RECENT_GAMES)
+    .fetch_one(db)
+    .await?;
+    if moves < MIN_MOVES {
+        return Ok(());
+    }
+
+    let expected_rate = expected_match_rate(rating);
+    let match_rate = matches as f64 / moves as f64;
+    let z = (match_rate - expected_rate) / (expected_rate * (1.0 - expected_rate) / moves as f64).sqrt();
+    let avg_cp_loss = cp_loss as f64 / moves as f64;
+    if z < MIN_Z || avg_cp_loss >= expected_cp_loss(rating) / 2.0 {
+        return Ok(());
+    }
+
+    sqlx::query(
+        "insert into cheat_flags (user_id, games, match_rate, avg_cp_loss, rating) values ($1, $2, $3, $4, $5)",
+    )
+    .bind(user_id)
+    .bind(games)
+    .bind(match_rate)
+    .bind(avg_cp_loss)
+    .bind(rating)
+    .execute(db)
+    .await?;
+    warn!("flagged {user_id} for fair play: {match_rate:.2} match rate, z = {z:.1}, {avg_cp_loss:.0} cp loss");
+
+    let text = format!(
+        "Fair play: {} ({user_id}) matched the engine on {:.0}% of {moves} moves over {games} games, \
+         losing {avg_cp_loss:.0} centipawns a move. Around {:.0}% and {:.0} are usual at {rating}.\n\
+         See /admin flags, and /admin clear {user_id} to dismiss.",
+        user_name(db, user_id).await?,
+        match_rate * 100.0,
+        expected_rate * 100.0,
+        expected_cp_loss(rating),
+    );
+    notify_admins(db, client, admins, &text).await
+}
+
+/// Sends the text to the admins listed in `ADMINS` and the promoted ones.
+pub async fn notify_admins(db: &Pool<Sqlite>, client: &Bot, admins: &[i64], text: &str) -> Result<()> {
+    let mut ids: Vec<i64> = sqlx::query_scalar("select id from users where admin").fetch_all(db).await?;
+    ids.extend_from_slice(admins);
+    ids.sort_unstable();
+    ids.dedup();
+    for id in ids {
+        client.send_message(packed_chat(id), text).await?;
+    }
+    Ok(())
+}
+
+/// Whether the player resigned the game having hardly played it.
+async fn quick_loss(db: &Pool<Sqlite>, game: &Game, color: Color) -> Result<bool> {
+    if game.termination.and_then(Termination::from_i64) != Some(Termination::Resign) {
+        return Ok(false);
+    }
+    let times = game_move_times(db, game).await?;
+    let own: Vec<i64> = times.iter().skip(color.fold_wb(0, 1)).step_by(2).map(|&(spent, _)| spent).collect();
+    let mut think_ms: i64 = own.iter().sum();
+    // the time they sat on their last turn before resigning
+    if let (Some(ended_at), Some(started_at), true) =
+        (game.ended_at, game.started_at, (times.len() % 2 == 0) == color.is_white())
+    {
+        let last_move_ms: Option<i64> = sqlx::query_scalar("select max(played_at) from moves where game_id = $1")
+            .bind(game.id)
+            .fetch_one(db)
+            .await?;
+        think_ms += (ended_at * 1000 - last_move_ms.unwrap_or(started_at * 1000)).max(0);
+    }
+    Ok(own.len() <= QUICK_LOSS_MOVES || think_ms <= QUICK_LOSS_THINK_MS)
+}
+
+/// Looks for sandbagging by everyone who finished a rated game in the last
+/// day.
+pub async fn sweep_sandbagging(db: &Pool<Sqlite>, client: &Bot, admins: &[i64]) -> Result<()> {
+    let users: Vec<i64> = sqlx::query_scalar(
+        "select w_id from games where ended and w_rating is not null and ended_at > unixepoch('now', '-1 day')
+         union select b_id from games where ended and w_rating is not null and ended_at > unixepoch('now', '-1 day')",
+    )
+    .fetch_all(db)
+    .await?;
+    for user_id in users {
+        check_sandbagging(db, client, admins, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Flags the player if they recently threw rated games and then went on to
+/// beat lower-rated players. As with engine flags, games before an admin last
+/// cleared them don't count.
+async fn check_sandbagging(db: &Pool<Sqlite>, client: &Bot, admins: &[i64], user_id: i64) -> Result<()> {
+    let open: bool = sqlx::query_scalar(
+        "select exists (select 1 from sandbag_flags where user_id = $1 and reviewed_at is null)",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+    if open {
+        return Ok(());
+    }
+    let ids: Vec<i64> = sqlx::query_scalar(
+        "select id from games where ended and w_rating is not null and (w_id = $1 or b_id = $1)
+         and ended_at > max(unixepoch('now', printf('-%d days', $2)),
+            coalesce((select max(reviewed_at) from sandbag_flags where user_id = $1), 0))
+         order by ended_at",
+    )
+    .bind(user_id)
+    .bind(SANDBAG_DAYS)
+    .fetch_all(db)
+    .await?;
+
+    let (mut quick_losses, mut rating_lost, mut wins) = (0, 0, 0);
+    for id in ids {
+        let Some(game) = game_by_id(db, id).await? else {
+            continue;
+        };
+        let color = if game.w_id == Some(user_id) { Color::White } else { Color::Black };
+        let (rating, diff, opponent_rating) = color.fold_wb(
+            (game.w_rating, game.w_rating_diff, game.b_rating),
+            (game.b_rating, game.b_rating_diff, game.w_rating),
+        );
+        match game.winner.map(|white_won| white_won == color.is_white()) {
+            Some(true) if quick_losses > 0 && opponent_rating < rating => wins += 1,
+            Some(false) if quick_loss(db, &game, color).await? => {
+                quick_losses += 1;
+                rating_lost -= diff.unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    if quick_losses < MIN_QUICK_LOSSES || rating_lost < MIN_RATING_LOST || wins < MIN_WINS_AFTER {
+        return Ok(());
+    }
+
+    sqlx::query("insert into sandbag_flags (user_id, quick_losses, rating_lost, wins) values ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(quick_losses)
+        .bind(rating_lost)
+        .bind(wins)
+        .execute(db)
+        .await?;
+    warn!("flagged {user_id} for sandbagging: {quick_losses} quick losses for {rating_lost} rating, then {wins} wins");
+
+    let text = format!(
+        "Fair play: {} ({user_id}) resigned {quick_losses} rated games after hardly playing them in the last \
+         {SANDBAG_DAYS} days, losing {rating_lost} rating, then won {wins} games against lower-rated players. \
+         Possible sandbagging.\nSee /admin flags, and /admin clear {user_id} to dismiss.",
+        user_name(db, user_id).await?,
+    );
+    notify_admins(db, client, admins, &text).await
+}
+
+/// Flags no admin has dismissed yet, for `/admin flags`.
+pub async fn open_flags(db: &Pool<Sqlite>) -> Result<String> {
+    let flags: Vec<(i64, i64, f64, f64, i64)> = sqlx::query_as(
+        "select user_id, games, match_rate, avg_cp_loss, rating from cheat_flags
+         where reviewed_at is null order by created_at",
+    )
+    .fetch_all(db)
+    .await?;
+    let sandbaggers: Vec<(i64, i64, i64, i64)> = sqlx::query_as(
+        "select user_id, quick_losses, rating_lost, wins from sandbag_flags
+         where reviewed_at is null order by created_at",
+    )
+    .fetch_all(db)
+    .await?;
+    if flags.is_empty() && sandbaggers.is_empty() {
+        return Ok("No open fair play flags.".to_string());
+    }
+    let mut text = "Open fair play flags:".to_string();
+    for (user_id, games, match_rate, avg_cp_loss, rating) in flags {
+        text = format!(
+            "{text}\n{} ({user_id}) at {rating}: {:.0}% engine moves, {avg_cp_loss:.0} cp loss over {games} games",
+            user_name(db, user_id).await?,
+            match_rate * 100.0,
+        );
+    }
+    for (user_id, quick_losses, rating_lost, wins) in sandbaggers {
+        text = format!(
+            "{text}\n{} ({user_id}): {quick_losses} quick losses for -{rating_lost}, then {wins} wins, possible sandbagging",
+            user_name(db, user_id).await?,
+        );
+    }
+    Ok(text)
+}
+
+/// Dismisses the user's open flags, for `/admin clear`.
+pub async fn clear(db: &Pool<Sqlite>, admin_id: i64, user_id: i64) -> Result<String> {
+    let mut cleared = 0;
+    for table in ["cheat_flags", "sandbag_flags"] {
+        cleared += sqlx::query(&format!(
+            "update {table} set reviewed_by = $2, reviewed_at = unixepoch() where user_id = $1 and reviewed_at is null"
+        ))
+        .bind(user_id)
+        .bind(admin_id)
+        .execute(db)
+        .await?
+        .rows_affected();
+    }
+    Ok(if cleared > 0 {
+        info!("{admin_id} cleared the fair play flag on {user_id}");
+        format!("Dismissed the open fair play flags on {}.", user_name(db, user_id).await?)
+    } else {
+        format!("No open fair play flag on {user_id}.")
+    })
+}
@@ -0,0 +1,243 @@
+//! The featured game channel: an admin connects a Telegram channel, and the
+//! bot keeps one live game there on a pinned board message that is edited
+//! after every move. Admins pick the game, such as a match final, or else
+//! the highest rated game in progress is shown.
+
+use crate::bot::Bot;
+use crate::diagram::Theme;
+use crate::{analysis, clock, game_by_id, game_ucis, position_from_fen, rating, san_moves, user_name, Game, State, Variant};
+use anyhow::Result;
+use grammers_session::{PackedChat, PackedType};
+use log::{debug, info};
+use shakmaty::Color;
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str = "/admin feature [channel <@channel or id> | <game> | auto | off]";
+
+#[derive(Debug, sqlx::FromRow)]
+struct Channel {
+    chat_id: i64,
+    access_hash: Option<i64>,
+    game_id: Option<i64>,
+    message_id: Option<i32>,
+}
+
+impl Channel {
+    fn chat(&self) -> PackedChat {
+        PackedChat {
+            id: self.chat_id,
+            ty: PackedType::Broadcast,
+            access_hash: self.access_hash,
+        }
+    }
+}
+
+async fn channel(db: &Pool<Sqlite>) -> Result<Option<Channel>> {
+    Ok(
+        sqlx::query_as("select chat_id, access_hash, game_id, message_id from featured_channel")
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+/// Handles `/admin feature`, returning the reply.
+pub async fn admin(state: &mut State, admin_id: i64, args: &str) -> Result<String> {
+    let (command, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    match (command, command.parse::<i64>()) {
+        ("", _) => status(&state.db).await,
+        ("channel", _) => connect(state, admin_id, rest.trim()).await,
+        ("off", _) => {
+            let Some(channel) = channel(&state.db).await? else {
+                return Ok("No channel is connected.".to_string());
+            };
+            unpin(&state.client, &channel).await;
+            sqlx::query("delete from featured_channel").execute(&state.db).await?;
+            info!("{admin_id} disconnected the featured channel");
+            Ok("The featured channel is disconnected.".to_string())
+        }
+        ("auto", _) => {
+            let Some(channel) = channel(&state.db).await? else {
+                return Ok(format!("No channel is connected.\nUsage: {USAGE}"));
+            };
+            unpin(&state.client, &channel).await;
+            sqlx::query("update featured_channel set game_id = null, message_id = null")
+                .execute(&state.db)
+                .await?;
+            pick(&state.db, &state.client).await?;
+            Ok("The highest rated game in progress is featured now.".to_string())
+        }
+        (_, Ok(game_id)) => {
+            let Some(channel) = channel(&state.db).await? else {
+                return Ok(format!("No channel is connected.\nUsage: {USAGE}"));
+            };
+            let game = game_by_id(&state.db, game_id).await?;
+            let Some(game) = game.filter(|g| !g.ended && g.w_id.is_some() && g.b_id.is_some()) else {
+                return Ok(format!("No game #{game_id} in progress."));
+            };
+            // spectators would see through the fog
+            if game.variant() != Variant::Standard {
+                return Ok("Fog of war games can't be featured.".to_string());
+            }
+            show(&state.db, &state.client, &channel, &game).await?;
+            info!("{admin_id} featured game {game_id}");
+            Ok(format!("Game #{game_id} is featured now."))
+        }
+        _ => Ok(format!("Usage: {USAGE}")),
+    }
+}
+
+async fn status(db: &Pool<Sqlite>) -> Result<String> {
+    let Some(channel) = channel(db).await? else {
+        return Ok(format!("No channel is connected.\nUsage: {USAGE}"));
+    };
+    Ok(match channel.game_id {
+        Some(game_id) => format!("Channel {} is showing game #{game_id}.", channel.chat_id),
+        None => format!("Channel {} is waiting for a game.", channel.chat_id),
+    })
+}
+
+/// Connects the channel, given as `@username` when running on Telegram or as
+/// a bare id. The bot has to be an admin of the channel to post and pin.
+async fn connect(state: &mut State, admin_id: i64, who: &str) -> Result<String> {
+    let (chat_id, access_hash) = match (who.strip_prefix('@'), who.parse::<i64>()) {
+        (_, Ok(id)) => (id, None),
+        (Some(username), _) => {
+            let Some(client) = state.client.telegram() else {
+                return Ok("Channels can only be looked up by username on Telegram, send its id.".to_string());
+            };
+            match client.resolve_username(username).await? {
+                Some(chat) => {
+                    let packed = chat.pack();
+                    if packed.ty != PackedType::Broadcast {
+                        return Ok(format!("@{username} is not a channel."));
+                    }
+                    (packed.id, packed.access_hash)
+                }
+                None => return Ok(format!("No channel @{username}.")),
+            }
+        }
+        _ => return Ok(format!("Usage: {USAGE}")),
+    };
+    if let Some(old) = channel(&state.db).await? {
+        unpin(&state.client, &old).await;
+    }
+    sqlx::query(
+        "insert into featured_channel (id, chat_id, access_hash) values (1, $1, $2)
+         on conflict (id) do update set chat_id = excluded.chat_id, access_hash = excluded.access_hash,
+            game_id = null, message_id = null",
+    )
+    .bind(chat_id)
+    .bind(access_hash)
+    .execute(&state.db)
+    .await?;
+    info!("{admin_id} connected featured channel {chat_id}");
+    pick(&state.db, &state.client).await?;
+    Ok(format!("Channel {who} is connected, live games will be shown there."))
+}
+
+async fn unpin(client: &Bot, channel: &Channel) {
+    if let Some(message_id) = channel.message_id {
+        if let Err(e) = client.unpin_message(channel.chat(), message_id).await {
+            debug!("cannot unpin featured board: {e}");
+        }
+    }
+}
+
+/// The board message for the game: players, position from White's side and
+/// the last move.
+async fn board_text(db: &Pool<Sqlite>, game: &Game) -> Result<String> {
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(String::new());
+    };
+    let player = |id: i64| async move {
+        let (rating, rated_games): (f64, i64) = sqlx::query_as("select rating, rated_games from users where id = $1")
+            .bind(id)
+            .fetch_one(db)
+            .await?;
+        anyhow::Ok(format!("{} ({})", user_name(db, id).await?, rating::display(rating, rated_games)))
+    };
+    let position = position_from_fen(&game.fen);
+    let mut text = format!(
+        "Game #{}: {} – {}\n{}",
+        game.id,
+        player(w_id).await?,
+        player(b_id).await?,
+        analysis::show(&position, Color::White, Theme::default())
+    );
+    let sans = san_moves(game.start_fen(), &game_ucis(db, game.id).await?);
+    if let Some(san) = sans.last() {
+        let ply = sans.len() - 1;
+        let dots = if ply.is_multiple_of(2) { "." } else { "..." };
+        text = format!("{text}\nLast move {}{dots} {san}", ply / 2 + 1);
+    }
+    if game.ended {
+        text = format!("{text}\nResult {}", game.result().replace("1/2", "½"));
+    } else if let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(clock::now_ms()) {
+        text = format!("{text}\n{}", clock::format_clocks(w_clock_ms, b_clock_ms));
+    }
+    Ok(text)
+}
+
+/// Posts the game's board in the channel and pins it in place of the last one.
+async fn show(db: &Pool<Sqlite>, client: &Bot, channel: &Channel, game: &Game) -> Result<()> {
+    unpin(client, channel).await;
+    let message = client.send_message(channel.chat(), board_text(db, game).await?).await?;
+    if let Err(e) = client.pin_message(channel.chat(), message.id()).await {
+        debug!("cannot pin featured board: {e}");
+    }
+    sqlx::query("update featured_channel set game_id = $1, message_id = $2")
+        .bind(game.id)
+        .bind(message.id())
+        .execute(db)
+        .await?;
+    debug!("featuring game {}", game.id);
+    Ok(())
+}
+
+/// Features the highest rated game in progress if the channel has none.
+pub async fn pick(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let Some(channel) = channel(db).await?.filter(|c| c.game_id.is_none()) else {
+        return Ok(());
+    };
+    let game_id: Option<i64> = sqlx::query_scalar(
+        "select g.id from games g join users w on w.id = g.w_id join users b on b.id = g.b_id
+         where not g.ended and g.variant = $1
+         order by w.rating + b.rating desc, g.id limit 1",
+    )
+    .bind(Variant::Standard as i64)
+    .fetch_optional(db)
+    .await?;
+    if let Some(game) = game_id.map(|id| game_by_id(db, id)) {
+        if let Some(game) = game.await? {
+            show(db, client, &channel, &game).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Brings the channel's board up to date after a move in the game.
+pub async fn game_moved(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some(channel) = channel(db).await?.filter(|c| c.game_id == Some(game_id)) else {
+        return Ok(());
+    };
+    let (Some(message_id), Some(game)) = (channel.message_id, game_by_id(db, game_id).await?) else {
+        return Ok(());
+    };
+    if let Err(e) = client.edit_message(channel.chat(), message_id, board_text(db, &game).await?).await {
+        debug!("cannot update featured board: {e}");
+    }
+    Ok(())
+}
+
+/// Shows the result of the featured game and moves on to the next one.
+pub async fn game_finished(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some(channel) = channel(db).await?.filter(|c| c.game_id == Some(game_id)) else {
+        return Ok(());
+    };
+    game_moved(db, client, game_id).await?;
+    unpin(client, &channel).await;
+    sqlx::query("update featured_channel set game_id = null, message_id = null")
+        .execute(db)
+        .await?;
+    pick(db, client).await
+}
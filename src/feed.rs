@@ -0,0 +1,148 @@
+//! Live game feed: `/game/<id>/events` streams a game's moves and its end as
+//! server-sent events, for the web viewer and external live boards. Moves
+//! come from chat handlers and scheduler jobs alike, so the feed watches the
+//! game's row rather than hooking into each of them. A corrected move comes
+//! as an `undo` event back to the ply before it, then the new `move`.
+
+use crate::{game_by_id, game_ucis, position_from_fen, web, Game, Termination, Variant};
+use anyhow::Result;
+use serde_json::json;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// How often a streamed game is checked for new moves.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often an idle stream gets a comment, so closed connections are
+/// noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Streams the game's moves until it ends or the client goes away. A client
+/// reconnecting with `Last-Event-ID` gets the moves after that ply, a new one
+/// the current position first.
+pub async fn stream(mut stream: TcpStream, db: &Pool<Sqlite>, id: i64, last_ply: Option<i64>) -> Result<()> {
+    let Some(game) = game_by_id(db, id).await? else {
+        return not_found(stream).await;
+    };
+    // a live fog of war game would give away what the players can't see
+    if game.variant() == Variant::FogOfWar && !game.ended {
+        return not_found(stream).await;
+    }
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+              Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    let mut sent = match last_ply {
+        Some(ply) => ply.clamp(0, game.plies),
+        None => {
+            let data = json!({ "ply": game.plies, "fen": game.fen });
+            send(&mut stream, "position", Some(game.plies), &data.to_string()).await?;
+            game.plies
+        }
+    };
+    let mut last_uci = last_move(db, id).await?;
+    let mut idle_since = Instant::now();
+    loop {
+        let row: Option<(i64, bool, Option<String>)> = sqlx::query_as(
+            "select plies, coalesce(ended, 0), (select uci from moves where game_id = games.id and ply = games.plies - 1)
+             from games where id = $1 and deleted_at is null",
+        )
+        .bind(id)
+        .fetch_optional(db)
+        .await?;
+        let Some((plies, ended, uci)) = row else {
+            return Ok(());
+        };
+        // a move corrected by editing its message is taken back before the
+        // new one is played, perhaps between two polls
+        if plies < sent || (plies == sent && uci != last_uci) {
+            sent = if plies < sent { plies } else { plies - 1 };
+            let data = json!({ "ply": sent });
+            send(&mut stream, "undo", Some(sent), &data.to_string()).await?;
+        }
+        last_uci = uci;
+        if plies > sent || ended {
+            let Some(game) = game_by_id(db, id).await? else {
+                return Ok(());
+            };
+            sent = send_moves(&mut stream, db, &game, sent).await?;
+            if game.ended {
+                let data = json!({
+                    "result": game.result(),
+                    "termination": game.termination.and_then(Termination::from_i64).map(|t| t.reason().to_lowercase()),
+                });
+                return send(&mut stream, "end", None, &data.to_string()).await;
+            }
+            idle_since = Instant::now();
+        } else if idle_since.elapsed() >= KEEPALIVE_INTERVAL {
+            stream.write_all(b": keepalive\n\n").await?;
+            idle_since = Instant::now();
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// The game's last move, to notice it being replaced.
+async fn last_move(db: &Pool<Sqlite>, id: i64) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar("select uci from moves where game_id = $1 order by ply desc limit 1")
+        .bind(id)
+        .fetch_optional(db)
+        .await?)
+}
+
+/// Sends a `move` event for each ply after `sent`, returning the last one
+/// sent. The clocks go with the last move.
+async fn send_moves(stream: &mut TcpStream, db: &Pool<Sqlite>, game: &Game, sent: i64) -> Result<i64> {
+    let ucis = game_ucis(db, game.id).await?;
+    let mut position = position_from_fen(game.start_fen());
+    let mut last = sent;
+    for (ply, uci) in (1..).zip(&ucis) {
+        let Some(m) = uci.parse::<Uci>().ok().and_then(|m| m.to_move(&position).ok()) else {
+            break;
+        };
+        let san = San::from_move(&position, &m).to_string();
+        position.play_unchecked(&m);
+        if ply <= sent {
+            continue;
+        }
+        let mut data = json!({
+            "ply": ply,
+            "uci": uci,
+            "san": san,
+            "fen": Fen::from_position(position.clone(), EnPassantMode::Legal).to_string(),
+        });
+        if ply as usize == ucis.len() && game.initial_ms.is_some() {
+            data["clock"] = json!({
+                "white_ms": game.w_clock_ms,
+                "black_ms": game.b_clock_ms,
+                "turn_started_ms": game.turn_started_ms,
+            });
+        }
+        send(stream, "move", Some(ply), &data.to_string()).await?;
+        last = ply;
+    }
+    Ok(last)
+}
+
+async fn send(stream: &mut TcpStream, event: &str, id: Option<i64>, data: &str) -> Result<()> {
+    let id = id.map_or(String::new(), |id| format!("id: {id}\n"));
+    stream
+        .write_all(format!("event: {event}\n{id}data: {data}\n\n").as_bytes())
+        .await?;
+    Ok(())
+}
+
+async fn not_found(mut stream: TcpStream) -> Result<()> {
+    let response = web::response("404 Not Found", "text/plain", "no such game");
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
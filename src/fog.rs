@@ -0,0 +1,34 @@
+use crate::diagram::{self, Theme};
+use shakmaty::{attacks, Bitboard, Chess, Color, Position, Rank, Role};
+
+/// Squares `color` can see: those its pieces stand on or attack, and those
+/// its pawns could advance to.
+pub fn visible(position: &Chess, color: Color) -> Bitboard {
+    let board = position.board();
+    let ours = board.by_color(color);
+    let mut seen = ours;
+    for square in ours {
+        let Some(piece) = board.piece_at(square) else {
+            continue;
+        };
+        seen |= attacks::attacks(square, piece, board.occupied());
+        if piece.role == Role::Pawn {
+            if let Some(ahead) = square.offset(if color.is_white() { 8 } else { -8 }) {
+                seen.add(ahead);
+                let home = if color.is_white() { Rank::Second } else { Rank::Seventh };
+                if square.rank() == home && !board.occupied().contains(ahead) {
+                    if let Some(two_ahead) = ahead.offset(if color.is_white() { 8 } else { -8 }) {
+                        seen.add(two_ahead);
+                    }
+                }
+            }
+        }
+    }
+    seen
+}
+
+/// Text diagram of the board as `color` sees it, from their side, with
+/// unseen squares fogged over.
+pub fn render(position: &Chess, color: Color, theme: Theme) -> String {
+    diagram::render(position.board(), color, visible(position, color), theme)
+}
@@ -0,0 +1,170 @@
+//! Following players: followers hear when a followed player's game starts,
+//! with a link to watch it, and how it ended.
+
+use crate::bot::Bot;
+use crate::{find_user, game_by_id, packed_chat, settings, user_name, Game, State, Termination, Variant};
+use anyhow::Result;
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+
+const USAGE: &str = "Usage: /follow <user id or @username> [all|starts|results], /unfollow <user>, /following";
+
+pub async fn on_follow(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let mut words = args.split_whitespace();
+    let (Some(who), mode, None) = (words.next(), words.next(), words.next()) else {
+        return reply(state, user_id, USAGE.to_string()).await;
+    };
+    let (notify_start, notify_end) = match mode.unwrap_or("all") {
+        "all" => (true, true),
+        "starts" => (true, false),
+        "results" => (false, true),
+        _ => return reply(state, user_id, USAGE.to_string()).await,
+    };
+    let Some(followed) = find_user(&state.db, who).await?.filter(|&id| id != user_id) else {
+        return reply(state, user_id, format!("No player {who}.")).await;
+    };
+    sqlx::query(
+        "insert into follows (follower_id, followed_id, notify_start, notify_end) values ($1, $2, $3, $4)
+         on conflict (follower_id, followed_id) do update set notify_start = excluded.notify_start, notify_end = excluded.notify_end",
+    )
+    .bind(user_id)
+    .bind(followed)
+    .bind(notify_start)
+    .bind(notify_end)
+    .execute(&state.db)
+    .await?;
+    let what = describe_mode(notify_start, notify_end);
+    let text = format!("Following {}: you'll hear about {what}.", user_name(&state.db, followed).await?);
+    reply(state, user_id, text).await
+}
+
+pub async fn on_unfollow(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let who = args.trim();
+    let Some(followed) = find_user(&state.db, who).await? else {
+        return reply(state, user_id, format!("No player {who}.")).await;
+    };
+    let removed = sqlx::query("delete from follows where follower_id = $1 and followed_id = $2")
+        .bind(user_id)
+        .bind(followed)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+    let name = user_name(&state.db, followed).await?;
+    let text = if removed > 0 {
+        format!("No longer following {name}.")
+    } else {
+        format!("You are not following {name}.")
+    };
+    reply(state, user_id, text).await
+}
+
+pub async fn on_following(state: &mut State, user_id: i64) -> Result<()> {
+    let followed: Vec<(i64, bool, bool)> = sqlx::query_as(
+        "select followed_id, notify_start, notify_end from follows where follower_id = $1 order by created_at",
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+    if followed.is_empty() {
+        return reply(state, user_id, format!("You are not following anyone.\n{USAGE}")).await;
+    }
+    let mut text = "You are following:".to_string();
+    for (id, notify_start, notify_end) in followed {
+        text = format!(
+            "{text}\n{} ({id}), {}",
+            user_name(&state.db, id).await?,
+            describe_mode(notify_start, notify_end)
+        );
+    }
+    reply(state, user_id, text).await
+}
+
+async fn reply(state: &State, user_id: i64, text: String) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+fn describe_mode(notify_start: bool, notify_end: bool) -> &'static str {
+    match (notify_start, notify_end) {
+        (true, true) => "games starting and results",
+        (true, false) => "games starting",
+        (false, true) => "results",
+        (false, false) => "nothing",
+    }
+}
+
+/// Followers of either player who want this kind of notification, each with
+/// the player they follow. Someone following both hears about it once, and
+/// the players themselves and those who turned notifications off not at all.
+async fn followers(db: &Pool<Sqlite>, game: &Game, start: bool) -> Result<BTreeMap<i64, (i64, i64)>> {
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(BTreeMap::new());
+    };
+    let mut followers = BTreeMap::new();
+    for (player, opponent) in [(w_id, b_id), (b_id, w_id)] {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            "select follower_id from follows where followed_id = $1
+             and case when $2 then notify_start else notify_end end",
+        )
+        .bind(player)
+        .bind(start)
+        .fetch_all(db)
+        .await?;
+        for id in ids.into_iter().filter(|&id| id != w_id && id != b_id) {
+            if !followers.contains_key(&id) && settings::notifications(db, id).await? {
+                followers.insert(id, (player, opponent));
+            }
+        }
+    }
+    Ok(followers)
+}
+
+/// Tells followers of either player that the game started.
+pub async fn game_started(db: &Pool<Sqlite>, client: &Bot, public_url: Option<&str>, game_id: i64) -> Result<()> {
+    let Some(game) = game_by_id(db, game_id).await? else {
+        return Ok(());
+    };
+    for (follower, (player, opponent)) in followers(db, &game, true).await? {
+        let mut text = format!(
+            "{} started game #{} against {}.",
+            user_name(db, player).await?,
+            game.id,
+            user_name(db, opponent).await?
+        );
+        // spectators would see through the fog
+        if let (Some(url), Variant::Standard) = (public_url, game.variant()) {
+            text = format!("{text}\nWatch: {url}/game/{}", game.id);
+        }
+        client.send_message(packed_chat(follower), text).await?;
+    }
+    Ok(())
+}
+
+/// Tells followers of either player how the game ended.
+pub async fn game_finished(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some(game) = game_by_id(db, game_id).await? else {
+        return Ok(());
+    };
+    let reason = game
+        .termination
+        .and_then(Termination::from_i64)
+        .map_or("Unknown", Termination::reason);
+    let followers = followers(db, &game, false).await?;
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(());
+    };
+    if followers.is_empty() {
+        return Ok(());
+    }
+    let text = format!(
+        "Game #{}: {} – {} {}, {reason}",
+        game.id,
+        user_name(db, w_id).await?,
+        user_name(db, b_id).await?,
+        game.result().replace("1/2", "½")
+    );
+    for follower in followers.keys() {
+        client.send_message(packed_chat(*follower), text.as_str()).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! Guess the move: the bot replays a master game and the user guesses each
+//! move of one side. An exact match scores full points; with an engine
+//! configured, other moves score by how little they give away.
+
+use crate::bot::Bot;
+use crate::engine::{self, Engine};
+use crate::{diagram, ongoing_game, packed_chat, parse_move, parse_typed_move, pgn, settings, training, State};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use rand::seq::SliceRandom;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{Bitboard, CastlingMode, Chess, Color, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+
+const EXACT_POINTS: i64 = 5;
+
+/// Points for a move that isn't the master's, by how many centipawns worse
+/// than it the engine rates it at most.
+const CLOSE_POINTS: &[(i64, i64)] = &[(0, 4), (30, 3), (100, 1)];
+
+const COLUMNS: &str = "id, user_id, game_id, white, ply, points, guesses, matched";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Session {
+    id: i64,
+    user_id: i64,
+    game_id: i64,
+    white: bool,
+    ply: i64,
+    points: i64,
+    guesses: i64,
+    matched: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct MasterGame {
+    white: String,
+    black: String,
+    event: Option<String>,
+    date: Option<String>,
+    result: String,
+    moves: String,
+}
+
+impl MasterGame {
+    fn line(&self) -> Vec<&str> {
+        self.moves.split_whitespace().collect()
+    }
+
+    fn player(&self, color: Color) -> &str {
+        if color.is_white() {
+            &self.white
+        } else {
+            &self.black
+        }
+    }
+
+    /// The position after `ply` moves, and the last of them in SAN.
+    fn position_at(&self, ply: usize) -> Result<(Chess, Option<String>)> {
+        let mut position = Chess::default();
+        let mut last = None;
+        for uci in self.line().into_iter().take(ply) {
+            let m = uci.parse::<Uci>()?.to_move(&position).context("illegal stored move")?;
+            last = Some(San::from_move(&position, &m).to_string());
+            position.play_unchecked(&m);
+        }
+        Ok((position, last))
+    }
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Session>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from guess_sessions where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+pub async fn on_guess(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let side = match args.trim() {
+        "stop" => {
+            return match running(&state.db, user_id).await? {
+                Some(session) => end(&state.db, &state.client, &session, "Stopped.").await,
+                None => {
+                    state
+                        .client
+                        .send_message(packed_chat(user_id), "You are not guessing moves.")
+                        .await?;
+                    Ok(())
+                }
+            };
+        }
+        "" => None,
+        "white" => Some(Color::White),
+        "black" => Some(Color::Black),
+        _ => {
+            state
+                .client
+                .send_message(packed_chat(user_id), "Usage: /guess [white|black|stop]")
+                .await?;
+            return Ok(());
+        }
+    };
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state
+            .client
+            .send_message(packed_chat(user_id), "Finish your game first.")
+            .await?;
+        return Ok(());
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        state
+            .client
+            .send_message(packed_chat(user_id), format!("Finish your {what} first."))
+            .await?;
+        return Ok(());
+    }
+
+    let Some((game_id, game)) = random_game(&state.db).await? else {
+        state
+            .client
+            .send_message(packed_chat(user_id), "There are no master games to replay.")
+            .await?;
+        return Ok(());
+    };
+    // by default, guess the winner's moves
+    let side = side.unwrap_or(match game.result.as_str() {
+        "0-1" => Color::Black,
+        "1-0" => Color::White,
+        _ => *[Color::White, Color::Black].choose(&mut rand::thread_rng()).expect("not empty"),
+    });
+    let ply = if side.is_white() { 0 } else { 1 };
+    sqlx::query("insert into guess_sessions (user_id, game_id, white, ply) values ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(game_id)
+        .bind(side.is_white())
+        .bind(ply)
+        .execute(&state.db)
+        .await?;
+
+    let mut text = format!("Guess the move: {} – {}", game.white, game.black);
+    if let Some(event) = &game.event {
+        text = format!("{text}, {event}");
+    }
+    if let Some(year) = game.date.as_deref().and_then(|d| d.split('.').next()).filter(|y| !y.contains('?')) {
+        text = format!("{text} {year}");
+    }
+    text = format!("{text}. You play {}'s moves.", game.player(side));
+    let (position, last) = game.position_at(ply as usize)?;
+    text = match last {
+        Some(last) => format!("{text}\n{} opened {last}.", game.player(!side)),
+        None => text,
+    };
+    let theme = settings::theme(&state.db, user_id).await?;
+    text = format!("{text}\n{}", diagram::render(position.board(), side, Bitboard::FULL, theme));
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn random_game(db: &Pool<Sqlite>) -> Result<Option<(i64, MasterGame)>> {
+    let id: Option<i64> = sqlx::query_scalar("select id from master_games order by random() limit 1")
+        .fetch_optional(db)
+        .await?;
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    Ok(Some((id, master_game(db, id).await?)))
+}
+
+async fn master_game(db: &Pool<Sqlite>, id: i64) -> Result<MasterGame> {
+    Ok(
+        sqlx::query_as("select white, black, event, date, result, moves from master_games where id = $1")
+            .bind(id)
+            .fetch_one(db)
+            .await?,
+    )
+}
+
+/// Points for a guess other than the master's move, and a remark on it.
+async fn rate_guess(engine: &Engine, position: &Chess, guess: &str, actual: &str) -> Result<(i64, String)> {
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let movetime = engine::DEFAULT_MOVETIME;
+    let guess_score = engine.score_move(&fen, guess, movetime).await?.centipawns();
+    let actual_score = engine.score_move(&fen, actual, movetime).await?.centipawns();
+    let loss = actual_score - guess_score;
+    let points = CLOSE_POINTS
+        .iter()
+        .find(|(max_loss, _)| loss <= *max_loss)
+        .map_or(0, |(_, points)| *points);
+    let mut remark = if loss <= 0 {
+        "The engine likes your move at least as much.".to_string()
+    } else {
+        format!("The engine rates your move {:.2} pawns worse.", loss as f64 / 100.0)
+    };
+    let best = engine.analyse(&fen, 1, movetime, &[]).await?;
+    let best = best.first().and_then(|line| line.pv.first()).filter(|&best| best != guess && best != actual);
+    if let Some(m) = best.and_then(|uci| uci.parse::<Uci>().ok()).and_then(|uci| uci.to_move(position).ok()) {
+        remark = format!("{remark} Its own choice is {}.", San::from_move(position, &m));
+    }
+    Ok((points, remark))
+}
+
+pub async fn on_move(state: &mut State, mut session: Session, notation: &str) -> Result<()> {
+    let chat = packed_chat(session.user_id);
+    let game = master_game(&state.db, session.game_id).await?;
+    let line = game.line();
+    let ply = session.ply as usize;
+    let (position, _) = game.position_at(ply)?;
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+    let guess = m.to_uci(CastlingMode::Standard).to_string();
+    let actual = line[ply];
+    let actual_san = actual
+        .parse::<Uci>()
+        .ok()
+        .and_then(|uci| uci.to_move(&position).ok())
+        .map(|m| San::from_move(&position, &m).to_string())
+        .unwrap_or_default();
+    let player = game.player(position.turn());
+
+    session.guesses += 1;
+    let mut text = if guess == actual {
+        session.matched += 1;
+        session.points += EXACT_POINTS;
+        format!("✓ {actual_san}, just like {player}! +{EXACT_POINTS}")
+    } else {
+        let mut text = format!("✗ {player} played {actual_san}.");
+        if let Some(engine) = &state.engine {
+            match rate_guess(engine, &position, &guess, actual).await {
+                Ok((points, remark)) => {
+                    session.points += points;
+                    text = format!("{text} {remark} +{points}");
+                }
+                Err(e) => warn!("cannot rate guess: {e}"),
+            }
+        }
+        text
+    };
+
+    // the master's move, then the reply
+    session.ply += 2;
+    sqlx::query("update guess_sessions set ply = $2, points = $3, guesses = $4, matched = $5 where id = $1")
+        .bind(session.id)
+        .bind(session.ply)
+        .bind(session.points)
+        .bind(session.guesses)
+        .bind(session.matched)
+        .execute(&state.db)
+        .await?;
+    if ply + 1 >= line.len() {
+        state.client.send_message(chat, text).await?;
+        return end(&state.db, &state.client, &session, &format!("That was the last move, {}.", game.result)).await;
+    }
+    let side = if session.white { Color::White } else { Color::Black };
+    let (position, reply) = game.position_at(ply + 2)?;
+    text = format!("{text}\n{} replied {}.", game.player(!side), reply.unwrap_or_default());
+    if ply + 2 >= line.len() {
+        state.client.send_message(chat, text).await?;
+        return end(&state.db, &state.client, &session, &format!("Game over, {}.", game.result)).await;
+    }
+    let theme = settings::theme(&state.db, session.user_id).await?;
+    text = format!("{text}\n{}", diagram::render(position.board(), side, Bitboard::FULL, theme));
+    debug!("guess session {} at ply {}", session.id, session.ply);
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+async fn end(db: &Pool<Sqlite>, client: &Bot, session: &Session, reason: &str) -> Result<()> {
+    sqlx::query("update guess_sessions set ended = 1 where id = $1")
+        .bind(session.id)
+        .execute(db)
+        .await?;
+    let text = format!(
+        "{reason} You scored {} of {} points, matching {} of {} moves.",
+        session.points,
+        session.guesses * EXACT_POINTS,
+        session.matched,
+        session.guesses
+    );
+    client.send_message(packed_chat(session.user_id), text).await?;
+    Ok(())
+}
+
+/// Loads the games in a PGN file for guessing, returning how many were
+/// imported. Games from a set-up position or with illegal moves are skipped.
+pub async fn import(db: &Pool<Sqlite>, text: &str) -> Result<usize> {
+    let mut tx = db.begin().await?;
+    let mut imported = 0;
+    for game in pgn::parse(text) {
+        if game.header("FEN").is_some() || game.mainline().is_empty() {
+            continue;
+        }
+        let mut position = Chess::default();
+        let mut ucis = Vec::with_capacity(game.mainline().len());
+        for san in game.mainline() {
+            let Some(m) = parse_move(san, &position) else {
+                break;
+            };
+            ucis.push(m.to_uci(CastlingMode::Standard).to_string());
+            position.play_unchecked(&m);
+        }
+        if ucis.len() != game.mainline().len() {
+            continue;
+        }
+        sqlx::query("insert into master_games (white, black, event, date, result, moves) values ($1, $2, $3, $4, $5, $6)")
+            .bind(game.header("White").unwrap_or("?"))
+            .bind(game.header("Black").unwrap_or("?"))
+            .bind(game.header("Event"))
+            .bind(game.header("Date"))
+            .bind(&game.result)
+            .bind(ucis.join(" "))
+            .execute(&mut *tx)
+            .await?;
+        imported += 1;
+    }
+    tx.commit().await?;
+    Ok(imported)
+}
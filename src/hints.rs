@@ -0,0 +1,87 @@
+//! `/hint`: the engine's suggestion for the user's move, from a quick shallow
+//! search. Unrated games have as many as the user likes; rated games allow
+//! `HINTS_PER_RATED_GAME`, none unless configured, and the opponent is told.
+
+use crate::{ongoing_game, packed_chat, position_from_fen, settings, templates, State, Variant};
+use anyhow::{anyhow, Result};
+use shakmaty::fen::Fen;
+use shakmaty::uci::Uci;
+use shakmaty::{Color, EnPassantMode, Position};
+
+/// How far the engine looks for a hint, shallow enough to miss deep ideas.
+const HINT_DEPTH: u32 = 8;
+
+pub async fn on_hint(state: &mut State, user_id: i64) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let Some(engine) = state.engine.clone() else {
+        state.client.send_message(chat, templates::text(state, "no_engine", &[])).await?;
+        return Ok(());
+    };
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state.client.send_message(chat, templates::text(state, "no_game", &[])).await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        state.client.send_message(chat, templates::text(state, "waiting_for_opponent", &[])).await?;
+        return Ok(());
+    };
+    if game.variant() == Variant::FogOfWar {
+        state.client.send_message(chat, templates::text(state, "hint_fog", &[])).await?;
+        return Ok(());
+    }
+    let (color, opponent) = if w_id == user_id { (Color::White, b_id) } else { (Color::Black, w_id) };
+    let position = position_from_fen(&game.fen);
+    if position.turn() != color {
+        state.client.send_message(chat, templates::text(state, "hint_not_your_turn", &[])).await?;
+        return Ok(());
+    }
+
+    // asking again for the same move costs nothing
+    let (used, repeat): (i64, bool) = sqlx::query_as(
+        "select count(*), coalesce(max(ply = $3), 0) from hints where game_id = $1 and user_id = $2",
+    )
+    .bind(game.id)
+    .bind(user_id)
+    .bind(game.plies)
+    .fetch_one(&state.db)
+    .await?;
+    let allowance = state.config.get().hints_per_rated_game;
+    let rated = game.rated();
+    if rated && !repeat && used >= allowance {
+        let text = match allowance {
+            0 => templates::text(state, "hint_unrated_only", &[]),
+            _ => templates::text(state, "hint_allowance_used", &[("allowance", &allowance)]),
+        };
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let uci = engine.shallow_move(&fen, HINT_DEPTH).await?;
+    let m = uci
+        .parse::<Uci>()
+        .ok()
+        .and_then(|uci| uci.to_move(&position).ok())
+        .ok_or_else(|| anyhow!("engine hint {uci} is not a move in {fen}"))?;
+    let inserted = sqlx::query("insert into hints (game_id, user_id, ply, uci) values ($1, $2, $3, $4) on conflict do nothing")
+        .bind(game.id)
+        .bind(user_id)
+        .bind(game.plies)
+        .bind(&uci)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+
+    let played = settings::get(&state.db, user_id).await?.notation().write(&position, &m);
+    let mut text = templates::text(state, "hint", &[("move", &played)]);
+    if rated {
+        let left = allowance - used - inserted as i64;
+        text.push_str(&templates::text(state, "hint_left", &[("left", &left)]));
+    }
+    state.client.send_message(chat, text).await?;
+    if rated && inserted > 0 {
+        let text = templates::text_for(state, opponent, "opponent_hint", &[]).await?;
+        state.client.send_message(packed_chat(opponent), text).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Invite links and where new users come from. A deep link opens the chat
+//! with `/start <payload>`; the payload of a user's first message is kept,
+//! and personal links credit the inviter.
+
+use crate::bot::Bot;
+use crate::{packed_chat, settings, user_name, State};
+use anyhow::Result;
+use log::info;
+use sqlx::{Pool, Sqlite};
+
+/// Payloads of personal invite links, followed by the inviter's id.
+pub const PAYLOAD_PREFIX: &str = "invite_";
+
+/// Telegram allows up to 64 characters of payload.
+const MAX_PAYLOAD_LEN: usize = 64;
+
+/// Records the payload of a new user's first `/start`, and who invited them.
+pub async fn record_start(db: &Pool<Sqlite>, client: &Bot, user_id: i64, payload: &str) -> Result<()> {
+    let payload: String = payload.trim().chars().take(MAX_PAYLOAD_LEN).collect();
+    let inviter = payload
+        .strip_prefix(PAYLOAD_PREFIX)
+        .and_then(|id| id.parse::<i64>().ok())
+        .filter(|&id| id != user_id);
+    sqlx::query(
+        "update users set start_payload = $2,
+            invited_by = (select id from users where id = $3) where id = $1",
+    )
+    .bind(user_id)
+    .bind(&payload)
+    .bind(inviter)
+    .execute(db)
+    .await?;
+
+    let invited_by: Option<i64> = sqlx::query_scalar("select invited_by from users where id = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await?;
+    if let Some(inviter) = invited_by {
+        info!("{user_id} was invited by {inviter}");
+        if !settings::notifications(db, inviter).await? {
+            return Ok(());
+        }
+        let text = format!("{} joined through your invite link!", user_name(db, user_id).await?);
+        client.send_message(packed_chat(inviter), text).await?;
+    }
+    Ok(())
+}
+
+/// How many users joined through the user's invite link.
+pub async fn count(db: &Pool<Sqlite>, user_id: i64) -> Result<i64> {
+    Ok(sqlx::query_scalar("select count(*) from users where invited_by = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await?)
+}
+
+pub async fn on_invite(state: &mut State, user_id: i64) -> Result<()> {
+    let link = format!("https://t.me/{}?start={PAYLOAD_PREFIX}{user_id}", state.bot_username);
+    let mut text = format!("Invite friends to play with your link:\n{link}");
+    let invited = count(&state.db, user_id).await?;
+    if invited > 0 {
+        text = format!("{text}\nYou have invited {invited} so far.");
+    }
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// New users over time, with the inviters and links that brought them.
+pub async fn growth(db: &Pool<Sqlite>) -> Result<String> {
+    let (today, week, month, invited): (i64, i64, i64, i64) = sqlx::query_as(
+        "select
+            coalesce(sum(joined_at >= unixepoch('now', 'start of day')), 0),
+            coalesce(sum(joined_at >= unixepoch('now', '-7 days')), 0),
+            coalesce(sum(joined_at >= unixepoch('now', '-30 days')), 0),
+            coalesce(sum(joined_at >= unixepoch('now', '-30 days') and invited_by is not null), 0)
+        from users",
+    )
+    .fetch_one(db)
+    .await?;
+    let mut text = format!(
+        "New users: {today} today, {week} this week, {month} in 30 days ({invited} invited)"
+    );
+
+    let inviters: Vec<(i64, i64)> = sqlx::query_as(
+        "select invited_by, count(*) as invited from users where invited_by is not null
+         group by invited_by order by invited desc limit 5",
+    )
+    .fetch_all(db)
+    .await?;
+    if !inviters.is_empty() {
+        text = format!("{text}\nTop inviters:");
+        for (inviter, invited) in inviters {
+            text = format!("{text}\n{} {invited}", user_name(db, inviter).await?);
+        }
+    }
+
+    let sources: Vec<(String, i64)> = sqlx::query_as(
+        "select start_payload, count(*) as users from users
+         where start_payload is not null and start_payload not like $1 || '%'
+         group by start_payload order by users desc limit 5",
+    )
+    .bind(PAYLOAD_PREFIX)
+    .fetch_all(db)
+    .await?;
+    if !sources.is_empty() {
+        text = format!("{text}\nOther links:");
+        for (payload, users) in sources {
+            text = format!("{text}\n{payload} {users}");
+        }
+    }
+    Ok(text)
+}
@@ -0,0 +1,385 @@
+//! Team leagues: an admin creates a league, adds clubs to its divisions and
+//! starts it. Each division plays a round robin of team matches, one round
+//! a week: a round opens for sign-ups when the last one closes, and at its
+//! deadline matches nobody started are paired with whoever signed up, the
+//! boards one club has no player for going to the other. The table counts
+//! 2 points for a match won and 1 for a draw, then board points.
+
+use crate::bot::Bot;
+use crate::config::Config;
+use crate::teams::{self, TeamMatch};
+use crate::{clubs, packed_chat, State};
+use anyhow::Result;
+use log::info;
+use sqlx::{Pool, Sqlite};
+use std::collections::{BTreeMap, BTreeSet};
+
+const DEFAULT_BOARDS: i64 = 4;
+const MAX_BOARDS: i64 = 32;
+const DEFAULT_ROUND_DAYS: i64 = 7;
+
+const USAGE: &str =
+    "/admin league new <name> [boards] [days per round] | add <league> <club> [division] | start <league>";
+
+#[derive(Debug, sqlx::FromRow)]
+struct League {
+    id: i64,
+    name: String,
+    boards: i64,
+    round_days: i64,
+    status: String,
+}
+
+const SELECT_LEAGUE: &str = "select id, name, boards, round_days, status from leagues";
+
+/// The league with the id or name.
+async fn find(db: &Pool<Sqlite>, league: &str) -> Result<Option<League>> {
+    Ok(sqlx::query_as(&format!("{SELECT_LEAGUE} where id = $1 or name = $2"))
+        .bind(league.trim_start_matches('#').parse::<i64>().ok())
+        .bind(league)
+        .fetch_optional(db)
+        .await?)
+}
+
+/// The clubs in the league with their divisions, by division.
+async fn clubs_of(db: &Pool<Sqlite>, league_id: i64) -> Result<Vec<(i64, String, i64)>> {
+    Ok(sqlx::query_as(
+        "select clubs.id, clubs.name, division from league_clubs join clubs on clubs.id = club_id
+         where league_id = $1 order by division, clubs.name",
+    )
+    .bind(league_id)
+    .fetch_all(db)
+    .await?)
+}
+
+pub async fn on_league(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let args = args.trim();
+    let text = if args.is_empty() {
+        list(&state.db).await?
+    } else {
+        match find(&state.db, args).await? {
+            Some(league) => describe(&state.db, &league).await?,
+            None => format!("There is no league {args}."),
+        }
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn list(db: &Pool<Sqlite>) -> Result<String> {
+    let leagues: Vec<League> =
+        sqlx::query_as(&format!("{SELECT_LEAGUE} order by status = 'finished', id desc limit 10"))
+            .fetch_all(db)
+            .await?;
+    if leagues.is_empty() {
+        return Ok("There are no leagues yet.".to_string());
+    }
+    let mut text = String::new();
+    for league in leagues {
+        let clubs = clubs_of(db, league.id).await?.len();
+        text = format!("{text}League #{}: {}, {clubs} clubs, {}\n", league.id, league.name, league.status);
+    }
+    Ok(format!("{text}/league <league> shows the table and fixtures."))
+}
+
+/// The tables of the league's divisions and the fixtures of its current round.
+async fn describe(db: &Pool<Sqlite>, league: &League) -> Result<String> {
+    let fixtures = teams::of_league(db, league.id).await?;
+    let rounds = fixtures.iter().filter_map(|m| m.round).max().unwrap_or(0);
+    let mut text = format!("League #{}: {}, {} boards", league.id, league.name, league.boards);
+    text = match league.status.as_str() {
+        "open" => format!("{text}, taking clubs"),
+        status => format!("{text}, {rounds} rounds of {} days, {status}", league.round_days),
+    };
+    text = format!("{text}\n{}", table(db, league, &fixtures).await?);
+
+    let current = fixtures.iter().filter(|m| m.status != "scheduled").filter_map(|m| m.round).max();
+    if let (Some(round), "running") = (current, league.status.as_str()) {
+        text = format!("{text}\nRound {round}:");
+        for fixture in fixtures.iter().filter(|m| m.round == Some(round)) {
+            text = format!("{text}\n{}, {}", fixture.title(), fixture.status);
+        }
+    }
+    Ok(text)
+}
+
+#[derive(Default)]
+struct Standing {
+    won: i64,
+    drawn: i64,
+    lost: i64,
+    /// Board points, in half points.
+    halves: i64,
+}
+
+impl Standing {
+    fn points(&self) -> i64 {
+        2 * self.won + self.drawn
+    }
+
+    fn add(&mut self, ours: i64, theirs: i64) {
+        self.halves += ours;
+        match ours.cmp(&theirs) {
+            std::cmp::Ordering::Greater => self.won += 1,
+            std::cmp::Ordering::Equal => self.drawn += 1,
+            std::cmp::Ordering::Less => self.lost += 1,
+        }
+    }
+}
+
+/// The standings of each division from the finished fixtures, e.g.
+/// `1. Knights 4 (2-0-0), board points 5½`.
+async fn table(db: &Pool<Sqlite>, league: &League, fixtures: &[TeamMatch]) -> Result<String> {
+    let clubs = clubs_of(db, league.id).await?;
+    if clubs.is_empty() {
+        return Ok("No clubs yet.".to_string());
+    }
+    let mut standings: BTreeMap<i64, Standing> = BTreeMap::new();
+    for fixture in fixtures.iter().filter(|m| m.status == "finished") {
+        let (home, away) = teams::points(db, fixture).await?;
+        standings.entry(fixture.home_id).or_default().add(home, away);
+        standings.entry(fixture.away_id).or_default().add(away, home);
+    }
+    let divisions: BTreeSet<i64> = clubs.iter().map(|&(_, _, division)| division).collect();
+    let mut lines = Vec::new();
+    for &division in &divisions {
+        if divisions.len() > 1 {
+            lines.push(format!("Division {division}"));
+        }
+        let none = Standing::default();
+        let mut rows: Vec<(&str, &Standing)> = clubs
+            .iter()
+            .filter(|&&(_, _, d)| d == division)
+            .map(|(id, name, _)| (name.as_str(), standings.get(id).unwrap_or(&none)))
+            .collect();
+        rows.sort_by_key(|&(name, s)| (-s.points(), -s.halves, name));
+        for (rank, (name, s)) in rows.into_iter().enumerate() {
+            lines.push(format!(
+                "{}. {name} {} ({}-{}-{}), board points {}",
+                rank + 1,
+                s.points(),
+                s.won,
+                s.drawn,
+                s.lost,
+                teams::format_points(s.halves)
+            ));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Handles `/admin league`, returning the reply.
+pub async fn admin(state: &mut State, admin_id: i64, args: &str) -> Result<String> {
+    let db = &state.db;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        ["new", name, rest @ ..] if rest.len() <= 2 => {
+            let boards = rest.first().map_or(Ok(DEFAULT_BOARDS), |b| b.parse());
+            let days = rest.get(1).map_or(Ok(DEFAULT_ROUND_DAYS), |d| d.parse());
+            let (Ok(boards @ 1..=MAX_BOARDS), Ok(days @ 1..)) = (boards, days) else {
+                return Ok(format!("A league has 1 to {MAX_BOARDS} boards and rounds of a day or more."));
+            };
+            if name.parse::<i64>().is_ok() || find(db, name).await?.is_some() {
+                return Ok(format!("There already is a league {name}."));
+            }
+            let (id,): (i64,) =
+                sqlx::query_as("insert into leagues (name, boards, round_days) values ($1, $2, $3) returning id")
+                    .bind(name)
+                    .bind(boards)
+                    .bind(days)
+                    .fetch_one(db)
+                    .await?;
+            info!("{admin_id} created league {id}");
+            Ok(format!("Created league #{id}: {name}. Add clubs with /admin league add {name} <club> [division]"))
+        }
+        ["add", league, club, rest @ ..] if rest.len() <= 1 => {
+            let division = rest.first().map_or(Ok(1), |d| d.parse::<i64>());
+            let Ok(division @ 1..) = division else {
+                return Ok("Divisions are numbered from 1.".to_string());
+            };
+            let (Some(league), Some(club)) = (find(db, league).await?, clubs::find(db, club).await?) else {
+                return Ok("There is no such league or club.".to_string());
+            };
+            if league.status != "open" {
+                return Ok(format!("{} has already started.", league.name));
+            }
+            sqlx::query(
+                "insert into league_clubs (league_id, club_id, division) values ($1, $2, $3)
+                 on conflict do update set division = excluded.division",
+            )
+            .bind(league.id)
+            .bind(club.id)
+            .bind(division)
+            .execute(db)
+            .await?;
+            for admin in clubs::admin_ids(db, club.id).await? {
+                let text = format!("{} plays in division {division} of league {}.", club.name, league.name);
+                state.client.send_message(packed_chat(admin), text).await?;
+            }
+            Ok(format!("{} plays in division {division} of {}.", club.name, league.name))
+        }
+        ["start", league] => {
+            let Some(league) = find(db, league).await? else {
+                return Ok(format!("There is no league {league}."));
+            };
+            start(db, &state.client, &league).await
+        }
+        _ => Ok(format!("Usage: {USAGE}")),
+    }
+}
+
+/// Round-robin fixtures by the circle method: every club meets every other
+/// in its division once, alternating home and away. With an odd number of
+/// clubs one of them sits out each round.
+fn round_robin(clubs: &[i64]) -> Vec<Vec<(i64, i64)>> {
+    let mut slots: Vec<Option<i64>> = clubs.iter().copied().map(Some).collect();
+    if !slots.len().is_multiple_of(2) {
+        slots.push(None);
+    }
+    let n = slots.len();
+    let mut rounds = Vec::with_capacity(n - 1);
+    for round in 0..n - 1 {
+        let mut fixtures = Vec::with_capacity(n / 2);
+        for i in 0..n / 2 {
+            if let (Some(a), Some(b)) = (slots[i], slots[n - 1 - i]) {
+                fixtures.push(if (round + i).is_multiple_of(2) { (a, b) } else { (b, a) });
+            }
+        }
+        rounds.push(fixtures);
+        slots[1..].rotate_right(1);
+    }
+    rounds
+}
+
+/// Schedules every round of the league and opens the first.
+async fn start(db: &Pool<Sqlite>, client: &Bot, league: &League) -> Result<String> {
+    if league.status != "open" {
+        return Ok(format!("{} has already started.", league.name));
+    }
+    let clubs = clubs_of(db, league.id).await?;
+    let mut divisions: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for (club_id, _, division) in clubs {
+        divisions.entry(division).or_default().push(club_id);
+    }
+    if divisions.is_empty() {
+        return Ok(format!("{} has no clubs.", league.name));
+    }
+    if let Some((division, _)) = divisions.iter().find(|(_, clubs)| clubs.len() < 2) {
+        return Ok(format!("Division {division} needs at least two clubs."));
+    }
+
+    let mut tx = db.begin().await?;
+    let claimed =
+        sqlx::query("update leagues set status = 'running', started_at = unixepoch() where id = $1 and status = 'open'")
+            .bind(league.id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    if claimed == 0 {
+        return Ok(format!("{} has already started.", league.name));
+    }
+    let (mut fixtures, mut rounds) = (0, 0);
+    for clubs in divisions.values() {
+        for (round, pairs) in (1..).zip(round_robin(clubs)) {
+            for (home, away) in pairs {
+                sqlx::query(
+                    "insert into team_matches (home_club_id, away_club_id, boards, status, league_id, round,
+                        opens_at, deadline)
+                     values ($1, $2, $3, 'scheduled', $4, $5, unixepoch() + ($5 - 1) * $6, unixepoch() + $5 * $6)",
+                )
+                .bind(home)
+                .bind(away)
+                .bind(league.boards)
+                .bind(league.id)
+                .bind(round)
+                .bind(league.round_days * 86400)
+                .execute(&mut *tx)
+                .await?;
+                fixtures += 1;
+            }
+            rounds = rounds.max(round);
+        }
+    }
+    tx.commit().await?;
+    info!("started league {} with {fixtures} fixtures", league.id);
+    open_rounds(db, client).await?;
+    Ok(format!("{} started: {rounds} rounds, {fixtures} matches.", league.name))
+}
+
+/// Opens the fixtures whose round has come for sign-ups.
+async fn open_rounds(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let opened: Vec<i64> = sqlx::query_scalar(
+        "update team_matches set status = 'accepted' where status = 'scheduled' and opens_at <= unixepoch()
+         returning id",
+    )
+    .fetch_all(db)
+    .await?;
+    for match_id in opened {
+        let Some(team_match) = teams::find(db, match_id).await? else {
+            continue;
+        };
+        let text = format!(
+            "League round {} is on!\n{}\nSign up with /match join {match_id}",
+            team_match.round.unwrap_or(0),
+            teams::describe(db, &team_match).await?
+        );
+        team_match.announce(db, client, &text).await?;
+    }
+    Ok(())
+}
+
+/// Pairs the league matches nobody started by their deadline, forfeiting
+/// the boards one club has no player for.
+async fn enforce_deadlines(db: &Pool<Sqlite>, client: &Bot, config: &Config) -> Result<()> {
+    let due: Vec<i64> = sqlx::query_scalar(
+        "select id from team_matches where league_id is not null and status = 'accepted' and deadline <= unixepoch()",
+    )
+    .fetch_all(db)
+    .await?;
+    for match_id in due {
+        let Some(team_match) = teams::find(db, match_id).await? else {
+            continue;
+        };
+        info!("deadline of match {match_id} passed");
+        let text = teams::pair(db, client, config, &team_match).await?;
+        let text = format!("The deadline of {} has passed. {text}", team_match.title());
+        team_match.announce(db, client, &text).await?;
+    }
+    Ok(())
+}
+
+/// Finishes the leagues whose fixtures are all done and sends the final
+/// table to their clubs.
+async fn finish_leagues(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let finished: Vec<i64> = sqlx::query_scalar(
+        "update leagues set status = 'finished' where status = 'running'
+         and not exists (select 1 from team_matches where league_id = leagues.id and status != 'finished')
+         returning id",
+    )
+    .fetch_all(db)
+    .await?;
+    for league_id in finished {
+        let Some(league) = find(db, &league_id.to_string()).await? else {
+            continue;
+        };
+        info!("league {league_id} finished");
+        let fixtures = teams::of_league(db, league_id).await?;
+        let text = format!("League {} is over. Final table:\n{}", league.name, table(db, &league, &fixtures).await?);
+        let mut members = BTreeSet::new();
+        for (club_id, _, _) in clubs_of(db, league_id).await? {
+            members.extend(clubs::member_ids(db, club_id).await?);
+        }
+        for member in members {
+            client.send_message(packed_chat(member), text.as_str()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the running leagues along: opens rounds, enforces deadlines and
+/// finishes leagues.
+pub async fn sweep(db: &Pool<Sqlite>, client: &Bot, config: &Config) -> Result<()> {
+    open_rounds(db, client).await?;
+    enforce_deadlines(db, client, config).await?;
+    finish_leagues(db, client).await
+}
@@ -0,0 +1,178 @@
+//! Databases from before `migrations/`. Their tables were created from
+//! `src/schema.sql` once and never changed, so they lack the columns added to
+//! it since. SQLite can't add a column defaulting to `unixepoch()`, so the
+//! tables are rebuilt from the first migration, keeping the columns they have.
+
+use crate::position_hash;
+use anyhow::Result;
+use log::info;
+use shakmaty::uci::Uci;
+use shakmaty::{Chess, Position};
+use sqlx::{Connection, Executor, Pool, Sqlite, SqliteConnection};
+
+/// The tables `src/schema.sql` created.
+const TABLES: [&str; 3] = ["users", "games", "moves"];
+
+/// Values for `not null` columns without a default that older tables lack.
+const FILLED: [(&str, &str, &str); 2] = [("moves", "played_at", "0"), ("moves", "zobrist", "0")];
+
+/// Whether the database has tables but no record of migrations.
+pub async fn predates_migrations(db: &Pool<Sqlite>) -> Result<bool> {
+    let tables: Vec<String> = sqlx::query_scalar("select name from sqlite_master where type = 'table'")
+        .fetch_all(db)
+        .await?;
+    Ok(tables.iter().any(|t| t == "games") && !tables.iter().any(|t| t == "_sqlx_migrations"))
+}
+
+/// Brings the tables up to the first migration, which then finds them in
+/// place.
+pub async fn upgrade(db: &Pool<Sqlite>) -> Result<()> {
+    let mut conn = db.acquire().await?;
+    // dropping the old tables mustn't cascade, and other tables keep
+    // referencing the tables by name rather than following the rename
+    let foreign_keys: bool = sqlx::query_scalar("pragma foreign_keys").fetch_one(&mut *conn).await?;
+    conn.execute("pragma foreign_keys = off; pragma legacy_alter_table = on").await?;
+    let result = rebuild(&mut conn).await;
+    conn.execute(format!("pragma foreign_keys = {foreign_keys}; pragma legacy_alter_table = off").as_str())
+        .await?;
+    result
+}
+
+async fn rebuild(conn: &mut SqliteConnection) -> Result<()> {
+    let mut tx = conn.begin().await?;
+    // indexes would follow the rename and keep the first migration from
+    // creating them
+    let indexes: Vec<String> = sqlx::query_scalar(
+        "select name from sqlite_master where type = 'index' and sql is not null \
+         and tbl_name in ('users', 'games', 'moves')",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for index in indexes {
+        tx.execute(format!("drop index {index}").as_str()).await?;
+    }
+    for table in TABLES {
+        tx.execute(format!("alter table {table} rename to legacy_{table}").as_str()).await?;
+    }
+    tx.execute(include_str!("../migrations/0001_schema.sql")).await?;
+
+    let mut lacking = Vec::new();
+    for table in TABLES {
+        let old = columns(&mut tx, &format!("legacy_{table}")).await?;
+        let new = columns(&mut tx, table).await?;
+        let mut names = Vec::new();
+        let mut values = Vec::new();
+        for column in &new {
+            if old.contains(column) {
+                names.push(column.as_str());
+                values.push(column.as_str());
+            } else if let Some(&(_, _, value)) = FILLED.iter().find(|&&(t, c, _)| t == table && c == column) {
+                names.push(column.as_str());
+                values.push(value);
+            }
+            if !old.contains(column) {
+                lacking.push(format!("{table}.{column}"));
+            }
+        }
+        let (names, values) = (names.join(", "), values.join(", "));
+        tx.execute(format!("insert into {table} ({names}) select {values} from legacy_{table}").as_str()).await?;
+    }
+    for table in TABLES {
+        tx.execute(format!("drop table legacy_{table}").as_str()).await?;
+    }
+
+    if lacking.iter().any(|c| c == "games.plies") {
+        sqlx::query("update games set plies = (select count(*) from moves where moves.game_id = games.id)")
+            .execute(&mut *tx)
+            .await?;
+    }
+    if lacking.iter().any(|c| c == "moves.zobrist") {
+        hash_moves(&mut tx).await?;
+    }
+    tx.commit().await?;
+    info!("upgraded the database from before migrations, added {}", lacking.join(", "));
+    Ok(())
+}
+
+async fn columns(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>> {
+    let columns = sqlx::query_scalar("select name from pragma_table_info($1)")
+        .bind(table)
+        .fetch_all(conn)
+        .await?;
+    Ok(columns)
+}
+
+/// Fills in `moves.zobrist` by replaying each game, which then started from
+/// the initial position.
+async fn hash_moves(conn: &mut SqliteConnection) -> Result<()> {
+    let moves: Vec<(i64, i64, String)> =
+        sqlx::query_as("select game_id, ply, uci from moves where game_id is not null order by game_id, ply")
+            .fetch_all(&mut *conn)
+            .await?;
+    let mut game = None;
+    let mut position = Chess::default();
+    for (game_id, ply, uci) in moves {
+        if game != Some(game_id) {
+            game = Some(game_id);
+            position = Chess::default();
+        }
+        let Some(m) = Uci::from_ascii(uci.as_bytes()).ok().and_then(|uci| uci.to_move(&position).ok()) else {
+            continue;
+        };
+        position.play_unchecked(&m);
+        sqlx::query("update moves set zobrist = $3 where game_id = $1 and ply = $2")
+            .bind(game_id)
+            .bind(ply)
+            .bind(position_hash(&position))
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrate, repetitions, Game, GAME_COLUMNS};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use tokio::runtime;
+
+    #[test]
+    fn migrates_a_database_from_before_migrations() {
+        let path = std::env::temp_dir().join(format!("tgpawn-legacy-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = SqliteConnectOptions::new().filename(&path).create_if_missing(true).foreign_keys(true);
+        let runtime = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let db = SqlitePoolOptions::new().connect_with(options).await.unwrap();
+            db.execute(include_str!("../tests/fixtures/schema_before_migrations.sql")).await.unwrap();
+            db.execute(
+                "insert into users (id) values (1), (2);
+                insert into games (id, w_id, b_id, ended, fen) values (1, 1, 2, 0, 'x');
+                insert into moves (game_id, ply, uci) values (1, 1, 'g1f3'), (1, 2, 'g8f6'), (1, 3, 'f3g1');",
+            )
+            .await
+            .unwrap();
+
+            assert!(predates_migrations(&db).await.unwrap());
+            migrate(&db).await.unwrap();
+            assert!(!predates_migrations(&db).await.unwrap());
+
+            let game: Game = sqlx::query_as(&format!("select {GAME_COLUMNS} from games where id = 1"))
+                .fetch_one(&db)
+                .await
+                .unwrap();
+            assert_eq!(game.plies, 3);
+            // the hashes are filled in by replaying the moves
+            let mut position = Chess::default();
+            for uci in ["g1f3", "g8f6", "f3g1"] {
+                let m = uci.parse::<Uci>().unwrap().to_move(&position).unwrap();
+                position.play_unchecked(&m);
+            }
+            let zobrist = position_hash(&position);
+            assert_eq!(repetitions(&db, &game, zobrist).await.unwrap(), 1);
+            assert_eq!(migrate(&db).await.unwrap(), 0);
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,277 +1,3105 @@
+mod analysis;
+mod api;
+mod badges;
+mod blocked;
+mod bot;
+mod cli;
+mod clock;
+mod clubs;
+mod commentary;
+mod commands;
+mod config;
+mod coords;
+mod corrections;
+mod diagram;
+mod editor;
+mod emotes;
+mod endgame;
+mod engine;
+mod exhibition;
+mod exports;
+mod fairplay;
+mod feed;
+mod featured;
+mod fog;
+mod follows;
+mod guess;
+mod hints;
+mod invites;
+mod leagues;
+mod legacy;
+mod material;
+mod notes;
+mod openings;
+mod pgn;
+mod pipeline;
+mod puzzles;
+mod rating;
+mod repl;
+mod replay;
+mod review;
+mod repertoire;
+mod rush;
+mod scheduler;
+mod seeks;
+mod settings;
+mod shards;
+mod simulate;
+mod srs;
+mod streaks;
+mod studies;
+mod tablebase;
+mod tactics;
+mod teams;
+mod templates;
+mod timing;
+mod voice;
+mod web;
+mod weekly;
+
 use anyhow::Result;
-use grammers_client::types::Chat;
-use grammers_client::{Client, Config, InitParams, Update};
+use bot::{Bot, Unreachable};
+use chrono::DateTime;
+use clock::{Delay, Pace, TimeControl};
+use futures_util::future::{self, Either};
+use rating::{Category, Rating};
+use scheduler::Scheduler;
+use grammers_client::{Client, Config, InitParams};
 use grammers_session::{PackedChat, Session};
-use log::{debug, error, info};
+use log::{debug, error, info, warn, LevelFilter};
 use shakmaty::fen::Fen;
 use shakmaty::san::San;
 use shakmaty::uci::Uci;
-use shakmaty::{CastlingMode, Chess, Color, Move, Outcome, Position};
-use sqlx::sqlite::{Sqlite, SqlitePool};
-use sqlx::{Connection, Executor, Pool};
-use std::error;
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Bitboard, CastlingMode, Chess, Color, EnPassantMode, Move, Position};
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use sqlx::{ConnectOptions, Executor, Pool};
 use std::pin::pin;
-use std::{collections::HashMap, env};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::{runtime, task};
-use std::env::var;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt::Display;
+use std::io::Write;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::runtime;
+use tokio::task::LocalSet;
+use tokio::signal::unix::{signal, SignalKind};
 
 const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
-enum Termination {
-    Timeout = 0,
-    Resign = 1,
-    Checkmate = 2,
-    Draw = 3,
+/// Black's share of White's starting time in armageddon games.
+const ARMAGEDDON_BLACK_TIME: f64 = 0.8;
+
+/// White's starting time in armageddon games when the bot's games are
+/// untimed.
+const DEFAULT_ARMAGEDDON_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// How long an open seek waits for an opponent before it is cancelled.
+const DEFAULT_SEEK_TTL_SECS: i64 = 60 * 60 * 24;
+
+/// How often expired seeks are swept.
+const SEEK_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many days a game may go without a move before it is abandoned.
+const DEFAULT_STALE_GAME_DAYS: i64 = 7;
+
+/// How often stale games are swept.
+const STALE_GAME_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often inactive players' rating deviations are increased.
+const RATING_DECAY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often opted-in users are checked for a due daily or weekly digest.
+const DIGEST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How many vacation days a player gets per year.
+const VACATION_DAYS_PER_YEAR: f64 = 30.0;
+
+/// How often vacations are checked against the yearly budget.
+const VACATION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often old games are deleted and deleted ones purged.
+const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long a deleted game can still be restored before it is purged.
+const PURGE_GRACE_DAYS: i64 = 30;
+
+/// How often finished rated games are run through the engine for fair play.
+const FAIR_PLAY_REVIEW_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often recent rated games are looked at for sandbagging.
+const SANDBAG_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often idle studies are closed.
+const STUDY_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often league rounds are opened and their deadlines enforced.
+const LEAGUE_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often the archives users asked for with /export are built.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often players are checked for a play streak about to end.
+const STREAK_REMINDER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the featured channel is given a game when it has none.
+const FEATURED_PICK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often failed sends are recorded and games with unreachable players
+/// looked at.
+const UNREACHABLE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often exhibitions are given their next move.
+const EXHIBITION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often running clocks are checked for expiry.
+const FLAG_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to look for puzzle rushes that have run out of time.
+const RUSH_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Players shown by /top.
+const LEADERBOARD_LEN: i64 = 10;
+
+/// Most players listed by /find.
+const FIND_LIMIT: i64 = 5;
+
+/// Shorter /find queries would match most players.
+const MIN_FIND_QUERY_LEN: usize = 2;
+
+/// How often the clocks in live board messages are refreshed.
+const LIVE_CLOCK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Games with at most this much initial time get live clock updates.
+const LIVE_CLOCK_MAX_INITIAL_MS: i64 = 30 * 60 * 1000;
+
+/// How often the Telegram session is saved, so a crash doesn't lose the
+/// update state and make the next start catch up from long ago.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Upper bound of the random delay added to each background job run.
+const JOB_JITTER: Duration = Duration::from_secs(10);
+
+/// Queries taking longer than this are logged as slow.
+const DEFAULT_SLOW_QUERY_MS: u64 = 100;
+
+static SLOW_QUERY: OnceLock<Duration> = OnceLock::new();
+
+/// How long a query waits for another connection's write lock before failing.
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+/// How long to wait for a free connection from the pool.
+const DEFAULT_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Prepared statements kept per connection.
+const DEFAULT_STATEMENT_CACHE: usize = 100;
+
+/// How many recent update handling times `/admin stats` averages over.
+const LATENCY_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    Standard = 0,
+    /// Each player only sees the squares their own pieces occupy or attack.
+    FogOfWar = 1,
+}
+
+impl Variant {
+    fn from_i64(v: i64) -> Self {
+        match v {
+            1 => Variant::FogOfWar,
+            _ => Variant::Standard,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::FogOfWar => "Fog of war",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Termination {
+    Timeout = 0,
+    Resign = 1,
+    Checkmate = 2,
+    Draw = 3,
+    Aborted = 4,
+    Abandoned = 5,
+}
+
+impl Termination {
+    fn from_i64(v: i64) -> Option<Self> {
+        Some(match v {
+            0 => Termination::Timeout,
+            1 => Termination::Resign,
+            2 => Termination::Checkmate,
+            3 => Termination::Draw,
+            4 => Termination::Aborted,
+            5 => Termination::Abandoned,
+            _ => return None,
+        })
+    }
+
+    /// Value of the PGN `Termination` tag.
+    fn pgn_tag(self) -> &'static str {
+        match self {
+            Termination::Timeout => "time forfeit",
+            Termination::Aborted | Termination::Abandoned => "abandoned",
+            Termination::Resign | Termination::Checkmate | Termination::Draw => "normal",
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Termination::Timeout => "Time forfeit",
+            Termination::Resign => "Resignation",
+            Termination::Checkmate => "Checkmate",
+            Termination::Draw => "Draw",
+            Termination::Aborted => "Aborted",
+            Termination::Abandoned => "Abandoned",
+        }
+    }
+}
+
+/// Shared by background jobs.
+#[derive(Clone)]
+struct JobContext {
+    db: Pool<Sqlite>,
+    client: Bot,
+}
+
+struct State {
+    db: Pool<Sqlite>,
+    client: Bot,
+    boards: HashMap<i64, Chess>,
+    bot_username: String,
+    /// The settings that can be reloaded while running.
+    config: config::Current,
+    /// How long the most recent updates took to handle, in any shard.
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+    /// Where incoming messages are recorded for `tgpawn replay`, if anywhere.
+    update_log: Option<replay::UpdateLog>,
+    /// Engine for the features that need evaluations, if one is installed.
+    engine: Option<engine::Engine>,
+    /// The incoming message being handled, remembered with the move it plays.
+    message_id: Option<i32>,
+    /// The language of the user being answered.
+    language: String,
+    /// The latest messages handled, as user and message id.
+    recent_messages: VecDeque<(i64, i32)>,
+    /// When each user's recent messages came in.
+    rate_limits: HashMap<i64, VecDeque<Instant>>,
+}
+
+impl State {
+    /// State for driving the bot without Telegram, with defaults for everything
+    /// that is normally configured.
+    fn offline(db: Pool<Sqlite>, client: Bot) -> Self {
+        State {
+            db,
+            client,
+            boards: HashMap::new(),
+            bot_username: "tgpawn_bot".to_string(),
+            config: config::Current::default(),
+            latencies: Arc::default(),
+            update_log: None,
+            engine: engine::Engine::from_env(),
+            message_id: None,
+            language: templates::DEFAULT_LANGUAGE.to_string(),
+            recent_messages: VecDeque::new(),
+            rate_limits: HashMap::new(),
+        }
+    }
+
+    /// State for another shard: the connections, configuration and engine
+    /// are shared, the caches start out empty.
+    fn shard(&self) -> Self {
+        State {
+            db: self.db.clone(),
+            client: self.client.clone(),
+            boards: HashMap::new(),
+            bot_username: self.bot_username.clone(),
+            config: self.config.clone(),
+            latencies: self.latencies.clone(),
+            update_log: self.update_log.clone(),
+            engine: self.engine.clone(),
+            message_id: None,
+            language: templates::DEFAULT_LANGUAGE.to_string(),
+            recent_messages: VecDeque::new(),
+            rate_limits: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Stats {
+    users: i64,
+    daily_active: i64,
+    weekly_active: i64,
+    created_today: i64,
+    finished_today: i64,
+    avg_plies: Option<f64>,
+    avg_duration_secs: Option<f64>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Game {
+    id: i64,
+    w_id: Option<i64>,
+    b_id: Option<i64>,
+    fen: String,
+    ended: bool,
+    winner: Option<bool>,
+    termination: Option<i64>,
+    started_at: Option<i64>,
+    ended_at: Option<i64>,
+    w_rating: Option<i64>,
+    b_rating: Option<i64>,
+    w_rating_diff: Option<i64>,
+    b_rating_diff: Option<i64>,
+    initial_ms: Option<i64>,
+    increment_ms: Option<i64>,
+    delay_ms: Option<i64>,
+    bronstein: Option<bool>,
+    w_clock_ms: Option<i64>,
+    b_clock_ms: Option<i64>,
+    turn_started_ms: Option<i64>,
+    w_message_id: Option<i32>,
+    b_message_id: Option<i32>,
+    w_board_text: Option<String>,
+    b_board_text: Option<String>,
+    plies: i64,
+    variant: i64,
+    /// Black has less time but wins if the game is drawn.
+    armageddon: bool,
+    /// Unrated by the players' choice.
+    casual: bool,
+    /// Days a correspondence game can go without a move, when the players
+    /// chose them.
+    days_per_move: Option<i64>,
+    /// The position set up with `/edit` the game started from, if not the
+    /// usual one.
+    initial_fen: Option<String>,
+}
+
+impl Game {
+    /// Result in PGN notation.
+    fn result(&self) -> &'static str {
+        match (self.winner, self.termination.and_then(Termination::from_i64)) {
+            (Some(true), _) => "1-0",
+            (Some(false), _) => "0-1",
+            (None, Some(Termination::Draw)) => "1/2-1/2",
+            _ => "*",
+        }
+    }
+
+    fn turn(&self) -> Color {
+        position_from_fen(&self.fen).turn()
+    }
+
+    /// The position before the first move.
+    fn start_fen(&self) -> &str {
+        self.initial_fen.as_deref().unwrap_or(STARTING_FEN)
+    }
+
+    /// The side that plays the move at `ply`, counted from 0. A game set up
+    /// with `/edit` may start with Black to move.
+    fn mover(&self, ply: usize) -> Color {
+        let first = position_from_fen(self.start_fen()).turn();
+        if ply.is_multiple_of(2) {
+            first
+        } else {
+            !first
+        }
+    }
+
+    /// The move at `ply` as numbered in notation, e.g. `12.` or `12...`.
+    fn move_number(&self, ply: usize) -> String {
+        let start = position_from_fen(self.start_fen());
+        let number = start.fullmoves().get() as usize + (ply + usize::from(start.turn().is_black())) / 2;
+        let dots = if self.mover(ply).is_white() { "." } else { "..." };
+        format!("{number}{dots}")
+    }
+
+    fn variant(&self) -> Variant {
+        Variant::from_i64(self.variant)
+    }
+
+    /// Whether the result changes the players' ratings.
+    fn rated(&self) -> bool {
+        // the time and draw odds make armageddon results a poor guide to strength,
+        // as do positions set up by the players
+        let house = [self.w_id, self.b_id].into_iter().flatten().any(exhibition::is_house_player);
+        !self.armageddon && !house && !self.casual && self.initial_fen.is_none()
+    }
+
+    /// The rating category the game counts for.
+    fn category(&self) -> Category {
+        match (self.variant(), self.time_control()) {
+            (Variant::FogOfWar, _) => Category::FogOfWar,
+            (Variant::Standard, tc) => Category::of_speed(tc.map(|tc| tc.initial), tc.map_or(Duration::ZERO, |tc| tc.increment)),
+        }
+    }
+
+    fn time_control(&self) -> Option<TimeControl> {
+        let ms = |ms: i64| Duration::from_millis(ms as u64);
+        Some(TimeControl {
+            initial: ms(self.initial_ms?),
+            increment: ms(self.increment_ms.unwrap_or(0)),
+            delay: self.delay_ms.map(|d| {
+                if self.bronstein == Some(true) {
+                    Delay::Bronstein(ms(d))
+                } else {
+                    Delay::Simple(ms(d))
+                }
+            }),
+        })
+    }
+
+    /// White's and black's clocks at `now`, if the game is timed and under way.
+    fn clocks_at(&self, now: i64) -> Option<(i64, i64)> {
+        let tc = self.time_control()?;
+        let (w_clock_ms, b_clock_ms) = (self.w_clock_ms?, self.b_clock_ms?);
+        // The clock of a player on vacation is paused.
+        let Some(turn_started_ms) = self.turn_started_ms else {
+            return Some((w_clock_ms, b_clock_ms));
+        };
+        Some(match self.turn() {
+            Color::White => (tc.running(w_clock_ms, turn_started_ms, now), b_clock_ms),
+            Color::Black => (w_clock_ms, tc.running(b_clock_ms, turn_started_ms, now)),
+        })
+    }
+}
+
+const GAME_COLUMNS: &str = "id, w_id, b_id, fen, ended, winner, termination, started_at, ended_at, w_rating, b_rating, w_rating_diff, b_rating_diff, initial_ms, increment_ms, delay_ms, bronstein, w_clock_ms, b_clock_ms, turn_started_ms, w_message_id, b_message_id, w_board_text, b_board_text, plies, variant, armageddon, casual, days_per_move, initial_fen";
+
+/// Awaits a query and logs it with `context`, typically the ids it was bound
+/// to, if it was slow. sqlx logs slow statements too but without their arguments.
+async fn timed<T>(context: impl FnOnce() -> String, query: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let result = query.await;
+    let elapsed = started.elapsed();
+    if elapsed >= *SLOW_QUERY.get_or_init(|| Duration::from_millis(DEFAULT_SLOW_QUERY_MS)) {
+        warn!("slow query: {} took {} ms", context(), elapsed.as_millis());
+    }
+    result
+}
+
+async fn game_by_id(db: &Pool<Sqlite>, id: i64) -> Result<Option<Game>> {
+    let query = format!("select {GAME_COLUMNS} from games where id = $1 and deleted_at is null");
+    Ok(timed(
+        || format!("game_by_id game={id}"),
+        sqlx::query_as(&query).bind(id).fetch_optional(db),
+    )
+    .await?)
+}
+
+/// The user's game their moves go to: the one picked with `/game` while it
+/// lasts, else the latest.
+async fn ongoing_game<'e>(
+    db: impl Executor<'e, Database = Sqlite>,
+    user_id: i64,
+) -> Result<Option<Game>> {
+    let query = format!(
+        "select {GAME_COLUMNS} from games where (w_id = $1 or b_id = $1) and ended = 0
+         order by coalesce(id = (select active_game_id from users where id = $1), 0) desc, id desc limit 1"
+    );
+    let game = timed(
+        || format!("ongoing_game user={user_id}"),
+        sqlx::query_as(&query).bind(user_id).fetch_optional(db),
+    )
+    .await?;
+    debug!("get ongoing game for {user_id}: got {game:?}");
+    Ok(game)
+}
+
+/// The trainer the user is in the middle of, if any. Trainers take over
+/// plain-text moves, so only one can run at a time and not during a game.
+async fn training(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<&'static str>> {
+    if rush::running(db, user_id).await?.is_some() {
+        return Ok(Some("puzzle rush"));
+    }
+    if tactics::running(db, user_id).await?.is_some() {
+        return Ok(Some("puzzle"));
+    }
+    if guess::running(db, user_id).await?.is_some() {
+        return Ok(Some("guess-the-move game"));
+    }
+    if repertoire::running(db, user_id).await?.is_some() {
+        return Ok(Some("repertoire drill"));
+    }
+    if endgame::running(db, user_id).await?.is_some() {
+        return Ok(Some("endgame drill"));
+    }
+    if coords::running(db, user_id).await?.is_some() {
+        return Ok(Some("coordinates round"));
+    }
+    if editor::running(db, user_id).await?.is_some() {
+        return Ok(Some("position setup"));
+    }
+    if analysis::running(db, user_id).await?.is_some() {
+        return Ok(Some("analysis"));
+    }
+    if studies::running(db, user_id).await?.is_some() {
+        return Ok(Some("study"));
+    }
+    if review::running(db, user_id).await?.is_some() {
+        return Ok(Some("game review"));
+    }
+    Ok(None)
+}
+
+/// Ends a game unless it has already ended, returning whether it did.
+async fn end_game<'e>(
+    db: impl Executor<'e, Database = Sqlite>,
+    id: i64,
+    winner: Option<Color>,
+    termination: Termination,
+) -> Result<bool> {
+    // a drawn armageddon game goes to black
+    let ended = sqlx::query(
+        "update games set ended = 1, winner = coalesce($2, case when armageddon and $3 = $4 then 0 end), termination = $3,
+         ended_at = unixepoch() where id = $1 and ended = 0",
+    )
+    .bind(id)
+    .bind(winner.map(|c| c.is_white()))
+    .bind(termination as i64)
+    .bind(Termination::Draw as i64)
+    .execute(db)
+    .await?
+    .rows_affected()
+        > 0;
+    debug!("end game {id}: {ended}");
+    Ok(ended)
+}
+
+async fn user_name(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let name: Option<String> = sqlx::query_scalar("select name from users where id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?
+        .flatten();
+    Ok(name.unwrap_or_else(|| user_id.to_string()))
+}
+
+/// Describes a player to their opponent, e.g. `Alice (@alice), 1500?, 5W 2L 1D`.
+async fn player_card(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let (name, username, rating, rated_games): (Option<String>, Option<String>, f64, i64) =
+        sqlx::query_as("select name, username, rating, rated_games from users where id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await?;
+    let (wins, losses, draws): (i64, i64, i64) = sqlx::query_as(
+        "select
+            coalesce(sum((w_id = $1 and winner = 1) or (b_id = $1 and winner = 0)), 0),
+            coalesce(sum((w_id = $1 and winner = 0) or (b_id = $1 and winner = 1)), 0),
+            coalesce(sum(winner is null and termination = $2), 0)
+        from games where (w_id = $1 or b_id = $1) and ended = 1",
+    )
+    .bind(user_id)
+    .bind(Termination::Draw as i64)
+    .fetch_one(db)
+    .await?;
+
+    let name = name.unwrap_or_else(|| user_id.to_string());
+    let mut card = badges::decorate(db, user_id, &name).await?;
+    if let Some(username) = username {
+        card = format!("{card} (@{username})");
+    }
+    Ok(format!(
+        "{card}, {}, {wins}W {losses}L {draws}D",
+        rating::display(rating, rated_games)
+    ))
+}
+
+/// Names a side with its player, e.g. `White (Alice)`.
+async fn player_label(db: &Pool<Sqlite>, color: Color, user_id: i64) -> Result<String> {
+    let side = if color.is_white() { "White" } else { "Black" };
+    Ok(format!("{side} ({})", user_name(db, user_id).await?))
+}
+
+/// Replays moves stored as UCI from the game's first position, returning them
+/// in SAN.
+fn san_moves(start_fen: &str, ucis: &[String]) -> Vec<String> {
+    let mut board = position_from_fen(start_fen);
+    let mut sans = Vec::with_capacity(ucis.len());
+    for uci in ucis {
+        let Some(m) = Uci::from_ascii(uci.as_bytes())
+            .ok()
+            .and_then(|uci| uci.to_move(&board).ok())
+        else {
+            error!("invalid stored move {uci}");
+            break;
+        };
+        sans.push(San::from_move(&board, &m).to_string());
+        board.play_unchecked(&m);
+    }
+    sans
+}
+
+async fn game_ucis(db: &Pool<Sqlite>, id: i64) -> Result<Vec<String>> {
+    Ok(timed(
+        || format!("game_ucis game={id}"),
+        sqlx::query_scalar("select uci from moves where game_id = $1 order by ply")
+            .bind(id)
+            .fetch_all(db),
+    )
+    .await?)
+}
+
+/// Time spent on each move of a game and the mover's clock after it, empty if
+/// the game never started.
+async fn game_move_times(db: &Pool<Sqlite>, game: &Game) -> Result<Vec<(i64, Option<i64>)>> {
+    let Some(started_at) = game.started_at else {
+        return Ok(Vec::new());
+    };
+    let timings: Vec<(i64, Option<i64>)> =
+        sqlx::query_as("select played_at, clock_ms from moves where game_id = $1 order by ply")
+            .bind(game.id)
+            .fetch_all(db)
+            .await?;
+    let played_at: Vec<i64> = timings.iter().map(|&(t, _)| t).collect();
+    Ok(timing::spent(started_at * 1000, &played_at)
+        .into_iter()
+        .zip(timings.into_iter().map(|(_, clock_ms)| clock_ms))
+        .collect())
+}
+
+/// Exports a game as PGN with full headers.
+async fn game_pgn(db: &Pool<Sqlite>, bot_username: Option<&str>, game: &Game) -> Result<String> {
+    let sans = san_moves(game.start_fen(), &game_ucis(db, game.id).await?);
+    let name = |id: Option<i64>| async move {
+        match id {
+            Some(id) => user_name(db, id).await,
+            None => Ok("?".to_string()),
+        }
+    };
+    let started = game
+        .started_at
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .map(|t| t.naive_utc());
+    let mut headers = vec![
+        ("Event", "Casual game".to_string()),
+        (
+            "Site",
+            bot_username.map_or("?".to_string(), |bot| format!("https://t.me/{bot}?start=game_{}", game.id)),
+        ),
+        ("Date", started.map_or("????.??.??".to_string(), |t| t.format("%Y.%m.%d").to_string())),
+        ("Round", "-".to_string()),
+        ("White", name(game.w_id).await?),
+        ("Black", name(game.b_id).await?),
+        ("Result", game.result().to_string()),
+        ("UTCDate", started.map_or("????.??.??".to_string(), |t| t.format("%Y.%m.%d").to_string())),
+        ("UTCTime", started.map_or("??:??:??".to_string(), |t| t.format("%H:%M:%S").to_string())),
+        (
+            "Variant",
+            match game.initial_fen {
+                Some(_) => "From Position".to_string(),
+                None => game.variant().name().to_string(),
+            },
+        ),
+        (
+            "TimeControl",
+            game.time_control().map_or("-".to_string(), |tc| {
+                format!("{}+{}", tc.initial.as_secs(), tc.increment.as_secs())
+            }),
+        ),
+        (
+            "Termination",
+            match game.termination.and_then(Termination::from_i64) {
+                Some(t) => t.pgn_tag().to_string(),
+                None => "unterminated".to_string(),
+            },
+        ),
+    ];
+    if let (Some(w_rating), Some(b_rating)) = (game.w_rating, game.b_rating) {
+        headers[0].1 = "Rated game".to_string();
+        headers.push(("WhiteElo", w_rating.to_string()));
+        headers.push(("BlackElo", b_rating.to_string()));
+    }
+    if let (Some(w_diff), Some(b_diff)) = (game.w_rating_diff, game.b_rating_diff) {
+        headers.push(("WhiteRatingDiff", format!("{w_diff:+}")));
+        headers.push(("BlackRatingDiff", format!("{b_diff:+}")));
+    }
+    if let Some(ended_at) = game.ended_at.and_then(|t| DateTime::from_timestamp(t, 0)) {
+        headers.push(("EndDate", ended_at.format("%Y.%m.%d").to_string()));
+    }
+    if let Some(fen) = &game.initial_fen {
+        headers.push(("SetUp", "1".to_string()));
+        headers.push(("FEN", fen.clone()));
+    }
+    let mut comments: Vec<String> = game_move_times(db, game)
+        .await?
+        .into_iter()
+        .map(|(spent_ms, clock_ms)| timing::comment(spent_ms, clock_ms))
+        .collect();
+    // the players' notes follow the move times
+    let mut nags = vec![Vec::new(); sans.len()];
+    comments.resize(sans.len(), String::new());
+    for note in notes::of_game(db, game.id).await? {
+        let Some(i) = usize::try_from(note.ply - 1).ok().filter(|&i| i < sans.len()) else {
+            continue;
+        };
+        nags[i].extend(note.nag);
+        if !note.text.is_empty() {
+            let text = format!("{}: {}", user_name(db, note.user_id).await?, note.text);
+            comments[i] = [comments[i].as_str(), &text].join(" ").trim_start().to_string();
+        }
+    }
+    Ok(pgn::render(&headers, &sans, &nags, &comments, game.result()))
+}
+
+/// Updates both players' ratings after a decisive or drawn game.
+async fn rate_game(db: &Pool<Sqlite>, game: &Game) -> Result<()> {
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(());
+    };
+    if !game.rated() {
+        return Ok(());
+    }
+    let score = match (game.winner, game.termination.and_then(Termination::from_i64)) {
+        (Some(true), _) => 1.0,
+        (Some(false), _) => 0.0,
+        (None, Some(Termination::Draw)) => 0.5,
+        _ => return Ok(()),
+    };
+
+    let category = game.category();
+
+    // the overall rating, and the one for the game's category that players see
+    let mut tx = db.begin().await?;
+    let (mut overall, mut rated) = (Vec::with_capacity(2), Vec::with_capacity(2));
+    for id in [w_id, b_id] {
+        let (rating, deviation): (f64, f64) = sqlx::query_as("select rating, rating_deviation from users where id = $1")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        overall.push(Rating { rating, deviation });
+        rated.push(rating::get(&mut *tx, id, category).await?);
+    }
+    let after = |white: Rating, black: Rating| (white.after_game(black, score), black.after_game(white, 1.0 - score));
+    let (overall_white, overall_black) = after(overall[0], overall[1]);
+    let (white, black) = (rated[0], rated[1]);
+    let (new_white, new_black) = after(white, black);
+
+    for (id, overall, new) in [(w_id, overall_white, new_white), (b_id, overall_black, new_black)] {
+        sqlx::query("update users set rating = $2, rating_deviation = $3, rated_games = rated_games + 1, deviation_updated_at = unixepoch() where id = $1")
+            .bind(id)
+            .bind(overall.rating)
+            .bind(overall.deviation)
+            .execute(&mut *tx)
+            .await?;
+        rating::record(&mut *tx, id, category, new).await?;
+    }
+    let diff = |old: Rating, new: Rating| new.rating.round() as i64 - old.rating.round() as i64;
+    sqlx::query("update games set w_rating = $2, b_rating = $3, w_rating_diff = $4, b_rating_diff = $5 where id = $1")
+        .bind(game.id)
+        .bind(white.rating.round() as i64)
+        .bind(black.rating.round() as i64)
+        .bind(diff(white, new_white))
+        .bind(diff(black, new_black))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    debug!("rated game {} as {}", game.id, category.as_str());
+    Ok(())
+}
+
+/// Rates a game that just ended and sends both players its summary.
+async fn finish_game(db: &Pool<Sqlite>, client: &Bot, id: i64) -> Result<()> {
+    sqlx::query("update users set active_game_id = null where active_game_id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    if let Some(game) = game_by_id(db, id).await? {
+        rate_game(db, &game).await?;
+        for (player, message_id) in [(game.w_id, game.w_message_id), (game.b_id, game.b_message_id)] {
+            if let Some(player) = player {
+                repin_board(db, client, player, message_id, None).await?;
+            }
+        }
+    }
+    send_summary(db, client, id).await?;
+    follows::game_finished(db, client, id).await?;
+    commentary::game_finished(db, client, id).await?;
+    featured::game_finished(db, client, id).await?;
+    teams::game_finished(db, client, id).await
+}
+
+/// Moves the pinned board message from `old` to `new` for players who asked
+/// for it. Failing to pin is logged rather than reported to the player.
+async fn repin_board(
+    db: &Pool<Sqlite>,
+    client: &Bot,
+    user_id: i64,
+    old: Option<i32>,
+    new: Option<i32>,
+) -> Result<()> {
+    let pin: bool = sqlx::query_scalar("select pin_board from users where id = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await?;
+    if !pin {
+        return Ok(());
+    }
+    if let Some(old) = old {
+        if let Err(e) = client.unpin_message(packed_chat(user_id), old).await {
+            debug!("cannot unpin board for {user_id}: {e}");
+        }
+    }
+    if let Some(new) = new {
+        if let Err(e) = client.pin_message(packed_chat(user_id), new).await {
+            debug!("cannot pin board for {user_id}: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Sends both players a summary of a finished game.
+async fn send_summary(db: &Pool<Sqlite>, client: &Bot, id: i64) -> Result<()> {
+    let Some(game) = game_by_id(db, id).await? else {
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(());
+    };
+    let sans = san_moves(game.start_fen(), &game_ucis(db, id).await?);
+
+    let termination = game.termination.and_then(Termination::from_i64);
+    let mut text = format!(
+        "Game #{id}: {}\n{}{}, {} moves",
+        game.result().replace("1/2", "½"),
+        termination.map_or("Unknown", Termination::reason),
+        if game.armageddon && termination == Some(Termination::Draw) { ", Black wins the armageddon" } else { "" },
+        sans.len().div_ceil(2),
+    );
+    // a set-up position has no opening
+    if let Some(opening) = openings::name(&sans).filter(|_| game.initial_fen.is_none()) {
+        text = format!("{text}\nOpening: {opening}");
+    }
+    if let (Some(w_rating), Some(b_rating), Some(w_diff), Some(b_diff)) =
+        (game.w_rating, game.b_rating, game.w_rating_diff, game.b_rating_diff)
+    {
+        text = format!(
+            "{text}\n{} ratings: White {} ({w_diff:+}), Black {} ({b_diff:+})",
+            game.category().name(),
+            w_rating + w_diff,
+            b_rating + b_diff,
+        );
+    }
+    let spent: Vec<i64> = game_move_times(db, &game).await?.into_iter().map(|(ms, _)| ms).collect();
+    if let Some(times) = timing::describe(&spent) {
+        text = format!("{text}\nTime per move:\n{times}");
+    }
+    for c in [packed_chat(w_id), packed_chat(b_id)] {
+        client.send_message(c, text.as_str()).await?;
+    }
+    Ok(())
+}
+
+fn packed_chat(id: i64) -> PackedChat {
+    PackedChat {
+        id,
+        ty: grammers_session::PackedType::User,
+        access_hash: None,
+    }
+}
+
+/// Zobrist hash of a position for repetition checks, as stored in `moves.zobrist`.
+fn position_hash(position: &Chess) -> i64 {
+    position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0 as i64
+}
+
+/// How many times the position with this hash has occurred in a game,
+/// counting the starting position.
+async fn repetitions<'e>(db: impl Executor<'e, Database = Sqlite>, game: &Game, zobrist: i64) -> Result<i64> {
+    let played: i64 = sqlx::query_scalar("select count(*) from moves where game_id = $1 and zobrist = $2")
+        .bind(game.id)
+        .bind(zobrist)
+        .fetch_one(db)
+        .await?;
+    let initial = position_hash(&position_from_fen(game.start_fen())) == zobrist;
+    Ok(played + initial as i64)
+}
+
+fn position_from_fen(fen: &str) -> Chess {
+    fen.parse::<Fen>()
+        .expect("fen from db")
+        .into_position(CastlingMode::Standard)
+        .expect("valid initial position")
+}
+
+/// The move with a queen promotion added, for players who let pawns reaching
+/// the last rank become queens without saying so.
+fn parse_auto_queen(language: &str, notation: &str, board: &impl Position) -> Option<Move> {
+    let notation = normalize_move(&localize_move(language, notation));
+    parse_move(&format!("{notation}=Q"), board).or_else(|| parse_move(&format!("{notation}q"), board))
+}
+
+/// Reads a move in SAN or UCI, forgiving the ways people actually type them:
+/// `e2-e4`, `0-0` or `o-o`, `Nf3+!`, `nf3`, `♘f3`, `exd6 e.p.`.
+fn parse_move(notation: &str, board: &impl Position) -> Option<Move> {
+    let notation = normalize_move(notation);
+    // `bc4` is a bishop only if it isn't a pawn capture
+    let capitalized = match notation.chars().next() {
+        Some(c @ ('n' | 'b' | 'r' | 'q' | 'k')) => Some(format!("{}{}", c.to_ascii_uppercase(), &notation[1..])),
+        _ => None,
+    };
+    // `x` is part of a SAN capture but only gets in the way of `e4xd5`
+    let candidates = [Some(notation.clone()), capitalized]
+        .into_iter()
+        .flatten()
+        .flat_map(|notation| [notation.clone(), notation.replace('x', "")]);
+    for notation in candidates {
+        if let Some(m) = parse_strict_move(&notation, board) {
+            return Some(m);
+        }
+    }
+    None
+}
+
+/// A move typed by a user, who may use their language's piece letters.
+fn parse_typed_move(language: &str, notation: &str, board: &impl Position) -> Option<Move> {
+    parse_move(&localize_move(language, notation), board)
+}
+
+fn parse_strict_move(notation: &str, board: &impl Position) -> Option<Move> {
+    if let Some(m) = San::from_ascii(notation.as_bytes())
+        .ok()
+        .and_then(|san| san.to_move(board).ok())
+    {
+        return Some(m);
+    }
+
+    Uci::from_ascii(notation.as_bytes())
+        .ok()
+        .and_then(|uci| uci.to_move(board).ok())
+}
+
+/// Piece letters other than English ones, by language, and what they stand
+/// for. Only the languages replies can be written in are ever used.
+const PIECE_LETTERS: &[(&str, &[(&str, char)])] = &[
+    ("de", &[("K", 'K'), ("D", 'Q'), ("T", 'R'), ("L", 'B'), ("S", 'N')]),
+    ("es", &[("R", 'K'), ("D", 'Q'), ("T", 'R'), ("A", 'B'), ("C", 'N')]),
+    ("fr", &[("R", 'K'), ("D", 'Q'), ("T", 'R'), ("F", 'B'), ("C", 'N')]),
+    ("it", &[("R", 'K'), ("D", 'Q'), ("T", 'R'), ("A", 'B'), ("C", 'N')]),
+    ("nl", &[("K", 'K'), ("D", 'Q'), ("T", 'R'), ("L", 'B'), ("P", 'N')]),
+    ("pl", &[("K", 'K'), ("H", 'Q'), ("W", 'R'), ("G", 'B'), ("S", 'N')]),
+    ("pt", &[("R", 'K'), ("D", 'Q'), ("T", 'R'), ("B", 'B'), ("C", 'N')]),
+    ("ru", &[("Кр", 'K'), ("Ф", 'Q'), ("Л", 'R'), ("С", 'B'), ("К", 'N')]),
+    ("uk", &[("Кр", 'K'), ("Ф", 'Q'), ("Т", 'R'), ("С", 'B'), ("К", 'N')]),
+];
+
+/// The move with the language's piece letters, at the start and after `=`,
+/// replaced by English ones.
+fn localize_move(language: &str, notation: &str) -> String {
+    let notation = notation.trim();
+    let Some((_, letters)) = PIECE_LETTERS.iter().find(|(l, _)| *l == language) else {
+        return notation.to_string();
+    };
+    let replace = |s: &str| {
+        // `Кр` before `К`
+        match letters.iter().find(|(letter, _)| s.starts_with(letter)) {
+            Some((letter, piece)) => format!("{piece}{}", &s[letter.len()..]),
+            None => s.to_string(),
+        }
+    };
+    match notation.split_once('=') {
+        Some((m, promotion)) => format!("{}={}", replace(m), replace(promotion)),
+        None => replace(notation),
+    }
+}
+
+/// The piece letter for a figurine, or nothing for a pawn.
+fn figurine_letter(c: char) -> Option<Option<char>> {
+    match c {
+        '♔' | '♚' => Some(Some('K')),
+        '♕' | '♛' => Some(Some('Q')),
+        '♖' | '♜' => Some(Some('R')),
+        '♗' | '♝' => Some(Some('B')),
+        '♘' | '♞' => Some(Some('N')),
+        '♙' | '♟' => Some(None),
+        _ => None,
+    }
+}
+
+/// The move without spaces, annotations, en passant suffixes or dashes, and
+/// with figurines, castling and promotions spelled as SAN has them.
+fn normalize_move(notation: &str) -> String {
+    let mut s: String = notation
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .filter_map(|c| figurine_letter(c).unwrap_or(Some(c)))
+        .collect();
+    s.truncate(s.trim_end_matches(['+', '#', '!', '?']).len());
+    for suffix in ["e.p.", "ep"] {
+        if s.to_ascii_lowercase().ends_with(suffix) && s.len() > suffix.len() {
+            s.truncate(s.len() - suffix.len());
+            break;
+        }
+    }
+    match s.to_ascii_lowercase().replace('0', "o").as_str() {
+        "o-o" | "oo" => return "O-O".to_string(),
+        "o-o-o" | "ooo" => return "O-O-O".to_string(),
+        _ => {}
+    }
+    s = s.replace('-', "");
+    if let Some((m, piece)) = s.split_once('=') {
+        s = format!("{m}={}", piece.to_ascii_uppercase());
+    }
+    s
+}
+
+const START_USAGE: &str = "Usage: /start [5+3|15+10|3d] [rated|casual] [white|black|random] [fog] [armageddon] \
+    [club <name>] [vs <user id or @username>], or /start bot [queen-odds|rook-odds|knight-odds|pawn-odds] \
+    [5+3|3d] [white|black] to play the engine";
+
+async fn on_start(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    start_game(state, user_id, args, None).await?;
+    Ok(())
+}
+
+/// Creates or joins a game as asked for with `/start`, from the position set
+/// up with `/edit` if `initial_fen` is given, returning whether it did.
+async fn start_game(state: &mut State, user_id: i64, args: &str, initial_fen: Option<&str>) -> Result<bool> {
+    if in_maintenance(&state.db).await? {
+        state.client.send_message(packed_chat(user_id), templates::text(state, "maintenance", &[])).await?;
+        return Ok(false);
+    }
+    let (mut preference, mut variant, mut club, mut opponent) = (None, Variant::Standard, None, None);
+    let (mut armageddon, mut join, mut pace, mut casual) = (false, None, None, None);
+    let (mut vs_bot, mut odds) = (false, None);
+    let mut args = args.split_whitespace();
+    while let Some(arg) = args.next() {
+        let has_value = args.clone().next().is_some();
+        if let Some(parsed) = Pace::parse(arg) {
+            let text = match parsed {
+                Ok(parsed) if pace.is_none() => {
+                    pace = Some(parsed);
+                    continue;
+                }
+                Ok(_) => format!("Give one time control.\n{START_USAGE}"),
+                Err(e) => format!("{e}\n{START_USAGE}"),
+            };
+            state.client.send_message(packed_chat(user_id), text).await?;
+            return Ok(false);
+        }
+        match arg {
+            "rated" => casual = Some(false),
+            "casual" => casual = Some(true),
+            "random" => preference = None,
+            "white" => preference = Some(Color::White),
+            "black" => preference = Some(Color::Black),
+            "fog" => variant = Variant::FogOfWar,
+            "armageddon" => armageddon = true,
+            "bot" => vs_bot = true,
+            arg if exhibition::Odds::parse(arg).is_some() => odds = exhibition::Odds::parse(arg),
+            "club" if club.is_none() && has_value => club = args.next(),
+            "vs" if opponent.is_none() && has_value => opponent = args.next(),
+            // recorded on first contact
+            payload if payload.starts_with(invites::PAYLOAD_PREFIX) => {}
+            // a seek's join button
+            payload if payload.starts_with(seeks::PAYLOAD_PREFIX) => {
+                join = payload[seeks::PAYLOAD_PREFIX.len()..].parse::<i64>().ok();
+            }
+            payload if payload.starts_with(review::PAYLOAD_PREFIX) => {
+                match payload[review::PAYLOAD_PREFIX.len()..].parse::<i64>() {
+                    Ok(game_id) => review::start(state, user_id, game_id).await?,
+                    Err(_) => review::on_review(state, user_id, "").await?,
+                }
+                return Ok(false);
+            }
+            _ => {
+                state.client.send_message(packed_chat(user_id), START_USAGE).await?;
+                return Ok(false);
+            }
+        }
+    }
+    if let (Some(Pace::Days(_)), true) = (pace, armageddon) {
+        let text = "Armageddon games need a clock, e.g. /start 5+3 armageddon.";
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(false);
+    }
+    let problem = if odds.is_some() && !vs_bot {
+        Some("Odds are given by the bot, e.g. /start bot queen-odds.")
+    } else if vs_bot && (club.is_some() || opponent.is_some() || join.is_some()) {
+        Some("The bot plays on its own, not in clubs or challenges.")
+    } else if vs_bot && (variant != Variant::Standard || armageddon || casual == Some(false)) {
+        Some("Games against the bot are unrated standard chess.")
+    } else if odds.is_some() && initial_fen.is_some() {
+        Some("Odds are given from the usual starting position.")
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        state.client.send_message(packed_chat(user_id), problem).await?;
+        return Ok(false);
+    }
+    let mut initial_fen = initial_fen.map(str::to_string);
+    if initial_fen.is_some() {
+        let problem = match (variant, casual) {
+            (Variant::FogOfWar, _) => Some("Fog of war games start from the usual position."),
+            (_, Some(false)) => Some("Games from a set-up position are unrated."),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            state.client.send_message(packed_chat(user_id), problem).await?;
+            return Ok(false);
+        }
+        casual = Some(true);
+    }
+
+    // the position comes from the editor, the only thing the user has open
+    if let Some(what) = training(&state.db, user_id).await?.filter(|_| initial_fen.is_none()) {
+        state
+            .client
+            .send_message(packed_chat(user_id), format!("Finish your {what} first."))
+            .await?;
+        return Ok(false);
+    }
+    if let Some(id) = join {
+        let seek = game_by_id(&state.db, id).await?;
+        let Some(seek) = seek.filter(|g| !g.ended && (g.w_id.is_none() || g.b_id.is_none())) else {
+            state
+                .client
+                .send_message(packed_chat(user_id), format!("Game #{id} was already taken or cancelled."))
+                .await?;
+            return Ok(false);
+        };
+        (variant, armageddon) = (seek.variant(), seek.armageddon);
+        initial_fen = seek.initial_fen;
+    }
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        debug!("already in game {user_id}");
+        state
+            .client
+            .send_message(
+                packed_chat(user_id),
+                "You are already playing. Type `resign` to leave.",
+            )
+            .await?;
+        return Ok(false);
+    };
+    if vs_bot {
+        return exhibition::against_engine(state, user_id, preference, pace, odds, initial_fen).await;
+    }
+    let club = match club {
+        Some(name) => match clubs::find(&state.db, name).await? {
+            Some(club) if clubs::is_member(&state.db, club.id, user_id).await? => Some(club),
+            found => {
+                let text = match found {
+                    Some(club) => templates::text(state, "not_in_club", &[("club", &club.name)]),
+                    None => templates::text(state, "no_club", &[("club", &name)]),
+                };
+                state.client.send_message(packed_chat(user_id), text).await?;
+                return Ok(false);
+            }
+        },
+        None => None,
+    };
+    let opponent = match opponent {
+        Some(arg) => match find_user(&state.db, arg).await? {
+            Some(id) if id != user_id => Some(id),
+            _ => {
+                state
+                    .client
+                    .send_message(packed_chat(user_id), templates::text(state, "no_player", &[("player", &arg)]))
+                    .await?;
+                return Ok(false);
+            }
+        },
+        None => None,
+    };
+
+    // A seek's creator sits in the slot of the color they asked for, or in
+    // the white slot with `random_color` set if they don't mind.
+    // Someone else may take the seek between finding and claiming it, in
+    // which case the next one is tried, unless it was the one asked for.
+    let paired = loop {
+        let maybe_pairable: Option<(i64, Option<i64>, Option<i64>, bool)> = sqlx::query_as(
+            "select id, w_id, b_id, random_color from games where (b_id is null or w_id is null) and ended = 0
+            and (random_color or $1 is null or ($1 and w_id is null) or (not $1 and b_id is null)) and variant = $2
+            and club_id is $3 and challenged_id is $4 and ($5 is null or w_id = $5 or b_id = $5) and armageddon = $6
+            and ($7 is null or id = $7) and ($8 is null or casual = $8)
+            and (not $9 or (initial_ms is $10 and increment_ms is $11 and delay_ms is null and days_per_move is $12))
+            and (initial_fen is $13 or ($13 is null and $5 is not null))
+            order by created_at limit 1",
+        )
+        .bind(preference.map(|c| c.is_white()))
+        .bind(variant as i64)
+        .bind(club.as_ref().map(|c| c.id))
+        // with an opponent, only their challenge to the user
+        .bind(opponent.map(|_| user_id))
+        .bind(opponent)
+        .bind(armageddon)
+        .bind(join)
+        .bind(casual)
+        // without a time control, any seek will do
+        .bind(pace.is_some())
+        .bind(match pace {
+            Some(Pace::Clock(tc)) => Some(tc.initial.as_millis() as i64),
+            _ => None,
+        })
+        .bind(match pace {
+            Some(Pace::Clock(tc)) => Some(tc.increment.as_millis() as i64),
+            _ => None,
+        })
+        .bind(match pace {
+            Some(Pace::Days(days)) => Some(days),
+            _ => None,
+        })
+        // accepting a challenge takes its position
+        .bind(&initial_fen)
+        .fetch_optional(&state.db)
+        .await?;
+        debug!("maybe_pairable? {maybe_pairable:?}");
+        let Some((id, w_id, b_id, random_color)) = maybe_pairable else {
+            break None;
+        };
+        let (w_id, b_id) = match (w_id, b_id) {
+            (Some(creator), None) if random_color => {
+                let color = preference.unwrap_or_else(|| Color::from_white(rand::random()));
+                match color {
+                    Color::White => (user_id, creator),
+                    Color::Black => (creator, user_id),
+                }
+            }
+            (Some(w_id), None) => (w_id, user_id),
+            (None, Some(b_id)) => (user_id, b_id),
+            _ => {
+                panic!("oh how surprising! you are stupid! {maybe_pairable:?}")
+            }
+        };
+        let claimed = sqlx::query_as::<_, (i64, i64, i64, Option<String>)>(
+            "update games set w_id = $1, b_id = $2, started_at = unixepoch(), last_move_at = unixepoch(),
+                w_clock_ms = initial_ms, b_clock_ms = coalesce(b_initial_ms, initial_ms), turn_started_ms = $4
+             where games.id = $3 and (w_id is null or b_id is null) and ended = 0
+             returning id, w_id, b_id, initial_fen",
+        )
+        .bind(w_id)
+        .bind(b_id)
+        .bind(id)
+        .bind(clock::now_ms())
+        .fetch_optional(&state.db)
+        .await?;
+        match claimed {
+            Some(game) => break Some(game),
+            None if join.is_some() => {
+                state
+                    .client
+                    .send_message(packed_chat(user_id), format!("Game #{id} was already taken or cancelled."))
+                    .await?;
+                return Ok(false);
+            }
+            None => debug!("seek {id} was taken first"),
+        }
+    };
+
+    if let Some((id, w_id, b_id, initial_fen)) = paired {
+        let (white, black) = (packed_chat(w_id), packed_chat(b_id));
+        for (chat, player, opponent, color, key) in [
+            (white, w_id, b_id, Color::White, "game_started_white"),
+            (black, b_id, w_id, Color::Black, "game_started_black"),
+        ] {
+            let config = state.config.get();
+            let player_settings = settings::get(&state.db, player).await?;
+            let language = player_settings.language();
+            let mut details = String::new();
+            if armageddon {
+                details.push('\n');
+                details.push_str(&armageddon_terms(state, player, id).await?);
+            }
+            if let Some(fen) = &initial_fen {
+                details.push_str(&starting_board(fen, color, player_settings.theme()));
+            }
+            // spectators would see through the fog
+            if let (Some(url), false) = (&config.public_url, variant == Variant::FogOfWar) {
+                let url = format!("{url}/game/{id}");
+                details.push_str(&config.templates.render(language, "watch_link", &[("url", &url)]));
+            }
+            if variant == Variant::FogOfWar {
+                let board = fog::render(&position_from_fen(STARTING_FEN), color, player_settings.theme());
+                details.push_str(&config.templates.render(language, "fog_intro", &[("board", &board)]));
+            }
+            let opponent = player_card(&state.db, opponent).await?;
+            let vars: [(&str, &dyn Display); 3] = [("id", &id), ("opponent", &opponent), ("details", &details)];
+            let text = config.templates.render(language, key, &vars);
+            state.client.send_message(chat, text).await?;
+        }
+        follows::game_started(&state.db, &state.client, state.config.get().public_url.as_deref(), id).await?;
+        seeks::taken(&state.db, &state.client, id).await?;
+    } else {
+        let (tc, days_per_move) = match pace {
+            Some(Pace::Clock(tc)) => (Some(tc), None),
+            Some(Pace::Days(days)) => (None, Some(days)),
+            None => (state.config.get().time_control, None),
+        };
+        let (tc, b_initial) = match armageddon {
+            true => {
+                let (tc, b_initial) = armageddon_time_control(tc);
+                (Some(tc), Some(b_initial))
+            }
+            false => (tc, None),
+        };
+        let delay = tc.and_then(|tc| tc.delay);
+        let (w_id, b_id) = match preference {
+            Some(Color::Black) => (None, Some(user_id)),
+            _ => (Some(user_id), None),
+        };
+        let (id,) = sqlx::query_as::<_, (i64,)>("insert into games (w_id, b_id, random_color, winner, ended, fen, initial_ms, increment_ms, delay_ms, bronstein, variant, club_id, challenged_id, armageddon, b_initial_ms, casual, days_per_move, initial_fen) values ($1, $7, $8, null, 0, $2, $3, $4, $5, $6, $9, $10, $11, $12, $13, $14, $15, $16) returning id")
+            .bind(w_id)
+            .bind(initial_fen.as_deref().unwrap_or(STARTING_FEN))
+            .bind(tc.map(|tc| tc.initial.as_millis() as i64))
+            .bind(tc.map(|tc| tc.increment.as_millis() as i64))
+            .bind(delay.map(|d| match d {
+                Delay::Simple(d) | Delay::Bronstein(d) => d.as_millis() as i64,
+            }))
+            .bind(delay.map(|d| matches!(d, Delay::Bronstein(_))))
+            .bind(b_id)
+            .bind(preference.is_none())
+            .bind(variant as i64)
+            .bind(club.as_ref().map(|c| c.id))
+            .bind(opponent)
+            .bind(armageddon)
+            .bind(b_initial.map(|d| d.as_millis() as i64))
+            .bind(casual == Some(true))
+            .bind(days_per_move)
+            .bind(&initial_fen)
+            .fetch_one(&state.db)
+            .await?;
+        debug!("create new game {id}");
+        let mut kind = pace.map_or(String::new(), |pace| format!("{pace} "));
+        if casual == Some(true) {
+            kind.push_str("casual ");
+        }
+        if variant != Variant::Standard {
+            kind.push_str(&format!("{} ", variant.name().to_lowercase()));
+        }
+        // accepting takes the same options
+        let mut options = kind.replace("fog of war ", "fog ");
+        if armageddon {
+            kind.push_str("armageddon ");
+            options.push_str("armageddon ");
+        }
+        // accepting takes the challenge's position without saying so
+        if initial_fen.is_some() {
+            kind.push_str("from-position ");
+        }
+        let text = match (&club, opponent) {
+            (_, Some(opponent)) => {
+                let player = player_card(&state.db, user_id).await?;
+                let vars: [(&str, &dyn Display); 4] =
+                    [("player", &player), ("kind", &kind), ("options", &options), ("id", &user_id)];
+                let challenge = templates::text_for(state, opponent, "challenge", &vars).await?;
+                state.client.send_message(packed_chat(opponent), challenge).await?;
+                let name = user_name(&state.db, opponent).await?;
+                templates::text(state, "created_challenge", &[("kind", &kind), ("opponent", &name)])
+            }
+            (Some(club), None) => templates::text(state, "created_in_club", &[("kind", &kind), ("club", &club.name)]),
+            (None, None) => templates::text(state, "created", &[("kind", &kind)]),
+        };
+        state.client.send_message(packed_chat(user_id), text).await?;
+        if club.is_none() && opponent.is_none() {
+            seeks::posted(&state.db, &state.client, &state.bot_username, id).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// The position a game from a set-up position starts from, for the message
+/// telling a player the game started.
+fn starting_board(fen: &str, color: Color, theme: diagram::Theme) -> String {
+    let board = diagram::render(position_from_fen(fen).board(), color, Bitboard::FULL, theme);
+    format!("\nThe game starts from this position:\n{board}")
+}
+
+/// White's time control and black's starting time for an armageddon game
+/// on the bot's time control.
+fn armageddon_time_control(tc: Option<TimeControl>) -> (TimeControl, Duration) {
+    let tc = tc.unwrap_or(TimeControl {
+        initial: DEFAULT_ARMAGEDDON_TIME,
+        increment: Duration::ZERO,
+        delay: None,
+    });
+    (tc, tc.initial.mul_f64(ARMAGEDDON_BLACK_TIME))
+}
+
+/// The time and draw odds of an armageddon game, for one of its players.
+async fn armageddon_terms(state: &State, user_id: i64, id: i64) -> Result<String> {
+    let (w_ms, b_ms): (Option<i64>, Option<i64>) =
+        sqlx::query_as("select initial_ms, coalesce(b_initial_ms, initial_ms) from games where id = $1")
+            .bind(id)
+            .fetch_one(&state.db)
+            .await?;
+    let white = w_ms.map_or("?".to_string(), clock::format_clock);
+    let black = b_ms.map_or("?".to_string(), clock::format_clock);
+    templates::text_for(state, user_id, "armageddon_terms", &[("white", &white), ("black", &black)]).await
+}
+
+async fn on_move(state: &mut State, user_id: i64, notation: &str) -> Result<()> {
+    if in_maintenance(&state.db).await? {
+        state.client.send_message(packed_chat(user_id), templates::text(state, "maintenance", &[])).await?;
+        return Ok(());
+    }
+    let settings = settings::get(&state.db, user_id).await?;
+    let config = state.config.get();
+    let message = |key, vars: &[(&str, &dyn Display)]| config.templates.render(&state.language, key, vars);
+    let mut tx = state.db.begin().await?;
+
+    let Some(game) = ongoing_game(&mut *tx, user_id).await? else {
+        let text = message("no_game", &[]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        let text = message("waiting_for_opponent", &[]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    };
+    let id = game.id;
+    let (mut w_clock_ms, mut b_clock_ms, mut turn_started_ms) =
+        (game.w_clock_ms, game.b_clock_ms, game.turn_started_ms);
+    // the engine's moves in games against the bot are played outside this state
+    if state.boards.get(&id).is_some_and(|board| {
+        Fen::from_position(board.clone(), EnPassantMode::Always).to_string() != game.fen
+    }) {
+        state.boards.remove(&id);
+    }
+    let board = state.boards.entry(id).or_insert_with(|| position_from_fen(&game.fen));
+    if !(board.turn() == Color::White && user_id == w_id
+        || board.turn() == Color::Black && user_id == b_id)
+    {
+        let text = message("not_your_turn", &[]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    }
+    // a confirmation plays the move waiting for it
+    let pending: Option<String> =
+        sqlx::query_scalar("select uci from pending_moves where user_id = $1 and game_id = $2 and ply = $3")
+            .bind(user_id)
+            .bind(id)
+            .bind(game.plies)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let notation = match (notation.trim().to_lowercase().as_str(), &pending) {
+        ("yes" | "y" | "ok", Some(uci)) => uci.clone(),
+        ("no" | "n", Some(_)) => {
+            sqlx::query("delete from pending_moves where user_id = $1")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            let text = message("move_cancelled", &[]);
+            state.client.send_message(packed_chat(user_id), text).await?;
+            return Ok(());
+        }
+        _ => notation.to_string(),
+    };
+    let parsed = parse_typed_move(&state.language, &notation, board);
+    let auto_queen = || parse_auto_queen(&state.language, &notation, board);
+    let Some(m) = parsed.or_else(|| settings.auto_queen.then(auto_queen).flatten()) else {
+        let text = message("invalid_move", &[]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    };
+    if !board.is_legal(&m) {
+        let text = message("illegal_move", &[]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    }
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    if settings.confirm_moves && pending.as_deref() != Some(uci.as_str()) {
+        sqlx::query(
+            "insert into pending_moves (user_id, game_id, ply, uci) values ($1, $2, $3, $4)
+             on conflict (user_id) do update set game_id = excluded.game_id, ply = excluded.ply, uci = excluded.uci",
+        )
+        .bind(user_id)
+        .bind(id)
+        .bind(game.plies)
+        .bind(&uci)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let played = settings.notation().write(board, &m);
+        let text = message("confirm_move", &[("id", &id), ("move", &played)]);
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    }
+    if let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(clock::now_ms()) {
+        let clock_ms = if board.turn().is_white() { w_clock_ms } else { b_clock_ms };
+        if clock_ms <= 0 {
+            let text = message("time_run_out", &[]);
+            state.client.send_message(packed_chat(user_id), text).await?;
+            return Ok(());
+        }
+    }
+    if let Some(tc) = game.time_control() {
+        let now = clock::now_ms();
+        let started_ms = turn_started_ms.unwrap_or(now);
+        let (clock_ms, opponent) = match board.turn() {
+            Color::White => (&mut w_clock_ms, b_id),
+            Color::Black => (&mut b_clock_ms, w_id),
+        };
+        *clock_ms = clock_ms.map(|c| tc.after_move(c, started_ms, now));
+        turn_started_ms = if on_vacation(&mut *tx, opponent).await? {
+            None
+        } else {
+            Some(now)
+        };
+    }
+    let mover_clock_ms = match board.turn() {
+        Color::White => w_clock_ms,
+        Color::Black => b_clock_ms,
+    };
+    let before = board.clone();
+    board.play_unchecked(&m);
+    debug!("playing move {m}");
+
+    let zobrist = position_hash(board);
+    let occurrences = repetitions(&mut *tx, &game, zobrist).await? + 1;
+    let fivefold = occurrences >= 5;
+    let ended = board.is_game_over() || fivefold;
+    let fen = Fen::from_position(board.clone(), shakmaty::EnPassantMode::Always).to_string();
+    let (winner, termination) = if board.is_checkmate() {
+        (Some(!board.turn()), Some(Termination::Checkmate))
+    } else if ended {
+        (None, Some(Termination::Draw))
+    } else {
+        (None, None)
+    };
+
+    // one round trip: the ply comes from the game row rather than counting moves
+    sqlx::query(
+        "insert into moves (game_id, ply, uci, played_at, clock_ms, zobrist, message_id) values ($5, $9, $10, $11, $12, $13, $15);
+         delete from pending_moves where game_id = $5;
+         update games set ended = $1, winner = coalesce($2, case when armageddon and $3 = $14 then 0 end), termination = $3, fen = $4, last_move_at = unixepoch(), ended_at = case when $1 then unixepoch() end, w_clock_ms = $6, b_clock_ms = $7, turn_started_ms = $8, plies = $9 + 1 where id = $5",
+    )
+    .bind(ended)
+    .bind(winner.map(|c| c.is_white()))
+    .bind(termination.map(|t| t as i64))
+    .bind(&fen)
+    .bind(id)
+    .bind(w_clock_ms)
+    .bind(b_clock_ms)
+    .bind(turn_started_ms)
+    .bind(game.plies)
+    .bind(&uci)
+    .bind(clock::now_ms())
+    .bind(mover_clock_ms)
+    .bind(zobrist)
+    .bind(Termination::Draw as i64)
+    .bind(state.message_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let winner_label = match winner {
+        Some(winner) => {
+            let winner_id = if winner.is_white() { w_id } else { b_id };
+            player_label(&state.db, winner, winner_id).await?
+        }
+        None => String::new(),
+    };
+    let announcement = if winner.is_some() {
+        Some("checkmate")
+    } else if board.is_stalemate() {
+        Some("stalemate")
+    } else if board.is_insufficient_material() {
+        Some("insufficient_material")
+    } else if fivefold {
+        Some("fivefold")
+    } else {
+        ended.then_some("draw")
+    };
+    let fog = game.variant() == Variant::FogOfWar;
+    let clocks = match (w_clock_ms, b_clock_ms) {
+        (Some(w_clock_ms), Some(b_clock_ms)) => format!("\n{}", clock::format_clocks(w_clock_ms, b_clock_ms)),
+        _ => String::new(),
+    };
+    let mut message_ids = Vec::with_capacity(2);
+    let mut board_texts = Vec::with_capacity(2);
+    for (color, player, old_message_id) in [
+        (Color::White, w_id, game.w_message_id),
+        (Color::Black, b_id, game.b_message_id),
+    ] {
+        // each player reads the move in their own notation and language
+        let player_settings = settings::get(&state.db, player).await?;
+        let played = player_settings.notation().write(&before, &m);
+        let language = player_settings.language();
+        let message = |key, vars: &[(&str, &dyn Display)]| config.templates.render(language, key, vars);
+        // each side only learns what its own pieces can see
+        let player_text = if fog && !ended {
+            let board_text = fog::render(board, color, player_settings.theme());
+            let mut player_text = if color == board.turn() {
+                message("opponent_moved", &[("id", &id), ("board", &board_text)])
+            } else {
+                message("you_played", &[("id", &id), ("move", &played), ("board", &board_text)])
+            };
+            if color == board.turn() && board.is_check() {
+                player_text.push_str(&message("you_are_in_check", &[]));
+            }
+            player_text
+        } else {
+            let mut details = String::new();
+            // captures are counted from the usual starting position
+            if let Some(material) = material::describe(board.board()).filter(|_| game.initial_fen.is_none()) {
+                details = format!("\n{material}");
+            }
+            if board.is_check() && !board.is_checkmate() {
+                details.push_str(&message("check", &[]));
+            }
+            if occurrences >= 3 && !ended {
+                details.push_str(&message("repetition", &[("count", &occurrences)]));
+            }
+            message("played", &[("id", &id), ("move", &played), ("fen", &fen), ("details", &details)])
+        };
+        let announcement = announcement.map(|key| {
+            let result = message(key, &[("id", &id), ("winner", &winner_label)]);
+            match winner {
+                None if game.armageddon => message("armageddon_draw", &[("result", &result)]),
+                _ => result,
+            }
+        });
+        // show fen image
+        let message = state
+            .client
+            .send_message(packed_chat(player), format!("{player_text}{clocks}"))
+            .await?;
+        message_ids.push(message.id());
+        board_texts.push(player_text);
+        if let Some(announcement) = &announcement {
+            state.client.send_message(packed_chat(player), announcement.as_str()).await?;
+        }
+        if !ended {
+            repin_board(&state.db, &state.client, player, old_message_id, Some(message.id())).await?;
+        }
+    }
+    if !ended && !fog {
+        // Remember the messages so that their clocks can be kept up to date.
+        sqlx::query(
+            "update games set w_message_id = $2, b_message_id = $3, w_board_text = $4, b_board_text = $5 where id = $1",
+        )
+        .bind(id)
+        .bind(message_ids[0])
+        .bind(message_ids[1])
+        .bind(&board_texts[0])
+        .bind(&board_texts[1])
+        .execute(&state.db)
+        .await?;
+    }
+    if ended {
+        state.boards.remove(&id);
+        finish_game(&state.db, &state.client, id).await?;
+    } else {
+        featured::game_moved(&state.db, &state.client, id).await?;
+    }
+    Ok(())
+}
+
+async fn on_clock(state: &mut State, user_id: i64) -> Result<()> {
+    let text = match ongoing_game(&state.db, user_id).await? {
+        None => templates::text(state, "no_game", &[]),
+        Some(Game {
+            initial_ms: None, ..
+        }) => "This game is not timed.".to_string(),
+        Some(game) => match game.clocks_at(clock::now_ms()) {
+            Some((w_clock_ms, b_clock_ms)) => format!(
+                "Game #{}\nWhite: {}\nBlack: {}\n{} to move{}.",
+                game.id,
+                clock::format_clock(w_clock_ms),
+                clock::format_clock(b_clock_ms),
+                if game.turn().is_white() { "White" } else { "Black" },
+                if game.turn_started_ms.is_none() { " (clock paused for vacation)" } else { "" },
+            ),
+            None => "The clock starts when an opponent joins.".to_string(),
+        },
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Sends the current position of the user's game again, from their side,
+/// with whose turn it is and the clocks.
+async fn on_board(state: &mut State, user_id: i64) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state.client.send_message(chat, templates::text(state, "no_game", &[])).await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        state.client.send_message(chat, templates::text(state, "waiting_for_opponent", &[])).await?;
+        return Ok(());
+    };
+    let color = if w_id == user_id { Color::White } else { Color::Black };
+    let opponent = if w_id == user_id { b_id } else { w_id };
+    let position = position_from_fen(&game.fen);
+    let fog = game.variant() == Variant::FogOfWar;
+    let theme = settings::theme(&state.db, user_id).await?;
+    let board = if fog {
+        fog::render(&position, color, theme)
+    } else {
+        diagram::render(position.board(), color, Bitboard::FULL, theme)
+    };
+    let mut text = format!(
+        "Game #{} against {}, move {}\n{board}",
+        game.id,
+        user_name(&state.db, opponent).await?,
+        position.fullmoves()
+    );
+    // in fog of war only the side in check can tell
+    text = match (position.turn() == color, position.is_check()) {
+        (true, true) => format!("{text}\nYour move, you are in check!"),
+        (true, false) => format!("{text}\nYour move."),
+        (false, true) if !fog => format!("{text}\nYour opponent's move, they are in check."),
+        (false, _) => format!("{text}\nYour opponent's move."),
+    };
+    if let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(clock::now_ms()) {
+        text = format!("{text}\n{}", clock::format_clocks(w_clock_ms, b_clock_ms));
+        if game.turn_started_ms.is_none() {
+            text = format!("{text} (paused for vacation)");
+        }
+    }
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// `/game [id]`: lists the user's games, or picks the one their moves and
+/// game commands go to.
+async fn on_game(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let games: Vec<(i64, Option<i64>, Option<i64>)> = sqlx::query_as(
+        "select id, w_id, b_id from games where (w_id = $1 or b_id = $1) and ended = 0 order by id",
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+    if games.is_empty() {
+        state.client.send_message(chat, templates::text(state, "no_game", &[])).await?;
+        return Ok(());
+    }
+    let arg = args.trim().trim_start_matches('#');
+    if arg.is_empty() {
+        let active = ongoing_game(&state.db, user_id).await?.map(|game| game.id);
+        let mut lines = Vec::with_capacity(games.len());
+        for (id, w_id, b_id) in games {
+            let opponent = match (w_id, b_id) {
+                (Some(w_id), Some(b_id)) => user_name(&state.db, if w_id == user_id { b_id } else { w_id }).await?,
+                _ => "nobody yet".to_string(),
+            };
+            let marker = if active == Some(id) { ", your moves go here" } else { "" };
+            lines.push(format!("#{id} against {opponent}{marker}"));
+        }
+        let text = format!("Your games:\n{}\nSwitch with /game <id>.", lines.join("\n"));
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+    let Some(id) = arg.parse::<i64>().ok().filter(|id| games.iter().any(|game| game.0 == *id)) else {
+        let text = format!("You are not playing a game #{arg}. /game lists yours.");
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    };
+    sqlx::query("update users set active_game_id = $2 where id = $1")
+        .bind(user_id)
+        .bind(id)
+        .execute(&state.db)
+        .await?;
+    state
+        .client
+        .send_message(chat, format!("Your moves now go to game #{id}."))
+        .await?;
+    on_board(state, user_id).await
+}
+
+/// Shows the opponent's last move on the board, for players coming back to a
+/// game after a while.
+async fn on_last(state: &mut State, user_id: i64) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state.client.send_message(chat, templates::text(state, "no_game", &[])).await?;
+        return Ok(());
+    };
+    if game.variant() == Variant::FogOfWar {
+        state.client.send_message(chat, "The moves are hidden in fog of war.").await?;
+        return Ok(());
+    }
+    let color = if game.w_id == Some(user_id) { Color::White } else { Color::Black };
+    let ucis = game_ucis(&state.db, game.id).await?;
+    let Some(ply) = (0..ucis.len()).rev().find(|&ply| game.mover(ply) != color) else {
+        state.client.send_message(chat, "Your opponent hasn't moved yet.").await?;
+        return Ok(());
+    };
+    let played_at: i64 = sqlx::query_scalar("select played_at from moves where game_id = $1 and ply = $2")
+        .bind(game.id)
+        .bind(ply as i64)
+        .fetch_one(&state.db)
+        .await?;
+    let sans = san_moves(game.start_fen(), &ucis);
+    let (Some(san), Ok(Uci::Normal { from, to, .. })) = (sans.get(ply), ucis[ply].parse::<Uci>()) else {
+        state.client.send_message(chat, "This game's moves can't be shown.").await?;
+        return Ok(());
+    };
+    let number = game.move_number(ply);
+    let when = settings::local_time(&state.db, user_id, played_at).await?;
+    let position = position_from_fen(&game.fen);
+    let mut text = format!(
+        "Your opponent played {number} {san} ({}) on {when}.\n{}",
+        ucis[ply],
+        diagram::with_move(position.board(), color, from, to, settings::theme(&state.db, user_id).await?)
+    );
+    if ply + 1 < ucis.len() {
+        text = format!("{text}\nYou have replied {}.", sans[ply + 1..].join(" "));
+    } else {
+        text = format!("{text}\nYour move.");
+    }
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+async fn on_flag(state: &mut State, user_id: i64) -> Result<()> {
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state
+            .client
+            .send_message(packed_chat(user_id), templates::text(state, "no_game", &[]))
+            .await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        state
+            .client
+            .send_message(packed_chat(user_id), templates::text(state, "waiting_for_opponent", &[]))
+            .await?;
+        return Ok(());
+    };
+    let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(clock::now_ms()) else {
+        state
+            .client
+            .send_message(packed_chat(user_id), "This game is not timed.")
+            .await?;
+        return Ok(());
+    };
+    let claimant = if user_id == w_id { Color::White } else { Color::Black };
+    let flagged_ms = if claimant.is_white() { b_clock_ms } else { w_clock_ms };
+    if game.turn() == claimant || flagged_ms > 0 {
+        state
+            .client
+            .send_message(packed_chat(user_id), "Your opponent still has time on the clock.")
+            .await?;
+        return Ok(());
+    }
+
+    state.boards.remove(&game.id);
+    flag_game(&state.db, &state.client, game.id, w_id, b_id, claimant).await
+}
+
+/// Ends a game whose side to move ran out of time, in favor of `winner`.
+async fn flag_game(
+    db: &Pool<Sqlite>,
+    client: &Bot,
+    id: i64,
+    w_id: i64,
+    b_id: i64,
+    winner: Color,
+) -> Result<()> {
+    if !end_game(db, id, Some(winner), Termination::Timeout).await? {
+        return Ok(());
+    }
+
+    let (winner_id, loser_id) = if winner.is_white() { (w_id, b_id) } else { (b_id, w_id) };
+    let text = format!(
+        "Game #{id}: {} ran out of time — {} wins",
+        player_label(db, !winner, loser_id).await?,
+        player_label(db, winner, winner_id).await?,
+    );
+    for c in [packed_chat(w_id), packed_chat(b_id)] {
+        client.send_message(c, text.as_str()).await?;
+    }
+    finish_game(db, client, id).await
+}
+
+/// Ends the game in a draw if its current position has occurred three times.
+async fn on_draw_claim(state: &mut State, user_id: i64) -> Result<()> {
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state
+            .client
+            .send_message(packed_chat(user_id), templates::text(state, "no_game", &[]))
+            .await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        state
+            .client
+            .send_message(packed_chat(user_id), templates::text(state, "waiting_for_opponent", &[]))
+            .await?;
+        return Ok(());
+    };
+    let zobrist: Option<i64> = sqlx::query_scalar("select zobrist from moves where game_id = $1 order by ply desc limit 1")
+        .bind(game.id)
+        .fetch_optional(&state.db)
+        .await?;
+    let occurrences = match zobrist {
+        Some(zobrist) => repetitions(&state.db, &game, zobrist).await?,
+        None => 1,
+    };
+    // fifty moves by each side without a capture or pawn move
+    let quiet_moves = position_from_fen(&game.fen).halfmoves() / 2;
+    let reason = if occurrences >= 3 {
+        "threefold repetition"
+    } else if quiet_moves >= 50 {
+        "the fifty-move rule"
+    } else {
+        let text = format!(
+            "No draw to claim: the current position has occurred {occurrences} times, it needs three, \
+             and the last {quiet_moves} moves had no capture or pawn move, it needs fifty."
+        );
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    };
+    if !end_game(&state.db, game.id, None, Termination::Draw).await? {
+        return Ok(());
+    }
+    state.boards.remove(&game.id);
+
+    let claimant = if user_id == w_id { Color::White } else { Color::Black };
+    let mut text = format!(
+        "Game #{}: {} claimed a draw by {reason}",
+        game.id,
+        player_label(&state.db, claimant, user_id).await?
+    );
+    if game.armageddon {
+        text = format!("{text}, so Black wins the armageddon");
+    }
+    for c in [packed_chat(w_id), packed_chat(b_id)] {
+        state.client.send_message(c, text.as_str()).await?;
+    }
+    finish_game(&state.db, &state.client, game.id).await?;
+    Ok(())
+}
+
+async fn on_resign(state: &mut State, user_id: i64) -> Result<()> {
+    let Some(game) = ongoing_game(&state.db, user_id).await? else {
+        state
+            .client
+            .send_message(packed_chat(user_id), templates::text(state, "no_game", &[]))
+            .await?;
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        end_game(&state.db, game.id, None, Termination::Aborted).await?;
+        seeks::cancelled(&state.db, &state.client, game.id).await?;
+        state
+            .client
+            .send_message(packed_chat(user_id), format!("Game #{} was cancelled.", game.id))
+            .await?;
+        return Ok(());
+    };
+
+    let loser = if user_id == w_id { Color::White } else { Color::Black };
+    if !end_game(&state.db, game.id, Some(!loser), Termination::Resign).await? {
+        return Ok(());
+    }
+    state.boards.remove(&game.id);
+
+    let text = format!("Game #{}: {} resigned", game.id, player_label(&state.db, loser, user_id).await?);
+    for c in [packed_chat(w_id), packed_chat(b_id)] {
+        state.client.send_message(c, text.as_str()).await?;
+    }
+    finish_game(&state.db, &state.client, game.id).await?;
+    Ok(())
+}
+
+async fn on_pgn(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    // without an id, the user's own game so far
+    if args.trim().is_empty() {
+        let text = match ongoing_game(&state.db, user_id).await? {
+            Some(game) if game.variant() == Variant::FogOfWar => "The moves are hidden in fog of war.".to_string(),
+            Some(game) => game_pgn(&state.db, Some(&state.bot_username), &game).await?,
+            None => "You are not playing. Usage: /pgn [game id]".to_string(),
+        };
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    }
+    let game = match args.trim().trim_start_matches('#').parse() {
+        Ok(id) => game_by_id(&state.db, id).await?,
+        Err(_) => None,
+    };
+    let text = match game {
+        Some(game) if game.ended => game_pgn(&state.db, Some(&state.bot_username), &game).await?,
+        Some(_) => "This game is still in progress.".to_string(),
+        None => "Usage: /pgn [game id]".to_string(),
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// The FEN of the user's current game, or of a finished game by id.
+async fn on_fen(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let text = if args.trim().is_empty() {
+        match ongoing_game(&state.db, user_id).await? {
+            // the FEN would show the pieces hidden in the fog
+            Some(game) if game.variant() == Variant::FogOfWar => "The position is hidden in fog of war.".to_string(),
+            Some(game) => game.fen,
+            None => "You are not playing. Usage: /fen [game id]".to_string(),
+        }
+    } else {
+        let game = match args.trim().trim_start_matches('#').parse() {
+            Ok(id) => game_by_id(&state.db, id).await?,
+            Err(_) => None,
+        };
+        match game {
+            Some(game) if game.ended => game.fen,
+            Some(_) => "This game is still in progress.".to_string(),
+            None => "Usage: /fen [game id]".to_string(),
+        }
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn on_leaderboard(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let category = match args.trim() {
+        "" => None,
+        name => match Category::parse(name) {
+            Some(category) => Some(category),
+            None => {
+                let names: Vec<&str> = rating::CATEGORIES.iter().map(|c| c.as_str()).collect();
+                let text = format!("Usage: /top [{}]", names.join("|"));
+                state.client.send_message(packed_chat(user_id), text).await?;
+                return Ok(());
+            }
+        },
+    };
+    let top = rating::top(&state.db, category, LEADERBOARD_LEN).await?;
+
+    let text = if top.is_empty() {
+        match category {
+            None => "Nobody has an established rating yet.".to_string(),
+            Some(category) => format!("Nobody has an established {} rating yet.", category.name().to_lowercase()),
+        }
+    } else {
+        let mut lines = Vec::new();
+        for (i, (id, name, rating)) in top.iter().enumerate() {
+            let name = badges::decorate(&state.db, *id, &name.clone().unwrap_or_else(|| id.to_string())).await?;
+            lines.push(format!("{}. {name} {}", i + 1, rating.round() as i64));
+        }
+        let lines = lines.join("\n");
+        match category {
+            None => lines,
+            Some(category) => format!("{}:\n{lines}", category.name()),
+        }
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// The player card with the user's invites and training results.
+async fn profile(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let mut text = player_card(db, user_id).await?;
+    if let Some(badges) = badges::describe(db, user_id).await? {
+        text = format!("{text}\n{badges}");
+    }
+    let ratings: Vec<(String, f64, i64)> = sqlx::query_as(
+        "select category, rating, rated_games from ratings where user_id = $1 order by rated_games desc",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    for (category, rating, rated_games) in ratings {
+        let Some(category) = Category::parse(&category) else {
+            continue;
+        };
+        text = format!(
+            "{text}\n{} {}, {rated_games} {}",
+            category.name(),
+            rating::display(rating, rated_games),
+            category.unit(rated_games)
+        );
+    }
+    if let Some(streak) = streaks::describe(db, user_id).await? {
+        text = format!("{text}\n{streak}");
+    }
+    let invited = invites::count(db, user_id).await?;
+    if invited > 0 {
+        text = format!("{text}\nInvited {invited} {}", if invited == 1 { "player" } else { "players" });
+    }
+    if let Some(coordinates) = coords::summary(db, user_id).await? {
+        text = format!("{text}\n{coordinates}");
+    }
+    Ok(text)
+}
+
+async fn on_profile(state: &mut State, user_id: i64) -> Result<()> {
+    let text = profile(&state.db, user_id).await?;
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Finds a user by id or `@username`.
+async fn find_user(db: &Pool<Sqlite>, arg: &str) -> Result<Option<i64>> {
+    let query = match arg.strip_prefix('@') {
+        Some(username) => sqlx::query_scalar("select id from users where username = $1 collate nocase").bind(username),
+        None => sqlx::query_scalar("select id from users where id = $1").bind(arg.parse::<i64>().unwrap_or(-1)),
+    };
+    Ok(query.fetch_optional(db).await?)
+}
+
+async fn on_find(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let query = args.trim();
+    let text = match query {
+        "on" | "off" => {
+            sqlx::query("update users set searchable = $2 where id = $1")
+                .bind(user_id)
+                .bind(query == "on")
+                .execute(&state.db)
+                .await?;
+            if query == "on" {
+                "Other players can find you with /find now.".to_string()
+            } else {
+                "You no longer show up in /find.".to_string()
+            }
+        }
+        query if query.chars().count() < MIN_FIND_QUERY_LEN => {
+            "Usage: /find <name or @username> | on | off".to_string()
+        }
+        query => find_players(&state.db, user_id, query).await?,
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Searches players who haven't opted out by name and username, exact
+/// usernames first.
+async fn find_players(db: &Pool<Sqlite>, user_id: i64, query: &str) -> Result<String> {
+    let username = query.strip_prefix('@').unwrap_or(query);
+    let pattern = format!(
+        "%{}%",
+        username.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    );
+    let found: Vec<i64> = sqlx::query_scalar(
+        "select id from users where searchable and id != $1
+         and (name like $2 escape '\\' or username like $2 escape '\\')
+         order by username = $3 collate nocase desc, rated_games desc limit $4",
+    )
+    .bind(user_id)
+    .bind(&pattern)
+    .bind(username)
+    .bind(FIND_LIMIT + 1)
+    .fetch_all(db)
+    .await?;
+
+    match found.as_slice() {
+        [] => Ok(format!("No players found for {query}.")),
+        [id] => Ok(format!("{}\nChallenge: /start vs {id}", profile(db, *id).await?)),
+        found => {
+            let mut text = String::new();
+            for id in found.iter().take(FIND_LIMIT as usize) {
+                text = format!("{text}{}\n/start vs {id}\n", player_card(db, *id).await?);
+            }
+            if found.len() as i64 > FIND_LIMIT {
+                text = format!("{text}More players match, try a longer name.");
+            }
+            Ok(text.trim_end().to_string())
+        }
+    }
+}
+
+/// Whether the user is listed in `ADMINS` or was promoted by another admin.
+async fn is_admin(state: &State, user_id: i64) -> Result<bool> {
+    if state.config.get().admins.contains(&user_id) {
+        return Ok(true);
+    }
+    let promoted: Option<bool> = sqlx::query_scalar("select admin from users where id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?;
+    Ok(promoted.unwrap_or(false))
+}
+
+/// Only reached by admins, see `commands::Requires`.
+async fn on_admin(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let (command, args) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let text = match (command, args.trim().parse::<i64>()) {
+        ("stats", _) => {
+            let latencies = state.latencies.lock().expect("latencies lock").clone();
+            admin_stats(&state.db, &latencies).await?
+        }
+        ("reload", _) => config::reload(&state.config),
+        ("vacuum", _) => admin_vacuum(&state.db).await?,
+        ("maintenance", _) => match (args.trim(), in_maintenance(&state.db).await?) {
+            ("on", true) | ("off", false) => format!("Maintenance mode is already {}.", args.trim()),
+            ("on", false) => {
+                start_maintenance(&state.db).await?;
+                "Maintenance mode on: new games and moves are refused and clocks are paused.".to_string()
+            }
+            ("off", true) => {
+                end_maintenance(&state.db).await?;
+                "Maintenance mode off, clocks are running again.".to_string()
+            }
+            (_, on) => format!("Maintenance mode is {}. Use /admin maintenance on|off", if on { "on" } else { "off" }),
+        },
+        ("delete", Ok(id)) => {
+            let deleted = sqlx::query("update games set deleted_at = unixepoch() where id = $1 and ended and deleted_at is null")
+                .bind(id)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if deleted > 0 {
+                format!("Game #{id} deleted, it can be restored for {PURGE_GRACE_DAYS} days.")
+            } else {
+                format!("No finished game #{id}.")
+            }
+        }
+        ("restore", Ok(id)) => {
+            let restored = sqlx::query("update games set deleted_at = null where id = $1 and deleted_at is not null")
+                .bind(id)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if restored > 0 {
+                format!("Game #{id} restored.")
+            } else {
+                format!("No deleted game #{id}.")
+            }
+        }
+        ("growth", _) => invites::growth(&state.db).await?,
+        ("flags", _) => fairplay::open_flags(&state.db).await?,
+        ("clear", Ok(id)) => fairplay::clear(&state.db, user_id, id).await?,
+        ("feature", _) => featured::admin(state, user_id, args).await?,
+        ("seeks", _) => seeks::admin(state, user_id, args).await?,
+        ("league", _) => leagues::admin(state, user_id, args).await?,
+        ("silence", _) => commentary::admin_silence(state, user_id, args.trim(), true).await?,
+        ("unsilence", _) => commentary::admin_silence(state, user_id, args.trim(), false).await?,
+        ("badge", _) => badges::admin(state, user_id, args, true).await?,
+        ("unbadge", _) => badges::admin(state, user_id, args, false).await?,
+        ("api", _) => api::admin(state, user_id, args).await?,
+        ("exhibition", _) => exhibition::admin(state, user_id, args).await?,
+        ("promote", Ok(id)) => {
+            let promoted = sqlx::query("update users set admin = 1 where id = $1")
+                .bind(id)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if promoted > 0 {
+                info!("{user_id} promoted {id} to admin");
+                format!("{} is now an admin.", user_name(&state.db, id).await?)
+            } else {
+                format!("No user {id}.")
+            }
+        }
+        ("ban", Ok(id)) if state.config.get().admins.contains(&id) => format!("{id} is listed in ADMINS and can't be banned."),
+        ("ban", Ok(id)) => {
+            let banned = sqlx::query("update users set banned_at = unixepoch() where id = $1 and banned_at is null")
+                .bind(id)
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            if banned > 0 {
+                info!("{user_id} banned {id}");
+                format!("{} is banned, their messages are ignored.", user_name(&state.db, id).await?)
+            } else {
+                format!("No user {id}, or they are banned already.")
+            }
+        }
+        ("unban", Ok(id)) => {
+            sqlx::query("update users set banned_at = null where id = $1")
+                .bind(id)
+                .execute(&state.db)
+                .await?;
+            info!("{user_id} unbanned {id}");
+            format!("{id} is no longer banned.")
+        }
+        ("demote", Ok(id)) if state.config.get().admins.contains(&id) => {
+            format!("{id} is listed in ADMINS and can't be demoted here.")
+        }
+        ("demote", Ok(id)) => {
+            sqlx::query("update users set admin = 0 where id = $1")
+                .bind(id)
+                .execute(&state.db)
+                .await?;
+            info!("{user_id} demoted {id}");
+            format!("{id} is no longer an admin.")
+        }
+        _ => "Usage: /admin stats | growth | flags | clear <user> | feature [channel <channel> | <game> | auto | off] | seeks [channel <chat> | off] | league new|add|start ... | exhibition <elo> <elo> [secs] | api [new <label> | revoke <label>] | reload | vacuum | maintenance on|off | delete <game> | restore <game> | promote <user> | demote <user> | ban <user> | unban <user> | silence <user> | unsilence <user> | badge <user> <badge> [title] | unbadge <user> <badge>".to_string(),
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn db_size(db: &Pool<Sqlite>) -> Result<i64> {
+    Ok(sqlx::query_scalar("select page_count * page_size from pragma_page_count(), pragma_page_size()")
+        .fetch_one(db)
+        .await?)
+}
+
+/// Checks the database for corruption, then compacts it and refreshes the
+/// query planner's statistics.
+async fn admin_vacuum(db: &Pool<Sqlite>) -> Result<String> {
+    let problems: Vec<String> = sqlx::query_scalar("pragma integrity_check").fetch_all(db).await?;
+    if problems != ["ok"] {
+        error!("integrity check failed: {problems:?}");
+        return Ok(format!("Integrity check failed, not vacuuming:\n{}", problems.join("\n")));
+    }
+    let before = db_size(db).await?;
+    db.execute("vacuum").await?;
+    db.execute("analyze").await?;
+    let after = db_size(db).await?;
+    info!("vacuumed database from {before} to {after} bytes");
+    Ok(format!(
+        "Integrity check ok. Reclaimed {:.1} MB ({:.1} MB → {:.1} MB), statistics updated.",
+        (before - after) as f64 / 1e6,
+        before as f64 / 1e6,
+        after as f64 / 1e6,
+    ))
+}
+
+async fn admin_stats(db: &Pool<Sqlite>, latencies: &VecDeque<Duration>) -> Result<String> {
+    let now = clock::now_ms();
+    let day_ms = 24 * 60 * 60 * 1000;
+    // a player is active if they made a move; White plays the even plies
+    // unless the game was set up with Black to move
+    let stats: Stats = sqlx::query_as(
+        "select
+            (select count(*) from users) as users,
+            (select count(distinct case when (m.ply + coalesce(instr(g.initial_fen, ' b ') > 0, 0)) % 2 = 0 then g.w_id else g.b_id end) from moves m join games g on g.id = m.game_id where m.played_at >= $1) as daily_active,
+            (select count(distinct case when (m.ply + coalesce(instr(g.initial_fen, ' b ') > 0, 0)) % 2 = 0 then g.w_id else g.b_id end) from moves m join games g on g.id = m.game_id where m.played_at >= $2) as weekly_active,
+            (select count(*) from games where created_at >= unixepoch('now', 'start of day')) as created_today,
+            (select count(*) from games where ended and started_at is not null and ended_at >= unixepoch('now', 'start of day')) as finished_today,
+            (select avg(plies) from (select count(*) as plies from moves m join games g on g.id = m.game_id where g.ended group by m.game_id)) as avg_plies,
+            (select avg(ended_at - started_at) from games where ended and started_at is not null) as avg_duration_secs",
+    )
+    .bind(now - day_ms)
+    .bind(now - 7 * day_ms)
+    .fetch_one(db)
+    .await?;
+
+    let mut text = format!(
+        "Users: {}\nActive players: {} today, {} this week\nGames today: {} created, {} finished",
+        stats.users, stats.daily_active, stats.weekly_active, stats.created_today, stats.finished_today,
+    );
+    if let (Some(plies), Some(secs)) = (stats.avg_plies, stats.avg_duration_secs) {
+        text = format!(
+            "{text}\nAverage game: {} moves, {}",
+            (plies / 2.0).round() as i64,
+            clock::format_clock((secs * 1000.0) as i64),
+        );
+    }
+    text = format!(
+        "{text}\nDatabase: {:.1} MB, {} connections ({} idle)",
+        db_size(db).await? as f64 / 1e6,
+        db.size(),
+        db.num_idle(),
+    );
+    if let Some(max) = latencies.iter().max() {
+        let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        text = format!(
+            "{text}\nUpdate latency: avg {} ms, max {} ms (last {})",
+            avg.as_millis(),
+            max.as_millis(),
+            latencies.len(),
+        );
+    }
+    Ok(text)
+}
+
+async fn sweep_seeks(db: &Pool<Sqlite>, client: &Bot, ttl: i64) -> Result<()> {
+    let expired: Vec<(i64, Option<i64>, Option<i64>)> = sqlx::query_as(
+        "update games set ended = 1, termination = $1, ended_at = unixepoch() where (w_id is null or b_id is null) and ended = 0 and created_at <= unixepoch() - $2 returning id, w_id, b_id",
+    )
+    .bind(Termination::Aborted as i64)
+    .bind(ttl)
+    .fetch_all(db)
+    .await?;
+
+    for (id, w_id, b_id) in expired {
+        debug!("expire seek {id}");
+        seeks::cancelled(db, client, id).await?;
+        let Some(user_id) = w_id.or(b_id) else {
+            continue;
+        };
+        client
+            .send_message(
+                packed_chat(user_id),
+                "Nobody joined your game in time, so it was cancelled. Type `/start` to seek again.",
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+async fn sweep_stale_games(db: &Pool<Sqlite>, client: &Bot, days: i64) -> Result<()> {
+    // in correspondence games the side to move loses on time
+    let overdue: Vec<(i64, i64, i64, String)> = sqlx::query_as(
+        "select id, w_id, b_id, fen from games where w_id is not null and b_id is not null and ended = 0
+        and days_per_move is not null and last_move_at <= unixepoch() - days_per_move * 86400
+        and not exists (select 1 from users where users.id in (w_id, b_id) and (vacation_started_at is not null or unreachable_since is not null))",
+    )
+    .fetch_all(db)
+    .await?;
+    for (id, w_id, b_id, fen) in overdue {
+        debug!("correspondence game {id} out of time");
+        flag_game(db, client, id, w_id, b_id, !position_from_fen(&fen).turn()).await?;
+    }
+
+    let abandoned: Vec<(i64, i64, i64)> = sqlx::query_as(
+        "update games set ended = 1, termination = $1, ended_at = unixepoch() where w_id is not null and b_id is not null and ended = 0
+        and days_per_move is null and last_move_at <= unixepoch() - $2 * 86400
+        and not exists (select 1 from users where users.id in (w_id, b_id) and (vacation_started_at is not null or unreachable_since is not null))
+        returning id, w_id, b_id",
+    )
+    .bind(Termination::Abandoned as i64)
+    .bind(days)
+    .fetch_all(db)
+    .await?;
+
+    for (id, w_id, b_id) in abandoned {
+        debug!("abandon stale game {id}");
+        for c in [packed_chat(w_id), packed_chat(b_id)] {
+            client
+                .send_message(
+                    c,
+                    format!("Nobody moved for {days} days, so the game was abandoned. Type `/start` to play again."),
+                )
+                .await?;
+        }
+        finish_game(db, client, id).await?;
+    }
+    Ok(())
+}
+
+async fn on_digest(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    if let Some(weekly) = args.trim().strip_prefix("weekly") {
+        let text = match weekly.trim() {
+            "on" | "off" => {
+                sqlx::query("update users set weekly_digest = $2 where id = $1")
+                    .bind(user_id)
+                    .bind(weekly.trim() == "on")
+                    .execute(&state.db)
+                    .await?;
+                match weekly.trim() {
+                    "on" => "You will get a weekly summary of your games and puzzles.",
+                    _ => "Weekly summaries are off.",
+                }
+            }
+            _ => "Usage: /digest [weekly] on|off",
+        };
+        state.client.send_message(packed_chat(user_id), text).await?;
+        return Ok(());
+    }
+    let enabled = match args.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+            state
+                .client
+                .send_message(packed_chat(user_id), "Usage: /digest [weekly] on|off")
+                .await?;
+            return Ok(());
+        }
+    };
+    sqlx::query("update users set digest = $2 where id = $1")
+        .bind(user_id)
+        .bind(enabled)
+        .execute(&state.db)
+        .await?;
+    let text = if enabled {
+        "You will get a daily summary of games waiting for your move."
+    } else {
+        "Daily summaries are off."
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Sends opted-in users a summary of the games where it's their move, once a day.
+async fn send_digests(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    // A little under a day, so that the hourly job doesn't drift later and later.
+    let due: Vec<i64> = sqlx::query_scalar(
+        "select id from users where digest and unreachable_since is null and (digest_sent_at is null or digest_sent_at <= unixepoch() - 82800)",
+    )
+    .fetch_all(db)
+    .await?;
+
+    for user_id in due {
+        let now = clock::now_ms();
+        let settings = settings::get(db, user_id).await?;
+        let mut lines = Vec::new();
+        for game in games_awaiting_move(db, user_id).await? {
+            let turn = game.turn();
+            let opponent = if turn.is_white() { game.b_id } else { game.w_id };
+            let Some(opponent) = opponent else {
+                continue;
+            };
+            let board = position_from_fen(&game.fen);
+            let mut line = format!(
+                "Game #{} vs {}: move {}, you play {}",
+                game.id,
+                user_name(db, opponent).await?,
+                board.fullmoves(),
+                if turn.is_white() { "white" } else { "black" },
+            );
+            if let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(now) {
+                let clock_ms = if turn.is_white() { w_clock_ms } else { b_clock_ms };
+                line = format!("{line}, {} left", clock::format_clock(clock_ms));
+                if game.turn_started_ms.is_some() {
+                    line = format!("{line}, until {}", settings.local_time(now + clock_ms));
+                }
+            }
+            if let Some(material) = material::describe(board.board())
+                .filter(|_| game.variant() == Variant::Standard && game.initial_fen.is_none())
+            {
+                line = format!("{line}\n  {material}");
+            }
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            continue;
+        }
+
+        let text = format!(
+            "Games waiting for your move as of {}:\n{}",
+            settings.local_time(now),
+            lines.join("\n")
+        );
+        client.send_message(packed_chat(user_id), text).await?;
+        sqlx::query("update users set digest_sent_at = unixepoch() where id = $1")
+            .bind(user_id)
+            .execute(db)
+            .await?;
+        debug!("sent digest to {user_id}");
+    }
+    Ok(())
+}
+
+async fn on_pin(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let pin = match args.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+            state
+                .client
+                .send_message(packed_chat(user_id), "Usage: /pin on|off")
+                .await?;
+            return Ok(());
+        }
+    };
+    sqlx::query("update users set pin_board = $2 where id = $1")
+        .bind(user_id)
+        .bind(pin)
+        .execute(&state.db)
+        .await?;
+    let text = if pin {
+        "The board of your current game will be pinned after each move."
+    } else {
+        "Boards will not be pinned."
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn on_vacation<'e>(db: impl Executor<'e, Database = Sqlite>, user_id: i64) -> Result<bool> {
+    let started: Option<i64> = sqlx::query_scalar("select vacation_started_at from users where id = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await?;
+    Ok(started.is_some())
 }
 
-struct State {
-    db: Pool<Sqlite>,
-    client: Client,
-    boards: HashMap<i64, Chess>,
+async fn in_maintenance<'e>(db: impl Executor<'e, Database = Sqlite>) -> Result<bool> {
+    let started: Option<i64> = sqlx::query_scalar("select started_at from maintenance")
+        .fetch_optional(db)
+        .await?;
+    Ok(started.is_some())
 }
 
-fn packed_chat(id: i64) -> PackedChat {
-    PackedChat {
-        id,
-        ty: grammers_session::PackedType::User,
-        access_hash: None,
+/// Enters maintenance mode, stopping every running clock where it stands.
+async fn start_maintenance(db: &Pool<Sqlite>) -> Result<()> {
+    let now = clock::now_ms();
+    let mut tx = db.begin().await?;
+    sqlx::query("insert into maintenance (started_at) values (unixepoch())")
+        .execute(&mut *tx)
+        .await?;
+    let running: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where ended = 0 and turn_started_ms is not null"
+    ))
+    .fetch_all(&mut *tx)
+    .await?;
+    for game in running {
+        let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(now) else {
+            continue;
+        };
+        sqlx::query("update games set w_clock_ms = $2, b_clock_ms = $3, turn_started_ms = null where id = $1")
+            .bind(game.id)
+            .bind(w_clock_ms)
+            .bind(b_clock_ms)
+            .execute(&mut *tx)
+            .await?;
     }
+    tx.commit().await?;
+    info!("maintenance started");
+    Ok(())
 }
 
-fn parse_move(notation: &str, board: &impl Position) -> Option<Move> {
-    if let Some(m) = San::from_ascii(notation.as_bytes())
-        .ok()
-        .and_then(|san| san.to_move(board).ok())
-    {
-        return Some(m);
+/// Leaves maintenance mode and restarts the clocks of players not on vacation.
+async fn end_maintenance(db: &Pool<Sqlite>) -> Result<()> {
+    let mut tx = db.begin().await?;
+    sqlx::query("delete from maintenance").execute(&mut *tx).await?;
+    let paused: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where ended = 0 and turn_started_ms is null and initial_ms is not null and w_id is not null and b_id is not null"
+    ))
+    .fetch_all(&mut *tx)
+    .await?;
+    for game in paused {
+        let mover = if game.turn().is_white() { game.w_id } else { game.b_id };
+        if on_vacation(&mut *tx, mover.expect("both players joined")).await? {
+            continue;
+        }
+        sqlx::query("update games set turn_started_ms = $2 where id = $1")
+            .bind(game.id)
+            .bind(clock::now_ms())
+            .execute(&mut *tx)
+            .await?;
     }
+    tx.commit().await?;
+    info!("maintenance ended");
+    Ok(())
+}
 
-    Uci::from_ascii(notation.as_bytes())
-        .ok()
-        .and_then(|uci| uci.to_move(board).ok())
+/// Vacation days taken over the last year, including an ongoing vacation.
+async fn vacation_days_used(db: &Pool<Sqlite>, user_id: i64) -> Result<f64> {
+    let secs: i64 = sqlx::query_scalar(
+        "select coalesce(sum(coalesce(ended_at, unixepoch()) - started_at), 0) from vacations where user_id = $1 and started_at > unixepoch() - 365 * 86400",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+    Ok(secs as f64 / 86400.0)
 }
 
-async fn on_start(state: &mut State, user_id: i64) -> Result<()> {
-    let maybe_playing_game: Option<(i64, i64, i64, bool, i64, String)> = sqlx::query_as(
-        "select id, w_id, b_id, winner, termination, fen from games where (w_id = $1 or b_id = $1) and ended = 0",
-    ).bind(user_id).fetch_optional(&state.db).await?;
+/// Ongoing games of `user_id` where it's their move.
+async fn games_awaiting_move<'e>(db: impl Executor<'e, Database = Sqlite>, user_id: i64) -> Result<Vec<Game>> {
+    let games: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where (w_id = $1 or b_id = $1) and w_id is not null and b_id is not null and ended = 0"
+    ))
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    Ok(games
+        .into_iter()
+        .filter(|g| {
+            let player = if g.turn().is_white() { g.w_id } else { g.b_id };
+            player == Some(user_id)
+        })
+        .collect())
+}
 
-    if maybe_playing_game.is_some() {
-        debug!("already in game {user_id}");
-        state
-            .client
-            .send_message(
-                packed_chat(user_id),
-                "You are already playing. Type `resign` to leave.",
-            )
+async fn start_vacation(db: &Pool<Sqlite>, user_id: i64) -> Result<()> {
+    let now = clock::now_ms();
+    let mut tx = db.begin().await?;
+    sqlx::query("update users set vacation_started_at = unixepoch() where id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("insert into vacations (user_id, started_at) values ($1, unixepoch())")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for game in games_awaiting_move(&mut *tx, user_id).await? {
+        let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(now) else {
+            continue;
+        };
+        sqlx::query("update games set w_clock_ms = $2, b_clock_ms = $3, turn_started_ms = null where id = $1")
+            .bind(game.id)
+            .bind(w_clock_ms)
+            .bind(b_clock_ms)
+            .execute(&mut *tx)
             .await?;
-        return Ok(());
-    };
-
-    let maybe_pairable: Option<(i64, Option<i64>, Option<i64>)> = sqlx::query_as("select id, w_id, b_id from games where (b_id is null or w_id is null) and ended = 0 limit 1")
-        .fetch_optional(&state.db).await?;
-    debug!("maybe_pairable? {maybe_pairable:?}");
+    }
+    tx.commit().await?;
+    debug!("start vacation {user_id}");
+    Ok(())
+}
 
-    if let Some((id, w_id, b_id)) = maybe_pairable {
-        let (w_id, b_id) = match (w_id, b_id) {
-            (Some(w_id), None) => (w_id, user_id),
-            (None, Some(b_id)) => (user_id, b_id),
-            _ => {
-                panic!("oh how surprising! you are stupid! {maybe_pairable:?}")
-            }
-        };
-        let (_id, w_id, b_id) = sqlx::query_as::<_, (i64, i64, i64)>(
-            "update games set w_id = $1, b_id = $2 where games.id = $3 returning id, w_id, b_id",
-        )
-        .bind(w_id)
-        .bind(b_id)
-        .bind(id)
-        .fetch_one(&state.db)
+async fn end_vacation(db: &Pool<Sqlite>, user_id: i64) -> Result<()> {
+    let mut tx = db.begin().await?;
+    sqlx::query("update users set vacation_started_at = null where id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
         .await?;
-        let (white, black) = (packed_chat(w_id), packed_chat(b_id));
-        state
-            .client
-            .send_message(white, "You are white. Your turn!")
-            .await?;
-        state
-            .client
-            .send_message(black, "You are black. Waiting for opponent's move.")
+    sqlx::query("update vacations set ended_at = unixepoch() where user_id = $1 and ended_at is null")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    // stale and correspondence games count days from the last move, which
+    // would otherwise take in the time away
+    sqlx::query("update games set last_move_at = unixepoch() where $1 in (w_id, b_id) and ended = 0")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    // clocks restart when maintenance ends
+    let paused = in_maintenance(&mut *tx).await?;
+    for game in games_awaiting_move(&mut *tx, user_id).await? {
+        if paused || game.time_control().is_none() || game.turn_started_ms.is_some() {
+            continue;
+        }
+        sqlx::query("update games set turn_started_ms = $2 where id = $1")
+            .bind(game.id)
+            .bind(clock::now_ms())
+            .execute(&mut *tx)
             .await?;
-    } else {
-        let (id,) = sqlx::query_as::<_, (i64,)>("insert into games (w_id, b_id, winner, ended, fen) values ($1, null, null, 0, $2) returning id").bind(user_id).bind(STARTING_FEN).fetch_one(&state.db).await?;
-        debug!("create new game {id}");
-        state
-            .client
+    }
+    tx.commit().await?;
+    debug!("end vacation {user_id}");
+    Ok(())
+}
+
+async fn on_vacation_command(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let used = vacation_days_used(&state.db, user_id).await?;
+    let away = on_vacation(&state.db, user_id).await?;
+    let text = match args.trim() {
+        "on" if away => "You are already on vacation.".to_string(),
+        "on" if used >= VACATION_DAYS_PER_YEAR => "You have no vacation days left this year.".to_string(),
+        "on" => {
+            start_vacation(&state.db, user_id).await?;
+            format!(
+                "Vacation started, your clocks are paused. You have {:.1} days left. Type `/vacation off` when you are back.",
+                VACATION_DAYS_PER_YEAR - used
+            )
+        }
+        "off" if !away => "You are not on vacation.".to_string(),
+        "off" => {
+            end_vacation(&state.db, user_id).await?;
+            "Welcome back! Your clocks are running again.".to_string()
+        }
+        "" => format!(
+            "You {} on vacation and have used {used:.1} of {VACATION_DAYS_PER_YEAR} vacation days this year.",
+            if away { "are" } else { "are not" }
+        ),
+        _ => "Usage: /vacation [on|off]".to_string(),
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Ends vacations which have used up the yearly budget.
+async fn sweep_vacations(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let away: Vec<i64> = sqlx::query_scalar("select id from users where vacation_started_at is not null")
+        .fetch_all(db)
+        .await?;
+    for user_id in away {
+        if vacation_days_used(db, user_id).await? < VACATION_DAYS_PER_YEAR {
+            continue;
+        }
+        end_vacation(db, user_id).await?;
+        client
             .send_message(
                 packed_chat(user_id),
-                "Created a new game. Waiting for an opponent to join.",
+                "Your vacation days for this year are used up, so your clocks are running again.",
             )
             .await?;
     }
     Ok(())
 }
 
-async fn on_move(state: &mut State, user_id: i64, notation: &str) -> Result<()> {
-    let mut tx = state.db.begin().await?;
+/// Ends games in which the side to move has run out of time.
+async fn sweep_flags(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let running: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where ended = 0 and turn_started_ms is not null"
+    ))
+    .fetch_all(db)
+    .await?;
 
-    let maybe_playing_game: Option<(i64, i64, i64, bool, i64, String)> = sqlx::query_as(
-        "select id, w_id, b_id, winner, termination, fen from games where (w_id = $1 or b_id = $1) and ended = 0",
-    ).bind(user_id).fetch_optional(&mut *tx).await?;
+    let now = clock::now_ms();
+    for game in running {
+        let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+            continue;
+        };
+        let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(now) else {
+            continue;
+        };
+        let turn = game.turn();
+        let clock_ms = if turn.is_white() { w_clock_ms } else { b_clock_ms };
+        if clock_ms <= 0 {
+            debug!("flag game {}", game.id);
+            flag_game(db, client, game.id, w_id, b_id, !turn).await?;
+        }
+    }
+    Ok(())
+}
 
-    debug!("get ongoing game for {user_id}: got {maybe_playing_game:?}");
+/// Edits the last board message of real-time games to show current clocks.
+async fn refresh_live_clocks(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let live: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where ended = 0 and turn_started_ms is not null and initial_ms <= $1 and w_board_text is not null"
+    ))
+    .bind(LIVE_CLOCK_MAX_INITIAL_MS)
+    .fetch_all(db)
+    .await?;
 
-    let Some((id, w_id, b_id, _winner, _termination, fen)) = maybe_playing_game else {
-        state
-            .client
-            .send_message(packed_chat(user_id), "Type `start` to join a game")
-            .await?;
-        return Ok(());
-    };
-    let board = state.boards.entry(id).or_insert_with(|| {
-        fen.parse::<Fen>()
-            .expect("fen from db")
-            .into_position(CastlingMode::Standard)
-            .expect("valid initial position")
-    });
-    if !(board.turn() == Color::White && user_id == w_id
-        || board.turn() == Color::Black && user_id == b_id)
-    {
-        state
-            .client
-            .send_message(packed_chat(user_id), "Not your turn!")
-            .await?;
-        return Ok(());
+    let now = clock::now_ms();
+    for game in live {
+        let Some((w_clock_ms, b_clock_ms)) = game.clocks_at(now) else {
+            continue;
+        };
+        let clocks = clock::format_clocks(w_clock_ms, b_clock_ms);
+        for (user_id, message_id, board_text) in [
+            (game.w_id, game.w_message_id, &game.w_board_text),
+            (game.b_id, game.b_message_id, &game.b_board_text),
+        ] {
+            let (Some(user_id), Some(message_id), Some(board_text)) = (user_id, message_id, board_text) else {
+                continue;
+            };
+            let text = format!("{board_text}\n{clocks}");
+            if let Err(e) = client.edit_message(packed_chat(user_id), message_id, text.as_str()).await {
+                debug!("cannot refresh clock of game {} for {user_id}: {e}", game.id);
+            }
+        }
     }
-    let Some(m) = parse_move(notation, board) else {
-        state
-            .client
-            .send_message(packed_chat(user_id), "This is not a valid move")
-            .await?;
-        return Ok(());
-    };
-    if !board.is_legal(&m) {
-        state
-            .client
-            .send_message(packed_chat(user_id), "This move is not legal")
+    Ok(())
+}
+
+async fn sweep_rating_deviations(db: &Pool<Sqlite>) -> Result<()> {
+    let inactive: Vec<(i64, f64, i64)> = sqlx::query_as(
+        "select id, rating_deviation, (unixepoch() - deviation_updated_at) / 86400 from users where rated_games > 0 and deviation_updated_at <= unixepoch() - 86400",
+    )
+    .fetch_all(db)
+    .await?;
+
+    for (id, deviation, days) in inactive {
+        // Only whole days are consumed, so restarts don't decay twice.
+        sqlx::query("update users set rating_deviation = $2, deviation_updated_at = deviation_updated_at + $3 * 86400 where id = $1")
+            .bind(id)
+            .bind(rating::decayed_deviation(deviation, days))
+            .bind(days)
+            .execute(db)
             .await?;
-        return Ok(());
     }
-    board.play_unchecked(&m);
-    debug!("playing move {m}");
-
-    let ended = board.is_game_over();
-    let fen = Fen::from_position(board.clone(), shakmaty::EnPassantMode::Always).to_string();
-    let winner = if ended {
-        Some(if board.turn().is_white() { w_id } else { b_id })
-    } else {
-        None
-    };
-    let termination = board.outcome().and_then(|o| match o {
-        Outcome::Draw => None,
-        Outcome::Decisive {
-            winner: Color::Black,
-        } => Some(true),
-        Outcome::Decisive {
-            winner: Color::White,
-        } => Some(false),
-    });
 
-    sqlx::query(
-        "insert into moves (game_id, ply, uci) values ($1, (select count(*) from moves where game_id = $1), $2)"
+    let inactive: Vec<(i64, String, f64, i64)> = sqlx::query_as(
+        "select user_id, category, rating_deviation, (unixepoch() - deviation_updated_at) / 86400 from ratings
+         where deviation_updated_at <= unixepoch() - 86400",
     )
+    .fetch_all(db)
+    .await?;
+    for (id, category, deviation, days) in inactive {
+        sqlx::query(
+            "update ratings set rating_deviation = $3, deviation_updated_at = deviation_updated_at + $4 * 86400
+             where user_id = $1 and category = $2",
+        )
         .bind(id)
-        .bind(m.to_uci(CastlingMode::Standard).to_string())
-        .execute(&mut *tx).await?;
+        .bind(category)
+        .bind(rating::decayed_deviation(deviation, days))
+        .bind(days)
+        .execute(db)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Deletes games that ended more than `retention_months` ago, if set, and purges
+/// games that have been deleted for longer than the grace period.
+async fn sweep_retention(db: &Pool<Sqlite>, retention_months: Option<i64>) -> Result<()> {
+    if let Some(months) = retention_months {
+        let deleted = sqlx::query(
+            "update games set deleted_at = unixepoch() where ended and deleted_at is null and ended_at < unixepoch('now', printf('-%d months', $1))",
+        )
+        .bind(months)
+        .execute(db)
+        .await?
+        .rows_affected();
+        if deleted > 0 {
+            info!("deleted {deleted} games older than {months} months");
+        }
+    }
 
+    let mut tx = db.begin().await?;
+    for table in ["moves", "engine_reviews", "hints"] {
+        sqlx::query(&format!(
+            "delete from {table} where game_id in (select id from games where deleted_at <= unixepoch() - $1 * 86400)"
+        ))
+        .bind(PURGE_GRACE_DAYS)
+        .execute(&mut *tx)
+        .await?;
+    }
+    // games that ended without being finished, e.g. abandoned ones, may still be active
     sqlx::query(
-        "update games set ended = $1, winner = $2, termination = $3, fen = $4 where id = $1",
+        "update users set active_game_id = null
+         where active_game_id in (select id from games where deleted_at <= unixepoch() - $1 * 86400)",
     )
-    .bind(ended)
-    .bind(winner)
-    .bind(termination)
-    .bind(&fen)
-    .bind(id)
+    .bind(PURGE_GRACE_DAYS)
     .execute(&mut *tx)
     .await?;
-
+    let purged = sqlx::query("delete from games where deleted_at <= unixepoch() - $1 * 86400")
+        .bind(PURGE_GRACE_DAYS)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
     tx.commit().await?;
-
-    for &c in [packed_chat(w_id), packed_chat(b_id)].iter() {
-        // show fen image
-        state
-            .client
-            .send_message(c, format!("Played {m}, FEN is now {fen}"))
-            .await?;
-        if ended {
-            state
-                .client
-                .send_message(packed_chat(user_id), format!("Game is over"))
-                .await?;
-        }
-    }
-    if ended {
-        state.boards.remove(&id);
+    if purged > 0 {
+        info!("purged {purged} deleted games");
     }
     Ok(())
 }
 
-async fn on_resign(state: &mut State, user_id: i64) -> Result<()> {
-    let maybe_playing_game: Option<(i64, i64, i64, bool, i64, String)> = sqlx::query_as(
-        "select id, w_id, b_id, winner, termination, fen from games where (w_id = $1 or b_id = $1) and ended = 0",
-    ).bind(user_id).fetch_optional(&state.db).await?;
+/// Saves the user's names, returning whether this is their first contact.
+async fn save_user(db: &Pool<Sqlite>, user_id: i64, name: &str, username: Option<&str>) -> Result<bool> {
+    let new: bool = sqlx::query_scalar("select not exists (select 1 from users where id = $1)")
+        .bind(user_id)
+        .fetch_one(db)
+        .await?;
+    timed(
+        || format!("upsert user={user_id}"),
+        sqlx::query("insert into users (id, name, username, joined_at) values ($1, $2, $3, unixepoch()) on conflict (id) do update set name = excluded.name, username = excluded.username")
+            .bind(user_id)
+            .bind(name)
+            .bind(username)
+            .execute(db),
+    )
+    .await?;
+    debug!("insert user {user_id}");
+    Ok(new)
+}
 
-    debug!("get ongoing game for {user_id}: got {maybe_playing_game:?}");
+/// Handles a text message, whether it came from Telegram or is simulated.
+async fn handle_message(
+    state: &mut State,
+    user_id: i64,
+    user_name: &str,
+    username: Option<&str>,
+    text: &str,
+) -> Result<()> {
+    let new = save_user(&state.db, user_id, user_name, username).await?;
 
-    if let Some(_) = maybe_playing_game {
-        error!("todo: resign");
+    let (command, mut args) = text.split_once(' ').unwrap_or((text, ""));
+    if new && command == "/start" && !args.is_empty() {
+        // a deep link's payload rather than game options
+        invites::record_start(&state.db, &state.client, user_id, args).await?;
+        // a seek's join button still joins the game, and a review link opens it
+        if !args.starts_with(seeks::PAYLOAD_PREFIX) && !args.starts_with(review::PAYLOAD_PREFIX) {
+            args = "";
+        }
+    }
+    if commands::dispatch(state, user_id, command, args).await? {
+        return Ok(());
+    }
+    // a running trainer takes the moves instead of games
+    if let Some(rush) = rush::running(&state.db, user_id).await? {
+        rush::on_move(state, rush, text).await?;
+    } else if let Some(attempt) = tactics::running(&state.db, user_id).await? {
+        tactics::on_move(state, attempt, text).await?;
+    } else if let Some(session) = guess::running(&state.db, user_id).await? {
+        guess::on_move(state, session, text).await?;
+    } else if let Some(drill) = repertoire::running(&state.db, user_id).await? {
+        repertoire::on_move(state, drill, text).await?;
+    } else if let Some(session) = endgame::running(&state.db, user_id).await? {
+        endgame::on_move(state, session, text).await?;
+    } else if let Some(round) = coords::running(&state.db, user_id).await? {
+        coords::on_move(state, round, text).await?;
+    } else if let Some(editor) = editor::running(&state.db, user_id).await? {
+        editor::on_message(state, editor, text).await?;
+    } else if let Some(board) = analysis::running(&state.db, user_id).await? {
+        analysis::on_move(state, board, text).await?;
+    } else if let Some(study) = studies::running(&state.db, user_id).await? {
+        studies::on_move(state, user_id, study, text).await?;
+    } else if let Some(review) = review::running(&state.db, user_id).await? {
+        review::on_move(state, review, text).await?;
+    } else if let Some(emote) = emotes::parse(text) {
+        emotes::send(state, user_id, emote).await?;
     } else {
-        error!("reject: need to join a game");
+        on_move(state, user_id, text).await?;
     }
     Ok(())
 }
 
-async fn handle_update(state: &mut State, update: Update) -> Result<()> {
-    match update {
-        Update::NewMessage(message) if !message.outgoing() => {
-            let chat = message.chat();
-            let user_id = chat.id();
-            let user_name = chat.name();
-            let text = message.text();
+/// Writes the Telegram session to a file beside `path` and renames it over,
+/// so a crash while writing leaves the previous session intact.
+fn save_session(client: &Client, path: &str) -> Result<()> {
+    let partial = format!("{path}.partial");
+    let mut file = std::fs::File::create(&partial)?;
+    file.write_all(&client.session().save())?;
+    file.sync_all()?;
+    std::fs::rename(&partial, path)?;
+    debug!("saved session to {path}");
+    Ok(())
+}
 
-            // let c = packed_chat(user_id);
+/// Opens the database, creating it and its schema if needed.
+async fn connect_db(database_url: &str) -> Result<Pool<Sqlite>> {
+    use sqlx::migrate::MigrateDatabase;
 
-            info!("message by {user_id} {user_name}: {text}");
+    if !Sqlite::database_exists(database_url).await? {
+        info!("create database {}", database_url);
+        Sqlite::create_database(database_url).await?;
+    }
 
-            sqlx::query("insert or ignore into users (id) values ($1)")
-                .bind(user_id)
-                .execute(&state.db)
-                .await?;
+    let slow_query = env::var("SLOW_QUERY_MS")
+        .map(|s| Duration::from_millis(s.parse().expect("SLOW_QUERY_MS invalid")))
+        .unwrap_or(Duration::from_millis(DEFAULT_SLOW_QUERY_MS));
+    SLOW_QUERY.set(slow_query).expect("set once");
+    let journal_mode = env::var("SQLITE_JOURNAL_MODE")
+        .map(|s| s.parse::<SqliteJournalMode>().expect("SQLITE_JOURNAL_MODE invalid"))
+        .unwrap_or(SqliteJournalMode::Wal);
+    let synchronous = env::var("SQLITE_SYNCHRONOUS")
+        .map(|s| s.parse::<SqliteSynchronous>().expect("SQLITE_SYNCHRONOUS invalid"))
+        .unwrap_or(SqliteSynchronous::Normal);
+    let busy_timeout = env::var("SQLITE_BUSY_TIMEOUT_MS")
+        .map(|s| Duration::from_millis(s.parse().expect("SQLITE_BUSY_TIMEOUT_MS invalid")))
+        .unwrap_or(DEFAULT_BUSY_TIMEOUT);
+    let foreign_keys = env::var("SQLITE_FOREIGN_KEYS")
+        .map(|s| s.parse::<bool>().expect("SQLITE_FOREIGN_KEYS invalid"))
+        .unwrap_or(true);
+    let max_connections = env::var("DB_MAX_CONNECTIONS")
+        .map(|s| s.parse().expect("DB_MAX_CONNECTIONS invalid"))
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+    let acquire_timeout = env::var("DB_ACQUIRE_TIMEOUT_SECS")
+        .map(|s| Duration::from_secs(s.parse().expect("DB_ACQUIRE_TIMEOUT_SECS invalid")))
+        .unwrap_or(DEFAULT_ACQUIRE_TIMEOUT);
+    let statement_cache = env::var("DB_STATEMENT_CACHE")
+        .map(|s| s.parse().expect("DB_STATEMENT_CACHE invalid"))
+        .unwrap_or(DEFAULT_STATEMENT_CACHE);
 
-            debug!("insert user {user_id}");
+    info!("connect to db");
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .busy_timeout(busy_timeout)
+        .foreign_keys(foreign_keys)
+        .log_statements(LevelFilter::Trace)
+        .log_slow_statements(LevelFilter::Warn, slow_query)
+        .statement_cache_capacity(statement_cache);
+    let db = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(acquire_timeout)
+        // ping idle connections before handing them out
+        .test_before_acquire(true)
+        .connect_with(options)
+        .await?;
+    Ok(db)
+}
 
-            match text.as_ref() {
-                "/start" => {
-                    on_start(state, user_id).await?;
-                }
-                "/resign" => {
-                    on_resign(state, user_id).await?;
-                }
-                notation => {
-                    on_move(state, user_id, notation).await?;
-                }
-            }
-        }
-        _ => {
-            debug!("unhandled update {update:?}");
-        }
+/// Applies pending migrations from `migrations/`, returning how many ran.
+async fn migrate(db: &Pool<Sqlite>) -> Result<usize> {
+    let migrator = sqlx::migrate!();
+    // the migrations table doesn't exist before the first run
+    let applied: Vec<i64> = sqlx::query_scalar("select version from _sqlx_migrations where success")
+        .fetch_all(db)
+        .await
+        .unwrap_or_default();
+    let pending = migrator.iter().filter(|m| !applied.contains(&m.version)).count();
+    if legacy::predates_migrations(db).await? {
+        legacy::upgrade(db).await?;
     }
-    Ok(())
+    migrator.run(db).await?;
+    Ok(pending)
 }
 
 async fn async_main() -> Result<()> {
-    env_logger::init();
-
     let api_id = env::var("TG_API_ID").expect("need TG_API_ID env var").parse().expect("api id invalid");
     let api_hash = env::var("TG_API_HASH").expect("need TG_API_HASH env var").to_string();
     let token = env::var("TG_BOT_TOKEN").expect("need TG_BOT_TOKEN env var").to_string();
@@ -279,20 +3107,28 @@ async fn async_main() -> Result<()> {
     let session_file = env::var("SESSION_FILE").expect("need SESSION_FILE env var").to_string();
     let database_url = env::var("DATABASE_URL").expect("need DATABASE_URL env var").to_string();
 
-    info!("startup");
-
-    use sqlx::migrate::MigrateDatabase;
+    let seek_ttl = env::var("SEEK_TTL_SECS")
+        .map(|s| s.parse().expect("SEEK_TTL_SECS invalid"))
+        .unwrap_or(DEFAULT_SEEK_TTL_SECS);
+    let retention_months: Option<i64> = env::var("RETENTION_MONTHS")
+        .ok()
+        .map(|s| s.parse().expect("RETENTION_MONTHS invalid"));
+    let stale_game_days = env::var("STALE_GAME_DAYS")
+        .map(|s| s.parse().expect("STALE_GAME_DAYS invalid"))
+        .unwrap_or(DEFAULT_STALE_GAME_DAYS);
+    let http_addr = env::var("HTTP_ADDR").ok();
+    let update_shards = env::var("UPDATE_SHARDS")
+        .map(|s| s.parse().expect("UPDATE_SHARDS invalid"))
+        .unwrap_or(shards::DEFAULT_SHARDS);
+    // what can change without a restart
+    let config = config::Current::new(config::Config::load()?);
 
-    if !Sqlite::database_exists(&database_url).await? {
-        info!("create database {}", &database_url);
-        Sqlite::create_database(&database_url).await?;
-    }
+    info!("startup");
 
-    info!("connect to db");
-    let db = SqlitePool::connect(&database_url).await?;
-    db.execute(include_str!("./schema.sql")).await?;
+    let db = connect_db(&database_url).await?;
+    migrate(&db).await?;
 
-    let mut boards = HashMap::<i64, Chess>::new();
+    let boards = HashMap::<i64, Chess>::new();
 
     info!("connecting to Telegram");
     let client = Client::connect(Config {
@@ -308,42 +3144,321 @@ async fn async_main() -> Result<()> {
 
     if !client.is_authorized().await? {
         client.bot_sign_in(&token).await?;
-        client.session().save_to_file(&session_file)?;
+        save_session(&client, &session_file)?;
         info!("signed in");
     }
+    let bot_username = client
+        .get_me()
+        .await?
+        .username()
+        .expect("bot has a username")
+        .to_string();
+
+    let bot = Bot::Telegram(client.clone(), Unreachable::default());
+    blocked::load(&db, &bot).await?;
+
+    let mut scheduler = Scheduler::new(JobContext {
+        db: db.clone(),
+        client: bot.clone(),
+    });
+    scheduler
+        .every("expire seeks", SEEK_SWEEP_INTERVAL, JOB_JITTER, move |ctx| async move {
+            sweep_seeks(&ctx.db, &ctx.client, seek_ttl).await
+        })
+        .every("abandon stale games", STALE_GAME_SWEEP_INTERVAL, JOB_JITTER, move |ctx| async move {
+            sweep_stale_games(&ctx.db, &ctx.client, stale_game_days).await
+        })
+        .every("decay rating deviations", RATING_DECAY_INTERVAL, JOB_JITTER, |ctx| async move {
+            sweep_rating_deviations(&ctx.db).await
+        })
+        .every("send digests", DIGEST_INTERVAL, JOB_JITTER, |ctx| async move {
+            send_digests(&ctx.db, &ctx.client).await
+        })
+        .every("send weekly digests", DIGEST_INTERVAL, JOB_JITTER, |ctx| async move {
+            weekly::send(&ctx.db, &ctx.client).await
+        })
+        .every("remind of play streaks", STREAK_REMINDER_INTERVAL, JOB_JITTER, |ctx| async move {
+            streaks::remind(&ctx.db, &ctx.client).await
+        })
+        .every("flag expired clocks", FLAG_SWEEP_INTERVAL, Duration::from_secs(1), |ctx| async move {
+            sweep_flags(&ctx.db, &ctx.client).await
+        })
+        .every("end puzzle rushes", RUSH_SWEEP_INTERVAL, Duration::ZERO, |ctx| async move {
+            rush::sweep(&ctx.db, &ctx.client).await
+        })
+        .every("refresh live clocks", LIVE_CLOCK_INTERVAL, Duration::from_secs(1), |ctx| async move {
+            refresh_live_clocks(&ctx.db, &ctx.client).await
+        })
+        .every("end vacations", VACATION_SWEEP_INTERVAL, JOB_JITTER, |ctx| async move {
+            sweep_vacations(&ctx.db, &ctx.client).await
+        })
+        .every("save the session", SESSION_SAVE_INTERVAL, Duration::ZERO, {
+            let session_file = session_file.clone();
+            move |ctx| {
+                let session_file = session_file.clone();
+                async move {
+                    match ctx.client.telegram() {
+                        Some(client) => save_session(client, &session_file),
+                        None => Ok(()),
+                    }
+                }
+            }
+        })
+        .every("handle unreachable users", UNREACHABLE_SWEEP_INTERVAL, JOB_JITTER, |ctx| async move {
+            blocked::sweep(&ctx.db, &ctx.client).await
+        })
+        .every("feature a live game", FEATURED_PICK_INTERVAL, JOB_JITTER, |ctx| async move {
+            featured::pick(&ctx.db, &ctx.client).await
+        })
+        .every("run leagues", LEAGUE_SWEEP_INTERVAL, JOB_JITTER, {
+            let config = config.clone();
+            move |ctx| {
+                let config = config.clone();
+                async move { leagues::sweep(&ctx.db, &ctx.client, &config.get()).await }
+            }
+        })
+        .every("send game archives", EXPORT_INTERVAL, JOB_JITTER, {
+            let bot_username = bot_username.clone();
+            move |ctx| {
+                let bot_username = bot_username.clone();
+                async move { exports::sweep(&ctx.db, &ctx.client, &bot_username).await }
+            }
+        })
+        .every("close idle studies", STUDY_SWEEP_INTERVAL, JOB_JITTER, |ctx| async move {
+            studies::sweep(&ctx.db, &ctx.client).await
+        })
+        .every("apply retention", RETENTION_SWEEP_INTERVAL, JOB_JITTER, move |ctx| async move {
+            sweep_retention(&ctx.db, retention_months).await
+        })
+        .every("look for sandbagging", SANDBAG_SWEEP_INTERVAL, JOB_JITTER, {
+            let config = config.clone();
+            move |ctx| {
+                let config = config.clone();
+                async move { fairplay::sweep_sandbagging(&ctx.db, &ctx.client, &config.get().admins).await }
+            }
+        });
+    // a process of its own, so reviews don't hold up the players' searches
+    if let Some(engine) = engine::Engine::from_env() {
+        let config = config.clone();
+        scheduler.every("review games for fair play", FAIR_PLAY_REVIEW_INTERVAL, JOB_JITTER, move |ctx| {
+            let (engine, config) = (engine.clone(), config.clone());
+            async move { fairplay::review(&ctx.db, &ctx.client, &engine, &config.get().admins).await }
+        });
+    }
+    // likewise for exhibitions, which keep their engine busy
+    if let Some(engine) = engine::Engine::from_env() {
+        scheduler.every("play exhibitions", EXHIBITION_INTERVAL, Duration::ZERO, move |ctx| {
+            let engine = engine.clone();
+            async move { exhibition::play(&ctx.db, &ctx.client, &engine).await }
+        });
+    }
+    let jobs = scheduler.start();
+
+    if let Some(addr) = http_addr {
+        tokio::spawn(web::serve(addr, db.clone()));
+    }
+    tokio::task::spawn_blocking(tablebase::warm_up);
 
-    let mut state = State { client, db, boards };
+    let state = State {
+        client: bot,
+        db,
+        boards,
+        bot_username,
+        config: config.clone(),
+        latencies: Arc::new(Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES))),
+        update_log: env::var("UPDATE_LOG").ok().map(|path| replay::UpdateLog::open(&path)).transpose()?,
+        engine: engine::Engine::from_env(),
+        message_id: None,
+        language: templates::DEFAULT_LANGUAGE.to_string(),
+        recent_messages: VecDeque::new(),
+        rate_limits: HashMap::new(),
+    };
+    let shards = shards::Shards::start(&state, update_shards);
 
     info!("waiting for messages");
 
+    let mut hangup = signal(SignalKind::hangup())?;
     loop {
-        let update = match state.client.next_update().await {
+        let next_update = {
+            let update = pin!(client.next_update());
+            let interrupt = pin!(tokio::signal::ctrl_c());
+            let reload = pin!(hangup.recv());
+            match future::select(update, future::select(interrupt, reload)).await {
+                Either::Left((update, _)) => update,
+                Either::Right((Either::Left(_), _)) => {
+                    info!("interrupted");
+                    break;
+                }
+                Either::Right((Either::Right(_), _)) => {
+                    info!("SIGHUP: {}", config::reload(&config));
+                    if let Err(e) = save_session(&client, &session_file) {
+                        error!("cannot save session: {e}");
+                    }
+                    continue;
+                }
+            }
+        };
+        let update = match next_update {
             Ok(u) => u,
             Err(e) => {
                 error!("cannot get update: {}", e);
+                // the connection is in trouble, keep what was received so far
+                if let Err(e) = save_session(&client, &session_file) {
+                    error!("cannot save session: {e}");
+                }
                 continue;
             }
         };
         match update {
             Some(update) => {
-                if let Err(e) = handle_update(&mut state, update).await {
-                    error!("error while handling update {e}");
-                }
+                let Some(incoming) = pipeline::Incoming::from_update(update) else {
+                    continue;
+                };
+                shards.dispatch(incoming).await;
             }
             None => break,
         }
     }
 
     info!("exiting");
-    state.client.session().save_to_file(&session_file)?;
+    shards.shutdown().await;
+    jobs.shutdown().await;
+    save_session(&client, &session_file)?;
 
     Ok(())
 }
 
 fn main() -> Result<()> {
-    runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async_main())
+    env_logger::init();
+    let runtime = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        // updates are handled on local tasks, see `shards`
+        None | Some("serve") => LocalSet::new().block_on(&runtime, async_main()),
+        Some("migrate") => runtime.block_on(cli::run_migrate()),
+        Some("export") => runtime.block_on(cli::run_export(&args[1..])),
+        Some("stats") => runtime.block_on(cli::run_stats()),
+        Some("import-puzzles") => runtime.block_on(cli::run_import_puzzles(&args[1..])),
+        Some("import-games") => runtime.block_on(cli::run_import_games(&args[1..])),
+        Some("repl") => runtime.block_on(repl::run()),
+        Some("replay") => runtime.block_on(replay::run(&args[1..])),
+        Some("simulate") => {
+            let users = args.get(1).map_or(Ok(simulate::DEFAULT_USERS), |n| n.parse())?;
+            runtime.block_on(simulate::run(users))
+        }
+        Some(_) => anyhow::bail!(cli::USAGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bot::Outbox;
+    use tokio::runtime;
+
+    /// The move as written with the language's piece letters.
+    fn localized(language: &str, san: &str) -> String {
+        let (_, letters) = PIECE_LETTERS.iter().find(|(l, _)| *l == language).unwrap();
+        san.chars()
+            .map(|c| match letters.iter().find(|&&(_, piece)| piece == c) {
+                Some((letter, _)) => letter.to_string(),
+                None => c.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn localized_moves_read_back_as_english() {
+        let sans = ["Kf1", "Qd1", "Rxa8", "Bb5+", "Nf3", "Nbd2", "e4", "e8=Q", "exd8=N#", "O-O", "O-O-O"];
+        for (language, _) in PIECE_LETTERS {
+            for san in sans {
+                let typed = localized(language, san);
+                assert_eq!(normalize_move(&localize_move(language, &typed)), normalize_move(san), "{language} {typed}");
+            }
+        }
+        assert_eq!(localize_move("ru", "Крe2"), "Ke2");
+        assert_eq!(localize_move("ru", "Кe2"), "Ne2");
+        assert_eq!(localize_move("de", "e8=D"), "e8=Q");
+        assert_eq!(localize_move("xx", "Sf3"), "Sf3");
+    }
+
+    #[test]
+    fn normalizes_the_ways_moves_are_typed() {
+        for (typed, move_) in [
+            ("O-O", "O-O"),
+            ("0-0", "O-O"),
+            ("o-o", "O-O"),
+            ("OO", "O-O"),
+            ("0-0+", "O-O"),
+            ("O-O-O", "O-O-O"),
+            ("0-0-0#", "O-O-O"),
+            ("ooo", "O-O-O"),
+            ("e2-e4", "e2e4"),
+            ("Nf3+!", "Nf3"),
+            ("♘f3", "Nf3"),
+            ("♟e4", "e4"),
+            ("exd6 e.p.", "exd6"),
+            ("e8=q", "e8=Q"),
+        ] {
+            assert_eq!(normalize_move(typed), move_, "{typed}");
+        }
+        // castling reads the same in every language
+        for (language, _) in PIECE_LETTERS {
+            assert_eq!(normalize_move(&localize_move(language, "0-0-0")), "O-O-O", "{language}");
+        }
+    }
+
+    async fn game_ended(db: &Pool<Sqlite>) -> (bool, Option<bool>, Option<i64>) {
+        sqlx::query_as("select ended, winner, termination from games where id = 1").fetch_one(db).await.unwrap()
+    }
+
+    async fn idle_for_days(db: &Pool<Sqlite>, days: i64) {
+        sqlx::query("update games set last_move_at = unixepoch() - $1 * 86400").bind(days).execute(db).await.unwrap();
+    }
+
+    #[test]
+    fn correspondence_deadline_waits_out_time_away() {
+        let path = env::temp_dir().join(format!("tgpawn-away-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = SqliteConnectOptions::new().filename(&path).create_if_missing(true).foreign_keys(true);
+        let runtime = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let db = SqlitePoolOptions::new().connect_with(options).await.unwrap();
+            migrate(&db).await.unwrap();
+            let client = Bot::Mock(Outbox::default());
+            sqlx::query(
+                "insert into users (id) values (1), (2);
+                 insert into games (id, w_id, b_id, ended, fen, started_at, days_per_move)
+                 values (1, 1, 2, 0, $1, unixepoch(), 3)",
+            )
+            .bind(STARTING_FEN)
+            .execute(&db)
+            .await
+            .unwrap();
+
+            // White, to move, goes on vacation for longer than the days per move
+            start_vacation(&db, 1).await.unwrap();
+            idle_for_days(&db, 10).await;
+            sweep_stale_games(&db, &client, 7).await.unwrap();
+            assert_eq!(game_ended(&db).await, (false, None, None));
+            end_vacation(&db, 1).await.unwrap();
+            sweep_stale_games(&db, &client, 7).await.unwrap();
+            assert_eq!(game_ended(&db).await, (false, None, None));
+
+            // and then can't be reached for as long
+            blocked::gone(&db, &client, 1).await.unwrap();
+            idle_for_days(&db, 10).await;
+            sweep_stale_games(&db, &client, 7).await.unwrap();
+            assert_eq!(game_ended(&db).await, (false, None, None));
+            blocked::back(&db, &client, 1).await.unwrap();
+            sweep_stale_games(&db, &client, 7).await.unwrap();
+            assert_eq!(game_ended(&db).await, (false, None, None));
+
+            // only missing the deadline while around loses on time
+            idle_for_days(&db, 4).await;
+            sweep_stale_games(&db, &client, 7).await.unwrap();
+            assert_eq!(game_ended(&db).await, (true, Some(false), Some(Termination::Timeout as i64)));
+        });
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,77 @@
+use shakmaty::{Board, ByRole, Color, Role};
+
+const ROLES: [Role; 5] = [Role::Queen, Role::Rook, Role::Bishop, Role::Knight, Role::Pawn];
+
+fn value(role: Role) -> i32 {
+    match role {
+        Role::Pawn => 1,
+        Role::Knight | Role::Bishop => 3,
+        Role::Rook => 5,
+        Role::Queen => 9,
+        Role::King => 0,
+    }
+}
+
+pub fn figurine(color: Color, role: Role) -> char {
+    match (color, role) {
+        (Color::White, Role::Pawn) => '♙',
+        (Color::White, Role::Knight) => '♘',
+        (Color::White, Role::Bishop) => '♗',
+        (Color::White, Role::Rook) => '♖',
+        (Color::White, Role::Queen) => '♕',
+        (Color::White, Role::King) => '♔',
+        (Color::Black, Role::Pawn) => '♟',
+        (Color::Black, Role::Knight) => '♞',
+        (Color::Black, Role::Bishop) => '♝',
+        (Color::Black, Role::Rook) => '♜',
+        (Color::Black, Role::Queen) => '♛',
+        (Color::Black, Role::King) => '♚',
+    }
+}
+
+fn starting_count(role: Role) -> u8 {
+    match role {
+        Role::Pawn => 8,
+        Role::Knight | Role::Bishop | Role::Rook => 2,
+        Role::Queen | Role::King => 1,
+    }
+}
+
+/// Pieces of `color` missing from the board compared to the starting position,
+/// most valuable first.
+fn captured(material: &ByRole<u8>, color: Color) -> String {
+    ROLES
+        .iter()
+        .flat_map(|&role| {
+            let missing = starting_count(role).saturating_sub(*material.get(role));
+            std::iter::repeat_n(figurine(color, role), missing.into())
+        })
+        .collect()
+}
+
+/// Describes captured pieces and the material balance, e.g.
+/// `Material: White +2 (♝♟ vs ♘)`, or `None` if nothing has been captured.
+pub fn describe(board: &Board) -> Option<String> {
+    let material = board.material();
+    let by_white = captured(&material.black, Color::Black);
+    let by_black = captured(&material.white, Color::White);
+    if by_white.is_empty() && by_black.is_empty() {
+        return None;
+    }
+
+    let balance: i32 = ROLES
+        .iter()
+        .map(|&role| {
+            let diff = i32::from(*material.white.get(role)) - i32::from(*material.black.get(role));
+            diff * value(role)
+        })
+        .sum();
+    let leader = match balance {
+        0 => "even".to_string(),
+        b if b > 0 => format!("White +{b}"),
+        b => format!("Black +{}", -b),
+    };
+    let by_white = if by_white.is_empty() { "-".to_string() } else { by_white };
+    let by_black = if by_black.is_empty() { "-".to_string() } else { by_black };
+    Some(format!("Material: {leader} ({by_white} vs {by_black})"))
+}
@@ -0,0 +1,158 @@
+//! Move notes: after a game, its players can `/note` a move with a comment
+//! and an annotation like `?` or `!!`. Notes go into the game's PGN and
+//! show up when the move is reached in `/review`.
+
+use crate::{game_by_id, game_ucis, packed_chat, review, san_moves, user_name, State};
+use anyhow::Result;
+use sqlx::{Pool, Sqlite};
+
+/// Annotations a note can start with, and their PGN glyph numbers.
+const NAGS: [(&str, u8); 6] = [("!", 1), ("?", 2), ("!!", 3), ("??", 4), ("!?", 5), ("?!", 6)];
+
+/// Notes are a line or two, not an essay.
+const MAX_NOTE_CHARS: usize = 300;
+
+const USAGE: &str = "Usage: /note [#game] <move> [!|?|!!|??|!?|?!] <text>, e.g. /note 12... ?? I missed Nxe5\n\
+    /note [#game] <move> off removes a note, /note [#game] lists them. Without a game, your last one.";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Note {
+    pub user_id: i64,
+    pub ply: i64,
+    pub nag: Option<u8>,
+    pub text: String,
+}
+
+/// The annotation for a glyph number, e.g. `??` for 4.
+pub fn symbol(nag: u8) -> Option<&'static str> {
+    NAGS.iter().find(|&&(_, n)| n == nag).map(|&(symbol, _)| symbol)
+}
+
+/// The game's notes, in move order.
+pub async fn of_game(db: &Pool<Sqlite>, game_id: i64) -> Result<Vec<Note>> {
+    Ok(sqlx::query_as(
+        "select user_id, ply, nag, text from move_notes where game_id = $1 order by ply, created_at, user_id",
+    )
+    .bind(game_id)
+    .fetch_all(db)
+    .await?)
+}
+
+/// A note as shown to users, e.g. `User 1: ?? I missed Nxe5`.
+pub async fn describe(db: &Pool<Sqlite>, note: &Note) -> Result<String> {
+    let mut text = format!("{}:", user_name(db, note.user_id).await?);
+    if let Some(symbol) = note.nag.and_then(symbol) {
+        text = format!("{text} {symbol}");
+    }
+    if !note.text.is_empty() {
+        text = format!("{text} {}", note.text);
+    }
+    Ok(text)
+}
+
+/// The move a note is for, e.g. `12` or `12.` for White's twelfth move and
+/// `12...` or `12b` for Black's.
+fn parse_move(s: &str) -> Option<i64> {
+    let (number, black) = match s.strip_suffix("...").or_else(|| s.strip_suffix('b')) {
+        Some(number) => (number, true),
+        None => (s.trim_end_matches('.'), false),
+    };
+    let ply = number.parse::<i64>().ok().filter(|&n| n >= 1)?.checked_mul(2)?;
+    Some(if black { ply } else { ply - 1 })
+}
+
+/// The move as written in notation, e.g. `12.` or `12...`.
+fn move_label(ply: i64) -> String {
+    let dots = if ply % 2 == 1 { "." } else { "..." };
+    format!("{}{dots}", (ply + 1) / 2)
+}
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+pub async fn on_note(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let mut words = args.split_whitespace().peekable();
+    let game_id = match words.next_if(|word| word.starts_with('#')) {
+        Some(word) => match word[1..].parse::<i64>() {
+            Ok(id) => id,
+            Err(_) => return reply(state, user_id, USAGE).await,
+        },
+        None => match review::last_finished(&state.db, user_id).await? {
+            Some(id) => id,
+            None => return reply(state, user_id, format!("You have no finished games.\n{USAGE}")).await,
+        },
+    };
+    let game = game_by_id(&state.db, game_id).await?;
+    let Some(game) = game.filter(|g| g.ended && g.started_at.is_some()) else {
+        return reply(state, user_id, format!("No finished game #{game_id}.")).await;
+    };
+    let Some(word) = words.next() else {
+        return list(state, user_id, game_id).await;
+    };
+    if game.w_id != Some(user_id) && game.b_id != Some(user_id) {
+        return reply(state, user_id, "Only the players can note a game's moves.").await;
+    }
+    let Some(ply) = parse_move(word) else {
+        return reply(state, user_id, USAGE).await;
+    };
+    let sans = san_moves(game.start_fen(), &game_ucis(&state.db, game_id).await?);
+    let Some(san) = sans.get(ply as usize - 1) else {
+        return reply(state, user_id, format!("Game #{game_id} ended before move {}", move_label(ply))).await;
+    };
+    let noted = format!("{} {san}", move_label(ply));
+    let nag = words.next_if(|word| symbol_nag(word).is_some()).and_then(symbol_nag);
+    // braces would end the PGN comment
+    let text = words.collect::<Vec<_>>().join(" ").replace(['{', '}'], "");
+    if nag.is_none() && text == "off" {
+        let removed = sqlx::query("delete from move_notes where game_id = $1 and user_id = $2 and ply = $3")
+            .bind(game_id)
+            .bind(user_id)
+            .bind(ply)
+            .execute(&state.db)
+            .await?
+            .rows_affected();
+        let text = match removed {
+            0 => format!("You have no note on {noted}."),
+            _ => format!("Removed your note on {noted}."),
+        };
+        return reply(state, user_id, text).await;
+    }
+    if nag.is_none() && text.is_empty() {
+        return reply(state, user_id, USAGE).await;
+    }
+    if text.chars().count() > MAX_NOTE_CHARS {
+        return reply(state, user_id, format!("Notes are up to {MAX_NOTE_CHARS} characters.")).await;
+    }
+    sqlx::query(
+        "insert into move_notes (game_id, user_id, ply, nag, text) values ($1, $2, $3, $4, $5)
+         on conflict (game_id, user_id, ply) do update set nag = excluded.nag, text = excluded.text,
+            created_at = unixepoch()",
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .bind(ply)
+    .bind(nag)
+    .bind(&text)
+    .execute(&state.db)
+    .await?;
+    let text = format!("Noted {noted} in game #{game_id}. It is in the game's /pgn and /review.");
+    reply(state, user_id, text).await
+}
+
+fn symbol_nag(word: &str) -> Option<u8> {
+    NAGS.iter().find(|&&(symbol, _)| symbol == word).map(|&(_, nag)| nag)
+}
+
+async fn list(state: &State, user_id: i64, game_id: i64) -> Result<()> {
+    let notes = of_game(&state.db, game_id).await?;
+    if notes.is_empty() {
+        return reply(state, user_id, format!("Game #{game_id} has no notes.\n{USAGE}")).await;
+    }
+    let mut text = format!("Notes on game #{game_id}:");
+    for note in &notes {
+        text = format!("{text}\n{} {}", move_label(note.ply), describe(&state.db, note).await?);
+    }
+    reply(state, user_id, text).await
+}
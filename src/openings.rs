@@ -0,0 +1,56 @@
+/// A few well-known openings, keyed by their moves in SAN.
+const OPENINGS: &[(&str, &str)] = &[
+    ("e4", "King's Pawn Opening"),
+    ("e4 e5", "Open Game"),
+    ("e4 e5 Nf3 Nc6 Bb5", "Ruy Lopez"),
+    ("e4 e5 Nf3 Nc6 Bc4", "Italian Game"),
+    ("e4 e5 Nf3 Nc6 Bc4 Bc5", "Giuoco Piano"),
+    ("e4 e5 Nf3 Nc6 Bc4 Nf6", "Two Knights Defense"),
+    ("e4 e5 Nf3 Nc6 d4", "Scotch Game"),
+    ("e4 e5 Nf3 Nc6 Nc3 Nf6", "Four Knights Game"),
+    ("e4 e5 Nf3 Nf6", "Petrov's Defense"),
+    ("e4 e5 Nf3 d6", "Philidor Defense"),
+    ("e4 e5 f4", "King's Gambit"),
+    ("e4 e5 Nc3", "Vienna Game"),
+    ("e4 c5", "Sicilian Defense"),
+    ("e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3 a6", "Sicilian Defense: Najdorf Variation"),
+    ("e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3 g6", "Sicilian Defense: Dragon Variation"),
+    ("e4 c5 Nc3", "Sicilian Defense: Closed"),
+    ("e4 c5 c3", "Sicilian Defense: Alapin Variation"),
+    ("e4 e6", "French Defense"),
+    ("e4 c6", "Caro-Kann Defense"),
+    ("e4 d5", "Scandinavian Defense"),
+    ("e4 d6", "Pirc Defense"),
+    ("e4 g6", "Modern Defense"),
+    ("e4 Nf6", "Alekhine's Defense"),
+    ("d4", "Queen's Pawn Opening"),
+    ("d4 d5 c4", "Queen's Gambit"),
+    ("d4 d5 c4 dxc4", "Queen's Gambit Accepted"),
+    ("d4 d5 c4 e6", "Queen's Gambit Declined"),
+    ("d4 d5 c4 c6", "Slav Defense"),
+    ("d4 d5 Bf4", "London System"),
+    ("d4 Nf6 Bf4", "London System"),
+    ("d4 Nf6 c4 e6 Nc3 Bb4", "Nimzo-Indian Defense"),
+    ("d4 Nf6 c4 e6 Nf3 b6", "Queen's Indian Defense"),
+    ("d4 Nf6 c4 g6", "King's Indian Defense"),
+    ("d4 Nf6 c4 g6 Nc3 d5", "Grünfeld Defense"),
+    ("d4 Nf6 c4 c5", "Benoni Defense"),
+    ("d4 f5", "Dutch Defense"),
+    ("c4", "English Opening"),
+    ("Nf3", "Réti Opening"),
+    ("g3", "Hungarian Opening"),
+    ("b3", "Nimzo-Larsen Attack"),
+    ("f4", "Bird's Opening"),
+];
+
+/// Name of the most specific known opening the game started with.
+pub fn name(sans: &[String]) -> Option<&'static str> {
+    OPENINGS
+        .iter()
+        .filter(|(moves, _)| {
+            let moves: Vec<&str> = moves.split(' ').collect();
+            moves.len() <= sans.len() && moves.iter().zip(sans).all(|(a, b)| *a == b.as_str())
+        })
+        .max_by_key(|(moves, _)| moves.split(' ').count())
+        .map(|&(_, name)| name)
+}
@@ -0,0 +1,146 @@
+/// Renders a game as PGN with the given tag pairs, wrapping movetext at 80 columns.
+/// `nags` and `comments` are attached to the moves they line up with and may
+/// be shorter than the moves; empty comments are left out.
+pub fn render(
+    headers: &[(&str, String)],
+    sans: &[String],
+    nags: &[Vec<u8>],
+    comments: &[String],
+    result: &str,
+) -> String {
+    let mut pgn = String::new();
+    for (name, value) in headers {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        pgn.push_str(&format!("[{name} \"{value}\"]\n"));
+    }
+    pgn.push('\n');
+
+    let mut tokens = Vec::with_capacity(sans.len() * 3 / 2 + 1);
+    for (ply, san) in sans.iter().enumerate() {
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        }
+        tokens.push(san.clone());
+        for nag in nags.get(ply).into_iter().flatten() {
+            tokens.push(format!("${nag}"));
+        }
+        if let Some(comment) = comments.get(ply).filter(|comment| !comment.is_empty()) {
+            tokens.push(format!("{{{comment}}}"));
+        }
+    }
+    tokens.push(result.to_string());
+
+    let mut line_len = 0;
+    for token in tokens {
+        if line_len > 0 && line_len + 1 + token.len() > 80 {
+            pgn.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            pgn.push(' ');
+            line_len += 1;
+        }
+        line_len += token.len();
+        pgn.push_str(&token);
+    }
+    pgn.push('\n');
+    pgn
+}
+
+/// A game read from PGN.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub headers: Vec<(String, String)>,
+    /// Every line from the starting position to the end of a variation, in
+    /// SAN, the main line first.
+    pub lines: Vec<Vec<String>>,
+    pub result: String,
+}
+
+impl Parsed {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn mainline(&self) -> &[String] {
+        self.lines.first().map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Reads the games in a PGN file. Comments and NAGs are dropped; moves are
+/// not checked for legality.
+pub fn parse(text: &str) -> Vec<Parsed> {
+    let mut games = Vec::new();
+    let mut game = Parsed::default();
+    let (mut line, mut parents): (Vec<String>, Vec<Vec<String>>) = (Vec::new(), Vec::new());
+    let mut in_movetext = false;
+    let mut finish = |game: &mut Parsed, line: &mut Vec<String>, parents: &mut Vec<Vec<String>>| {
+        if !line.is_empty() || !game.headers.is_empty() {
+            game.lines.insert(0, std::mem::take(line));
+            games.push(std::mem::take(game));
+        }
+        parents.clear();
+    };
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' if parents.is_empty() => {
+                if in_movetext {
+                    finish(&mut game, &mut line, &mut parents);
+                    in_movetext = false;
+                }
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                if let Some((name, value)) = tag.split_once(' ') {
+                    let value = value.trim().trim_matches('"').replace("\\\"", "\"").replace("\\\\", "\\");
+                    game.headers.push((name.to_string(), value));
+                }
+            }
+            '{' => {
+                chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+            }
+            ';' => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+            }
+            '(' => {
+                in_movetext = true;
+                parents.push(line.clone());
+                line.pop();
+            }
+            ')' => {
+                if let Some(parent) = parents.pop() {
+                    game.lines.push(std::mem::replace(&mut line, parent));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                in_movetext = true;
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "(){};[".contains(c) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" if parents.is_empty() => {
+                        game.result = token;
+                        finish(&mut game, &mut line, &mut parents);
+                        in_movetext = false;
+                    }
+                    t if t.starts_with('$') => {}
+                    t => {
+                        // "12.", "12...", or a move glued to its number like "12.e4"
+                        let san = t.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                        let san = san.trim_end_matches(['!', '?']);
+                        if !san.is_empty() {
+                            line.push(san.to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    finish(&mut game, &mut line, &mut parents);
+    games
+}
@@ -0,0 +1,340 @@
+//! Incoming updates pass through a fixed chain of stages before a handler
+//! sees them: tracing, dropping redelivered messages, bans, flood limits and
+//! the user's language are dealt with here once, rather than in every
+//! handler. A stage either passes the update on or stops it.
+
+use crate::{
+    blocked, corrections, handle_message, on_move, packed_chat, repertoire, save_user, settings, templates, voice,
+    State, LATENCY_SAMPLES,
+};
+use anyhow::Result;
+use grammers_client::types::{Chat, Message};
+use grammers_client::Update;
+use grammers_tl_types as tl;
+use log::{debug, error, info};
+use shakmaty::Color;
+use std::time::{Duration, Instant};
+
+/// Messages remembered to notice Telegram delivering one twice.
+const RECENT_MESSAGES: usize = 256;
+
+/// Most messages a user may send within `DEFAULT_RATE_WINDOW`, unless
+/// configured otherwise; the rest are dropped.
+pub const DEFAULT_RATE_LIMIT: usize = 20;
+pub const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Users tracked for the rate limit before quiet ones are forgotten.
+const RATE_LIMITED_USERS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Message,
+    /// An earlier message, edited.
+    Edit,
+    /// The user blocked (`true`) or unblocked the bot.
+    BotStopped(bool),
+}
+
+/// An update from a user, whether from Telegram or typed into the REPL.
+pub struct Incoming {
+    event: Event,
+    user_id: i64,
+    user_name: String,
+    username: Option<String>,
+    message_id: Option<i32>,
+    text: String,
+    /// The Telegram message, for voice messages and documents.
+    message: Option<Message>,
+    /// The language of the user's Telegram app, if known.
+    app_language: Option<String>,
+    /// Set by the language stage.
+    language: String,
+}
+
+impl Incoming {
+    /// A message or edit without Telegram, such as one typed into the REPL.
+    pub fn offline(event: Event, user_id: i64, message_id: i32, text: &str) -> Self {
+        Incoming {
+            event,
+            user_id,
+            user_name: format!("User {user_id}"),
+            username: None,
+            message_id: Some(message_id),
+            text: text.to_string(),
+            message: None,
+            app_language: None,
+            language: String::new(),
+        }
+    }
+
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    /// The update, unless it's one the bot ignores.
+    pub fn from_update(update: Update) -> Option<Self> {
+        let (event, message) = match update {
+            Update::NewMessage(message) if !message.outgoing() => (Event::Message, message),
+            Update::MessageEdited(message) if !message.outgoing() => (Event::Edit, message),
+            Update::Raw(tl::enums::Update::BotStopped(update)) => {
+                return Some(Incoming {
+                    event: Event::BotStopped(update.stopped),
+                    user_id: update.user_id,
+                    user_name: String::new(),
+                    username: None,
+                    message_id: None,
+                    text: String::new(),
+                    message: None,
+                    app_language: None,
+                    language: String::new(),
+                });
+            }
+            update => {
+                debug!("unhandled update {update:?}");
+                return None;
+            }
+        };
+        let chat = message.chat();
+        let app_language = match &chat {
+            Chat::User(user) => user.lang_code().map(str::to_string),
+            _ => None,
+        };
+        Some(Incoming {
+            event,
+            user_id: chat.id(),
+            user_name: chat.name().to_string(),
+            username: chat.username().map(str::to_string),
+            message_id: Some(message.id()),
+            text: message.text().to_string(),
+            message: Some(message),
+            app_language,
+            language: String::new(),
+        })
+    }
+}
+
+enum Flow {
+    Next,
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Trace,
+    Dedupe,
+    Ban,
+    RateLimit,
+    Language,
+    Route,
+}
+
+const STAGES: [Stage; 6] = [
+    Stage::Trace,
+    Stage::Dedupe,
+    Stage::Ban,
+    Stage::RateLimit,
+    Stage::Language,
+    Stage::Route,
+];
+
+impl Stage {
+    async fn run(self, state: &mut State, incoming: &mut Incoming) -> Result<Flow> {
+        match self {
+            Stage::Trace => {
+                info!("{:?} by {} {}: {}", incoming.event, incoming.user_id, incoming.user_name, incoming.text);
+                Ok(Flow::Next)
+            }
+            Stage::Dedupe => Ok(dedupe(state, incoming)),
+            Stage::Ban => ban(state, incoming).await,
+            Stage::RateLimit => rate_limit(state, incoming).await,
+            Stage::Language => {
+                let templates = state.config.get().templates.clone();
+                let app_language = incoming.app_language.as_deref();
+                incoming.language = settings::language(&state.db, &templates, incoming.user_id, app_language).await?;
+                state.language = incoming.language.clone();
+                Ok(Flow::Next)
+            }
+            Stage::Route => {
+                route(state, incoming).await?;
+                Ok(Flow::Next)
+            }
+        }
+    }
+}
+
+/// Runs the update through the stages, timing it for `/admin stats`.
+pub async fn run(state: &mut State, mut incoming: Incoming) -> Result<()> {
+    let started = Instant::now();
+    state.message_id = incoming.message_id;
+    state.language = templates::DEFAULT_LANGUAGE.to_string();
+    let mut result = Ok(());
+    for stage in STAGES {
+        match stage.run(state, &mut incoming).await {
+            Ok(Flow::Next) => {}
+            Ok(Flow::Stop) => {
+                debug!("{stage:?} stopped {:?} by {}", incoming.event, incoming.user_id);
+                break;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    {
+        let mut latencies = state.latencies.lock().expect("latencies lock");
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(started.elapsed());
+    }
+    debug!(
+        "{:?} by {} ({}) took {:?}",
+        incoming.event,
+        incoming.user_id,
+        incoming.language,
+        started.elapsed()
+    );
+    result
+}
+
+/// Telegram may deliver a message again after reconnecting.
+fn dedupe(state: &mut State, incoming: &Incoming) -> Flow {
+    let (Event::Message, Some(message_id)) = (incoming.event, incoming.message_id) else {
+        return Flow::Next;
+    };
+    let key = (incoming.user_id, message_id);
+    if state.recent_messages.contains(&key) {
+        return Flow::Stop;
+    }
+    if state.recent_messages.len() == RECENT_MESSAGES {
+        state.recent_messages.pop_front();
+    }
+    state.recent_messages.push_back(key);
+    Flow::Next
+}
+
+/// Banned users are ignored, though the bot still notes when they block it.
+async fn ban(state: &State, incoming: &Incoming) -> Result<Flow> {
+    if let Event::BotStopped(_) = incoming.event {
+        return Ok(Flow::Next);
+    }
+    let banned: Option<bool> = sqlx::query_scalar("select banned_at is not null from users where id = $1")
+        .bind(incoming.user_id)
+        .fetch_optional(&state.db)
+        .await?;
+    Ok(if banned.unwrap_or(false) { Flow::Stop } else { Flow::Next })
+}
+
+/// Drops messages from users sending too many, telling them once.
+async fn rate_limit(state: &mut State, incoming: &Incoming) -> Result<Flow> {
+    if let Event::BotStopped(_) = incoming.event {
+        return Ok(Flow::Next);
+    }
+    let config = state.config.get();
+    let (limit, window) = (config.rate_limit, config.rate_window);
+    let now = Instant::now();
+    if state.rate_limits.len() >= RATE_LIMITED_USERS {
+        state
+            .rate_limits
+            .retain(|_, sent| sent.back().is_some_and(|&at| now.duration_since(at) < window));
+    }
+    let sent = state.rate_limits.entry(incoming.user_id).or_default();
+    while sent.front().is_some_and(|&at| now.duration_since(at) >= window) {
+        sent.pop_front();
+    }
+    match sent.len() {
+        n if n < limit => {
+            sent.push_back(now);
+            Ok(Flow::Next)
+        }
+        // the first message over the limit counts, so only it is answered
+        n if n == limit => {
+            sent.push_back(now);
+            let text = templates::text_for(state, incoming.user_id, "rate_limited", &[]).await?;
+            state.client.send_message(packed_chat(incoming.user_id), text).await?;
+            Ok(Flow::Stop)
+        }
+        _ => Ok(Flow::Stop),
+    }
+}
+
+/// Hands the update to its handler.
+async fn route(state: &mut State, incoming: &Incoming) -> Result<()> {
+    let user_id = incoming.user_id;
+    let message_id = incoming.message_id.unwrap_or_default();
+    match incoming.event {
+        Event::Edit => corrections::on_edit(state, user_id, message_id, &incoming.text).await,
+        Event::BotStopped(true) => blocked::gone(&state.db, &state.client, user_id).await,
+        Event::BotStopped(false) => blocked::back(&state.db, &state.client, user_id).await,
+        Event::Message => {
+            blocked::back(&state.db, &state.client, user_id).await?;
+            if let Some(message) = &incoming.message {
+                if on_media(state, incoming, message).await? {
+                    return Ok(());
+                }
+            }
+            if let Some(log) = &state.update_log {
+                log.record(user_id, &incoming.text);
+            }
+            let username = incoming.username.as_deref();
+            handle_message(state, user_id, &incoming.user_name, username, &incoming.text).await
+        }
+    }
+}
+
+/// Handles PGN documents and voice messages, returning whether the message
+/// was one.
+async fn on_media(state: &mut State, incoming: &Incoming, message: &Message) -> Result<bool> {
+    let user_id = incoming.user_id;
+    let (user_name, username) = (incoming.user_name.as_str(), incoming.username.as_deref());
+    if let (Some(media), Some(client)) = (repertoire::pgn_document(message), state.client.telegram()) {
+        save_user(&state.db, user_id, user_name, username).await?;
+        let pgn = repertoire::download(client, media).await?;
+        let color = if incoming.text.to_lowercase().contains("black") {
+            Color::Black
+        } else {
+            Color::White
+        };
+        repertoire::add_lines(state, user_id, color, &pgn).await?;
+        return Ok(true);
+    }
+
+    let voice = match (&state.config.get().transcriber, state.client.telegram()) {
+        (Some(transcriber), Some(client)) => {
+            voice::voice_media(message).map(|media| (transcriber.clone(), client.clone(), media))
+        }
+        _ => None,
+    };
+    let Some((transcriber, client, media)) = voice else {
+        return Ok(false);
+    };
+    save_user(&state.db, user_id, user_name, username).await?;
+    let spoken = match transcriber.transcribe(&client, message, media).await {
+        Ok(spoken) => spoken,
+        Err(e) => {
+            error!("cannot transcribe voice message by {user_id}: {e}");
+            state
+                .client
+                .send_message(packed_chat(user_id), "Couldn't make out that voice message, please type your move.")
+                .await?;
+            return Ok(true);
+        }
+    };
+    info!("voice message by {user_id}: {spoken}");
+    match voice::normalize(&spoken) {
+        Some(notation) => {
+            if let Some(log) = &state.update_log {
+                log.record(user_id, &notation);
+            }
+            on_move(state, user_id, &notation).await?
+        }
+        None => {
+            state
+                .client
+                .send_message(packed_chat(user_id), format!("Heard \"{spoken}\", but that's not a move."))
+                .await?;
+        }
+    }
+    Ok(true)
+}
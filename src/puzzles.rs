@@ -0,0 +1,154 @@
+//! Puzzles in the format of the Lichess puzzle database, which
+//! `tgpawn import-puzzles` loads from its CSV export.
+
+use crate::rating::{self, Category, Rating};
+use anyhow::{anyhow, Context, Result};
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{CastlingMode, Chess, Move, Position};
+use sqlx::{Pool, Sqlite};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+pub const COLUMNS: &str = "id, fen, moves, rating, themes";
+
+/// How sure a puzzle's rating is taken to be when a user is rated against it.
+const PUZZLE_DEVIATION: f64 = 75.0;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Puzzle {
+    pub id: String,
+    /// Position before the opponent's move that sets up the puzzle.
+    pub fen: String,
+    /// The line in UCI, starting with the opponent's move.
+    pub moves: String,
+    pub rating: i64,
+    /// Space-separated Lichess theme tags, e.g. `mate mateIn2 short`.
+    pub themes: String,
+}
+
+impl Puzzle {
+    pub fn line(&self) -> Vec<&str> {
+        self.moves.split_whitespace().collect()
+    }
+
+    /// The position after the first `step` moves of the line, and the last of
+    /// those moves in SAN.
+    pub fn position_at(&self, step: usize) -> Result<(Chess, Option<String>)> {
+        let mut position: Chess = self
+            .fen
+            .parse::<Fen>()?
+            .into_position(CastlingMode::Standard)
+            .map_err(|e| anyhow!("puzzle {}: {e}", self.id))?;
+        let mut last = None;
+        for uci in self.line().into_iter().take(step) {
+            let m = uci
+                .parse::<Uci>()?
+                .to_move(&position)
+                .map_err(|e| anyhow!("puzzle {}: {e}", self.id))?;
+            last = Some(San::from_move(&position, &m).to_string());
+            position.play_unchecked(&m);
+        }
+        Ok((position, last))
+    }
+
+    /// Whether `m`, played in `position` at `step` of the line, solves it.
+    /// Any mate is accepted, as on Lichess.
+    pub fn accepts(&self, position: &Chess, step: usize, m: &Move) -> bool {
+        if self.line().get(step) == Some(&m.to_uci(CastlingMode::Standard).to_string().as_str()) {
+            return true;
+        }
+        let mut after = position.clone();
+        after.play_unchecked(m);
+        after.is_checkmate()
+    }
+}
+
+/// Loads puzzles from a Lichess CSV export, replacing any with the same id.
+/// Returns how many were imported; malformed rows are skipped.
+pub async fn import(db: &Pool<Sqlite>, path: &str) -> Result<usize> {
+    let file = File::open(path).with_context(|| format!("cannot open {path}"))?;
+    let mut tx = db.begin().await?;
+    let mut imported = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+        let fields: Vec<&str> = line.split(',').collect();
+        let (Some(id), Some(fen), Some(moves), Some(rating), Some(themes)) =
+            (fields.first(), fields.get(1), fields.get(2), fields.get(3), fields.get(7))
+        else {
+            continue;
+        };
+        let Ok(rating) = rating.parse::<i64>() else {
+            // the header, or garbage
+            continue;
+        };
+        let puzzle = Puzzle {
+            id: id.to_string(),
+            fen: fen.to_string(),
+            moves: moves.to_string(),
+            rating,
+            themes: themes.to_string(),
+        };
+        if puzzle.line().len() < 2 || puzzle.position_at(puzzle.line().len()).is_err() {
+            continue;
+        }
+        sqlx::query("insert or replace into puzzles (id, fen, moves, rating, themes) values ($1, $2, $3, $4, $5)")
+            .bind(&puzzle.id)
+            .bind(&puzzle.fen)
+            .bind(&puzzle.moves)
+            .bind(puzzle.rating)
+            .bind(&puzzle.themes)
+            .execute(&mut *tx)
+            .await?;
+        imported += 1;
+    }
+    tx.commit().await?;
+    Ok(imported)
+}
+
+/// Rates an attempt at the puzzle against its difficulty, returning the
+/// user's new puzzle rating and how much it changed.
+pub async fn rate_attempt(db: &Pool<Sqlite>, user_id: i64, puzzle: &Puzzle, solved: bool) -> Result<(i64, i64)> {
+    let mut tx = db.begin().await?;
+    let old = rating::get(&mut *tx, user_id, Category::Puzzle).await?;
+    let difficulty = Rating {
+        rating: puzzle.rating as f64,
+        deviation: PUZZLE_DEVIATION,
+    };
+    let new = old.after_game(difficulty, if solved { 1.0 } else { 0.0 });
+    rating::record(&mut *tx, user_id, Category::Puzzle, new).await?;
+    tx.commit().await?;
+    let rounded = new.rating.round() as i64;
+    Ok((rounded, rounded - old.rating.round() as i64))
+}
+
+/// Saves a puzzle from the owner's own game, which only they are given.
+/// Returns whether it is new.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_personal(
+    db: &Pool<Sqlite>,
+    owner_id: i64,
+    game_id: i64,
+    ply: usize,
+    rating: i64,
+    fen: &str,
+    moves: &[String],
+    themes: &str,
+) -> Result<bool> {
+    let saved = sqlx::query(
+        "insert or ignore into puzzles (id, fen, moves, rating, themes, owner_id, game_id) values ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(format!("g{game_id}-{ply}"))
+    .bind(fen)
+    .bind(moves.join(" "))
+    .bind(rating)
+    .bind(themes)
+    .bind(owner_id)
+    .bind(game_id)
+    .execute(db)
+    .await?
+    .rows_affected();
+    Ok(saved > 0)
+}
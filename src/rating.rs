@@ -0,0 +1,201 @@
+use anyhow::Result;
+use sqlx::{Executor, Sqlite};
+use std::f64::consts::{LN_10, PI};
+use std::time::Duration;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+pub const MAX_DEVIATION: f64 = 350.0;
+pub const MIN_DEVIATION: f64 = 45.0;
+
+/// Deviation gained per day without rated games, so that an established
+/// rating becomes as uncertain as a new one after about a year away.
+const DEVIATION_DECAY_PER_DAY: f64 = 18.1;
+
+/// Number of rated games before a rating is considered established.
+pub const PROVISIONAL_GAMES: i64 = 10;
+
+/// A Glicko-1 rating.
+#[derive(Debug, Clone, Copy)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: INITIAL_RATING,
+            deviation: MAX_DEVIATION,
+        }
+    }
+}
+
+/// What a rating is for: standard chess at each speed, each variant at any
+/// speed, and solving puzzles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+    FogOfWar,
+    Puzzle,
+}
+
+pub const CATEGORIES: [Category; 7] = [
+    Category::Bullet,
+    Category::Blitz,
+    Category::Rapid,
+    Category::Classical,
+    Category::Correspondence,
+    Category::FogOfWar,
+    Category::Puzzle,
+];
+
+impl Category {
+    /// The speed of a standard game with this time control, judged by the
+    /// initial time plus 40 increments. Untimed games are correspondence.
+    pub fn of_speed(initial: Option<Duration>, increment: Duration) -> Category {
+        let Some(initial) = initial else {
+            return Category::Correspondence;
+        };
+        match (initial + increment * 40).as_secs() {
+            0..180 => Category::Bullet,
+            180..480 => Category::Blitz,
+            480..1500 => Category::Rapid,
+            1500..86400 => Category::Classical,
+            _ => Category::Correspondence,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Category> {
+        CATEGORIES.into_iter().find(|c| c.as_str() == s)
+    }
+
+    /// The key stored in `ratings.category`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Bullet => "bullet",
+            Category::Blitz => "blitz",
+            Category::Rapid => "rapid",
+            Category::Classical => "classical",
+            Category::Correspondence => "correspondence",
+            Category::FogOfWar => "fog",
+            Category::Puzzle => "puzzle",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Bullet => "Bullet",
+            Category::Blitz => "Blitz",
+            Category::Rapid => "Rapid",
+            Category::Classical => "Classical",
+            Category::Correspondence => "Correspondence",
+            Category::FogOfWar => "Fog of war",
+            Category::Puzzle => "Puzzles",
+        }
+    }
+
+    /// What `rated_games` counts for the category.
+    pub fn unit(self, n: i64) -> &'static str {
+        match (self, n) {
+            (Category::Puzzle, 1) => "puzzle",
+            (Category::Puzzle, _) => "puzzles",
+            (_, 1) => "game",
+            _ => "games",
+        }
+    }
+}
+
+/// The user's rating in the category, or a new one.
+pub async fn get<'e>(db: impl Executor<'e, Database = Sqlite>, user_id: i64, category: Category) -> Result<Rating> {
+    let rating: Option<(f64, f64)> =
+        sqlx::query_as("select rating, rating_deviation from ratings where user_id = $1 and category = $2")
+            .bind(user_id)
+            .bind(category.as_str())
+            .fetch_optional(db)
+            .await?;
+    Ok(rating.map_or_else(Rating::default, |(rating, deviation)| Rating { rating, deviation }))
+}
+
+/// The highest established ratings, overall or in the category, with the
+/// users' ids and names.
+pub async fn top<'e>(
+    db: impl Executor<'e, Database = Sqlite>,
+    category: Option<Category>,
+    limit: i64,
+) -> Result<Vec<(i64, Option<String>, f64)>> {
+    Ok(match category {
+        None => sqlx::query_as("select id, name, rating from users where rated_games >= $1 order by rating desc limit $2")
+            .bind(PROVISIONAL_GAMES)
+            .bind(limit)
+            .fetch_all(db)
+            .await?,
+        Some(category) => sqlx::query_as(
+            "select u.id, u.name, r.rating from ratings r join users u on u.id = r.user_id
+             where r.category = $3 and r.rated_games >= $1 order by r.rating desc limit $2",
+        )
+        .bind(PROVISIONAL_GAMES)
+        .bind(limit)
+        .bind(category.as_str())
+        .fetch_all(db)
+        .await?,
+    })
+}
+
+/// Stores the user's rating in the category after one more rated game.
+pub async fn record<'e>(
+    db: impl Executor<'e, Database = Sqlite>,
+    user_id: i64,
+    category: Category,
+    rating: Rating,
+) -> Result<()> {
+    sqlx::query(
+        "insert into ratings (user_id, category, rating, rating_deviation, rated_games, deviation_updated_at)
+         values ($1, $2, $3, $4, 1, unixepoch())
+         on conflict (user_id, category) do update set rating = excluded.rating, rating_deviation = excluded.rating_deviation,
+            rated_games = rated_games + 1, deviation_updated_at = excluded.deviation_updated_at",
+    )
+    .bind(user_id)
+    .bind(category.as_str())
+    .bind(rating.rating)
+    .bind(rating.deviation)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+const Q: f64 = LN_10 / 400.0;
+
+fn g(deviation: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * Q * Q * deviation * deviation / (PI * PI)).sqrt()
+}
+
+impl Rating {
+    /// Rating after a single game against `opponent`, scoring 1, ½ or 0.
+    pub fn after_game(self, opponent: Rating, score: f64) -> Rating {
+        let g = g(opponent.deviation);
+        let expected = 1.0 / (1.0 + 10f64.powf(-g * (self.rating - opponent.rating) / 400.0));
+        let d2 = 1.0 / (Q * Q * g * g * expected * (1.0 - expected));
+        let precision = 1.0 / (self.deviation * self.deviation) + 1.0 / d2;
+        Rating {
+            rating: self.rating + Q / precision * g * (score - expected),
+            deviation: (1.0 / precision).sqrt().clamp(MIN_DEVIATION, MAX_DEVIATION),
+        }
+    }
+}
+
+/// Deviation after `days` without rated games.
+pub fn decayed_deviation(deviation: f64, days: i64) -> f64 {
+    (deviation * deviation + DEVIATION_DECAY_PER_DAY * DEVIATION_DECAY_PER_DAY * days as f64)
+        .sqrt()
+        .min(MAX_DEVIATION)
+}
+
+/// Formats a rating for display, marking provisional ones with `?`.
+pub fn display(rating: f64, rated_games: i64) -> String {
+    let marker = if rated_games < PROVISIONAL_GAMES { "?" } else { "" };
+    format!("{}{marker}", rating.round() as i64)
+}
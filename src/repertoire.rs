@@ -0,0 +1,424 @@
+//! Opening repertoire drills. Users send their lines as PGN; every position
+//! where it's their move becomes a card, and `/drill` plays the opponent's
+//! side up to due cards and asks for the repertoire move, rescheduling each
+//! card with SM-2.
+
+use crate::bot::Bot;
+use crate::srs::Schedule;
+use crate::{
+    clock, diagram, ongoing_game, openings, packed_chat, parse_move, parse_typed_move, pgn, settings, training, State,
+};
+use anyhow::{bail, Result};
+use grammers_client::types::{Downloadable, Media, Message};
+use grammers_client::Client;
+use log::debug;
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{Bitboard, CastlingMode, Chess, Color, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+
+/// Largest PGN file accepted as an upload.
+const MAX_UPLOAD_BYTES: i64 = 1 << 20;
+
+/// Answers quicker than this are graded perfect, slower ones merely correct.
+const QUICK_ANSWER_MS: i64 = 10_000;
+
+const SECS_PER_DAY: f64 = 86_400.0;
+
+const COLUMNS: &str = "id, user_id, card_id, prompted_at, reviewed, correct";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Drill {
+    id: i64,
+    user_id: i64,
+    card_id: Option<i64>,
+    prompted_at: Option<i64>,
+    reviewed: i64,
+    correct: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Card {
+    id: i64,
+    white: bool,
+    path: String,
+    answers: String,
+    #[sqlx(flatten)]
+    schedule: Schedule,
+}
+
+/// FEN without the halfmove clock and move number.
+fn position_key(position: &Chess) -> String {
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    fen.split(' ').take(4).collect::<Vec<_>>().join(" ")
+}
+
+/// The PGN file attached to `message`, if any.
+pub fn pgn_document(message: &Message) -> Option<Media> {
+    let media = message.media()?;
+    let Media::Document(document) = &media else {
+        return None;
+    };
+    let is_pgn = document.name().to_lowercase().ends_with(".pgn")
+        || document.mime_type().is_some_and(|mime| mime.contains("chess-pgn"));
+    is_pgn.then_some(media)
+}
+
+pub async fn download(client: &Client, media: Media) -> Result<String> {
+    if let Media::Document(document) = &media {
+        if document.size() > MAX_UPLOAD_BYTES {
+            bail!("PGN file too large");
+        }
+    }
+    let mut bytes = Vec::new();
+    let mut download = client.iter_download(&Downloadable::Media(media));
+    while let Some(chunk) = download.next().await? {
+        bytes.extend(chunk);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Adds the lines of a PGN to the user's repertoire for `color`, returning
+/// how many positions were new.
+async fn import(db: &Pool<Sqlite>, user_id: i64, color: Color, text: &str) -> Result<usize> {
+    // position -> (path, answers)
+    let mut cards: BTreeMap<String, (Vec<String>, Vec<String>)> = BTreeMap::new();
+    for game in pgn::parse(text) {
+        if game.header("FEN").is_some() {
+            continue;
+        }
+        for line in &game.lines {
+            let mut position = Chess::default();
+            let mut path = Vec::new();
+            for san in line {
+                let Some(m) = parse_move(san, &position) else {
+                    break;
+                };
+                let uci = m.to_uci(CastlingMode::Standard).to_string();
+                if position.turn() == color {
+                    let (_, answers) = cards
+                        .entry(position_key(&position))
+                        .or_insert_with(|| (path.clone(), Vec::new()));
+                    if !answers.contains(&uci) {
+                        answers.push(uci.clone());
+                    }
+                }
+                path.push(uci);
+                position.play_unchecked(&m);
+            }
+        }
+    }
+
+    let mut tx = db.begin().await?;
+    let mut added = 0;
+    for (key, (path, answers)) in cards {
+        let existing: Option<String> =
+            sqlx::query_scalar("select answers from repertoire_cards where user_id = $1 and white = $2 and position = $3")
+                .bind(user_id)
+                .bind(color.is_white())
+                .bind(&key)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let mut merged: Vec<String> = existing
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        for answer in answers {
+            if !merged.contains(&answer) {
+                merged.push(answer);
+            }
+        }
+        if existing.is_none() {
+            added += 1;
+        }
+        sqlx::query(
+            "insert into repertoire_cards (user_id, white, position, path, answers, due_at) values ($1, $2, $3, $4, $5, unixepoch())
+             on conflict (user_id, white, position) do update set answers = excluded.answers",
+        )
+        .bind(user_id)
+        .bind(color.is_white())
+        .bind(&key)
+        .bind(path.join(" "))
+        .bind(merged.join(" "))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(added)
+}
+
+/// Imports a PGN sent by the user and tells them how it went.
+pub async fn add_lines(state: &mut State, user_id: i64, color: Color, text: &str) -> Result<()> {
+    let added = import(&state.db, user_id, color, text).await?;
+    let side = if color.is_white() { "White" } else { "Black" };
+    let reply = if added == 0 {
+        format!("No new positions found for {side}. Is that a PGN of your lines?")
+    } else {
+        format!("Added {added} positions to your repertoire as {side}. Type /drill to practice them.")
+    };
+    state.client.send_message(packed_chat(user_id), reply).await?;
+    Ok(())
+}
+
+pub async fn on_repertoire(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let (first, rest) = args.trim().split_once(char::is_whitespace).unwrap_or((args.trim(), ""));
+    match (first, rest.trim()) {
+        ("white", pgn) | ("black", pgn) if !pgn.is_empty() => {
+            let color = if first == "white" { Color::White } else { Color::Black };
+            add_lines(state, user_id, color, pgn).await
+        }
+        ("clear", side @ ("white" | "black")) => {
+            let deleted = sqlx::query("delete from repertoire_cards where user_id = $1 and white = $2")
+                .bind(user_id)
+                .bind(side == "white")
+                .execute(&state.db)
+                .await?
+                .rows_affected();
+            state
+                .client
+                .send_message(packed_chat(user_id), format!("Removed {deleted} positions."))
+                .await?;
+            Ok(())
+        }
+        ("", "") => {
+            let (white, black, due): (i64, i64, i64) = sqlx::query_as(
+                "select coalesce(sum(white), 0), coalesce(sum(not white), 0), coalesce(sum(due_at <= unixepoch()), 0)
+                 from repertoire_cards where user_id = $1",
+            )
+            .bind(user_id)
+            .fetch_one(&state.db)
+            .await?;
+            let text = format!(
+                "Your repertoire has {white} positions as White and {black} as Black, {due} due for review.\n\
+                 Send a PGN file (captioned \"black\" for Black's lines) or /repertoire white|black <pgn> to add lines, \
+                 /drill to practice, /repertoire clear white|black to start over."
+            );
+            state.client.send_message(packed_chat(user_id), text).await?;
+            Ok(())
+        }
+        _ => {
+            state
+                .client
+                .send_message(packed_chat(user_id), "Usage: /repertoire [white|black <pgn> | clear white|black]")
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Drill>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from drills where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+pub async fn on_drill(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    match args.trim() {
+        "" => {}
+        "stop" => {
+            return match running(&state.db, user_id).await? {
+                Some(drill) => end(&state.db, &state.client, &drill).await,
+                None => {
+                    state
+                        .client
+                        .send_message(packed_chat(user_id), "You are not drilling.")
+                        .await?;
+                    Ok(())
+                }
+            };
+        }
+        _ => {
+            state.client.send_message(packed_chat(user_id), "Usage: /drill [stop]").await?;
+            return Ok(());
+        }
+    }
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state
+            .client
+            .send_message(packed_chat(user_id), "Finish your game first.")
+            .await?;
+        return Ok(());
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        state
+            .client
+            .send_message(packed_chat(user_id), format!("Finish your {what} first."))
+            .await?;
+        return Ok(());
+    }
+    sqlx::query("insert into drills (user_id) values ($1)")
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+    let drill = running(&state.db, user_id).await?.expect("just started");
+    next_card(&state.db, &state.client, &drill).await
+}
+
+/// Shows the next due card, going through lines from the start, or ends the
+/// drill when nothing is due.
+async fn next_card(db: &Pool<Sqlite>, client: &Bot, drill: &Drill) -> Result<()> {
+    let card: Option<Card> = sqlx::query_as(
+        "select id, white, path, answers, ease, interval_days, repetitions from repertoire_cards
+         where user_id = $1 and due_at <= unixepoch() order by due_at, length(path) limit 1",
+    )
+    .bind(drill.user_id)
+    .fetch_optional(db)
+    .await?;
+    let Some(card) = card else {
+        return end(db, client, drill).await;
+    };
+    sqlx::query("update drills set card_id = $2, prompted_at = $3 where id = $1")
+        .bind(drill.id)
+        .bind(card.id)
+        .bind(clock::now_ms())
+        .execute(db)
+        .await?;
+
+    let (position, sans) = replay(&card.path);
+    let color = if card.white { Color::White } else { Color::Black };
+    let mut text = if sans.is_empty() {
+        "Your first move?".to_string()
+    } else {
+        let mut text = numbered(&sans);
+        if let Some(name) = openings::name(&sans) {
+            text = format!("{name}: {text}");
+        }
+        format!("{text}\nYour move?")
+    };
+    let theme = settings::theme(db, drill.user_id).await?;
+    text = format!("{text}\n{}", diagram::render(position.board(), color, Bitboard::FULL, theme));
+    client.send_message(packed_chat(drill.user_id), text).await?;
+    Ok(())
+}
+
+/// The position after a path of UCI moves, and the moves in SAN.
+fn replay(path: &str) -> (Chess, Vec<String>) {
+    let mut position = Chess::default();
+    let mut sans = Vec::new();
+    for uci in path.split_whitespace() {
+        let Some(m) = uci.parse::<Uci>().ok().and_then(|uci| uci.to_move(&position).ok()) else {
+            break;
+        };
+        sans.push(San::from_move(&position, &m).to_string());
+        position.play_unchecked(&m);
+    }
+    (position, sans)
+}
+
+/// `1. e4 e5 2. Nf3`
+fn numbered(sans: &[String]) -> String {
+    sans.iter()
+        .enumerate()
+        .map(|(ply, san)| {
+            if ply % 2 == 0 {
+                format!("{}. {san}", ply / 2 + 1)
+            } else {
+                san.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub async fn on_move(state: &mut State, mut drill: Drill, notation: &str) -> Result<()> {
+    let chat = packed_chat(drill.user_id);
+    let card: Option<Card> = match drill.card_id {
+        Some(id) => {
+            sqlx::query_as("select id, white, path, answers, ease, interval_days, repetitions from repertoire_cards where id = $1")
+                .bind(id)
+                .fetch_optional(&state.db)
+                .await?
+        }
+        None => None,
+    };
+    let Some(card) = card else {
+        return next_card(&state.db, &state.client, &drill).await;
+    };
+    let (position, _) = replay(&card.path);
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    let answers: Vec<&str> = card.answers.split_whitespace().collect();
+
+    let quality = if answers.contains(&uci.as_str()) {
+        drill.correct += 1;
+        let spent = clock::now_ms() - drill.prompted_at.unwrap_or(0);
+        if spent <= QUICK_ANSWER_MS {
+            5
+        } else {
+            4
+        }
+    } else {
+        1
+    };
+    let schedule = card.schedule.review(quality);
+    debug!("card {} graded {quality}: {schedule:?}", card.id);
+    sqlx::query(
+        "update repertoire_cards set ease = $2, interval_days = $3, repetitions = $4, due_at = unixepoch() + $5 where id = $1",
+    )
+    .bind(card.id)
+    .bind(schedule.ease)
+    .bind(schedule.interval_days)
+    .bind(schedule.repetitions)
+    .bind((schedule.interval_days * SECS_PER_DAY) as i64)
+    .execute(&state.db)
+    .await?;
+    drill.reviewed += 1;
+    sqlx::query("update drills set reviewed = $2, correct = $3 where id = $1")
+        .bind(drill.id)
+        .bind(drill.reviewed)
+        .bind(drill.correct)
+        .execute(&state.db)
+        .await?;
+
+    let text = if quality >= 4 {
+        format!("✓ {}", San::from_move(&position, &m))
+    } else {
+        let expected: Vec<String> = answers
+            .iter()
+            .filter_map(|uci| uci.parse::<Uci>().ok()?.to_move(&position).ok())
+            .map(|m| San::from_move(&position, &m).to_string())
+            .collect();
+        format!("✗ Your repertoire has {} here.", expected.join(" or "))
+    };
+    state.client.send_message(chat, text).await?;
+    next_card(&state.db, &state.client, &drill).await
+}
+
+async fn end(db: &Pool<Sqlite>, client: &Bot, drill: &Drill) -> Result<()> {
+    sqlx::query("update drills set ended = 1 where id = $1")
+        .bind(drill.id)
+        .execute(db)
+        .await?;
+    let next_due: Option<i64> = sqlx::query_scalar("select min(due_at) from repertoire_cards where user_id = $1")
+        .bind(drill.user_id)
+        .fetch_one(db)
+        .await?;
+    let mut text = if drill.reviewed == 0 {
+        "Nothing to review right now.".to_string()
+    } else {
+        format!("Reviewed {} positions, {} right.", drill.reviewed, drill.correct)
+    };
+    match next_due {
+        Some(due) => {
+            let wait_ms = (due * 1000 - clock::now_ms()).max(0);
+            text = format!(
+                "{text} Next review in {} ({}).",
+                clock::format_clock(wait_ms),
+                settings::local_time(db, drill.user_id, due * 1000).await?
+            );
+        }
+        None => text = format!("{text} Send a PGN of your lines to build a repertoire."),
+    }
+    client.send_message(packed_chat(drill.user_id), text).await?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! `tgpawn repl`: type messages on stdin and read the bot's replies on stdout,
+//! without Telegram. A line is sent as the current user; `@2 e5` sends `e5` as
+//! user 2 and makes them the current user, and `*e5` edits the current user's
+//! last message to `e5`. Lines go through the same stages as Telegram updates.
+
+use crate::bot::{Bot, Outbox};
+use crate::config::Config;
+use crate::pipeline::{self, Event, Incoming};
+use crate::{connect_db, migrate, State};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, Write};
+
+/// Users in the REPL are admins, so `/admin` commands can be tried too.
+const ADMIN: i64 = 1;
+
+pub async fn run() -> Result<()> {
+    // a scratch database unless one is given
+    let scratch = env::temp_dir().join(format!("tgpawn-repl-{}.db", std::process::id()));
+    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| format!("sqlite://{}", scratch.display()));
+    let db = connect_db(&database_url).await?;
+    migrate(&db).await?;
+
+    let outbox = Outbox::default();
+    let mut state = State::offline(db.clone(), Bot::Mock(outbox.clone()));
+    let mut config = Config::load()?;
+    config.admins.push(ADMIN);
+    state.config.set(config);
+
+    println!("Messages are sent as user {ADMIN}; `@<user> <message>` switches users. Ctrl-D quits.");
+    let mut user_id = ADMIN;
+    // each line is a message, numbered like Telegram's
+    let mut message_id = 0;
+    let mut last_message = HashMap::new();
+    let stdin = io::stdin();
+    loop {
+        print!("{user_id}> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let mut text = line.trim();
+        if let Some(rest) = text.strip_prefix('@') {
+            let (user, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+            match user.parse() {
+                Ok(user) => user_id = user,
+                Err(_) => {
+                    println!("not a user id: {user}");
+                    continue;
+                }
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let incoming = match text.strip_prefix('*') {
+            Some(edit) => match last_message.get(&user_id) {
+                Some(&id) => Incoming::offline(Event::Edit, user_id, id, edit),
+                None => {
+                    println!("no message to edit");
+                    continue;
+                }
+            },
+            None => {
+                message_id += 1;
+                last_message.insert(user_id, message_id);
+                Incoming::offline(Event::Message, user_id, message_id, text)
+            }
+        };
+        let result = pipeline::run(&mut state, incoming).await;
+        if let Err(e) = result {
+            println!("error: {e}");
+        }
+        for sent in outbox.drain() {
+            println!("[to {}] {}", sent.user_id, sent.text.replace('\n', "\n    "));
+        }
+    }
+
+    db.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", scratch.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! An append-only log of incoming messages, and `tgpawn replay` to feed one
+//! back through `handle_message` against a scratch database.
+//!
+//! Each line is `<unix ms>\t<user id>\t<text>` with backslashes, tabs and
+//! newlines escaped. Names are left out; replayed users are called `User <id>`.
+//! Clock-dependent outcomes (flags, timeouts) may differ on replay since the
+//! original timing isn't reproduced.
+
+use crate::bot::{Bot, Outbox};
+use crate::simulate::check_invariants;
+use crate::{clock, connect_db, handle_message, migrate, State};
+use anyhow::{bail, Context, Result};
+use log::error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
+
+/// Shared by the shards, which append whole lines.
+#[derive(Clone)]
+pub struct UpdateLog(Arc<Mutex<File>>);
+
+impl UpdateLog {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("cannot open update log {path}"))?;
+        Ok(UpdateLog(Arc::new(Mutex::new(file))))
+    }
+
+    pub fn record(&self, user_id: i64, text: &str) {
+        let line = format!("{}\t{user_id}\t{}\n", clock::now_ms(), escape(text));
+        if let Err(e) = self.0.lock().expect("update log lock").write_all(line.as_bytes()) {
+            error!("cannot write update log: {e}");
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// `tgpawn replay <log> [--until <n>] [--verbose]`: replays the first `n`
+/// entries (all by default), then checks the database invariants.
+pub async fn run(args: &[String]) -> Result<()> {
+    let Some(path) = args.first() else {
+        bail!("usage: tgpawn replay <log> [--until <n>] [--verbose]");
+    };
+    let until = match args.iter().position(|a| a == "--until") {
+        Some(i) => args.get(i + 1).context("--until needs a number")?.parse()?,
+        None => usize::MAX,
+    };
+    let verbose = args.iter().any(|a| a == "--verbose");
+
+    let scratch = std::env::temp_dir().join(format!("tgpawn-replay-{}.db", std::process::id()));
+    let db = connect_db(&format!("sqlite://{}", scratch.display())).await?;
+    migrate(&db).await?;
+    let outbox = Outbox::default();
+    let mut state = State::offline(db.clone(), Bot::Mock(outbox.clone()));
+
+    let mut replayed = 0;
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate().take(until) {
+        let line = line?;
+        let mut fields = line.splitn(3, '\t');
+        let (Some(_), Some(user_id), Some(text)) = (fields.next(), fields.next(), fields.next()) else {
+            bail!("malformed entry {}: {line}", n + 1);
+        };
+        let user_id: i64 = user_id.parse()?;
+        let text = unescape(text);
+        if verbose {
+            println!("#{} {user_id}> {text}", n + 1);
+        }
+        if let Err(e) = handle_message(&mut state, user_id, &format!("User {user_id}"), None, &text).await {
+            println!("#{} failed: {e}", n + 1);
+        }
+        for sent in outbox.drain() {
+            if verbose {
+                println!("[to {}] {}", sent.user_id, sent.text.replace('\n', "\n    "));
+            }
+        }
+        replayed += 1;
+    }
+
+    let violations = check_invariants(&db).await?;
+    db.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{suffix}", scratch.display()));
+    }
+    println!("replayed {replayed} entries");
+    for violation in &violations {
+        println!("invariant violated: {violation}");
+    }
+    if !violations.is_empty() {
+        bail!("{} invariant violations", violations.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Game review: `/review <game>` steps through a finished game a move at a
+//! time, showing the board, the move and the engine's evaluation. The
+//! review takes the user's messages until they stop it, like a trainer:
+//! `next` and `prev` move along, a number jumps to that move. A review can
+//! be shared as a link that opens it for whoever follows it.
+
+use crate::{analysis, notes};
+use crate::{game_by_id, game_ucis, ongoing_game, packed_chat, san_moves, settings, training, State, STARTING_FEN};
+use anyhow::Result;
+use log::debug;
+use shakmaty::Color;
+use sqlx::{Pool, Sqlite};
+
+/// Payloads of review links, followed by the game id.
+pub const PAYLOAD_PREFIX: &str = "review_";
+
+const USAGE: &str = "Usage: /review [game]";
+
+const CONTROLS: &str = "next (n) | prev (p) | first | last | <move number> | stop";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Review {
+    user_id: i64,
+    game_id: i64,
+    ply: i64,
+    white: bool,
+}
+
+/// The review the user has open.
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Review>> {
+    Ok(
+        sqlx::query_as("select user_id, game_id, ply, white from reviews where user_id = $1")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+/// The user's last finished game.
+pub async fn last_finished(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<i64>> {
+    Ok(sqlx::query_scalar(
+        "select id from games where (w_id = $1 or b_id = $1) and ended = 1 and started_at is not null
+         order by ended_at desc, id desc limit 1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?)
+}
+
+async fn reply(state: &State, user_id: i64, text: impl Into<String>) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text.into()).await?;
+    Ok(())
+}
+
+pub async fn on_review(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let args = args.trim().trim_start_matches('#');
+    let game_id = if args.is_empty() {
+        match last_finished(&state.db, user_id).await? {
+            Some(id) => id,
+            None => return reply(state, user_id, format!("You have no finished games.\n{USAGE}")).await,
+        }
+    } else {
+        match args.parse::<i64>() {
+            Ok(id) => id,
+            Err(_) => return reply(state, user_id, USAGE).await,
+        }
+    };
+    start(state, user_id, game_id).await
+}
+
+/// Opens a review of the game at its first move.
+pub async fn start(state: &mut State, user_id: i64, game_id: i64) -> Result<()> {
+    let game = game_by_id(&state.db, game_id).await?;
+    let Some(game) = game.filter(|g| g.ended && g.w_id.is_some() && g.b_id.is_some()) else {
+        return reply(state, user_id, format!("No finished game #{game_id}.")).await;
+    };
+    // moves would go to the review
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        return reply(state, user_id, "Finish your game first.").await;
+    }
+    if let Some(what) = training(&state.db, user_id).await?.filter(|&what| what != "game review") {
+        return reply(state, user_id, format!("Finish your {what} first.")).await;
+    }
+    sqlx::query(
+        "insert into reviews (user_id, game_id, ply, white) values ($1, $2, 0, $3)
+         on conflict (user_id) do update set game_id = excluded.game_id, ply = 0, white = excluded.white,
+            created_at = unixepoch()",
+    )
+    .bind(user_id)
+    .bind(game_id)
+    .bind(game.b_id != Some(user_id))
+    .execute(&state.db)
+    .await?;
+    debug!("{user_id} reviews game {game_id}");
+    let mut text = format!("Reviewing game #{game_id}, {}.", game.result().replace("1/2", "½"));
+    if !state.bot_username.is_empty() {
+        text = format!("{text}\nShare: https://t.me/{}?start={PAYLOAD_PREFIX}{game_id}", state.bot_username);
+    }
+    reply(state, user_id, text).await?;
+    let Some(review) = running(&state.db, user_id).await? else {
+        return Ok(());
+    };
+    step(state, review, 1).await
+}
+
+/// Takes a message sent during a review.
+pub async fn on_move(state: &mut State, review: Review, text: &str) -> Result<()> {
+    let plies = game_ucis(&state.db, review.game_id).await?.len() as i64;
+    let target = match text.trim().to_lowercase().as_str() {
+        "next" | "n" | "+" => review.ply + 1,
+        "prev" | "p" | "back" | "-" => review.ply - 1,
+        "first" => 1,
+        "last" => plies,
+        "stop" | "end" | "quit" => {
+            sqlx::query("delete from reviews where user_id = $1")
+                .bind(review.user_id)
+                .execute(&state.db)
+                .await?;
+            return reply(state, review.user_id, format!("Closed the review of game #{}.", review.game_id)).await;
+        }
+        // White's move of that number
+        number => match number.trim_end_matches('.').parse::<i64>() {
+            Ok(n) if n >= 1 => n.saturating_mul(2) - 1,
+            _ => return reply(state, review.user_id, CONTROLS).await,
+        },
+    };
+    step(state, review, target).await
+}
+
+/// Shows the position after `ply` moves, kept within the game.
+async fn step(state: &mut State, review: Review, ply: i64) -> Result<()> {
+    let ucis = game_ucis(&state.db, review.game_id).await?;
+    let ply = ply.clamp(0, ucis.len() as i64);
+    sqlx::query("update reviews set ply = $2 where user_id = $1")
+        .bind(review.user_id)
+        .bind(ply)
+        .execute(&state.db)
+        .await?;
+    let shown = &ucis[..ply as usize];
+    let game = game_by_id(&state.db, review.game_id).await?;
+    let start_fen = game.as_ref().map_or(STARTING_FEN, |game| game.start_fen());
+    let position = analysis::replay(start_fen, &shown.join(" "));
+    let mut text = match san_moves(start_fen, shown).last() {
+        Some(san) => {
+            let dots = if ply % 2 == 1 { "." } else { "..." };
+            format!("Game #{}, move {}{dots} {san} ({ply}/{})", review.game_id, (ply + 1) / 2, ucis.len())
+        }
+        None => format!("Game #{}, the starting position (0/{})", review.game_id, ucis.len()),
+    };
+    for note in notes::of_game(&state.db, review.game_id).await?.iter().filter(|note| note.ply == ply) {
+        text = format!("{text}\n{}", notes::describe(&state.db, note).await?);
+    }
+    let side = if review.white { Color::White } else { Color::Black };
+    let theme = settings::theme(&state.db, review.user_id).await?;
+    text = format!("{text}\n{}", analysis::show(&position, side, theme));
+    if state.engine.is_some() {
+        text = format!("{text}\n{}", analysis::evaluate(state, &position).await?);
+    }
+    if ply as usize == ucis.len() {
+        text = format!("{text}\nThat was the last move. stop closes the review.");
+    } else {
+        text = format!("{text}\n{CONTROLS}");
+    }
+    reply(state, review.user_id, text).await
+}
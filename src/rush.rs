@@ -0,0 +1,338 @@
+//! Puzzle rush: solve as many puzzles as possible before time runs out. Each
+//! solved puzzle makes the next one harder, and three mistakes end the rush.
+
+use crate::bot::Bot;
+use crate::puzzles::{self, Puzzle};
+use crate::rating::Category;
+use crate::diagram::{self, Theme};
+use crate::{clock, ongoing_game, packed_chat, parse_typed_move, settings, training, State};
+use anyhow::Result;
+use log::debug;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{Bitboard, Chess, Position};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(3 * 60);
+
+const MAX_STRIKES: i64 = 3;
+
+/// Rating of the first puzzle, and how much harder each solved one makes the next.
+const START_RATING: i64 = 600;
+const RATING_STEP: i64 = 100;
+
+/// Puzzles this close to the target rating are picked from at random.
+const RATING_WINDOW: i64 = 50;
+
+const COLUMNS: &str = "id, user_id, ends_at, score, strikes, puzzle_id, step";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Rush {
+    id: i64,
+    user_id: i64,
+    ends_at: i64,
+    score: i64,
+    strikes: i64,
+    puzzle_id: Option<String>,
+    step: i64,
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Rush>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from rushes where user_id = $1 and ended = 0"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+pub async fn on_rush(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    match args.trim() {
+        "" => start(state, user_id).await,
+        "stop" => match running(&state.db, user_id).await? {
+            Some(rush) => end(&state.db, &state.client, &rush, "Rush stopped.").await,
+            None => {
+                state
+                    .client
+                    .send_message(packed_chat(user_id), "You are not in a puzzle rush.")
+                    .await?;
+                Ok(())
+            }
+        },
+        "top" => on_leaderboard(state, user_id).await,
+        _ => {
+            state
+                .client
+                .send_message(packed_chat(user_id), "Usage: /rush [stop|top]")
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn start(state: &mut State, user_id: i64) -> Result<()> {
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        state
+            .client
+            .send_message(packed_chat(user_id), "Finish your game first.")
+            .await?;
+        return Ok(());
+    }
+    if let Some(what) = training(&state.db, user_id).await? {
+        state
+            .client
+            .send_message(packed_chat(user_id), format!("Finish your {what} first."))
+            .await?;
+        return Ok(());
+    }
+    let now = clock::now_ms();
+    let ends_at = now + state.config.get().rush_duration.as_millis() as i64;
+    sqlx::query("insert into rushes (user_id, started_at, ends_at) values ($1, $2, $3)")
+        .bind(user_id)
+        .bind(now)
+        .bind(ends_at)
+        .execute(&state.db)
+        .await?;
+    state
+        .client
+        .send_message(
+            packed_chat(user_id),
+            format!(
+                "Puzzle rush! Solve as many puzzles as you can in {}. {MAX_STRIKES} mistakes and you're out.",
+                clock::format_clock(ends_at - now)
+            ),
+        )
+        .await?;
+    let rush = running(&state.db, user_id).await?.expect("just started");
+    next_puzzle(&state.db, &state.client, &rush).await
+}
+
+/// Handles a move sent during a rush.
+pub async fn on_move(state: &mut State, mut rush: Rush, notation: &str) -> Result<()> {
+    let chat = packed_chat(rush.user_id);
+    if clock::now_ms() >= rush.ends_at {
+        return end(&state.db, &state.client, &rush, "Time's up!").await;
+    }
+    let Some(puzzle) = current_puzzle(&state.db, &rush).await? else {
+        return next_puzzle(&state.db, &state.client, &rush).await;
+    };
+    let step = rush.step as usize;
+    let (position, _) = puzzle.position_at(step)?;
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+
+    if !puzzle.accepts(&position, step, &m) {
+        rush.strikes += 1;
+        record_attempt(&state.db, &rush, &puzzle.id, false).await?;
+        let (rating, diff) = puzzles::rate_attempt(&state.db, rush.user_id, &puzzle, false).await?;
+        sqlx::query("update rushes set strikes = $2 where id = $1")
+            .bind(rush.id)
+            .bind(rush.strikes)
+            .execute(&state.db)
+            .await?;
+        let solution = puzzle.line()[step]
+            .parse::<Uci>()
+            .ok()
+            .and_then(|uci| uci.to_move(&position).ok())
+            .map(|m| San::from_move(&position, &m).to_string())
+            .unwrap_or_default();
+        state
+            .client
+            .send_message(
+                chat,
+                format!(
+                    "✗ Wrong, the answer was {solution}. ({}/{MAX_STRIKES})\nPuzzle rating {rating} ({diff:+})",
+                    rush.strikes
+                ),
+            )
+            .await?;
+        if rush.strikes >= MAX_STRIKES {
+            return end(&state.db, &state.client, &rush, "Three strikes!").await;
+        }
+        return next_puzzle(&state.db, &state.client, &rush).await;
+    }
+
+    let mut after = position.clone();
+    after.play_unchecked(&m);
+    if after.is_checkmate() || step + 1 >= puzzle.line().len() {
+        rush.score += 1;
+        record_attempt(&state.db, &rush, &puzzle.id, true).await?;
+        let (rating, diff) = puzzles::rate_attempt(&state.db, rush.user_id, &puzzle, true).await?;
+        sqlx::query("update rushes set score = $2 where id = $1")
+            .bind(rush.id)
+            .bind(rush.score)
+            .execute(&state.db)
+            .await?;
+        state
+            .client
+            .send_message(
+                chat,
+                format!("✓ Solved! Score: {}\nPuzzle rating {rating} ({diff:+})", rush.score),
+            )
+            .await?;
+        return next_puzzle(&state.db, &state.client, &rush).await;
+    }
+
+    // the opponent answers with the next move of the line
+    rush.step += 2;
+    sqlx::query("update rushes set step = $2 where id = $1")
+        .bind(rush.id)
+        .bind(rush.step)
+        .execute(&state.db)
+        .await?;
+    let (position, reply) = puzzle.position_at(rush.step as usize)?;
+    let text = format!(
+        "✓ Correct. Your opponent answered {}.\n{}",
+        reply.unwrap_or_default(),
+        board_text(&position, rush.ends_at, settings::theme(&state.db, rush.user_id).await?)
+    );
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+async fn current_puzzle(db: &Pool<Sqlite>, rush: &Rush) -> Result<Option<Puzzle>> {
+    Ok(
+        sqlx::query_as(&format!("select {} from puzzles where id = $1", puzzles::COLUMNS))
+            .bind(&rush.puzzle_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+async fn record_attempt(db: &Pool<Sqlite>, rush: &Rush, puzzle_id: &str, solved: bool) -> Result<()> {
+    sqlx::query("insert or ignore into rush_attempts (rush_id, puzzle_id, solved) values ($1, $2, $3)")
+        .bind(rush.id)
+        .bind(puzzle_id)
+        .bind(solved)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+fn board_text(position: &Chess, ends_at: i64, theme: Theme) -> String {
+    format!(
+        "{}\n{} to move · {} left",
+        diagram::render(position.board(), position.turn(), Bitboard::FULL, theme),
+        if position.turn().is_white() { "White" } else { "Black" },
+        clock::format_clock(ends_at - clock::now_ms())
+    )
+}
+
+/// Sends a puzzle close to the rush's target rating that hasn't come up in
+/// it yet, or ends the rush if there are none left.
+async fn next_puzzle(db: &Pool<Sqlite>, client: &Bot, rush: &Rush) -> Result<()> {
+    let target = START_RATING + rush.score * RATING_STEP;
+    let unseen = "owner_id is null and id not in (select puzzle_id from rush_attempts where rush_id = $1)";
+    let mut puzzle: Option<Puzzle> = sqlx::query_as(&format!(
+        "select {} from puzzles where rating between $2 - $3 and $2 + $3 and {unseen} order by random() limit 1",
+        puzzles::COLUMNS
+    ))
+    .bind(rush.id)
+    .bind(target)
+    .bind(RATING_WINDOW)
+    .fetch_optional(db)
+    .await?;
+    if puzzle.is_none() {
+        puzzle = sqlx::query_as(&format!(
+            "select {} from puzzles where {unseen} order by abs(rating - $2) limit 1",
+            puzzles::COLUMNS
+        ))
+        .bind(rush.id)
+        .bind(target)
+        .fetch_optional(db)
+        .await?;
+    }
+    let Some(puzzle) = puzzle else {
+        return end(db, client, rush, "You've seen every puzzle there is!").await;
+    };
+    debug!("rush {} gets puzzle {} rated {}", rush.id, puzzle.id, puzzle.rating);
+
+    let (position, setup) = puzzle.position_at(1)?;
+    sqlx::query("update rushes set puzzle_id = $2, step = 1 where id = $1")
+        .bind(rush.id)
+        .bind(&puzzle.id)
+        .execute(db)
+        .await?;
+    let text = format!(
+        "Puzzle {} · rated {}\nYour opponent played {}.\n{}",
+        rush.score + rush.strikes + 1,
+        puzzle.rating,
+        setup.unwrap_or_default(),
+        board_text(&position, rush.ends_at, settings::theme(db, rush.user_id).await?)
+    );
+    client.send_message(packed_chat(rush.user_id), text).await?;
+    Ok(())
+}
+
+/// Ends a rush unless it has already ended, telling the player their score.
+async fn end(db: &Pool<Sqlite>, client: &Bot, rush: &Rush, reason: &str) -> Result<()> {
+    let ended = sqlx::query("update rushes set ended = 1 where id = $1 and ended = 0")
+        .bind(rush.id)
+        .execute(db)
+        .await?
+        .rows_affected()
+        > 0;
+    if !ended {
+        return Ok(());
+    }
+    let best: Option<i64> = sqlx::query_scalar("select max(score) from rushes where user_id = $1 and ended and id != $2")
+        .bind(rush.user_id)
+        .bind(rush.id)
+        .fetch_one(db)
+        .await?;
+    let plural = if rush.score == 1 { "" } else { "s" };
+    let mut text = format!("{reason} You solved {} puzzle{plural}.", rush.score);
+    match best {
+        Some(best) if best >= rush.score => text = format!("{text} Your best is {best}."),
+        Some(_) => text = format!("{text} That's a new personal best!"),
+        None => {}
+    }
+    client.send_message(packed_chat(rush.user_id), text).await?;
+    Ok(())
+}
+
+/// Ends rushes whose time has run out.
+pub async fn sweep(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let expired: Vec<Rush> = sqlx::query_as(&format!("select {COLUMNS} from rushes where ended = 0 and ends_at <= $1"))
+        .bind(clock::now_ms())
+        .fetch_all(db)
+        .await?;
+    for rush in expired {
+        debug!("rush {} timed out", rush.id);
+        end(db, client, &rush, "Time's up!").await?;
+    }
+    Ok(())
+}
+
+async fn on_leaderboard(state: &mut State, user_id: i64) -> Result<()> {
+    let top: Vec<(i64, Option<String>, i64, Option<f64>)> = sqlx::query_as(
+        "select users.id, users.name, max(score) as best, ratings.rating from rushes
+         join users on users.id = rushes.user_id
+         left join ratings on ratings.user_id = users.id and ratings.category = $1
+         where rushes.ended group by users.id order by best desc limit 10",
+    )
+    .bind(Category::Puzzle.as_str())
+    .fetch_all(&state.db)
+    .await?;
+
+    let text = if top.is_empty() {
+        "Nobody has finished a puzzle rush yet.".to_string()
+    } else {
+        top.iter()
+            .enumerate()
+            .map(|(i, (id, name, best, rating))| {
+                let name = name.clone().unwrap_or_else(|| id.to_string());
+                match rating {
+                    Some(rating) => format!("{}. {name} {best}, puzzle rating {}", i + 1, rating.round() as i64),
+                    None => format!("{}. {name} {best}", i + 1),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use futures_util::future::{self, BoxFuture, Either, FutureExt};
+use log::{debug, error, info};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+type JobFn<C> = Arc<dyn Fn(C) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+struct Job<C> {
+    name: &'static str,
+    interval: Duration,
+    jitter: Duration,
+    run: JobFn<C>,
+}
+
+/// Registry of recurring background jobs sharing a context `C`.
+pub struct Scheduler<C> {
+    ctx: C,
+    jobs: Vec<Job<C>>,
+}
+
+/// Jobs started by [`Scheduler::start`].
+pub struct Running {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<C: Clone + Send + 'static> Scheduler<C> {
+    pub fn new(ctx: C) -> Self {
+        Scheduler { ctx, jobs: Vec::new() }
+    }
+
+    /// Registers a job that runs right away and then every `interval`, each
+    /// run delayed by a random amount up to `jitter`.
+    pub fn every<F, Fut>(&mut self, name: &'static str, interval: Duration, jitter: Duration, f: F) -> &mut Self
+    where
+        F: Fn(C) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name,
+            interval,
+            jitter,
+            run: Arc::new(move |ctx| f(ctx).boxed()),
+        });
+        self
+    }
+
+    pub fn start(self) -> Running {
+        let (shutdown, stopped) = watch::channel(false);
+        let tasks = self
+            .jobs
+            .into_iter()
+            .map(|job| tokio::spawn(run_job(job, self.ctx.clone(), stopped.clone())))
+            .collect();
+        Running { shutdown, tasks }
+    }
+}
+
+impl Running {
+    /// Stops scheduling new runs and waits for the ones in progress to finish.
+    pub async fn shutdown(self) {
+        info!("stopping background jobs");
+        let _ = self.shutdown.send(true);
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+async fn run_job<C: Clone + Send + 'static>(job: Job<C>, ctx: C, mut stopped: watch::Receiver<bool>) {
+    let mut delay = Duration::ZERO;
+    loop {
+        let jitter = job.jitter.mul_f64(rand::random());
+        let sleep = pin!(time::sleep(delay + jitter));
+        let stop = pin!(stopped.wait_for(|&stopped| stopped));
+        if let Either::Right(_) = future::select(sleep, stop).await {
+            break;
+        }
+
+        debug!("running job {}", job.name);
+        // Run in a separate task so that a panicking job is only logged.
+        match tokio::spawn((job.run)(ctx.clone())).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("error in job {}: {e}", job.name),
+            Err(e) => error!("job {} panicked: {e}", job.name),
+        }
+        delay = job.interval;
+    }
+    debug!("stopped job {}", job.name);
+}
@@ -0,0 +1,188 @@
+//! The seek channel: an admin connects a public Telegram channel or group,
+//! and every open game seek is posted there with a button that joins it.
+//! The post shows the players once the seek is taken and is deleted when
+//! it is cancelled or expires.
+
+use crate::bot::Bot;
+use crate::rating::Category;
+use crate::{clock, game_by_id, player_card, Game, State, Variant};
+use anyhow::Result;
+use grammers_session::{PackedChat, PackedType};
+use log::{debug, info};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+
+/// Payloads of join buttons, followed by the game id.
+pub const PAYLOAD_PREFIX: &str = "join_";
+
+const USAGE: &str = "/admin seeks [channel <@channel, @group or id> | off]";
+
+async fn channel(db: &Pool<Sqlite>) -> Result<Option<PackedChat>> {
+    let chat: Option<String> = sqlx::query_scalar("select chat from seek_channel").fetch_optional(db).await?;
+    Ok(chat.and_then(|hex| PackedChat::from_hex(&hex).ok()))
+}
+
+/// Handles `/admin seeks`, returning the reply.
+pub async fn admin(state: &mut State, admin_id: i64, args: &str) -> Result<String> {
+    let (command, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    match command {
+        "" => Ok(match channel(&state.db).await? {
+            Some(chat) => format!("Open seeks are posted in chat {}.", chat.id),
+            None => format!("No chat is connected.\nUsage: {USAGE}"),
+        }),
+        "channel" => connect(state, admin_id, rest.trim()).await,
+        "off" => {
+            if channel(&state.db).await?.is_none() {
+                return Ok("No chat is connected.".to_string());
+            }
+            sqlx::query("delete from seek_channel").execute(&state.db).await?;
+            info!("{admin_id} disconnected the seek channel");
+            Ok("Open seeks are no longer posted.".to_string())
+        }
+        _ => Ok(format!("Usage: {USAGE}")),
+    }
+}
+
+/// Connects the chat, given as `@username` when running on Telegram or as a
+/// bare channel id. The bot has to be able to post and delete messages there.
+async fn connect(state: &mut State, admin_id: i64, who: &str) -> Result<String> {
+    let chat = match (who.strip_prefix('@'), who.parse::<i64>()) {
+        (_, Ok(id)) => PackedChat {
+            id,
+            ty: PackedType::Broadcast,
+            access_hash: None,
+        },
+        (Some(username), _) => {
+            let Some(client) = state.client.telegram() else {
+                return Ok("Chats can only be looked up by username on Telegram, send its id.".to_string());
+            };
+            match client.resolve_username(username).await? {
+                Some(chat) => {
+                    let packed = chat.pack();
+                    if matches!(packed.ty, PackedType::User | PackedType::Bot) {
+                        return Ok(format!("@{username} is not a channel or group."));
+                    }
+                    packed
+                }
+                None => return Ok(format!("No channel or group @{username}.")),
+            }
+        }
+        _ => return Ok(format!("Usage: {USAGE}")),
+    };
+    sqlx::query(
+        "insert into seek_channel (id, chat) values (1, $1) on conflict (id) do update set chat = excluded.chat",
+    )
+    .bind(chat.to_hex())
+    .execute(&state.db)
+    .await?;
+    info!("{admin_id} connected seek channel {}", chat.id);
+    Ok(format!("Chat {who} is connected, open seeks will be posted there."))
+}
+
+/// What the seek is for, e.g. `blitz game, 5:00 + 3s`.
+fn terms(game: &Game) -> String {
+    let mut kind = match game.variant() {
+        Variant::Standard => String::new(),
+        variant => format!("{} ", variant.name().to_lowercase()),
+    };
+    if game.armageddon {
+        kind.push_str("armageddon ");
+    }
+    let tc = game.time_control();
+    let speed = Category::of_speed(tc.map(|tc| tc.initial), tc.map_or(Duration::ZERO, |tc| tc.increment));
+    if game.casual {
+        kind.push_str("casual ");
+    }
+    if game.initial_fen.is_some() {
+        kind.push_str("from-position ");
+    }
+    let mut text = format!("{kind}{} game", speed.name().to_lowercase());
+    if let Some(days) = game.days_per_move {
+        text = format!("{text}, {days} days per move");
+    }
+    if let Some(tc) = tc {
+        text = format!("{text}, {}", clock::format_clock(tc.initial.as_millis() as i64));
+        if !tc.increment.is_zero() {
+            text = format!("{text} + {}s", tc.increment.as_secs());
+        }
+    }
+    text
+}
+
+/// Posts a new open seek in the channel, if one is connected.
+pub async fn posted(db: &Pool<Sqlite>, client: &Bot, bot_username: &str, game_id: i64) -> Result<()> {
+    let Some(chat) = channel(db).await? else {
+        return Ok(());
+    };
+    let Some(game) = game_by_id(db, game_id).await? else {
+        return Ok(());
+    };
+    let Some(creator) = game.w_id.or(game.b_id) else {
+        return Ok(());
+    };
+    let text = format!("Game #{game_id}: {} seeks a {}.", player_card(db, creator).await?, terms(&game));
+    let url = format!("https://t.me/{bot_username}?start={PAYLOAD_PREFIX}{game_id}");
+    let message = match client.send_with_link(chat, text, "Join", &url).await {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("cannot post seek {game_id}: {e}");
+            return Ok(());
+        }
+    };
+    sqlx::query("update games set seek_message_id = $2 where id = $1")
+        .bind(game_id)
+        .bind(message.id())
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// The seek's post, forgetting it.
+async fn take_post(db: &Pool<Sqlite>, game_id: i64) -> Result<Option<(PackedChat, i32)>> {
+    let message_id: Option<i32> = sqlx::query_scalar(
+        "update games set seek_message_id = null where id = $1 and seek_message_id is not null
+         returning seek_message_id",
+    )
+    .bind(game_id)
+    .fetch_optional(db)
+    .await?;
+    let Some(message_id) = message_id else {
+        return Ok(None);
+    };
+    Ok(channel(db).await?.map(|chat| (chat, message_id)))
+}
+
+/// Replaces the post of a seek that was taken with the pairing, removing
+/// its button.
+pub async fn taken(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some((chat, message_id)) = take_post(db, game_id).await? else {
+        return Ok(());
+    };
+    let Some(game) = game_by_id(db, game_id).await? else {
+        return Ok(());
+    };
+    let (Some(w_id), Some(b_id)) = (game.w_id, game.b_id) else {
+        return Ok(());
+    };
+    let text = format!(
+        "Game #{game_id}: {} – {}, {}.",
+        player_card(db, w_id).await?,
+        player_card(db, b_id).await?,
+        terms(&game)
+    );
+    if let Err(e) = client.edit_message(chat, message_id, text).await {
+        debug!("cannot update seek post {game_id}: {e}");
+    }
+    Ok(())
+}
+
+/// Deletes the post of a seek that was cancelled or expired.
+pub async fn cancelled(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let Some((chat, message_id)) = take_post(db, game_id).await? else {
+        return Ok(());
+    };
+    if let Err(e) = client.delete_message(chat, message_id).await {
+        debug!("cannot delete seek post {game_id}: {e}");
+    }
+    Ok(())
+}
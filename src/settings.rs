@@ -0,0 +1,275 @@
+//! Per-user settings: how boards and moves are shown, which notifications
+//! to get, and how game moves are entered. Users without a row get the
+//! defaults.
+
+use crate::diagram::Theme;
+use crate::templates::{Templates, DEFAULT_LANGUAGE};
+use crate::{packed_chat, State};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Offset, Utc};
+use shakmaty::san::San;
+use shakmaty::{CastlingMode, Chess, Move};
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str = "Usage: /settings [language en | theme figurines|letters | notation long|san|uci | \
+    timezone UTC|+3|-05:30 | notifications on|off | confirm on|off | autoqueen on|off | emotes on|off | \
+    streak on|off]";
+
+/// Offsets in use around the world run from UTC-12:00 to UTC+14:00.
+const MAX_UTC_OFFSET_MINUTES: i64 = 14 * 60;
+
+/// How moves are written in game messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// Like e2-e4 and Ng1xf3.
+    #[default]
+    Long,
+    San,
+    Uci,
+}
+
+impl Notation {
+    fn parse(s: &str) -> Option<Notation> {
+        match s {
+            "long" => Some(Notation::Long),
+            "san" => Some(Notation::San),
+            "uci" => Some(Notation::Uci),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Notation::Long => "long",
+            Notation::San => "san",
+            Notation::Uci => "uci",
+        }
+    }
+
+    /// The move played in `position`, written this way.
+    pub fn write(self, position: &Chess, m: &Move) -> String {
+        match self {
+            Notation::Long => m.to_string(),
+            Notation::San => San::from_move(position, m).to_string(),
+            Notation::Uci => m.to_uci(CastlingMode::Standard).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Settings {
+    language: String,
+    theme: String,
+    notation: String,
+    utc_offset: i64,
+    pub notifications: bool,
+    pub confirm_moves: bool,
+    pub auto_queen: bool,
+    /// Whether opponents' emotes are shown.
+    pub emotes: bool,
+    /// Whether to be reminded of a play streak about to end.
+    pub streak_reminders: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            language: "en".to_string(),
+            theme: Theme::default().as_str().to_string(),
+            notation: Notation::default().as_str().to_string(),
+            utc_offset: 0,
+            notifications: true,
+            confirm_moves: false,
+            auto_queen: false,
+            emotes: true,
+            streak_reminders: true,
+        }
+    }
+}
+
+impl Settings {
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn theme(&self) -> Theme {
+        Theme::parse(&self.theme).unwrap_or_default()
+    }
+
+    pub fn notation(&self) -> Notation {
+        Notation::parse(&self.notation).unwrap_or_default()
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.utc_offset as i32 * 60).unwrap_or_else(|| Utc.fix())
+    }
+
+    /// A moment, in milliseconds since the epoch, in the user's time zone,
+    /// such as `Wed 15 Oct 14:30 UTC+02:00`.
+    pub fn local_time(&self, ms: i64) -> String {
+        match DateTime::from_timestamp_millis(ms) {
+            Some(t) => format!(
+                "{} {}",
+                t.with_timezone(&self.offset()).format("%a %-d %b %H:%M"),
+                format_offset(self.utc_offset)
+            ),
+            None => "?".to_string(),
+        }
+    }
+}
+
+/// Parses `UTC`, `+3`, `-05:30` or `UTC+5:45` into minutes east of UTC.
+fn parse_offset(s: &str) -> Option<i64> {
+    let s = s.trim_start_matches("UTC").trim_start_matches("GMT");
+    if s.is_empty() {
+        return Some(0);
+    }
+    let (sign, s) = match s.split_at(1) {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = s.split_once(':').unwrap_or((s, "0"));
+    let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+    let offset = sign * (hours * 60 + minutes);
+    (minutes < 60 && offset.abs() <= MAX_UTC_OFFSET_MINUTES).then_some(offset)
+}
+
+fn format_offset(minutes: i64) -> String {
+    if minutes == 0 {
+        return "UTC".to_string();
+    }
+    let sign = if minutes < 0 { '-' } else { '+' };
+    format!("UTC{sign}{:02}:{:02}", minutes.abs() / 60, minutes.abs() % 60)
+}
+
+pub async fn get(db: &Pool<Sqlite>, user_id: i64) -> Result<Settings> {
+    let settings: Option<Settings> = sqlx::query_as(
+        "select language, theme, notation, utc_offset, notifications, confirm_moves, auto_queen, emotes,
+            streak_reminders
+         from user_settings where user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// The language to answer the user in: the one they chose, else their
+/// Telegram app's if there is one like it, else English.
+pub async fn language(
+    db: &Pool<Sqlite>,
+    templates: &Templates,
+    user_id: i64,
+    app_language: Option<&str>,
+) -> Result<String> {
+    let chosen: Option<String> = sqlx::query_scalar("select language from user_settings where user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+    // apps send tags like pt-br
+    let app = app_language
+        .and_then(|tag| tag.split('-').next())
+        .filter(|language| templates.has(language));
+    Ok(chosen.or(app.map(str::to_string)).unwrap_or_else(|| DEFAULT_LANGUAGE.to_string()))
+}
+
+/// The user's board theme, which is all most callers need.
+pub async fn theme(db: &Pool<Sqlite>, user_id: i64) -> Result<Theme> {
+    Ok(get(db, user_id).await?.theme())
+}
+
+/// A moment, in milliseconds since the epoch, in the user's time zone.
+pub async fn local_time(db: &Pool<Sqlite>, user_id: i64, ms: i64) -> Result<String> {
+    Ok(get(db, user_id).await?.local_time(ms))
+}
+
+/// Whether the user wants to hear about other players.
+pub async fn notifications(db: &Pool<Sqlite>, user_id: i64) -> Result<bool> {
+    Ok(get(db, user_id).await?.notifications)
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn describe(settings: &Settings) -> String {
+    format!(
+        "Your settings:\nlanguage {}\ntheme {}\nnotation {}\ntimezone {}\nnotifications {}\nconfirm {}\nautoqueen {}\n\
+         emotes {}\nstreak {}",
+        settings.language,
+        settings.theme().as_str(),
+        settings.notation().as_str(),
+        format_offset(settings.utc_offset),
+        on_off(settings.notifications),
+        on_off(settings.confirm_moves),
+        on_off(settings.auto_queen),
+        on_off(settings.emotes),
+        on_off(settings.streak_reminders),
+    )
+}
+
+pub async fn on_settings(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let mut words = args.split_whitespace();
+    let (name, value) = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => {
+            let text = format!("{}\n{USAGE}", describe(&get(&state.db, user_id).await?));
+            state.client.send_message(chat, text).await?;
+            return Ok(());
+        }
+        (Some(name), Some(value), None) => (name, value),
+        _ => {
+            state.client.send_message(chat, USAGE).await?;
+            return Ok(());
+        }
+    };
+    let flag = match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    };
+    // the column is one of these literals, never user input
+    let (column, value): (&str, String) = match (name, flag) {
+        ("language", _) if state.config.get().templates.has(value) => ("language", value.to_string()),
+        ("language", _) => {
+            let text = format!("Languages: {}.", state.config.get().templates.languages().join(", "));
+            state.client.send_message(chat, text).await?;
+            return Ok(());
+        }
+        ("theme", _) if Theme::parse(value).is_some() => ("theme", value.to_string()),
+        ("notation", _) if Notation::parse(value).is_some() => ("notation", value.to_string()),
+        ("timezone", _) => match parse_offset(&value.to_uppercase()) {
+            Some(offset) => ("utc_offset", offset.to_string()),
+            None => {
+                let text = format!("Send the time zone as an offset from UTC, like +3 or -05:30.\n{USAGE}");
+                state.client.send_message(chat, text).await?;
+                return Ok(());
+            }
+        },
+        ("notifications", Some(on)) => ("notifications", i64::from(on).to_string()),
+        ("confirm", Some(on)) => ("confirm_moves", i64::from(on).to_string()),
+        ("autoqueen", Some(on)) => ("auto_queen", i64::from(on).to_string()),
+        ("emotes", Some(on)) => ("emotes", i64::from(on).to_string()),
+        ("streak", Some(on)) => ("streak_reminders", i64::from(on).to_string()),
+        _ => {
+            state.client.send_message(chat, USAGE).await?;
+            return Ok(());
+        }
+    };
+    sqlx::query(&format!(
+        "insert into user_settings (user_id, {column}) values ($1, $2)
+         on conflict (user_id) do update set {column} = excluded.{column}"
+    ))
+    .bind(user_id)
+    .bind(value)
+    .execute(&state.db)
+    .await?;
+    let text = describe(&get(&state.db, user_id).await?);
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Updates are handled in a few shards, each a task with its own `State`
+//! working through its queue in order. An update goes to the shard of the
+//! game its sender is in, so both players' moves in a game are handled one
+//! after the other while other games go ahead in parallel; users without a
+//! game are sharded by their id. A user's messages may thus change shard when
+//! their game starts or ends, after which the new shard has them in order.
+
+use crate::pipeline::{self, Incoming};
+use crate::State;
+use log::error;
+use sqlx::{Pool, Sqlite};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_SHARDS: usize = 4;
+
+/// Updates a shard may have waiting before the dispatcher waits for it.
+const QUEUE: usize = 64;
+
+pub struct Shards {
+    db: Pool<Sqlite>,
+    queues: Vec<mpsc::Sender<Incoming>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Shards {
+    /// Starts `n` shards on the current `LocalSet`, each with a state of its
+    /// own made from `state`.
+    pub fn start(state: &State, n: usize) -> Self {
+        let (queues, tasks) = (0..n.max(1))
+            .map(|_| {
+                let (queue, mut updates) = mpsc::channel(QUEUE);
+                let mut state = state.shard();
+                let task = tokio::task::spawn_local(async move {
+                    while let Some(incoming) = updates.recv().await {
+                        if let Err(e) = pipeline::run(&mut state, incoming).await {
+                            error!("error while handling update {e}");
+                        }
+                    }
+                });
+                (queue, task)
+            })
+            .unzip();
+        Shards {
+            db: state.db.clone(),
+            queues,
+            tasks,
+        }
+    }
+
+    /// Queues the update on its shard.
+    pub async fn dispatch(&self, incoming: Incoming) {
+        let key = match game_of(&self.db, incoming.user_id()).await {
+            Ok(Some(game_id)) => game_id,
+            Ok(None) => incoming.user_id(),
+            Err(e) => {
+                error!("cannot find the game of {}: {e}", incoming.user_id());
+                incoming.user_id()
+            }
+        };
+        let shard = key.rem_euclid(self.queues.len() as i64) as usize;
+        if self.queues[shard].send(incoming).await.is_err() {
+            error!("shard {shard} is gone");
+        }
+    }
+
+    /// Lets the shards finish what they have queued.
+    pub async fn shutdown(self) {
+        drop(self.queues);
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                error!("shard failed: {e}");
+            }
+        }
+    }
+}
+
+/// The game the user is playing or waiting in, as `ongoing_game` picks it.
+async fn game_of(db: &Pool<Sqlite>, user_id: i64) -> sqlx::Result<Option<i64>> {
+    sqlx::query_scalar(
+        "select id from games where ended = 0 and deleted_at is null and $1 in (w_id, b_id)
+         order by coalesce(id = (select active_game_id from users where id = $1), 0) desc, id desc limit 1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
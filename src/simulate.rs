@@ -0,0 +1,125 @@
+//! `tgpawn simulate [users]`: synthetic players pair up and play random legal
+//! games through `handle_message` against a scratch database, with replies
+//! going to an in-memory outbox instead of Telegram.
+
+use crate::bot::{Bot, Outbox};
+use crate::{connect_db, game_ucis, handle_message, migrate, position_from_fen, Game, State, GAME_COLUMNS};
+use anyhow::{bail, Result};
+use log::{debug, info};
+use rand::seq::SliceRandom;
+use shakmaty::fen::Fen;
+use shakmaty::uci::Uci;
+use shakmaty::{CastlingMode, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+use std::time::Instant;
+
+pub const DEFAULT_USERS: i64 = 100;
+
+/// Games still going after this many plies are resigned.
+const MAX_PLIES: i64 = 400;
+
+pub async fn run(users: i64) -> Result<()> {
+    let path = std::env::temp_dir().join(format!("tgpawn-simulate-{}.db", std::process::id()));
+    let db = connect_db(&format!("sqlite://{}", path.display())).await?;
+    migrate(&db).await?;
+    let outbox = Outbox::default();
+    let mut state = State::offline(db.clone(), Bot::Mock(outbox.clone()));
+
+    let started = Instant::now();
+    let (mut updates, mut replies) = (0, 0);
+    for user_id in 1..=users {
+        handle_message(&mut state, user_id, &format!("Player {user_id}"), None, "/start").await?;
+        updates += 1;
+    }
+    loop {
+        let games: Vec<Game> = sqlx::query_as(&format!(
+            "select {GAME_COLUMNS} from games where ended = 0 and w_id is not null and b_id is not null"
+        ))
+        .fetch_all(&db)
+        .await?;
+        if games.is_empty() {
+            break;
+        }
+        for game in games {
+            let mover = if game.turn().is_white() { game.w_id } else { game.b_id }.expect("paired");
+            let position = position_from_fen(&game.fen);
+            let text = match position.legal_moves().choose(&mut rand::thread_rng()) {
+                Some(m) if game.plies < MAX_PLIES => m.to_uci(CastlingMode::Standard).to_string(),
+                _ => "/resign".to_string(),
+            };
+            handle_message(&mut state, mover, &format!("Player {mover}"), None, &text).await?;
+            updates += 1;
+        }
+        for sent in outbox.drain() {
+            debug!("to {}: {}", sent.user_id, sent.text);
+            replies += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+
+    let finished: i64 = sqlx::query_scalar("select count(*) from games where ended and started_at is not null")
+        .fetch_one(&db)
+        .await?;
+    println!(
+        "{users} users played {finished} games: {updates} updates and {replies} replies in {:.1}s, {:.0} updates/s",
+        elapsed.as_secs_f64(),
+        updates as f64 / elapsed.as_secs_f64(),
+    );
+
+    let violations = check_invariants(&db).await?;
+    db.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+    for violation in &violations {
+        println!("invariant violated: {violation}");
+    }
+    if !violations.is_empty() {
+        bail!("{} invariant violations", violations.len());
+    }
+    info!("all invariants hold");
+    Ok(())
+}
+
+/// Checks the database for states the bot should never produce.
+pub async fn check_invariants(db: &Pool<Sqlite>) -> Result<Vec<String>> {
+    let mut violations = Vec::new();
+
+    let busy: Vec<i64> = sqlx::query_scalar(
+        "select id from users where (select count(*) from games where (w_id = users.id or b_id = users.id) and ended = 0) > 1",
+    )
+    .fetch_all(db)
+    .await?;
+    violations.extend(busy.iter().map(|id| format!("user {id} has several ongoing games")));
+
+    let miscounted: Vec<i64> =
+        sqlx::query_scalar("select id from games where plies != (select count(*) from moves where game_id = games.id)")
+            .fetch_all(db)
+            .await?;
+    violations.extend(miscounted.iter().map(|id| format!("game {id} has a wrong ply count")));
+
+    let unterminated: Vec<i64> = sqlx::query_scalar("select id from games where ended and termination is null")
+        .fetch_all(db)
+        .await?;
+    violations.extend(unterminated.iter().map(|id| format!("game {id} ended without a termination")));
+
+    let games: Vec<Game> = sqlx::query_as(&format!("select {GAME_COLUMNS} from games"))
+        .fetch_all(db)
+        .await?;
+    for game in games {
+        let mut position = position_from_fen(game.start_fen());
+        for uci in game_ucis(db, game.id).await? {
+            match uci.parse::<Uci>().ok().and_then(|uci| uci.to_move(&position).ok()) {
+                Some(m) => position.play_unchecked(&m),
+                None => {
+                    violations.push(format!("game {} has illegal move {uci}", game.id));
+                    break;
+                }
+            }
+        }
+        if Fen::from_position(position, EnPassantMode::Always).to_string() != game.fen {
+            violations.push(format!("game {} doesn't replay to its stored position", game.id));
+        }
+    }
+    Ok(violations)
+}
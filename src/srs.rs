@@ -0,0 +1,36 @@
+//! SM-2 spaced repetition scheduling.
+
+const MIN_EASE: f64 = 1.3;
+
+/// Answers below this quality count as forgotten.
+const PASSING_QUALITY: u8 = 3;
+
+#[derive(Debug, Clone, Copy, sqlx::FromRow)]
+pub struct Schedule {
+    pub ease: f64,
+    pub interval_days: f64,
+    pub repetitions: i64,
+}
+
+impl Schedule {
+    /// The schedule after a review graded from 0 (blackout) to 5 (perfect).
+    pub fn review(self, quality: u8) -> Schedule {
+        let quality = quality.min(5);
+        let (interval_days, repetitions) = if quality < PASSING_QUALITY {
+            (1.0, 0)
+        } else {
+            let interval_days = match self.repetitions {
+                0 => 1.0,
+                1 => 6.0,
+                _ => self.interval_days * self.ease,
+            };
+            (interval_days, self.repetitions + 1)
+        };
+        let miss = f64::from(5 - quality);
+        Schedule {
+            ease: (self.ease + 0.1 - miss * (0.08 + miss * 0.02)).max(MIN_EASE),
+            interval_days,
+            repetitions,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Play streaks: the days in a row, in the user's time zone, on which they
+//! made a move or worked on a puzzle. The current and best streak are shown
+//! on the profile, and in the evening players whose streak would end at
+//! midnight get a reminder, unless they turned it off in `/settings`.
+
+use crate::bot::Bot;
+use crate::{packed_chat, settings};
+use anyhow::Result;
+use chrono::{Days, NaiveDate, Timelike, Utc};
+use log::debug;
+use sqlx::{Pool, Sqlite};
+
+/// Streaks shorter than this end without a reminder.
+const MIN_REMINDED_STREAK: i64 = 3;
+
+/// The local hour from which players are reminded.
+const REMINDER_HOUR: u32 = 20;
+
+/// At most one reminder in this many seconds.
+const REMINDER_EVERY_SECS: i64 = 20 * 60 * 60;
+
+pub struct Streak {
+    pub current: i64,
+    pub best: i64,
+    /// Whether today already counts.
+    pub today: bool,
+}
+
+/// The days the user was active on, in their time zone, latest first.
+async fn active_days(db: &Pool<Sqlite>, user_id: i64, offset_secs: i64) -> Result<Vec<NaiveDate>> {
+    let days: Vec<String> = sqlx::query_scalar(
+        "select distinct date(t + $2, 'unixepoch') as day from (
+            select m.played_at / 1000 as t from moves m join games g on g.id = m.game_id
+            where (g.w_id = $1 and m.ply % 2 = 0) or (g.b_id = $1 and m.ply % 2 = 1)
+            union all select created_at from puzzle_attempts where user_id = $1 and solved is not null
+            union all select started_at / 1000 from rushes where user_id = $1
+         ) order by day desc",
+    )
+    .bind(user_id)
+    .bind(offset_secs)
+    .fetch_all(db)
+    .await?;
+    Ok(days.iter().filter_map(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()).collect())
+}
+
+pub async fn of_user(db: &Pool<Sqlite>, user_id: i64) -> Result<Streak> {
+    let offset = settings::get(db, user_id).await?.offset();
+    let days = active_days(db, user_id, offset.local_minus_utc() as i64).await?;
+    let today = Utc::now().with_timezone(&offset).date_naive();
+
+    // runs of consecutive days, latest first
+    let mut runs: Vec<i64> = Vec::new();
+    for (i, day) in days.iter().enumerate() {
+        match i.checked_sub(1).map(|i| days[i]) {
+            Some(later) if later.checked_sub_days(Days::new(1)) == Some(*day) => *runs.last_mut().expect("a run") += 1,
+            _ => runs.push(1),
+        }
+    }
+    let yesterday = today.checked_sub_days(Days::new(1));
+    let current = match days.first() {
+        Some(&last) if last == today || Some(last) == yesterday => runs[0],
+        _ => 0,
+    };
+    Ok(Streak {
+        current,
+        best: runs.into_iter().max().unwrap_or(0),
+        today: days.first() == Some(&today),
+    })
+}
+
+fn count_days(n: i64) -> String {
+    if n == 1 {
+        "1 day".to_string()
+    } else {
+        format!("{n} days")
+    }
+}
+
+/// The streak line of the profile.
+pub async fn describe(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<String>> {
+    let streak = of_user(db, user_id).await?;
+    if streak.best == 0 {
+        return Ok(None);
+    }
+    Ok(Some(format!("Play streak: {}, best {}", count_days(streak.current), count_days(streak.best))))
+}
+
+/// Reminds players whose streak ends at midnight if they don't play today.
+pub async fn remind(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    // only players active since the day before yesterday can have a streak to keep
+    let candidates: Vec<i64> = sqlx::query_scalar(
+        "select id from users u where unreachable_since is null and vacation_started_at is null
+         and (streak_reminded_at is null or streak_reminded_at <= unixepoch() - $1)
+         and (exists (select 1 from games g where (g.w_id = u.id or g.b_id = u.id)
+                and g.last_move_at > unixepoch() - 172800)
+            or exists (select 1 from puzzle_attempts p where p.user_id = u.id and p.created_at > unixepoch() - 172800)
+            or exists (select 1 from rushes r where r.user_id = u.id
+                and r.started_at > (unixepoch() - 172800) * 1000))",
+    )
+    .bind(REMINDER_EVERY_SECS)
+    .fetch_all(db)
+    .await?;
+    for user_id in candidates {
+        let settings = settings::get(db, user_id).await?;
+        if !settings.notifications || !settings.streak_reminders {
+            continue;
+        }
+        if Utc::now().with_timezone(&settings.offset()).hour() < REMINDER_HOUR {
+            continue;
+        }
+        let streak = of_user(db, user_id).await?;
+        if streak.today || streak.current < MIN_REMINDED_STREAK {
+            continue;
+        }
+        let text = format!(
+            "Your play streak of {} ends at midnight. A move or a /puzzle keeps it going. (/settings streak off)",
+            count_days(streak.current)
+        );
+        client.send_message(packed_chat(user_id), text).await?;
+        sqlx::query("update users set streak_reminded_at = unixepoch() where id = $1")
+            .bind(user_id)
+            .execute(db)
+            .await?;
+        debug!("reminded {user_id} of their {}-day streak", streak.current);
+    }
+    Ok(())
+}
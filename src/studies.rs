@@ -0,0 +1,373 @@
+//! Shared studies: an analysis board several users look at together. Moves
+//! and notes from any member go out to all of them, which makes for
+//! post-mortems of games played here. A study closes when its last member
+//! leaves or after a day without activity.
+
+use crate::analysis;
+use crate::bot::Bot;
+use crate::{
+    game_by_id, game_ucis, ongoing_game, packed_chat, parse_typed_move, settings, training, user_name, State,
+    STARTING_FEN,
+};
+use anyhow::Result;
+use log::{debug, info};
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
+use sqlx::{Pool, Sqlite};
+
+const USAGE: &str =
+    "Usage: /study new [fen] | game <id> | join <id> | back [n] | note <text> | eval | leave";
+
+/// Studies with no moves or notes for this long are closed.
+const IDLE_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Study {
+    id: i64,
+    game_id: Option<i64>,
+    start_fen: String,
+    moves: String,
+}
+
+impl Study {
+    fn position(&self) -> Chess {
+        analysis::replay(&self.start_fen, &self.moves)
+    }
+
+    fn plies(&self) -> i64 {
+        self.moves.split_whitespace().count() as i64
+    }
+}
+
+/// The open study the user is a member of.
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Study>> {
+    Ok(sqlx::query_as(
+        "select s.id, s.game_id, s.start_fen, s.moves from studies s
+         join study_members m on m.study_id = s.id where m.user_id = $1 and not s.ended",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?)
+}
+
+async fn find(db: &Pool<Sqlite>, id: i64) -> Result<Option<Study>> {
+    Ok(
+        sqlx::query_as("select id, game_id, start_fen, moves from studies where id = $1 and not ended")
+            .bind(id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+/// Members with the side each sees the board from.
+async fn members(db: &Pool<Sqlite>, study_id: i64) -> Result<Vec<(i64, bool)>> {
+    Ok(
+        sqlx::query_as("select user_id, white from study_members where study_id = $1 order by joined_at")
+            .bind(study_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+/// Sends the text to every member, followed by the board if given.
+async fn broadcast(db: &Pool<Sqlite>, client: &Bot, study: &Study, text: &str, board: bool) -> Result<()> {
+    let position = study.position();
+    for (user_id, white) in members(db, study.id).await? {
+        let text = if board {
+            let side = if white { Color::White } else { Color::Black };
+            format!("{text}\n{}", analysis::show(&position, side, settings::theme(db, user_id).await?))
+        } else {
+            text.to_string()
+        };
+        client.send_message(packed_chat(user_id), text).await?;
+    }
+    Ok(())
+}
+
+async fn touch(db: &Pool<Sqlite>, study: &Study) -> Result<()> {
+    sqlx::query("update studies set moves = $2, last_activity_at = unixepoch() where id = $1")
+        .bind(study.id)
+        .bind(&study.moves)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Notes written at the study's current move.
+async fn notes(db: &Pool<Sqlite>, study: &Study) -> Result<String> {
+    let notes: Vec<(i64, String)> =
+        sqlx::query_as("select user_id, text from study_notes where study_id = $1 and ply = $2 order by id")
+            .bind(study.id)
+            .bind(study.plies())
+            .fetch_all(db)
+            .await?;
+    let mut text = String::new();
+    for (user_id, note) in notes {
+        text = format!("{text}\n{}: {note}", user_name(db, user_id).await?);
+    }
+    Ok(text)
+}
+
+pub async fn on_study(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let (command, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let rest = rest.trim();
+    let study = running(&state.db, user_id).await?;
+    match (command, study) {
+        ("new", None) => create(state, user_id, rest).await,
+        ("game", None) => match rest.trim_start_matches('#').parse::<i64>() {
+            Ok(game_id) => create_from_game(state, user_id, game_id).await,
+            Err(_) => reply(state, user_id, USAGE.to_string()).await,
+        },
+        ("join", None) => match rest.trim_start_matches('#').parse::<i64>() {
+            Ok(id) => join(state, user_id, id).await,
+            Err(_) => reply(state, user_id, USAGE.to_string()).await,
+        },
+        ("new" | "game" | "join", Some(study)) => {
+            reply(state, user_id, format!("You are in study #{}, /study leave first.", study.id)).await
+        }
+        ("leave", Some(study)) => leave(state, user_id, study).await,
+        ("back", Some(mut study)) => {
+            let n = match rest {
+                "" => 1,
+                n => match n.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => return reply(state, user_id, USAGE.to_string()).await,
+                },
+            };
+            let ucis: Vec<&str> = study.moves.split_whitespace().collect();
+            if ucis.is_empty() {
+                return reply(state, user_id, "There are no moves to take back.".to_string()).await;
+            }
+            let n = n.min(ucis.len());
+            study.moves = ucis[..ucis.len() - n].join(" ");
+            touch(&state.db, &study).await?;
+            let name = user_name(&state.db, user_id).await?;
+            let taken = if n == 1 { "a move".to_string() } else { format!("{n} moves") };
+            let text = format!("{name} took back {taken}.{}", notes(&state.db, &study).await?);
+            broadcast(&state.db, &state.client, &study, &text, true).await
+        }
+        ("note", Some(study)) if !rest.is_empty() => {
+            sqlx::query("insert into study_notes (study_id, user_id, ply, text) values ($1, $2, $3, $4)")
+                .bind(study.id)
+                .bind(user_id)
+                .bind(study.plies())
+                .bind(rest)
+                .execute(&state.db)
+                .await?;
+            touch(&state.db, &study).await?;
+            let text = format!("{}: {rest}", user_name(&state.db, user_id).await?);
+            broadcast(&state.db, &state.client, &study, &text, false).await
+        }
+        ("eval", Some(study)) => {
+            let text = analysis::evaluate(state, &study.position()).await?;
+            reply(state, user_id, text).await
+        }
+        ("", Some(study)) => show(state, user_id, &study).await,
+        ("leave" | "back" | "note" | "eval", None) => {
+            reply(state, user_id, format!("You are not in a study.\n{USAGE}")).await
+        }
+        _ => reply(state, user_id, USAGE.to_string()).await,
+    }
+}
+
+/// The board from the user's side, with the members and notes at this move.
+async fn show(state: &State, user_id: i64, study: &Study) -> Result<()> {
+    let white: bool = sqlx::query_scalar("select white from study_members where study_id = $1 and user_id = $2")
+        .bind(study.id)
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+    let mut names = Vec::new();
+    for (id, _) in members(&state.db, study.id).await? {
+        names.push(user_name(&state.db, id).await?);
+    }
+    let side = if white { Color::White } else { Color::Black };
+    let text = format!(
+        "Study #{} with {}{}\n{}",
+        study.id,
+        names.join(", "),
+        notes(&state.db, study).await?,
+        analysis::show(&study.position(), side, settings::theme(&state.db, user_id).await?)
+    );
+    reply(state, user_id, text).await
+}
+
+async fn reply(state: &State, user_id: i64, text: String) -> Result<()> {
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+/// Why the user can't be in a study right now, if they can't.
+async fn busy(state: &State, user_id: i64) -> Result<Option<String>> {
+    if ongoing_game(&state.db, user_id).await?.is_some() {
+        return Ok(Some("Finish your game first.".to_string()));
+    }
+    Ok(training(&state.db, user_id).await?.map(|what| format!("Finish your {what} first.")))
+}
+
+/// Opens a study with the user as its only member.
+async fn open(
+    db: &Pool<Sqlite>,
+    user_id: i64,
+    game_id: Option<i64>,
+    start_fen: &str,
+    moves: &str,
+    white: bool,
+) -> Result<Study> {
+    let mut tx = db.begin().await?;
+    let id: i64 =
+        sqlx::query_scalar("insert into studies (game_id, start_fen, moves) values ($1, $2, $3) returning id")
+            .bind(game_id)
+            .bind(start_fen)
+            .bind(moves)
+            .fetch_one(&mut *tx)
+            .await?;
+    sqlx::query("insert into study_members (study_id, user_id, white) values ($1, $2, $3)")
+        .bind(id)
+        .bind(user_id)
+        .bind(white)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("{user_id} opened study {id}");
+    Ok(Study {
+        id,
+        game_id,
+        start_fen: start_fen.to_string(),
+        moves: moves.to_string(),
+    })
+}
+
+async fn create(state: &mut State, user_id: i64, fen: &str) -> Result<()> {
+    let fen = if fen.is_empty() { STARTING_FEN } else { fen };
+    let Some(position) = fen
+        .parse::<Fen>()
+        .ok()
+        .and_then(|fen| fen.into_position::<Chess>(CastlingMode::Standard).ok())
+    else {
+        return reply(state, user_id, format!("That is not a valid position.\n{USAGE}")).await;
+    };
+    if let Some(why) = busy(state, user_id).await? {
+        return reply(state, user_id, why).await;
+    }
+    let start_fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let study = open(&state.db, user_id, None, &start_fen, "", position.turn().is_white()).await?;
+    let text = format!(
+        "Study #{0} is open. Others join with /study join {0}; everyone's moves and /study notes go to all members.\n{1}",
+        study.id,
+        analysis::show(&position, position.turn(), settings::theme(&state.db, user_id).await?)
+    );
+    reply(state, user_id, text).await
+}
+
+/// Opens a study at the end of a finished game and invites the opponent.
+async fn create_from_game(state: &mut State, user_id: i64, game_id: i64) -> Result<()> {
+    let Some(game) = game_by_id(&state.db, game_id).await?.filter(|game| game.ended) else {
+        return reply(state, user_id, format!("No finished game #{game_id}.")).await;
+    };
+    if let Some(why) = busy(state, user_id).await? {
+        return reply(state, user_id, why).await;
+    }
+    let moves = game_ucis(&state.db, game_id).await?.join(" ");
+    let white = game.b_id != Some(user_id);
+    let study = open(&state.db, user_id, Some(game_id), game.start_fen(), &moves, white).await?;
+    let side = if white { Color::White } else { Color::Black };
+    let theme = settings::theme(&state.db, user_id).await?;
+    let text = format!(
+        "Study #{0} of game #{game_id} is open at its final position; /study back [n] goes back through it. \
+         Others join with /study join {0}.\n{1}",
+        study.id,
+        analysis::show(&study.position(), side, theme)
+    );
+    reply(state, user_id, text).await?;
+
+    let opponents = [game.w_id, game.b_id].into_iter().flatten().filter(|&id| id != user_id);
+    for opponent in opponents {
+        let text = format!(
+            "{} opened a study of game #{game_id}. Join the post-mortem with /study join {}",
+            user_name(&state.db, user_id).await?,
+            study.id
+        );
+        state.client.send_message(packed_chat(opponent), text).await?;
+    }
+    Ok(())
+}
+
+async fn join(state: &mut State, user_id: i64, id: i64) -> Result<()> {
+    let Some(study) = find(&state.db, id).await? else {
+        return reply(state, user_id, format!("No open study #{id}.")).await;
+    };
+    if let Some(why) = busy(state, user_id).await? {
+        return reply(state, user_id, why).await;
+    }
+    // players of the studied game see it from their side
+    let black = match study.game_id {
+        Some(game_id) => game_by_id(&state.db, game_id).await?.is_some_and(|game| game.b_id == Some(user_id)),
+        None => false,
+    };
+    let text = format!("{} joined study #{id}.", user_name(&state.db, user_id).await?);
+    broadcast(&state.db, &state.client, &study, &text, false).await?;
+    sqlx::query("insert into study_members (study_id, user_id, white) values ($1, $2, $3)")
+        .bind(study.id)
+        .bind(user_id)
+        .bind(!black)
+        .execute(&state.db)
+        .await?;
+    debug!("{user_id} joined study {id}");
+    show(state, user_id, &study).await
+}
+
+async fn leave(state: &mut State, user_id: i64, study: Study) -> Result<()> {
+    sqlx::query("delete from study_members where study_id = $1 and user_id = $2")
+        .bind(study.id)
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+    reply(state, user_id, format!("You left study #{}.", study.id)).await?;
+    if members(&state.db, study.id).await?.is_empty() {
+        sqlx::query("update studies set ended = 1 where id = $1")
+            .bind(study.id)
+            .execute(&state.db)
+            .await?;
+        info!("study {} closed", study.id);
+        return Ok(());
+    }
+    let text = format!("{} left the study.", user_name(&state.db, user_id).await?);
+    broadcast(&state.db, &state.client, &study, &text, false).await
+}
+
+/// Plays a move sent by a member and shows it to everyone.
+pub async fn on_move(state: &mut State, user_id: i64, mut study: Study, notation: &str) -> Result<()> {
+    let mut position = study.position();
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        return reply(state, user_id, "This is not a valid move".to_string()).await;
+    };
+    let played = analysis::numbered(&position, &San::from_move(&position, &m));
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    study.moves = if study.moves.is_empty() { uci } else { format!("{} {uci}", study.moves) };
+    position.play_unchecked(&m);
+    touch(&state.db, &study).await?;
+    let text = format!("{}: {played}", user_name(&state.db, user_id).await?);
+    broadcast(&state.db, &state.client, &study, &text, true).await
+}
+
+/// Closes studies nobody has touched for a while.
+pub async fn sweep(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let idle: Vec<Study> = sqlx::query_as(
+        "select id, game_id, start_fen, moves from studies where not ended and last_activity_at < unixepoch() - $1",
+    )
+    .bind(IDLE_SECS)
+    .fetch_all(db)
+    .await?;
+    for study in idle {
+        let text = format!("Study #{} was closed after a day without activity.", study.id);
+        broadcast(db, client, &study, &text, false).await?;
+        sqlx::query("update studies set ended = 1 where id = $1")
+            .bind(study.id)
+            .execute(db)
+            .await?;
+        info!("closed idle study {}", study.id);
+    }
+    Ok(())
+}
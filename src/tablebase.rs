@@ -0,0 +1,354 @@
+//! Perfect play for endgames. Endings of a lone king against king and queen,
+//! rook or pawn are solved in memory on first use; anything bigger is probed
+//! through the engine, if it has Syzygy tables.
+
+use crate::engine::{self, Engine, Score};
+use anyhow::Result;
+use log::info;
+use rand::Rng;
+use shakmaty::fen::Fen;
+use shakmaty::uci::Uci;
+use shakmaty::{Board, CastlingMode, Chess, Color, EnPassantMode, FromSetup, Move, Piece, Position, Role, Setup, Square};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Engine evaluations at least this big are tablebase wins (or mates).
+const ENGINE_WIN_CP: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    Draw,
+    Win,
+}
+
+/// The result of perfect play for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub wdl: Wdl,
+    /// Plies until mate, when known.
+    pub dtm: Option<u32>,
+}
+
+impl Outcome {
+    const DRAW: Outcome = Outcome {
+        wdl: Wdl::Draw,
+        dtm: None,
+    };
+
+    /// The outcome for the side that moved into this position.
+    pub fn for_mover(self) -> Outcome {
+        let dtm = self.dtm.map(|plies| plies + 1);
+        match self.wdl {
+            Wdl::Win => Outcome { wdl: Wdl::Loss, dtm },
+            Wdl::Loss => Outcome { wdl: Wdl::Win, dtm },
+            Wdl::Draw => Outcome::DRAW,
+        }
+    }
+
+    /// Orders outcomes by how good they are: quick wins first, then draws,
+    /// then the longest losses.
+    pub fn rank(self) -> i64 {
+        let dtm = i64::from(self.dtm.unwrap_or(0));
+        match self.wdl {
+            Wdl::Win => 1_000_000 - dtm,
+            Wdl::Draw => 0,
+            Wdl::Loss => -1_000_000 + dtm,
+        }
+    }
+}
+
+/// The outcome of a finished game, or `None` if it goes on.
+fn terminal(position: &Chess) -> Option<Outcome> {
+    if position.is_checkmate() {
+        Some(Outcome {
+            wdl: Wdl::Loss,
+            dtm: Some(0),
+        })
+    } else if position.is_stalemate() || position.is_insufficient_material() {
+        Some(Outcome::DRAW)
+    } else {
+        None
+    }
+}
+
+/// Perfect-play outcome of a position, or `None` if it isn't covered.
+pub async fn probe(engine: Option<&Engine>, position: &Chess) -> Result<Option<Outcome>> {
+    if let Some(outcome) = terminal(position).or_else(|| probe_builtin(position)) {
+        return Ok(Some(outcome));
+    }
+    Ok(probe_engine(engine, position).await?.map(|(outcome, _)| outcome))
+}
+
+/// The engine's verdict and choice of move, if it has tablebases.
+async fn probe_engine(engine: Option<&Engine>, position: &Chess) -> Result<Option<(Outcome, Option<String>)>> {
+    let Some(engine) = engine.filter(|e| e.has_tablebases()) else {
+        return Ok(None);
+    };
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let lines = engine.analyse(&fen, 1, engine::DEFAULT_MOVETIME, &[]).await?;
+    let Some(line) = lines.first() else {
+        return Ok(None);
+    };
+    let outcome = match line.score {
+        Score::Mate(n) if n > 0 => Outcome {
+            wdl: Wdl::Win,
+            dtm: Some(n as u32 * 2 - 1),
+        },
+        Score::Mate(n) => Outcome {
+            wdl: Wdl::Loss,
+            dtm: Some(n.unsigned_abs() as u32 * 2),
+        },
+        Score::Cp(cp) if cp >= ENGINE_WIN_CP => Outcome { wdl: Wdl::Win, dtm: None },
+        Score::Cp(cp) if cp <= -ENGINE_WIN_CP => Outcome { wdl: Wdl::Loss, dtm: None },
+        Score::Cp(_) => Outcome::DRAW,
+    };
+    Ok(Some((outcome, line.pv.first().cloned())))
+}
+
+/// The best move by perfect play with its outcome for the side playing it:
+/// the quickest win, a draw, or the most stubborn defence.
+pub async fn best_move(engine: Option<&Engine>, position: &Chess) -> Result<Option<(Move, Outcome)>> {
+    if locate(position).is_none() {
+        let Some((outcome, Some(uci))) = probe_engine(engine, position).await? else {
+            return Ok(None);
+        };
+        let m = uci.parse::<Uci>()?.to_move(position)?;
+        return Ok(Some((m, outcome)));
+    }
+    let mut best: Option<(Move, Outcome)> = None;
+    for m in position.legal_moves() {
+        let mut after = position.clone();
+        after.play_unchecked(&m);
+        let Some(outcome) = terminal(&after).or_else(|| probe_builtin(&after)) else {
+            return Ok(None);
+        };
+        let outcome = outcome.for_mover();
+        if best.as_ref().is_none_or(|(_, b)| outcome.rank() > b.rank()) {
+            best = Some((m, outcome));
+        }
+    }
+    Ok(best)
+}
+
+/// The endings solved in memory: White has a king and one piece of `role`.
+struct Table {
+    /// Indexed by `index`: positive wins in that many plies, negative loses
+    /// in `-v - 1` plies, zero draws or is illegal.
+    values: Vec<i16>,
+}
+
+static QUEEN: OnceLock<Table> = OnceLock::new();
+static ROOK: OnceLock<Table> = OnceLock::new();
+static PAWN: OnceLock<Table> = OnceLock::new();
+
+fn table(role: Role) -> Option<&'static Table> {
+    let cell = match role {
+        Role::Queen => &QUEEN,
+        Role::Rook => &ROOK,
+        Role::Pawn => &PAWN,
+        _ => return None,
+    };
+    Some(cell.get_or_init(|| {
+        let started = Instant::now();
+        let table = Table::solve(role);
+        let longest = table.values.iter().max().copied().unwrap_or(0);
+        info!(
+            "solved K{}vK in {:.1}s, longest mate {longest} plies",
+            role.upper_char(),
+            started.elapsed().as_secs_f64()
+        );
+        table
+    }))
+}
+
+/// Solves all built-in tables up front so that the first probe is quick.
+pub fn warm_up() {
+    for role in [Role::Queen, Role::Rook, Role::Pawn] {
+        table(role);
+    }
+}
+
+const POSITIONS: usize = 2 * 64 * 64 * 64;
+
+fn index(turn: Color, white_king: Square, black_king: Square, piece: Square) -> usize {
+    ((turn as usize * 64 + white_king as usize) * 64 + black_king as usize) * 64 + piece as usize
+}
+
+/// The built-in table covering a position, and its index there.
+fn locate(position: &Chess) -> Option<(Role, usize)> {
+    let board = position.board();
+    if board.occupied().count() != 3 || board.black().count() != 1 {
+        return None;
+    }
+    let piece_square = (board.white() & !board.kings()).first()?;
+    let role = board.role_at(piece_square)?;
+    let (white_king, black_king) = (board.king_of(Color::White)?, board.king_of(Color::Black)?);
+    Some((role, index(position.turn(), white_king, black_king, piece_square)))
+}
+
+/// Perfect-play outcome from the built-in tables, if they cover the position.
+pub fn probe_builtin(position: &Chess) -> Option<Outcome> {
+    let (role, index) = locate(position)?;
+    Some(decode(table(role)?.values[index]))
+}
+
+fn decode(value: i16) -> Outcome {
+    match value {
+        0 => Outcome::DRAW,
+        v if v > 0 => Outcome {
+            wdl: Wdl::Win,
+            dtm: Some(v as u32),
+        },
+        v => Outcome {
+            wdl: Wdl::Loss,
+            dtm: Some((-v - 1) as u32),
+        },
+    }
+}
+
+fn encode(outcome: Outcome) -> i16 {
+    match (outcome.wdl, outcome.dtm) {
+        (Wdl::Win, Some(plies)) => plies as i16,
+        (Wdl::Loss, Some(plies)) => -(plies as i16) - 1,
+        _ => 0,
+    }
+}
+
+fn setup(turn: Color, white_king: Square, black_king: Square, piece: Square, role: Role) -> Option<Chess> {
+    if white_king == black_king || piece == white_king || piece == black_king {
+        return None;
+    }
+    let mut board = Board::empty();
+    board.set_piece_at(white_king, Piece { color: Color::White, role: Role::King });
+    board.set_piece_at(black_king, Piece { color: Color::Black, role: Role::King });
+    board.set_piece_at(piece, Piece { color: Color::White, role });
+    let setup = Setup {
+        board,
+        turn,
+        ..Setup::empty()
+    };
+    Chess::from_setup(setup, CastlingMode::Standard).ok()
+}
+
+/// A successor of a position in the table being solved.
+enum Child {
+    Index(u32),
+    /// A position outside the table, with its value from its own side's view.
+    Solved(i16),
+}
+
+impl Table {
+    /// Retrograde analysis: mates first, then one ply further back each pass.
+    fn solve(role: Role) -> Table {
+        let mut values = vec![0i16; POSITIONS];
+        let mut resolved = vec![false; POSITIONS];
+        let mut starts = vec![0u32; POSITIONS + 1];
+        let mut children = Vec::new();
+
+        for i in 0..POSITIONS {
+            starts[i] = children.len() as u32;
+            let (turn, white_king, black_king, piece) = (
+                if i / (64 * 64 * 64) == Color::White as usize { Color::White } else { Color::Black },
+                Square::new((i / (64 * 64) % 64) as u32),
+                Square::new((i / 64 % 64) as u32),
+                Square::new((i % 64) as u32),
+            );
+            let Some(position) = setup(turn, white_king, black_king, piece, role) else {
+                resolved[i] = true;
+                continue;
+            };
+            if let Some(outcome) = terminal(&position) {
+                values[i] = encode(outcome);
+                resolved[i] = true;
+                continue;
+            }
+            for m in position.legal_moves() {
+                let mut after = position.clone();
+                after.play_unchecked(&m);
+                children.push(match locate(&after) {
+                    Some((r, child)) if r == role => Child::Index(child as u32),
+                    Some((r, child)) => Child::Solved(table(r).map_or(0, |t| t.values[child])),
+                    // the piece was taken
+                    None => Child::Solved(0),
+                });
+            }
+        }
+        starts[POSITIONS] = children.len() as u32;
+
+        for plies in 1.. {
+            let mut changed = false;
+            let mut pending_wins = false;
+            for i in 0..POSITIONS {
+                if resolved[i] {
+                    continue;
+                }
+                let (mut win, mut all_lost, mut longest) = (false, true, 0);
+                for child in &children[starts[i] as usize..starts[i + 1] as usize] {
+                    let value = match *child {
+                        Child::Index(c) if !resolved[c as usize] => {
+                            all_lost = false;
+                            continue;
+                        }
+                        Child::Index(c) => values[c as usize],
+                        Child::Solved(v) => v,
+                    };
+                    let outcome = decode(value);
+                    match outcome.wdl {
+                        Wdl::Loss if outcome.dtm == Some(plies - 1) => win = true,
+                        Wdl::Loss => {
+                            all_lost = false;
+                            pending_wins |= outcome.dtm.is_some_and(|d| d >= plies);
+                        }
+                        Wdl::Win => longest = longest.max(outcome.dtm.unwrap_or(0)),
+                        Wdl::Draw => all_lost = false,
+                    }
+                }
+                if win {
+                    values[i] = encode(Outcome {
+                        wdl: Wdl::Win,
+                        dtm: Some(plies),
+                    });
+                } else if all_lost {
+                    values[i] = encode(Outcome {
+                        wdl: Wdl::Loss,
+                        dtm: Some(longest + 1),
+                    });
+                } else {
+                    continue;
+                }
+                resolved[i] = true;
+                changed = true;
+            }
+            if !changed && !pending_wins {
+                break;
+            }
+        }
+        Table { values }
+    }
+}
+
+/// A random position from a built-in table for `turn` to move whose outcome
+/// for the side to move satisfies `want`.
+pub fn random_position(role: Role, turn: Color, want: impl Fn(&Chess, Outcome) -> bool) -> Option<Chess> {
+    let table = table(role)?;
+    let mut rng = rand::thread_rng();
+    for _ in 0..100_000 {
+        let (white_king, black_king, piece) = (
+            Square::new(rng.gen_range(0..64)),
+            Square::new(rng.gen_range(0..64)),
+            Square::new(rng.gen_range(0..64)),
+        );
+        let Some(position) = setup(turn, white_king, black_king, piece, role) else {
+            continue;
+        };
+        if terminal(&position).is_some() {
+            continue;
+        }
+        let outcome = decode(table.values[index(turn, white_king, black_king, piece)]);
+        if want(&position, outcome) {
+            return Some(position);
+        }
+    }
+    None
+}
@@ -0,0 +1,335 @@
+//! Single puzzles on demand: `/puzzle` picks one near the user's puzzle
+//! rating, optionally by theme tag and rating range, and rates the attempt.
+//! `/puzzle mine` gives the ones made from wins the user missed in their own
+//! games. Solving stats are kept per theme, counting puzzle rushes too.
+
+use crate::puzzles::{self, Puzzle};
+use crate::rating::{self, Category};
+use crate::{diagram, ongoing_game, packed_chat, parse_typed_move, settings, training, State};
+use anyhow::Result;
+use log::debug;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+use shakmaty::{Bitboard, Position};
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+
+const USAGE: &str = "Usage: /puzzle [theme|mine] [min-max] | skip | stats, e.g. /puzzle mateIn2 1200-1600";
+
+/// Without a range, puzzles are picked this close to the user's puzzle rating.
+const RATING_WINDOW: i64 = 150;
+
+/// Themes listed by `/puzzle stats`, the most attempted first.
+const STATS_THEMES: usize = 15;
+
+const COLUMNS: &str = "id, user_id, puzzle_id, theme, min_rating, max_rating, step";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Attempt {
+    id: i64,
+    user_id: i64,
+    puzzle_id: String,
+    theme: Option<String>,
+    min_rating: Option<i64>,
+    max_rating: Option<i64>,
+    step: i64,
+}
+
+impl Attempt {
+    /// The command that picks another puzzle the same way.
+    fn again(&self) -> String {
+        let mut command = "/puzzle".to_string();
+        if let Some(theme) = &self.theme {
+            command = format!("{command} {theme}");
+        }
+        if let (Some(min), Some(max)) = (self.min_rating, self.max_rating) {
+            command = format!("{command} {min}-{max}");
+        }
+        command
+    }
+}
+
+pub async fn running(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<Attempt>> {
+    Ok(
+        sqlx::query_as(&format!("select {COLUMNS} from puzzle_attempts where user_id = $1 and solved is null"))
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?,
+    )
+}
+
+async fn puzzle(db: &Pool<Sqlite>, attempt: &Attempt) -> Result<Puzzle> {
+    Ok(
+        sqlx::query_as(&format!("select {} from puzzles where id = $1", puzzles::COLUMNS))
+            .bind(&attempt.puzzle_id)
+            .fetch_one(db)
+            .await?,
+    )
+}
+
+/// Parses `1200-1600`.
+fn parse_range(s: &str) -> Option<(i64, i64)> {
+    let (min, max) = s.split_once('-')?;
+    let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+    (min <= max).then_some((min, max))
+}
+
+pub async fn on_puzzle(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let chat = packed_chat(user_id);
+    let current = running(&state.db, user_id).await?;
+    let text = match (args.trim(), current) {
+        ("stats", _) => stats(&state.db, user_id).await?,
+        ("skip", Some(attempt)) => {
+            let puzzle = puzzle(&state.db, &attempt).await?;
+            finish(&state.db, &attempt, &puzzle, false).await?
+        }
+        ("skip", None) => format!("You are not solving a puzzle.\n{USAGE}"),
+        ("", Some(attempt)) => {
+            let puzzle = puzzle(&state.db, &attempt).await?;
+            let (position, _) = puzzle.position_at(attempt.step as usize)?;
+            let theme = settings::theme(&state.db, user_id).await?;
+            format!(
+                "Your puzzle, rated {}:\n{}\n{} to move. /puzzle skip gives up.",
+                puzzle.rating,
+                diagram::render(position.board(), position.turn(), Bitboard::FULL, theme),
+                if position.turn().is_white() { "White" } else { "Black" }
+            )
+        }
+        (args, current) => {
+            let (mut theme, mut range) = (None, None);
+            for word in args.split_whitespace() {
+                match parse_range(word) {
+                    Some(r) if range.is_none() => range = Some(r),
+                    _ if theme.is_none() && !word.starts_with(|c: char| c.is_ascii_digit()) => {
+                        theme = Some(word.to_string())
+                    }
+                    _ => {
+                        state.client.send_message(chat, USAGE).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            return start(state, user_id, current, theme, range).await;
+        }
+    };
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Picks a puzzle the user hasn't tried, replacing the one they were on.
+async fn start(
+    state: &mut State,
+    user_id: i64,
+    current: Option<Attempt>,
+    theme: Option<String>,
+    range: Option<(i64, i64)>,
+) -> Result<()> {
+    let chat = packed_chat(user_id);
+    if current.is_none() {
+        if ongoing_game(&state.db, user_id).await?.is_some() {
+            state.client.send_message(chat, "Finish your game first.").await?;
+            return Ok(());
+        }
+        if let Some(what) = training(&state.db, user_id).await? {
+            state
+                .client
+                .send_message(chat, format!("Finish your {what} first."))
+                .await?;
+            return Ok(());
+        }
+    }
+    let target = rating::get(&state.db, user_id, Category::Puzzle).await?.rating.round() as i64;
+    let (min, max) = range.unwrap_or((target - RATING_WINDOW, target + RATING_WINDOW));
+    // tags are matched whole and regardless of case, `matein2` finding `mateIn2`;
+    // `mine` picks the puzzles made from the user's own games, which nobody
+    // else gets
+    let tagged = if theme.as_deref() == Some("mine") {
+        "owner_id = $1"
+    } else {
+        "owner_id is null and ($2 is null or ' ' || themes || ' ' like '% ' || $2 || ' %')"
+    };
+    let untried = "id not in (select puzzle_id from puzzle_attempts where user_id = $1)";
+    let mut puzzle: Option<Puzzle> = sqlx::query_as(&format!(
+        "select {} from puzzles where {tagged} and {untried} and rating between $3 and $4 order by random() limit 1",
+        puzzles::COLUMNS
+    ))
+    .bind(user_id)
+    .bind(&theme)
+    .bind(min)
+    .bind(max)
+    .fetch_optional(&state.db)
+    .await?;
+    // without a range asked for, the closest one will do
+    if puzzle.is_none() && range.is_none() {
+        puzzle = sqlx::query_as(&format!(
+            "select {} from puzzles where {tagged} and {untried} order by abs(rating - $3) limit 1",
+            puzzles::COLUMNS
+        ))
+        .bind(user_id)
+        .bind(&theme)
+        .bind(target)
+        .fetch_optional(&state.db)
+        .await?;
+    }
+    let Some(puzzle) = puzzle else {
+        let text = match &theme {
+            Some(theme) if theme == "mine" => {
+                "No new puzzles from your games. They are made from missed wins once your rated games are reviewed."
+                    .to_string()
+            }
+            Some(theme) => format!("No new puzzles tagged {theme} in that range."),
+            None => "No new puzzles in that range.".to_string(),
+        };
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    };
+    let (position, setup) = puzzle.position_at(1)?;
+
+    let mut tx = state.db.begin().await?;
+    // a puzzle left for another counts as failed
+    if let Some(current) = &current {
+        sqlx::query("update puzzle_attempts set solved = 0 where id = $1")
+            .bind(current.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        "insert into puzzle_attempts (user_id, puzzle_id, theme, min_rating, max_rating) values ($1, $2, $3, $4, $5)",
+    )
+    .bind(user_id)
+    .bind(&puzzle.id)
+    .bind(&theme)
+    .bind(range.map(|(min, _)| min))
+    .bind(range.map(|(_, max)| max))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    if let Some(current) = &current {
+        let old = self::puzzle(&state.db, current).await?;
+        puzzles::rate_attempt(&state.db, user_id, &old, false).await?;
+    }
+    debug!("{user_id} gets puzzle {} rated {}", puzzle.id, puzzle.rating);
+
+    let theme = settings::theme(&state.db, user_id).await?;
+    let text = format!(
+        "Puzzle rated {}\nYour opponent played {}.\n{}\n{} to move.",
+        puzzle.rating,
+        setup.unwrap_or_default(),
+        diagram::render(position.board(), position.turn(), Bitboard::FULL, theme),
+        if position.turn().is_white() { "White" } else { "Black" }
+    );
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Handles a move sent while solving a puzzle.
+pub async fn on_move(state: &mut State, mut attempt: Attempt, notation: &str) -> Result<()> {
+    let chat = packed_chat(attempt.user_id);
+    let puzzle = puzzle(&state.db, &attempt).await?;
+    let step = attempt.step as usize;
+    let (position, _) = puzzle.position_at(step)?;
+    let Some(m) = parse_typed_move(&state.language, notation, &position).filter(|m| position.is_legal(m)) else {
+        state.client.send_message(chat, "This is not a valid move").await?;
+        return Ok(());
+    };
+    if !puzzle.accepts(&position, step, &m) {
+        let text = finish(&state.db, &attempt, &puzzle, false).await?;
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+    let mut after = position.clone();
+    after.play_unchecked(&m);
+    if after.is_checkmate() || step + 1 >= puzzle.line().len() {
+        let text = finish(&state.db, &attempt, &puzzle, true).await?;
+        state.client.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    // the opponent answers with the next move of the line
+    attempt.step += 2;
+    sqlx::query("update puzzle_attempts set step = $2 where id = $1")
+        .bind(attempt.id)
+        .bind(attempt.step)
+        .execute(&state.db)
+        .await?;
+    let (position, reply) = puzzle.position_at(attempt.step as usize)?;
+    let text = format!(
+        "✓ Correct. Your opponent answered {}.\n{}",
+        reply.unwrap_or_default(),
+        diagram::render(
+            position.board(),
+            position.turn(),
+            Bitboard::FULL,
+            settings::theme(&state.db, attempt.user_id).await?
+        )
+    );
+    state.client.send_message(chat, text).await?;
+    Ok(())
+}
+
+/// Ends the attempt and rates it, returning what to tell the user.
+async fn finish(db: &Pool<Sqlite>, attempt: &Attempt, puzzle: &Puzzle, solved: bool) -> Result<String> {
+    sqlx::query("update puzzle_attempts set solved = $2 where id = $1")
+        .bind(attempt.id)
+        .bind(solved)
+        .execute(db)
+        .await?;
+    let (rating, diff) = puzzles::rate_attempt(db, attempt.user_id, puzzle, solved).await?;
+    let mut text = if solved {
+        "✓ Solved!".to_string()
+    } else {
+        let (position, _) = puzzle.position_at(attempt.step as usize)?;
+        let solution = puzzle.line()[attempt.step as usize]
+            .parse::<Uci>()
+            .ok()
+            .and_then(|uci| uci.to_move(&position).ok())
+            .map(|m| San::from_move(&position, &m).to_string())
+            .unwrap_or_default();
+        format!("✗ The answer was {solution}.")
+    };
+    text = format!("{text} Puzzle rating {rating} ({diff:+}).");
+    let themes = puzzle.themes.split_whitespace().collect::<Vec<_>>().join(", ");
+    if !themes.is_empty() {
+        text = format!("{text}\nThemes: {themes}");
+    }
+    Ok(format!("{text}\nNext: {}", attempt.again()))
+}
+
+/// Success rates per theme tag over the user's finished puzzles, in rushes
+/// as well.
+async fn stats(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let attempts: Vec<(String, bool)> = sqlx::query_as(
+        "select p.themes, a.solved from puzzle_attempts a join puzzles p on p.id = a.puzzle_id
+         where a.user_id = $1 and a.solved is not null
+         union all
+         select p.themes, a.solved from rush_attempts a join rushes r on r.id = a.rush_id join puzzles p on p.id = a.puzzle_id
+         where r.user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    if attempts.is_empty() {
+        return Ok(format!("You haven't finished any puzzles yet.\n{USAGE}"));
+    }
+    let mut tally: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+    for (themes, solved) in &attempts {
+        for theme in themes.split_whitespace() {
+            let (tried, right) = tally.entry(theme).or_default();
+            *tried += 1;
+            *right += i64::from(*solved);
+        }
+    }
+    let mut themes: Vec<_> = tally.into_iter().collect();
+    themes.sort_by_key(|(_, (tried, _))| -tried);
+    let lines: Vec<String> = themes
+        .iter()
+        .take(STATS_THEMES)
+        .map(|(theme, (tried, right))| format!("{theme}: {right}/{tried} ({}%)", right * 100 / tried))
+        .collect();
+    let solved = attempts.iter().filter(|(_, solved)| *solved).count();
+    Ok(format!(
+        "Puzzles solved: {solved}/{}\n{}",
+        attempts.len(),
+        lines.join("\n")
+    ))
+}
@@ -0,0 +1,677 @@
+//! Team matches between two clubs. An admin of one club challenges another;
+//! once accepted, members of both sign up and the players are paired board
+//! by board in rating order. The match is scored from its games and both
+//! clubs hear the result when the last one ends. A tied match can be settled
+//! by an armageddon game between the players on the top board. League
+//! fixtures (see `leagues`) are matches too, scheduled rather than challenged.
+
+use crate::bot::Bot;
+use crate::clock::Delay;
+use crate::config::Config;
+use crate::{
+    armageddon_terms, armageddon_time_control, clock, clubs, follows, ongoing_game, packed_chat, player_card, training,
+    user_name, State, Termination, STARTING_FEN,
+};
+use anyhow::Result;
+use chrono::DateTime;
+use log::info;
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeSet;
+
+const DEFAULT_BOARDS: i64 = 4;
+const MAX_BOARDS: i64 = 32;
+
+const USAGE: &str = "Usage:
+/match — your clubs' matches
+/match <id>
+/match challenge <your club> <their club> [boards]
+/match accept|decline|join|start <id>
+/match playoff <id> — settle a tied match with an armageddon game";
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct TeamMatch {
+    pub id: i64,
+    boards: i64,
+    pub status: String,
+    pub home_id: i64,
+    pub home: String,
+    pub away_id: i64,
+    pub away: String,
+    playoff_game_id: Option<i64>,
+    league_id: Option<i64>,
+    pub round: Option<i64>,
+    deadline: Option<i64>,
+    home_forfeits: i64,
+    away_forfeits: i64,
+}
+
+const SELECT_MATCH: &str = "select team_matches.id, boards, status, home.id as home_id, home.name as home,
+    away.id as away_id, away.name as away, playoff_game_id, league_id, round, deadline, home_forfeits, away_forfeits
+    from team_matches
+    join clubs as home on home.id = home_club_id join clubs as away on away.id = away_club_id";
+
+pub async fn find(db: &Pool<Sqlite>, id: i64) -> Result<Option<TeamMatch>> {
+    Ok(sqlx::query_as(&format!("{SELECT_MATCH} where team_matches.id = $1"))
+        .bind(id)
+        .fetch_optional(db)
+        .await?)
+}
+
+/// The fixtures of a league, by round.
+pub async fn of_league(db: &Pool<Sqlite>, league_id: i64) -> Result<Vec<TeamMatch>> {
+    Ok(
+        sqlx::query_as(&format!("{SELECT_MATCH} where league_id = $1 order by round, team_matches.id"))
+            .bind(league_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+impl TeamMatch {
+    pub fn title(&self) -> String {
+        format!("Match #{}: {} vs {}", self.id, self.home, self.away)
+    }
+
+    /// The user's side in the match, if they are in either club.
+    async fn club_of(&self, db: &Pool<Sqlite>, user_id: i64) -> Result<Option<i64>> {
+        for club_id in [self.home_id, self.away_id] {
+            if clubs::is_member(db, club_id, user_id).await? {
+                return Ok(Some(club_id));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn is_admin(&self, db: &Pool<Sqlite>, user_id: i64) -> Result<bool> {
+        Ok(clubs::is_admin(db, self.home_id, user_id).await? || clubs::is_admin(db, self.away_id, user_id).await?)
+    }
+
+    /// Sends a message to every member of both clubs.
+    pub async fn announce(&self, db: &Pool<Sqlite>, client: &Bot, text: &str) -> Result<()> {
+        let mut members = BTreeSet::new();
+        for club_id in [self.home_id, self.away_id] {
+            members.extend(clubs::member_ids(db, club_id).await?);
+        }
+        for member in members {
+            client.send_message(packed_chat(member), text).await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn on_match(state: &mut State, user_id: i64, args: &str) -> Result<()> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let text = match words.as_slice() {
+        [] => list(&state.db, user_id).await?,
+        [id] if id.parse::<i64>().is_ok() => match find(&state.db, id.parse()?).await? {
+            Some(team_match) => describe(&state.db, &team_match).await?,
+            None => format!("There is no match #{id}."),
+        },
+        ["challenge", club, opponent] => challenge(state, user_id, club, opponent, DEFAULT_BOARDS).await?,
+        ["challenge", club, opponent, boards] => match boards.parse() {
+            Ok(boards @ 1..=MAX_BOARDS) => challenge(state, user_id, club, opponent, boards).await?,
+            _ => format!("A match has 1 to {MAX_BOARDS} boards."),
+        },
+        [command @ ("accept" | "decline" | "join" | "start" | "playoff"), id] => {
+            let Some(team_match) = find(&state.db, id.parse().unwrap_or(0)).await? else {
+                state
+                    .client
+                    .send_message(packed_chat(user_id), format!("There is no match #{id}."))
+                    .await?;
+                return Ok(());
+            };
+            match *command {
+                "accept" | "decline" => respond(state, user_id, &team_match, *command == "accept").await?,
+                "join" => join(state, user_id, &team_match).await?,
+                "playoff" => playoff(state, user_id, &team_match).await?,
+                _ => start(state, user_id, &team_match).await?,
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+    state.client.send_message(packed_chat(user_id), text).await?;
+    Ok(())
+}
+
+async fn list(db: &Pool<Sqlite>, user_id: i64) -> Result<String> {
+    let matches: Vec<TeamMatch> = sqlx::query_as(&format!(
+        "{SELECT_MATCH} where (home_club_id in (select club_id from club_members where user_id = $1)
+         or away_club_id in (select club_id from club_members where user_id = $1)) and status != 'declined'
+         order by status = 'finished', team_matches.id desc limit 10"
+    ))
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    if matches.is_empty() {
+        return Ok(format!("Your clubs have no matches.\n{USAGE}"));
+    }
+    Ok(matches
+        .iter()
+        .map(|m| format!("{}, {}, {}", m.title(), count_boards(m.boards), m.status))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+async fn challenge(state: &State, user_id: i64, club: &str, opponent: &str, boards: i64) -> Result<String> {
+    let db = &state.db;
+    let (Some(club), Some(opponent)) = (clubs::find(db, club).await?, clubs::find(db, opponent).await?) else {
+        return Ok("There is no such club.".to_string());
+    };
+    if club.id == opponent.id {
+        return Ok("A club can't play itself.".to_string());
+    }
+    if !clubs::is_admin(db, club.id, user_id).await? {
+        return Ok(format!("Only admins of {} can challenge other clubs.", club.name));
+    }
+    let (id,): (i64,) =
+        sqlx::query_as("insert into team_matches (home_club_id, away_club_id, boards) values ($1, $2, $3) returning id")
+            .bind(club.id)
+            .bind(opponent.id)
+            .bind(boards)
+            .fetch_one(db)
+            .await?;
+    info!("{user_id} challenged {} to match {id} for {}", opponent.name, club.name);
+    let text = format!(
+        "{} challenges {} to a {boards}-board match #{id}. /match accept {id} or /match decline {id}",
+        club.name, opponent.name
+    );
+    for admin in clubs::admin_ids(db, opponent.id).await? {
+        state.client.send_message(packed_chat(admin), text.as_str()).await?;
+    }
+    Ok(format!("Challenged {} to match #{id}.", opponent.name))
+}
+
+async fn respond(state: &State, user_id: i64, team_match: &TeamMatch, accept: bool) -> Result<String> {
+    let db = &state.db;
+    if !clubs::is_admin(db, team_match.away_id, user_id).await? {
+        return Ok(format!("Only admins of {} can answer the challenge.", team_match.away));
+    }
+    let status = if accept { "accepted" } else { "declined" };
+    let updated = sqlx::query("update team_matches set status = $2 where id = $1 and status = 'proposed'")
+        .bind(team_match.id)
+        .bind(status)
+        .execute(db)
+        .await?
+        .rows_affected();
+    if updated == 0 {
+        return Ok(format!("{} is already {}.", team_match.title(), team_match.status));
+    }
+    if accept {
+        let text = format!(
+            "{} is on! Sign up with /match join {}",
+            team_match.title(),
+            team_match.id
+        );
+        team_match.announce(db, &state.client, &text).await?;
+    } else {
+        let text = format!("{} declined {}.", team_match.away, team_match.title());
+        for admin in clubs::admin_ids(db, team_match.home_id).await? {
+            state.client.send_message(packed_chat(admin), text.as_str()).await?;
+        }
+    }
+    Ok(format!("{} {status}.", team_match.title()))
+}
+
+async fn join(state: &State, user_id: i64, team_match: &TeamMatch) -> Result<String> {
+    let db = &state.db;
+    if team_match.status != "accepted" {
+        return Ok(format!("{} isn't taking sign-ups.", team_match.title()));
+    }
+    let Some(club_id) = team_match.club_of(db, user_id).await? else {
+        return Ok(format!("Only members of {} and {} can play.", team_match.home, team_match.away));
+    };
+    let joined = sqlx::query(
+        "insert into team_match_players (match_id, club_id, user_id) values ($1, $2, $3) on conflict do nothing",
+    )
+    .bind(team_match.id)
+    .bind(club_id)
+    .bind(user_id)
+    .execute(db)
+    .await?
+    .rows_affected();
+    Ok(if joined > 0 {
+        format!("Signed up for {}. Games start when an admin runs /match start {}", team_match.title(), team_match.id)
+    } else {
+        format!("You already signed up for {}.", team_match.title())
+    })
+}
+
+/// Signed-up players of a club who are free to play, strongest first.
+async fn lineup(db: &Pool<Sqlite>, match_id: i64, club_id: i64) -> Result<Vec<i64>> {
+    let signed_up: Vec<i64> = sqlx::query_scalar(
+        "select user_id from team_match_players join users on users.id = user_id
+         where match_id = $1 and club_id = $2 order by users.rating desc",
+    )
+    .bind(match_id)
+    .bind(club_id)
+    .fetch_all(db)
+    .await?;
+    let mut free = Vec::with_capacity(signed_up.len());
+    for user_id in signed_up {
+        if is_free(db, user_id).await? {
+            free.push(user_id);
+        }
+    }
+    Ok(free)
+}
+
+/// Whether the user can start a game now.
+async fn is_free(db: &Pool<Sqlite>, user_id: i64) -> Result<bool> {
+    Ok(ongoing_game(db, user_id).await?.is_none() && training(db, user_id).await?.is_none())
+}
+
+async fn start(state: &State, user_id: i64, team_match: &TeamMatch) -> Result<String> {
+    if !team_match.is_admin(&state.db, user_id).await? {
+        return Ok("Only club admins can start the match.".to_string());
+    }
+    if team_match.status != "accepted" {
+        return Ok(format!("{} can't be started, it is {}.", team_match.title(), team_match.status));
+    }
+    pair(&state.db, &state.client, &state.config.get(), team_match).await
+}
+
+/// Pairs the signed-up players and starts the games. Home plays white on
+/// odd boards. A league match keeps all its boards: those only one club has
+/// a player for are forfeited by the other.
+pub async fn pair(db: &Pool<Sqlite>, client: &Bot, config: &Config, team_match: &TeamMatch) -> Result<String> {
+    let home = lineup(db, team_match.id, team_match.home_id).await?;
+    let away = lineup(db, team_match.id, team_match.away_id).await?;
+    let boards = team_match.boards.min(home.len() as i64).min(away.len() as i64);
+    let fielded = |players: &[i64]| team_match.boards.min(players.len() as i64);
+    let (home_forfeits, away_forfeits) = match team_match.league_id {
+        Some(_) => (fielded(&away) - boards, fielded(&home) - boards),
+        None if boards == 0 => return Ok("Both clubs need a player who is signed up and not busy.".to_string()),
+        None => (0, 0),
+    };
+
+    let tc = config.time_control;
+    let delay = tc.and_then(|tc| tc.delay);
+    let mut tx = db.begin().await?;
+    // an admin and the league deadline, or two admins, may pair at once
+    let claimed = sqlx::query("update team_matches set status = 'playing' where id = $1 and status = 'accepted'")
+        .bind(team_match.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if claimed == 0 {
+        return Ok(format!("{} has already started.", team_match.title()));
+    }
+    let mut games = Vec::new();
+    for (board, (&home_player, &away_player)) in (1..=boards).zip(home.iter().zip(&away)) {
+        let (w_id, b_id) = if home_is_white(board) {
+            (home_player, away_player)
+        } else {
+            (away_player, home_player)
+        };
+        let (id,): (i64,) = sqlx::query_as(
+            "insert into games (w_id, b_id, ended, fen, initial_ms, increment_ms, delay_ms, bronstein,
+                started_at, last_move_at, w_clock_ms, b_clock_ms, turn_started_ms, team_match_id, board)
+             values ($1, $2, 0, $3, $4, $5, $6, $7, unixepoch(), unixepoch(), $4, $4, $8, $9, $10) returning id",
+        )
+        .bind(w_id)
+        .bind(b_id)
+        .bind(STARTING_FEN)
+        .bind(tc.map(|tc| tc.initial.as_millis() as i64))
+        .bind(tc.map(|tc| tc.increment.as_millis() as i64))
+        .bind(delay.map(|d| match d {
+            Delay::Simple(d) | Delay::Bronstein(d) => d.as_millis() as i64,
+        }))
+        .bind(delay.map(|d| matches!(d, Delay::Bronstein(_))))
+        .bind(clock::now_ms())
+        .bind(team_match.id)
+        .bind(board)
+        .fetch_one(&mut *tx)
+        .await?;
+        games.push((id, board, w_id, b_id));
+    }
+    sqlx::query(
+        "update team_matches set boards = case when league_id is null then $2 else boards end,
+            home_forfeits = $3, away_forfeits = $4 where id = $1",
+    )
+    .bind(team_match.id)
+    .bind(boards)
+    .bind(home_forfeits)
+    .bind(away_forfeits)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    info!("started match {} on {boards} boards", team_match.id);
+
+    for (id, board, w_id, b_id) in games {
+        let prefix = format!("{}, board {board}. Game #{id}", team_match.title());
+        let text = format!(
+            "{prefix}. You are white, playing against {}. Your turn!",
+            player_card(db, b_id).await?
+        );
+        client.send_message(packed_chat(w_id), text).await?;
+        let text = format!(
+            "{prefix}. You are black, playing against {}. Waiting for opponent's move.",
+            player_card(db, w_id).await?
+        );
+        client.send_message(packed_chat(b_id), text).await?;
+        follows::game_started(db, client, config.public_url.as_deref(), id).await?;
+    }
+    if boards == 0 {
+        settle(db, client, team_match.id).await?;
+        return Ok(format!("{} was decided by forfeit.", team_match.title()));
+    }
+    Ok(format!("{} started on {}.", team_match.title(), count_boards(boards)))
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Board {
+    board: i64,
+    w_id: i64,
+    b_id: i64,
+    ended: bool,
+    winner: Option<bool>,
+    termination: Option<i64>,
+}
+
+fn count_boards(n: i64) -> String {
+    if n == 1 {
+        "1 board".to_string()
+    } else {
+        format!("{n} boards")
+    }
+}
+
+/// Home has white on odd boards.
+pub fn home_is_white(board: i64) -> bool {
+    board % 2 == 1
+}
+
+/// The points of a finished game for white and black, in half points.
+pub fn half_points(winner: Option<bool>, termination: Option<i64>) -> (i64, i64) {
+    match (winner, termination.and_then(Termination::from_i64)) {
+        (Some(true), _) => (2, 0),
+        (Some(false), _) => (0, 2),
+        (None, Some(Termination::Draw)) => (1, 1),
+        _ => (0, 0),
+    }
+}
+
+pub fn format_points(halves: i64) -> String {
+    match (halves / 2, halves % 2) {
+        (0, 1) => "½".to_string(),
+        (whole, 1) => format!("{whole}½"),
+        (whole, _) => whole.to_string(),
+    }
+}
+
+async fn boards(db: &Pool<Sqlite>, match_id: i64) -> Result<Vec<Board>> {
+    Ok(sqlx::query_as(
+        "select board, w_id, b_id, coalesce(ended, 0) as ended, winner, termination from games
+         where team_match_id = $1 order by board",
+    )
+    .bind(match_id)
+    .fetch_all(db)
+    .await?)
+}
+
+/// Both clubs' scores from the forfeits and finished boards, in half points.
+fn score(team_match: &TeamMatch, games: &[Board]) -> (i64, i64) {
+    let (mut home_score, mut away_score) = (2 * team_match.away_forfeits, 2 * team_match.home_forfeits);
+    for game in games.iter().filter(|game| game.ended) {
+        let (white, black) = half_points(game.winner, game.termination);
+        let (home, away) = if home_is_white(game.board) { (white, black) } else { (black, white) };
+        home_score += home;
+        away_score += away;
+    }
+    (home_score, away_score)
+}
+
+/// Both clubs' scores in half points.
+pub async fn points(db: &Pool<Sqlite>, team_match: &TeamMatch) -> Result<(i64, i64)> {
+    Ok(score(team_match, &boards(db, team_match.id).await?))
+}
+
+/// The boards with their results so far, and both clubs' scores in half
+/// points.
+pub async fn describe(db: &Pool<Sqlite>, team_match: &TeamMatch) -> Result<String> {
+    let mut text = format!("{}, {}, {}", team_match.title(), count_boards(team_match.boards), team_match.status);
+    if let (Some(deadline), "scheduled" | "accepted") = (team_match.deadline, team_match.status.as_str()) {
+        if let Some(deadline) = DateTime::from_timestamp(deadline, 0) {
+            text = format!("{text}\nBoards not played by {} are forfeited", deadline.format("%a %-d %b %H:%M UTC"));
+        }
+    }
+    let games = boards(db, team_match.id).await?;
+    if games.is_empty() && team_match.status != "finished" {
+        let players: Vec<(i64, i64)> =
+            sqlx::query_as("select club_id, user_id from team_match_players where match_id = $1")
+                .bind(team_match.id)
+                .fetch_all(db)
+                .await?;
+        for (club_id, club) in [(team_match.home_id, &team_match.home), (team_match.away_id, &team_match.away)] {
+            let signed_up = players.iter().filter(|(c, _)| *c == club_id).count();
+            text = format!("{text}\n{club}: {signed_up} signed up");
+        }
+        return Ok(text);
+    }
+
+    let (home_score, away_score) = score(team_match, &games);
+    for Board { board, w_id, b_id, ended, winner, termination } in games {
+        let result = if ended {
+            let (white, black) = half_points(winner, termination);
+            format!("{}-{}", format_points(white), format_points(black))
+        } else {
+            "*".to_string()
+        };
+        text = format!(
+            "{text}\n{board}. {} – {} {result}",
+            user_name(db, w_id).await?,
+            user_name(db, b_id).await?
+        );
+    }
+    let forfeits = [(&team_match.home, team_match.home_forfeits), (&team_match.away, team_match.away_forfeits)];
+    for (club, forfeits) in forfeits {
+        if forfeits > 0 {
+            text = format!("{text}\n{club} forfeited {}", count_boards(forfeits));
+        }
+    }
+    text = format!(
+        "{text}\n{} {} – {} {}",
+        team_match.home,
+        format_points(home_score),
+        format_points(away_score),
+        team_match.away
+    );
+    if let Some(game_id) = team_match.playoff_game_id {
+        let (w_id, b_id, winner): (i64, i64, Option<bool>) =
+            sqlx::query_as("select w_id, b_id, winner from games where id = $1")
+                .bind(game_id)
+                .fetch_one(db)
+                .await?;
+        text = format!(
+            "{text}\nArmageddon playoff, game #{game_id}: {} – {}",
+            user_name(db, w_id).await?,
+            user_name(db, b_id).await?
+        );
+        if let Some(white_won) = winner {
+            let club = playoff_winner(db, team_match, if white_won { w_id } else { b_id }).await?;
+            text = format!("{text}, {club} wins the playoff");
+        }
+    }
+    Ok(text)
+}
+
+/// The club of the player who won the playoff.
+async fn playoff_winner<'a>(db: &Pool<Sqlite>, team_match: &'a TeamMatch, user_id: i64) -> Result<&'a str> {
+    let club_id: Option<i64> =
+        sqlx::query_scalar("select club_id from team_match_players where match_id = $1 and user_id = $2")
+            .bind(team_match.id)
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+    Ok(if club_id == Some(team_match.home_id) { &team_match.home } else { &team_match.away })
+}
+
+/// Starts an armageddon game to settle a tied match, between the players on
+/// the top board that has both of them free. Who gets white is drawn by lot.
+async fn playoff(state: &State, user_id: i64, team_match: &TeamMatch) -> Result<String> {
+    let db = &state.db;
+    if !team_match.is_admin(db, user_id).await? {
+        return Ok("Only club admins can start the playoff.".to_string());
+    }
+    if team_match.status != "tied" {
+        return Ok(format!("{} isn't tied, it is {}.", team_match.title(), team_match.status));
+    }
+    let mut players = None;
+    for game in boards(db, team_match.id).await? {
+        let (home, away) = if home_is_white(game.board) { (game.w_id, game.b_id) } else { (game.b_id, game.w_id) };
+        if is_free(db, home).await? && is_free(db, away).await? {
+            players = Some((home, away));
+            break;
+        }
+    }
+    let Some((home, away)) = players else {
+        return Ok("No board has both players free for the playoff yet.".to_string());
+    };
+    let (w_id, b_id) = if rand::random() { (home, away) } else { (away, home) };
+
+    let (tc, b_initial) = armageddon_time_control(state.config.get().time_control);
+    let delay = tc.delay;
+    let mut tx = db.begin().await?;
+    let (id,): (i64,) = sqlx::query_as(
+        "insert into games (w_id, b_id, ended, fen, initial_ms, increment_ms, delay_ms, bronstein, started_at,
+            last_move_at, w_clock_ms, b_clock_ms, turn_started_ms, armageddon, b_initial_ms)
+         values ($1, $2, 0, $3, $4, $5, $6, $7, unixepoch(), unixepoch(), $4, $8, $9, 1, $8) returning id",
+    )
+    .bind(w_id)
+    .bind(b_id)
+    .bind(STARTING_FEN)
+    .bind(tc.initial.as_millis() as i64)
+    .bind(tc.increment.as_millis() as i64)
+    .bind(delay.map(|d| match d {
+        Delay::Simple(d) | Delay::Bronstein(d) => d.as_millis() as i64,
+    }))
+    .bind(delay.map(|d| matches!(d, Delay::Bronstein(_))))
+    .bind(b_initial.as_millis() as i64)
+    .bind(clock::now_ms())
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query("update team_matches set status = 'playoff', playoff_game_id = $2 where id = $1")
+        .bind(team_match.id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    info!("started playoff game {id} for match {}", team_match.id);
+
+    let prefix = format!("{}, armageddon playoff. Game #{id}", team_match.title());
+    let text = format!(
+        "{prefix}. You are white, playing against {}. Your turn!\n{}",
+        player_card(db, b_id).await?,
+        armageddon_terms(state, w_id, id).await?
+    );
+    state.client.send_message(packed_chat(w_id), text).await?;
+    let text = format!(
+        "{prefix}. You are black, playing against {}. Waiting for opponent's move.\n{}",
+        player_card(db, w_id).await?,
+        armageddon_terms(state, b_id, id).await?
+    );
+    state.client.send_message(packed_chat(b_id), text).await?;
+    follows::game_started(db, &state.client, state.config.get().public_url.as_deref(), id).await?;
+    let text = format!(
+        "{prefix}: {} – {} decides the match.",
+        user_name(db, w_id).await?,
+        user_name(db, b_id).await?
+    );
+    team_match.announce(db, &state.client, &text).await?;
+    Ok(format!("Started the playoff, game #{id}."))
+}
+
+/// Called when any game ends: finishes the game's match once all its
+/// boards are done, and tells both clubs the result.
+pub async fn game_finished(db: &Pool<Sqlite>, client: &Bot, game_id: i64) -> Result<()> {
+    let playoff_of: Option<i64> = sqlx::query_scalar("select id from team_matches where playoff_game_id = $1")
+        .bind(game_id)
+        .fetch_optional(db)
+        .await?;
+    if let Some(match_id) = playoff_of {
+        return playoff_finished(db, client, match_id, game_id).await;
+    }
+    let match_id: Option<i64> = sqlx::query_scalar("select team_match_id from games where id = $1")
+        .bind(game_id)
+        .fetch_optional(db)
+        .await?
+        .flatten();
+    let Some(match_id) = match_id else {
+        return Ok(());
+    };
+    let playing: i64 =
+        sqlx::query_scalar("select count(*) from games where team_match_id = $1 and coalesce(ended, 0) = 0")
+            .bind(match_id)
+            .fetch_one(db)
+            .await?;
+    if playing > 0 {
+        return Ok(());
+    }
+    settle(db, client, match_id).await
+}
+
+/// Finishes a match whose boards are all done and tells both clubs the
+/// result. A tied league match is a draw in the table, without a playoff.
+async fn settle(db: &Pool<Sqlite>, client: &Bot, match_id: i64) -> Result<()> {
+    let Some(team_match) = find(db, match_id).await? else {
+        return Ok(());
+    };
+    let (home, away) = score(&team_match, &boards(db, match_id).await?);
+    let tied = home == away && team_match.league_id.is_none();
+    let status = if tied { "tied" } else { "finished" };
+    let finished = sqlx::query(
+        "update team_matches set status = $2, finished_at = case when $2 = 'finished' then unixepoch() end
+         where id = $1 and status = 'playing'",
+    )
+    .bind(match_id)
+    .bind(status)
+    .execute(db)
+    .await?
+    .rows_affected();
+    let Some(team_match) = find(db, match_id).await?.filter(|_| finished > 0) else {
+        return Ok(());
+    };
+    info!("match {match_id} {status}");
+    let text = if tied {
+        format!(
+            "{}\nThe match is tied. Club admins can settle it with /match playoff {match_id}, an armageddon game \
+             where Black wins a draw.",
+            describe(db, &team_match).await?
+        )
+    } else {
+        format!("Final result:\n{}", describe(db, &team_match).await?)
+    };
+    team_match.announce(db, client, &text).await
+}
+
+/// Finishes a tied match once its playoff game is decided, or lets admins
+/// start another if it was aborted.
+async fn playoff_finished(db: &Pool<Sqlite>, client: &Bot, match_id: i64, game_id: i64) -> Result<()> {
+    let winner: Option<bool> = sqlx::query_scalar("select winner from games where id = $1")
+        .bind(game_id)
+        .fetch_one(db)
+        .await?;
+    let (status, set_game) = if winner.is_some() { ("finished", Some(game_id)) } else { ("tied", None) };
+    sqlx::query(
+        "update team_matches set status = $2, playoff_game_id = $3,
+         finished_at = case when $2 = 'finished' then unixepoch() end where id = $1 and status = 'playoff'",
+    )
+    .bind(match_id)
+    .bind(status)
+    .bind(set_game)
+    .execute(db)
+    .await?;
+    let Some(team_match) = find(db, match_id).await? else {
+        return Ok(());
+    };
+    info!("match {match_id} playoff ended, {status}");
+    let text = if winner.is_some() {
+        format!("Final result:\n{}", describe(db, &team_match).await?)
+    } else {
+        format!(
+            "The playoff of {} was not played out. Club admins can start another with /match playoff {match_id}",
+            team_match.title()
+        )
+    };
+    team_match.announce(db, client, &text).await
+}
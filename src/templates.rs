@@ -0,0 +1,126 @@
+//! The text the bot sends, kept out of the handlers. The English wording is
+//! built in from `templates/en.txt`; `TEMPLATES_DIR` may hold files like it,
+//! named after their language (`de.txt`), that reword or translate any of its
+//! messages. A message missing from a language falls back to English.
+
+use crate::{settings, State};
+use anyhow::{bail, Context, Result};
+use log::error;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const DEFAULTS: &str = include_str!("../templates/en.txt");
+
+/// The messages, by language and then key.
+pub struct Templates(HashMap<String, HashMap<String, String>>);
+
+impl Default for Templates {
+    fn default() -> Self {
+        let defaults = parse(DEFAULTS).expect("built-in templates invalid");
+        Templates(HashMap::from([(DEFAULT_LANGUAGE.to_string(), defaults)]))
+    }
+}
+
+impl Templates {
+    /// The built-in messages with those in `dir` over them, checking that
+    /// each is a message the bot sends and uses only its variables.
+    pub fn load(dir: Option<&str>) -> Result<Self> {
+        let mut templates = Templates::default();
+        let Some(dir) = dir else {
+            return Ok(templates);
+        };
+        let entries = fs::read_dir(dir).with_context(|| format!("cannot read templates in {dir}"))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("txt") {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+            let messages = parse(&text).with_context(|| format!("in {}", path.display()))?;
+            templates.add(&language, messages, &path)?;
+        }
+        Ok(templates)
+    }
+
+    fn add(&mut self, language: &str, messages: HashMap<String, String>, path: &Path) -> Result<()> {
+        let defaults = &self.0[DEFAULT_LANGUAGE];
+        for (key, text) in &messages {
+            let Some(default) = defaults.get(key) else {
+                bail!("unknown message {key} in {}", path.display());
+            };
+            let known = variables(default);
+            if let Some(name) = variables(text).into_iter().find(|name| !known.contains(name)) {
+                bail!("message {key} in {} has no variable {{{name}}}", path.display());
+            }
+        }
+        self.0.entry(language.to_string()).or_default().extend(messages);
+        Ok(())
+    }
+
+    /// Whether there are messages in the language.
+    pub fn has(&self, language: &str) -> bool {
+        self.0.contains_key(language)
+    }
+
+    /// The languages there are messages in, English first.
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        languages.sort_by_key(|&language| (language != DEFAULT_LANGUAGE, language));
+        languages
+    }
+
+    /// The message `key` in the language, with its variables filled in.
+    pub fn render(&self, language: &str, key: &str, vars: &[(&str, &dyn Display)]) -> String {
+        let template = self
+            .0
+            .get(language)
+            .and_then(|messages| messages.get(key))
+            .or_else(|| self.0[DEFAULT_LANGUAGE].get(key));
+        let Some(template) = template else {
+            error!("no message {key}");
+            return key.to_string();
+        };
+        vars.iter()
+            .fold(template.clone(), |text, (name, value)| text.replace(&format!("{{{name}}}"), &value.to_string()))
+    }
+}
+
+/// Reads `key = text` lines, skipping blank lines and comments.
+fn parse(text: &str) -> Result<HashMap<String, String>> {
+    let mut messages = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, text)) = line.split_once(" = ") else {
+            bail!("line {} is not `key = text`", n + 1);
+        };
+        messages.insert(key.trim().to_string(), text.trim().replace("\\n", "\n"));
+    }
+    Ok(messages)
+}
+
+/// The names in braces in a message.
+fn variables(text: &str) -> Vec<&str> {
+    text.split('{').skip(1).filter_map(|s| s.split_once('}')).map(|(name, _)| name).collect()
+}
+
+/// The message in the language of the user being answered.
+pub fn text(state: &State, key: &str, vars: &[(&str, &dyn Display)]) -> String {
+    state.config.get().templates.render(&state.language, key, vars)
+}
+
+/// The message in the language of some other user.
+pub async fn text_for(state: &State, user_id: i64, key: &str, vars: &[(&str, &dyn Display)]) -> Result<String> {
+    let config = state.config.get();
+    let language = settings::language(&state.db, &config.templates, user_id, None).await?;
+    Ok(config.templates.render(&language, key, vars))
+}
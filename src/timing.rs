@@ -0,0 +1,49 @@
+use crate::clock::format_clock;
+
+/// Time spent on each ply, from when the game started and when each move was played.
+pub fn spent(started_ms: i64, played_at: &[i64]) -> Vec<i64> {
+    let mut previous = started_ms;
+    played_at
+        .iter()
+        .map(|&t| {
+            let spent = (t - previous).max(0);
+            previous = t;
+            spent
+        })
+        .collect()
+}
+
+/// Summarizes both players' thinking times, e.g.
+/// `White: avg 0:12, longest 1:40 (move 23)`, one line per player.
+pub fn describe(spent: &[i64]) -> Option<String> {
+    let lines: Vec<String> = ["White", "Black"]
+        .iter()
+        .enumerate()
+        .filter_map(|(side, name)| {
+            let moves: Vec<(usize, i64)> = spent.iter().copied().enumerate().skip(side).step_by(2).collect();
+            let (longest_ply, longest) = moves.iter().copied().max_by_key(|&(_, ms)| ms)?;
+            let average = moves.iter().map(|&(_, ms)| ms).sum::<i64>() / moves.len() as i64;
+            Some(format!(
+                "{name}: avg {}, longest {} (move {})",
+                format_clock(average),
+                format_clock(longest),
+                longest_ply / 2 + 1,
+            ))
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// PGN comment with the time spent on a move and, for timed games, the clock left after it.
+pub fn comment(spent_ms: i64, clock_ms: Option<i64>) -> String {
+    match clock_ms {
+        Some(clock_ms) => format!("[%emt {}] [%clk {}]", pgn_time(spent_ms), pgn_time(clock_ms)),
+        None => format!("[%emt {}]", pgn_time(spent_ms)),
+    }
+}
+
+/// `h:mm:ss` as used by `%clk` and `%emt`; hours aren't wrapped into days.
+fn pgn_time(ms: i64) -> String {
+    let secs = ms.max(0) / 1000;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, bail, Result};
+use grammers_client::types::{Downloadable, Media, Message};
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use log::debug;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Longest voice message worth transcribing, in seconds. A move takes a couple.
+const MAX_DURATION_SECS: i32 = 15;
+
+/// How many times to ask Telegram again while a transcription is pending.
+const TELEGRAM_POLLS: u32 = 10;
+const TELEGRAM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where voice messages get turned into text.
+#[derive(Debug, Clone)]
+pub enum Transcriber {
+    /// Telegram's own speech recognition.
+    Telegram,
+    /// A local service (e.g. a whisper server) that takes the raw audio as a
+    /// POST body and answers with plain text.
+    Http { host: String, path: String },
+}
+
+impl FromStr for Transcriber {
+    type Err = anyhow::Error;
+
+    /// Parses `telegram` or `http://host:port/path`.
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "telegram" {
+            return Ok(Transcriber::Telegram);
+        }
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("expected `telegram` or an http:// url"))?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            bail!("missing host");
+        }
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+        Ok(Transcriber::Http {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// The audio attached to `message`, if it is a voice message short enough to be a move.
+pub fn voice_media(message: &Message) -> Option<Media> {
+    let media = message.media()?;
+    let Media::Document(document) = &media else {
+        return None;
+    };
+    if !document.mime_type()?.starts_with("audio/") {
+        return None;
+    }
+    if document.duration().unwrap_or(0) > MAX_DURATION_SECS {
+        return None;
+    }
+    Some(media)
+}
+
+impl Transcriber {
+    pub async fn transcribe(&self, client: &Client, message: &Message, media: Media) -> Result<String> {
+        match self {
+            Transcriber::Telegram => {
+                let request = tl::functions::messages::TranscribeAudio {
+                    peer: message.chat().pack().to_input_peer(),
+                    msg_id: message.id(),
+                };
+                for _ in 0..TELEGRAM_POLLS {
+                    let tl::enums::messages::TranscribedAudio::Audio(audio) = client.invoke(&request).await?;
+                    if !audio.pending {
+                        return Ok(audio.text);
+                    }
+                    tokio::time::sleep(TELEGRAM_POLL_INTERVAL).await;
+                }
+                bail!("transcription still pending")
+            }
+            Transcriber::Http { host, path } => {
+                let mut audio = Vec::new();
+                let mut download = client.iter_download(&Downloadable::Media(media));
+                while let Some(chunk) = download.next().await? {
+                    audio.extend(chunk);
+                }
+                post(host, path, &audio).await
+            }
+        }
+    }
+}
+
+async fn post(host: &str, path: &str, body: &[u8]) -> Result<String> {
+    let mut stream = TcpStream::connect(host).await?;
+    let head = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: audio/ogg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("malformed response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    debug!("transcriber answered {status}");
+    if status.split(' ').nth(1) != Some("200") {
+        bail!("transcriber answered {status}");
+    }
+    Ok(body.trim().to_string())
+}
+
+/// Turns a spoken move ("knight takes f three", "e two e four",
+/// "castle kingside") into notation `parse_move` understands.
+pub fn normalize(text: &str) -> Option<String> {
+    let text = text.to_lowercase();
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    if words.iter().any(|w| matches!(*w, "castle" | "castles" | "castling" | "o")) {
+        if words.iter().any(|w| matches!(*w, "queenside" | "long" | "queen")) {
+            return Some("O-O-O".to_string());
+        }
+        return Some("O-O".to_string());
+    }
+
+    let mut notation = String::new();
+    let mut promoting = false;
+    for word in words {
+        let after_file = notation.ends_with(|c: char| ('a'..='h').contains(&c));
+        let piece = match word {
+            "king" => Some('K'),
+            "queen" => Some('Q'),
+            "rook" | "castle" => Some('R'),
+            "bishop" => Some('B'),
+            "knight" | "night" | "horse" => Some('N'),
+            _ => None,
+        };
+        if let Some(piece) = piece {
+            if promoting {
+                notation.push('=');
+            }
+            notation.push(piece);
+            continue;
+        }
+        let rank = match word {
+            "one" | "won" => Some('1'),
+            "two" => Some('2'),
+            "to" | "too" if after_file => Some('2'),
+            "three" => Some('3'),
+            "four" => Some('4'),
+            "for" if after_file => Some('4'),
+            "five" => Some('5'),
+            "six" => Some('6'),
+            "seven" => Some('7'),
+            "eight" => Some('8'),
+            "ate" if after_file => Some('8'),
+            _ => None,
+        };
+        if let Some(rank) = rank {
+            notation.push(rank);
+            continue;
+        }
+        let file = match word {
+            "alpha" | "alfa" => Some('a'),
+            "bravo" | "be" | "bee" => Some('b'),
+            "charlie" | "see" | "sea" => Some('c'),
+            "delta" | "dee" => Some('d'),
+            "echo" => Some('e'),
+            "foxtrot" => Some('f'),
+            "golf" | "gee" => Some('g'),
+            "hotel" => Some('h'),
+            _ => None,
+        };
+        if let Some(file) = file {
+            notation.push(file);
+            continue;
+        }
+        match word {
+            "takes" | "take" | "captures" | "capture" | "x" => notation.push('x'),
+            "promotes" | "promote" | "promoting" | "equals" => promoting = true,
+            // "e4", "f3", "nf3" and the like come through already written out
+            w if w.len() <= 5 && w.chars().all(|c| c.is_ascii_alphanumeric()) && w.chars().any(|c| c.is_ascii_digit()) => {
+                let mut chars = w.chars();
+                if let Some(first) = chars.next() {
+                    match first {
+                        'k' | 'q' | 'r' | 'n' if w.len() > 2 => notation.push(first.to_ascii_uppercase()),
+                        _ => notation.push(first),
+                    }
+                }
+                notation.extend(chars);
+            }
+            w if w.len() == 1 && ('a'..='h').contains(&w.chars().next().unwrap()) => notation.push_str(w),
+            // "to", "pawn", "check", "please" and other filler
+            _ => {}
+        }
+    }
+
+    (!notation.is_empty()).then_some(notation)
+}
@@ -0,0 +1,230 @@
+use crate::material::figurine;
+use crate::{api, feed, game_by_id, game_ucis, position_from_fen, san_moves, user_name, Variant};
+use anyhow::Result;
+use log::{debug, error, info};
+use shakmaty::{Color, File, Position, Rank, Square};
+use sqlx::{Pool, Sqlite};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Serves read-only game pages at `/game/<id>`, their live feed at
+/// `/game/<id>/events` and the JSON API under `/api/` until the process exits.
+pub async fn serve(addr: String, db: Pool<Sqlite>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("cannot listen on {addr}: {e}");
+            return;
+        }
+    };
+    info!("serving games on {addr}");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("cannot accept connection: {e}");
+                continue;
+            }
+        };
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &db).await {
+                debug!("error while serving {peer}: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, db: &Pool<Sqlite>) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+    debug!("http {method:?} {path:?}");
+
+    if let (Some("GET"), Some(path)) = (method, path) {
+        // the page asks for the moves after the ones it shows, a reconnecting
+        // browser for the ones after the last event it got
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        if let Some(id) = path.strip_prefix("/game/").and_then(|p| p.strip_suffix("/events")?.parse().ok()) {
+            let last_ply = header(&head, "Last-Event-ID")
+                .or_else(|| query.strip_prefix("after="))
+                .and_then(|ply| ply.parse().ok());
+            return feed::stream(stream, db, id, last_ply).await;
+        }
+    }
+    let response = match (method, path) {
+        (Some("GET"), Some(path)) if path.starts_with("/api/") => {
+            let (status, body) = api::respond(db, &head, path).await?;
+            response(status, "application/json", &body)
+        }
+        (Some("GET"), Some(path)) => match path.strip_prefix("/game/").and_then(|id| id.parse().ok()) {
+            Some(id) => match game_page(db, id).await? {
+                Some(page) => response("200 OK", "text/html; charset=utf-8", &page),
+                None => response("404 Not Found", "text/plain", "no such game"),
+            },
+            None => response("404 Not Found", "text/plain", "not found"),
+        },
+        _ => response("405 Method Not Allowed", "text/plain", "method not allowed"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// The value of the request header, whose name is matched regardless of case.
+pub fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then_some(value.trim())
+    })
+}
+
+pub fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn game_page(db: &Pool<Sqlite>, id: i64) -> Result<Option<String>> {
+    let Some(game) = game_by_id(db, id).await? else {
+        return Ok(None);
+    };
+    // a live fog of war game would give away what the players can't see
+    if game.variant() == Variant::FogOfWar && !game.ended {
+        return Ok(None);
+    }
+    let name = |id: Option<i64>| async move {
+        match id {
+            Some(id) => user_name(db, id).await.map(|n| escape(&n)),
+            None => Ok("?".to_string()),
+        }
+    };
+    let (white, black) = (name(game.w_id).await?, name(game.b_id).await?);
+    let sans = san_moves(game.start_fen(), &game_ucis(db, id).await?);
+    let board = position_from_fen(&game.fen);
+
+    let mut rows = String::new();
+    for rank in Rank::ALL.into_iter().rev() {
+        rows.push_str("<tr>");
+        for file in File::ALL {
+            let square = Square::from_coords(file, rank);
+            let shade = if square.is_light() { "light" } else { "dark" };
+            let piece = board
+                .board()
+                .piece_at(square)
+                .map(|p| figurine(p.color, p.role))
+                .unwrap_or(' ');
+            rows.push_str(&format!("<td class=\"{shade}\">{piece}</td>"));
+        }
+        rows.push_str("</tr>\n");
+    }
+
+    // a game set up with Black to move opens with Black's half of a move
+    let start = position_from_fen(game.start_fen());
+    let black_first = start.turn() == Color::Black;
+    let mut cells: Vec<&str> = sans.iter().map(String::as_str).collect();
+    if black_first {
+        cells.insert(0, "…");
+    }
+    let mut moves = String::new();
+    for (i, pair) in cells.chunks(2).enumerate() {
+        moves.push_str(&format!("<li>{}</li>", pair.join(" ")));
+        if i % 10 == 9 {
+            moves.push('\n');
+        }
+    }
+
+    let (live, status) = if game.ended {
+        (String::new(), format!("Result: {}", game.result()))
+    } else {
+        let turn = if board.turn() == Color::White { "White" } else { "Black" };
+        (live_script(id, sans.len(), black_first), format!("{turn} to move"))
+    };
+
+    let first_number = start.fullmoves();
+    Ok(Some(format!(
+        "<!doctype html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Game #{id}: {white} vs {black}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 2em auto; }}
+table {{ border-collapse: collapse; }}
+td {{ width: 2em; height: 2em; text-align: center; font-size: 1.5em; }}
+.light {{ background: #f0d9b5; }}
+.dark {{ background: #b58863; }}
+</style>
+</head>
+<body>
+<h1>{white} vs {black}</h1>
+<table id=\"board\">
+{rows}</table>
+<p id=\"status\">{status}</p>
+<ol id=\"moves\" start=\"{first_number}\">
+{moves}</ol>
+{live}</body>
+</html>
+"
+    )))
+}
+
+/// Keeps the page of a game in progress up to date from its live feed.
+/// White's moves start a new item, which is the odd plies unless Black moved
+/// first.
+fn live_script(id: i64, plies: usize, black_first: bool) -> String {
+    let white_parity = if black_first { 0 } else { 1 };
+    format!(
+        "<script>
+const pieces = {{ K: '♔', Q: '♕', R: '♖', B: '♗', N: '♘', P: '♙', k: '♚', q: '♛', r: '♜', b: '♝', n: '♞', p: '♟' }};
+const events = new EventSource('{id}/events?after={plies}');
+events.addEventListener('move', e => {{
+  const move = JSON.parse(e.data);
+  const [placement, turn] = move.fen.split(' ');
+  const cells = document.querySelectorAll('#board td');
+  let i = 0;
+  for (const c of placement.replaceAll('/', '')) {{
+    if (c >= '1' && c <= '8') {{
+      for (let n = 0; n < Number(c); n++) cells[i++].textContent = ' ';
+    }} else {{
+      cells[i++].textContent = pieces[c];
+    }}
+  }}
+  const moves = document.getElementById('moves');
+  if (move.ply % 2 === {white_parity}) {{
+    const item = document.createElement('li');
+    item.textContent = move.san;
+    moves.appendChild(item);
+  }} else {{
+    moves.lastElementChild.textContent += ' ' + move.san;
+  }}
+  document.getElementById('status').textContent = (turn === 'w' ? 'White' : 'Black') + ' to move';
+}});
+events.addEventListener('undo', () => location.reload());
+events.addEventListener('end', e => {{
+  document.getElementById('status').textContent = 'Result: ' + JSON.parse(e.data).result;
+  events.close();
+}});
+</script>
+"
+    )
+}
@@ -0,0 +1,170 @@
+//! The weekly digest: once a week, users who turned it on with `/digest
+//! weekly on` get a summary of their last seven days — games and rating
+//! change, their best win and roughest game, puzzles solved — and the run of
+//! results they are on.
+
+use crate::bot::Bot;
+use crate::rating::Category;
+use crate::{packed_chat, streaks, teams, user_name, Game, GAME_COLUMNS};
+use anyhow::Result;
+use log::debug;
+use sqlx::{Pool, Sqlite};
+
+const WEEK_SECS: i64 = 7 * 86400;
+
+/// A little under a week, so the hourly job doesn't drift later and later.
+const DIGEST_EVERY_SECS: i64 = WEEK_SECS - 3600;
+
+/// Sends the weekly digests that are due.
+pub async fn send(db: &Pool<Sqlite>, client: &Bot) -> Result<()> {
+    let due: Vec<i64> = sqlx::query_scalar(
+        "select id from users where weekly_digest and unreachable_since is null
+         and (weekly_digest_sent_at is null or weekly_digest_sent_at <= unixepoch() - $1)",
+    )
+    .bind(DIGEST_EVERY_SECS)
+    .fetch_all(db)
+    .await?;
+    for user_id in due {
+        // a quiet week is not worth a message
+        if let Some(text) = summary(db, user_id).await? {
+            client.send_message(packed_chat(user_id), text).await?;
+            debug!("sent weekly digest to {user_id}");
+        }
+        sqlx::query("update users set weekly_digest_sent_at = unixepoch() where id = $1")
+            .bind(user_id)
+            .execute(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The user's last seven days, if they played or solved anything.
+pub async fn summary(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<String>> {
+    let games: Vec<Game> = sqlx::query_as(&format!(
+        "select {GAME_COLUMNS} from games where (w_id = $1 or b_id = $1) and ended = 1 and started_at is not null
+         and deleted_at is null and ended_at > unixepoch() - $2 order by ended_at"
+    ))
+    .bind(user_id)
+    .bind(WEEK_SECS)
+    .fetch_all(db)
+    .await?;
+    let solved: i64 = sqlx::query_scalar(
+        "select count(*) from puzzle_attempts where user_id = $1 and solved and created_at > unixepoch() - $2",
+    )
+    .bind(user_id)
+    .bind(WEEK_SECS)
+    .fetch_one(db)
+    .await?;
+    if games.is_empty() && solved == 0 {
+        return Ok(None);
+    }
+
+    let mut half_points = 0;
+    let mut rating_changes: Vec<(Category, i64)> = Vec::new();
+    // the win against the highest rated opponent, with their rating
+    let mut best_win: Option<(&Game, i64, i64)> = None;
+    for game in &games {
+        let white = game.w_id == Some(user_id);
+        let (white_points, black_points) = teams::half_points(game.winner, game.termination);
+        half_points += if white { white_points } else { black_points };
+        let (diff, opponent, opponent_rating) = match white {
+            true => (game.w_rating_diff, game.b_id, game.b_rating),
+            false => (game.b_rating_diff, game.w_id, game.w_rating),
+        };
+        if let Some(diff) = diff {
+            match rating_changes.iter_mut().find(|(category, _)| *category == game.category()) {
+                Some((_, change)) => *change += diff,
+                None => rating_changes.push((game.category(), diff)),
+            }
+        }
+        let won = game.winner == Some(white);
+        if let (true, Some(opponent), Some(rating)) = (won, opponent, opponent_rating) {
+            if best_win.is_none_or(|(_, _, best)| rating > best) {
+                best_win = Some((game, opponent, rating));
+            }
+        }
+    }
+
+    let mut text = format!(
+        "Your week: {} played, {} points",
+        count(games.len() as i64, "game", "games"),
+        teams::format_points(half_points)
+    );
+    if solved > 0 {
+        text = format!("{text}, {} solved", count(solved, "puzzle", "puzzles"));
+    }
+    text.push('.');
+    if !rating_changes.is_empty() {
+        let changes: Vec<String> =
+            rating_changes.iter().map(|(category, change)| format!("{} {change:+}", category.name())).collect();
+        text = format!("{text}\nRating: {}", changes.join(", "));
+    }
+    if let Some((game, opponent, rating)) = best_win {
+        text = format!("{text}\nBest win: game #{} against {} ({rating})", game.id, user_name(db, opponent).await?);
+    }
+    if let Some((game_id, loss)) = roughest_game(db, user_id).await? {
+        text = format!("{text}\nRoughest game: #{game_id}, {loss} centipawns lost a move. /review {game_id}");
+    }
+    if let Some(streak) = streak(db, user_id).await? {
+        text = format!("{text}\nCurrent streak: {streak}");
+    }
+    let play_streak = streaks::of_user(db, user_id).await?;
+    if play_streak.current > 1 {
+        text = format!("{text}\nPlay streak: {} days, best {}", play_streak.current, play_streak.best);
+    }
+    Ok(Some(text))
+}
+
+/// The game of the week the engine liked the user's moves least in, with the
+/// centipawns they lost a move on average.
+async fn roughest_game(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<(i64, i64)>> {
+    Ok(sqlx::query_as(
+        "select r.game_id, r.cp_loss / r.moves from engine_reviews r join games g on g.id = r.game_id
+         where r.user_id = $1 and r.moves > 0 and g.ended_at > unixepoch() - $2 and g.deleted_at is null
+         order by r.cp_loss * 1.0 / r.moves desc limit 1",
+    )
+    .bind(user_id)
+    .bind(WEEK_SECS)
+    .fetch_optional(db)
+    .await?)
+}
+
+/// The user's run of wins, losses or draws up to their last game, if it is
+/// longer than one.
+async fn streak(db: &Pool<Sqlite>, user_id: i64) -> Result<Option<String>> {
+    let results: Vec<(bool, Option<bool>, Option<i64>)> = sqlx::query_as(
+        "select w_id = $1, winner, termination from games where (w_id = $1 or b_id = $1) and ended = 1
+         and started_at is not null and deleted_at is null order by ended_at desc, id desc limit 100",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+    let points: Vec<i64> = results
+        .into_iter()
+        .filter_map(|(white, winner, termination)| match teams::half_points(winner, termination) {
+            // aborted and abandoned games break no streak
+            (0, 0) => None,
+            (white_points, black_points) => Some(if white { white_points } else { black_points }),
+        })
+        .collect();
+    let Some(&last) = points.first() else {
+        return Ok(None);
+    };
+    let n = points.iter().take_while(|&&points| points == last).count() as i64;
+    if n < 2 {
+        return Ok(None);
+    }
+    Ok(Some(match last {
+        2 => count(n, "win", "wins"),
+        1 => count(n, "draw", "draws"),
+        _ => count(n, "loss", "losses"),
+    }))
+}
+
+fn count(n: i64, one: &str, many: &str) -> String {
+    if n == 1 {
+        format!("1 {one}")
+    } else {
+        format!("{n} {many}")
+    }
+}